@@ -0,0 +1,182 @@
+//! One client with 1,000 simulated peers connected over a `MemoryNetwork`, each peer
+//! being a link at the other end of the in-memory transport of its connection.
+//!
+//! Criterion measures sending to every peer from several threads while each thread
+//! also polls the state of the peers of the others, which contend on the connection
+//! registry. The memory used per connection is reported from the resident set size of
+//! the process, which is read from `/proc/self/status` and only available on Linux.
+//! With the `lock-profiling` feature the contention of the `connections` locks is
+//! reported as well.
+
+use std::fs;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use aether_lib::config::Config;
+use aether_lib::link::Link;
+use aether_lib::peer::Aether;
+use aether_lib::sequence::Seq;
+use aether_lib::test_util::{connect_link, identity, MemoryNetwork};
+use aether_lib::transport::Transport;
+
+const PEERS: usize = 1_000;
+const THREADS: usize = 8;
+
+/// Resident set size of this process in bytes
+fn resident_memory() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+fn uid(i: usize) -> String {
+    format!("peer-{}", i)
+}
+
+/// Start a client and connect it to `PEERS` simulated peers, returning the client and
+/// the links of the peers
+fn connect_peers(network: &MemoryNetwork) -> (Arc<Aether>, Vec<Link>) {
+    let tracker_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 8982));
+    let (client_id, client_public) = identity();
+    let (peer_id, peer_public) = identity();
+    let config = Config::default();
+
+    let aether = Arc::new(Aether::new_with_id(client_id.clone(), tracker_addr));
+    aether.start();
+
+    // The peers are started first so that the memory of the client's connections can
+    // be measured on its own
+    let mut peers = Vec::with_capacity(PEERS);
+    let mut transports = Vec::with_capacity(PEERS);
+    for _ in 0..PEERS {
+        let (local, remote) = network.pair();
+        let mut peer = Link::new(
+            peer_id.clone(),
+            remote,
+            local.local_addr().unwrap(),
+            client_public.clone(),
+            Seq(1000),
+            Seq(0),
+            config,
+        )
+        .unwrap();
+        peer.start();
+        transports.push((local, peer.local_addr().unwrap()));
+        peers.push(peer);
+    }
+
+    let before = resident_memory();
+    for (i, (local, peer_addr)) in transports.into_iter().enumerate() {
+        let mut link = Link::new(
+            client_id.clone(),
+            local,
+            peer_addr,
+            peer_public.clone(),
+            Seq(0),
+            Seq(1000),
+            config,
+        )
+        .unwrap();
+        link.start();
+        connect_link(&aether, &uid(i), link);
+    }
+
+    match (before, resident_memory()) {
+        (Some(before), Some(after)) => println!(
+            "connection_registry/{}: {} bytes of memory per connection",
+            PEERS,
+            after.saturating_sub(before) / PEERS
+        ),
+        _ => println!("connection_registry: memory is not available on this platform"),
+    }
+
+    (aether, peers)
+}
+
+/// Send a message to every peer from `THREADS` threads, each thread also polling the
+/// state of the peers the others send to
+fn send_to_peers(aether: &Arc<Aether>) {
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let aether = aether.clone();
+            thread::spawn(move || {
+                for i in (t..PEERS).step_by(THREADS) {
+                    aether.send_to(&uid(i), b"Hello".to_vec()).unwrap();
+                    aether.is_connected(&uid((i + 1) % PEERS));
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// Receive the messages of all peers until `done` is set
+fn drain(peers: Vec<Link>, done: Arc<AtomicBool>) -> thread::JoinHandle<Vec<Link>> {
+    thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            let mut received = false;
+            for peer in &peers {
+                while let Ok(Some(_)) = peer.try_recv() {
+                    received = true;
+                }
+            }
+            if !received {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        peers
+    })
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let network = MemoryNetwork::new();
+    let (aether, peers) = connect_peers(&network);
+    assert!((0..PEERS).all(|i| aether.is_connected(&uid(i))));
+
+    let done = Arc::new(AtomicBool::new(false));
+    let drain_handle = drain(peers, done.clone());
+
+    let mut group = c.benchmark_group("connection_registry");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(PEERS as u64));
+
+    group.bench_with_input(BenchmarkId::from_parameter(PEERS), &PEERS, |b, _| {
+        b.iter(|| send_to_peers(&aether))
+    });
+
+    group.finish();
+
+    #[cfg(feature = "lock-profiling")]
+    for lock in aether_lib::sync::report()
+        .iter()
+        .filter(|lock| lock.name == "connections")
+    {
+        println!(
+            "connection_registry/{}: {} acquisitions of the connections locks, {} \
+             contended, {:?} waited in total, {:?} at most",
+            PEERS, lock.acquisitions, lock.contentions, lock.wait, lock.max_wait
+        );
+    }
+
+    done.store(true, Ordering::Relaxed);
+    let peers = drain_handle.join().unwrap();
+
+    // Stopping a link waits for its receive thread to time out reading its transport,
+    // so the links are left to end with the process instead of stopping them in turn
+    mem::forget(aether);
+    mem::forget(peers);
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
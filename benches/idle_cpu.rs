@@ -0,0 +1,136 @@
+//! CPU used by idle links and clients, which should be close to zero since their
+//! threads sleep until there is something to do.
+//!
+//! Criterion measures wall clock time, so this benchmark reports the CPU time of the
+//! process instead. It reads `/proc/self/stat` and only runs on Linux.
+
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aether_lib::config::Config;
+use aether_lib::identity::{Id, PublicId};
+use aether_lib::link::Link;
+use aether_lib::peer::Aether;
+use aether_lib::sequence::Seq;
+
+const LINK_PAIRS: usize = 8;
+const CLIENTS: usize = 4;
+const IDLE_TIME: Duration = Duration::from_secs(5);
+
+/// Clock ticks per second used by `/proc` (`USER_HZ`, which is 100 on Linux)
+const CLOCK_TICKS: u64 = 100;
+
+/// Returns the CPU time (user and system) used by all threads of this process
+fn cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+
+    // The command name may contain spaces, the other fields follow it
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split(' ').collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+
+    Some(Duration::from_millis((utime + stime) * 1000 / CLOCK_TICKS))
+}
+
+fn public_id(id: &Id) -> PublicId {
+    PublicId::from_base64(&id.public_key_to_base64().unwrap()).unwrap()
+}
+
+fn link_pair() -> (Link, Link) {
+    let socket1 = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+    let socket2 = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+
+    let peer_addr1 = socket1.local_addr().unwrap();
+    let peer_addr2 = socket2.local_addr().unwrap();
+
+    let id1 = Id::new().unwrap();
+    let id2 = Id::new().unwrap();
+    let id1_public = public_id(&id1);
+    let id2_public = public_id(&id2);
+
+    let config = Config::default();
+    let mut link1 = Link::new(
+        id1,
+        socket1,
+        peer_addr2,
+        id2_public,
+        Seq(0),
+        Seq(1000),
+        config,
+    )
+    .unwrap();
+    let mut link2 = Link::new(
+        id2,
+        socket2,
+        peer_addr1,
+        id1_public,
+        Seq(1000),
+        Seq(0),
+        config,
+    )
+    .unwrap();
+
+    link1.start();
+    link2.start();
+
+    // Encryption adds a thread to each link
+    let handle = thread::spawn(move || {
+        link2.enable_encryption().unwrap();
+        link2
+    });
+    link1.enable_encryption().unwrap();
+    let link2 = handle.join().unwrap();
+
+    (link1, link2)
+}
+
+fn main() {
+    if cpu_time().is_none() {
+        println!("idle_cpu: CPU time is not available on this platform, skipping");
+        return;
+    }
+
+    let mut links = Vec::new();
+    for _ in 0..LINK_PAIRS {
+        let (link1, link2) = link_pair();
+
+        // Let the links exchange their first packet before measuring
+        link1.send(b"Hello".to_vec()).unwrap();
+        link2.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        links.push((link1, link2));
+    }
+
+    // Clients wait for connection requests of other peers, the tracker does not have to
+    // exist for that
+    let tracker_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 8982));
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let client = Aether::new_with_id(Id::new().unwrap(), tracker_addr);
+            client.start();
+            client
+        })
+        .collect();
+
+    thread::sleep(Duration::from_secs(1));
+
+    let start = Instant::now();
+    let cpu_start = cpu_time().unwrap();
+    thread::sleep(IDLE_TIME);
+    let cpu = cpu_time().unwrap() - cpu_start;
+    let elapsed = start.elapsed();
+
+    println!(
+        "idle_cpu/{} links/{} clients: {:?} CPU time in {:?} ({:.2}% of a core)",
+        LINK_PAIRS * 2,
+        CLIENTS,
+        cpu,
+        elapsed,
+        cpu.as_secs_f64() * 100.0 / elapsed.as_secs_f64()
+    );
+
+    drop(links);
+    drop(clients);
+}
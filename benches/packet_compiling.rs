@@ -3,6 +3,7 @@ use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criteri
 use aether_lib::{
     acknowledgement::AcknowledgementList,
     packet::{PType, Packet},
+    sequence::Seq,
     util::gen_nonce,
 };
 
@@ -17,12 +18,12 @@ pub fn criterion_benchmark(c: &mut Criterion) {
 
         group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
             b.iter(|| {
-                let mut packet = black_box(Packet::new(PType::Data, 32));
-                let mut ack = black_box(AcknowledgementList::new(1000));
-                ack.insert(1002);
-                ack.insert(1003);
-                ack.insert(1005);
-                ack.insert(2000);
+                let mut packet = black_box(Packet::new(PType::Data, Seq(32)));
+                let mut ack = black_box(AcknowledgementList::new(Seq(1000)));
+                ack.insert(Seq(1002));
+                ack.insert(Seq(1003));
+                ack.insert(Seq(1005));
+                ack.insert(Seq(2000));
                 packet.add_ack(ack.get());
 
                 packet.set_enc(true);
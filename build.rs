@@ -0,0 +1,17 @@
+//! Generates the protobuf encoding of tracker packets from `proto/tracker.proto` with
+//! the `protobuf` feature (refer `tracker::protobuf`).
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/tracker.proto");
+
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is not vendored");
+        std::env::set_var("PROTOC", protoc);
+
+        prost_build::compile_protos(&["proto/tracker.proto"], &["proto"])
+            .expect("Unable to compile proto/tracker.proto");
+    }
+}
@@ -0,0 +1,74 @@
+//! Measures the throughput of a link between two peers.
+//!
+//! ```text
+//! cargo run --example bandwidth --features test-util [-- <megabytes>]
+//! ```
+//!
+//! Sends 1 MB if no size is given.
+
+mod common;
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aether_lib::test_util::TestTracker;
+
+use common::{client, connect};
+
+/// How long the receiver waits for the next message
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    let megabytes: usize = env::args()
+        .nth(1)
+        .map_or(1, |size| size.parse().expect("invalid size"));
+    let total = megabytes * 1024 * 1024;
+
+    let tracker = TestTracker::start();
+    let sender = client(&tracker);
+    let receiver = client(&tracker);
+    connect(&sender, &receiver);
+
+    let sender_uid = sender.get_uid().to_string();
+    let receiver_uid = receiver.get_uid().to_string();
+    // Messages have to fit into a single (encrypted) packet
+    let negotiated = sender.negotiated(&receiver_uid).expect("not connected");
+    let message_size = negotiated.max_message_size.min(negotiated.mtu);
+
+    let receiving = thread::spawn(move || {
+        let mut received = 0;
+        while received < total {
+            received += receiver
+                .recv_timeout_from(&sender_uid, RECV_TIMEOUT)
+                .expect("message lost")
+                .len();
+        }
+        receiver
+    });
+
+    let start = Instant::now();
+    let mut sent = 0;
+    while sent < total {
+        let size = message_size.min(total - sent);
+        sender
+            .send_to(&receiver_uid, vec![0; size])
+            .expect("unable to send");
+        sent += size;
+    }
+
+    let receiver = receiving.join().expect("receiver failed");
+    let elapsed = start.elapsed();
+    let stats = sender.stats_for(receiver.get_uid()).expect("not connected");
+
+    println!(
+        "{} MB in {:?}: {:.2} Mbit/s",
+        megabytes,
+        elapsed,
+        (total * 8) as f64 / elapsed.as_secs_f64() / 1_000_000.0
+    );
+    println!(
+        "{} packets sent, {} retransmitted, round trip time {} us",
+        stats.packets_sent, stats.retransmissions, stats.rtt_us
+    );
+}
@@ -0,0 +1,43 @@
+//! Helpers shared by the examples. Every example runs all of its peers in one process
+//! against a [`TestTracker`], so it can be run without a tracker server.
+
+// Not every example uses every helper
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use aether_lib::peer::Aether;
+use aether_lib::test_util::{identity, wait_until, TestTracker};
+
+/// How long the peers of an example may take to connect to each other
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Start a client with a throwaway identity using `tracker`
+pub fn client(tracker: &TestTracker) -> Aether {
+    let aether = Aether::new_with_id(identity().0, tracker.addr());
+    aether.start();
+    aether
+}
+
+/// Connect `first` and `second` to each other
+/// # Panics
+/// If the clients are not connected within [`CONNECT_TIMEOUT`]
+pub fn connect(first: &Aether, second: &Aether) {
+    first.connect(second.get_uid()).expect("unable to connect");
+    second.connect(first.get_uid()).expect("unable to connect");
+
+    let connected = wait_until(CONNECT_TIMEOUT, || {
+        first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+    });
+    assert!(
+        connected,
+        "peers did not connect within {:?}",
+        CONNECT_TIMEOUT
+    );
+}
+
+/// Returns a short form of `uid` to be printed. UIDs start with the same encoded key
+/// header, so the start of the key itself is used
+pub fn short(uid: &str) -> &str {
+    &uid[uid.len().min(38)..uid.len().min(50)]
+}
@@ -0,0 +1,98 @@
+//! Sends a file from one peer to another.
+//!
+//! The sender offers the file in a [`Control`] message and then sends its contents in
+//! messages of the maximum message size agreed on with the receiver.
+//!
+//! ```text
+//! cargo run --example file_transfer --features test-util [-- <path>]
+//! ```
+//!
+//! Sends `Cargo.toml` if no path is given.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aether_lib::test_util::TestTracker;
+use aether_lib::wire::control::{self, Control};
+use serde::{Deserialize, Serialize};
+
+use common::{client, connect};
+
+/// How long the receiver waits for the next part of the file
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Control message announcing a file, followed by its contents
+#[derive(Serialize, Deserialize, Debug)]
+struct FileOffer {
+    name: String,
+    size: u64,
+}
+
+impl Control for FileOffer {
+    const KIND: u16 = 0x8001;
+}
+
+fn main() {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("Cargo.toml"));
+    let data = fs::read(&path).expect("unable to read file");
+
+    let tracker = TestTracker::start();
+    let sender = client(&tracker);
+    let receiver = client(&tracker);
+    connect(&sender, &receiver);
+
+    let sender_uid = sender.get_uid().to_string();
+    let receiver_uid = receiver.get_uid().to_string();
+    let receiving = thread::spawn(move || {
+        let frame = receiver
+            .recv_timeout_from(&sender_uid, RECV_TIMEOUT)
+            .expect("no file offered");
+        let offer: FileOffer = control::decode(&frame).expect("invalid offer");
+        println!("Receiving {} ({} bytes)", offer.name, offer.size);
+
+        let mut contents = Vec::new();
+        while (contents.len() as u64) < offer.size {
+            let part = receiver
+                .recv_timeout_from(&sender_uid, RECV_TIMEOUT)
+                .expect("file incomplete");
+            contents.extend(part);
+        }
+        contents
+    });
+
+    // Messages have to fit into a single (encrypted) packet
+    let negotiated = sender.negotiated(&receiver_uid).expect("not connected");
+    let chunk_size = negotiated.max_message_size.min(negotiated.mtu);
+
+    let start = Instant::now();
+    let offer = FileOffer {
+        name: path.clone(),
+        size: data.len() as u64,
+    };
+    sender
+        .send_to(
+            &receiver_uid,
+            control::encode(&offer).expect("unable to encode"),
+        )
+        .expect("unable to send offer");
+    for chunk in data.chunks(chunk_size) {
+        sender
+            .send_to(&receiver_uid, chunk.to_vec())
+            .expect("unable to send file");
+    }
+
+    let received = receiving.join().expect("receiver failed");
+    assert_eq!(received, data, "received file differs");
+    println!(
+        "Sent {} in {} messages within {:?}",
+        path,
+        (data.len() + chunk_size - 1) / chunk_size,
+        start.elapsed()
+    );
+}
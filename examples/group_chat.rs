@@ -0,0 +1,71 @@
+//! Group chat between three peers using [`PubSub`].
+//!
+//! Every peer subscribes to the `chat` topic and publishes a line to it, which is
+//! delivered to the other members of the group.
+//!
+//! ```text
+//! cargo run --example group_chat --features test-util
+//! ```
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use aether_lib::pubsub::PubSub;
+use aether_lib::test_util::{wait_until, TestTracker};
+
+use common::{client, connect, short, CONNECT_TIMEOUT};
+
+/// Topic the group chats on
+const TOPIC: &str = "chat";
+
+/// How long to wait for the lines of the other members
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    let tracker = TestTracker::start();
+    let names = ["alice", "bob", "carol"];
+    let clients: Vec<_> = names.iter().map(|_| client(&tracker)).collect();
+
+    for (i, first) in clients.iter().enumerate() {
+        for second in &clients[i + 1..] {
+            connect(first, second);
+        }
+    }
+
+    let members: Vec<PubSub> = clients
+        .into_iter()
+        .map(|aether| PubSub::new(Arc::new(aether)))
+        .collect();
+
+    for member in &members {
+        member.start();
+        member.subscribe(TOPIC).expect("unable to subscribe");
+    }
+
+    // Wait until every member knows the other members are in the group
+    let joined = wait_until(CONNECT_TIMEOUT, || {
+        members
+            .iter()
+            .all(|member| member.subscribers(TOPIC).map_or(false, |s| s.len() == 2))
+    });
+    assert!(joined, "members did not join the group");
+
+    for (name, member) in names.iter().zip(&members) {
+        let line = format!("Hi, this is {}", name);
+        member
+            .publish(TOPIC, line.into_bytes())
+            .expect("unable to publish");
+    }
+
+    for (name, member) in names.iter().zip(&members) {
+        for _ in 0..names.len() - 1 {
+            let publication = member
+                .recv_timeout(RECV_TIMEOUT)
+                .expect("line not delivered");
+            let line = String::from_utf8(publication.payload).expect("invalid line");
+            println!("[{}] {}: {}", name, short(&publication.from), line);
+        }
+    }
+}
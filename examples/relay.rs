@@ -0,0 +1,80 @@
+//! A relay node forwarding messages between peers that are not connected to each
+//! other.
+//!
+//! The relay receives from all of its peers with [`Aether::recv_any`] and forwards each
+//! message to every other peer connected to it.
+//!
+//! ```text
+//! cargo run --example relay --features test-util
+//! ```
+
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use aether_lib::error::AetherError;
+use aether_lib::peer::Aether;
+use aether_lib::test_util::TestTracker;
+
+use common::{client, connect, short};
+
+/// How long the relay waits for messages before checking if it should stop
+const POLL_TIME: Duration = Duration::from_millis(100);
+
+/// How long the peers wait for relayed messages
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Forward the messages received from any peer to all other connected peers, until
+/// `relay` is stopped
+fn run_relay(relay: &Aether) -> Result<(), AetherError> {
+    loop {
+        let (from, message) = match relay.recv_any_timeout(POLL_TIME) {
+            Ok(received) => received,
+            Err(AetherError::RecvTimeout(_)) => continue,
+            Err(err) => return Err(err),
+        };
+
+        for peer in relay.connected_peers()? {
+            if peer != from {
+                relay.send_to(&peer, message.clone())?;
+            }
+        }
+        println!("Relayed {} bytes from {}", message.len(), short(&from));
+    }
+}
+
+fn main() {
+    let tracker = TestTracker::start();
+    let relay = Arc::new(client(&tracker));
+    let alice = client(&tracker);
+    let bob = client(&tracker);
+
+    // Alice and Bob are only connected to the relay
+    connect(&alice, &relay);
+    connect(&bob, &relay);
+    assert!(!alice.is_connected(bob.get_uid()));
+
+    let relaying = relay.clone();
+    thread::spawn(move || {
+        if let Err(err) = run_relay(&relaying) {
+            println!("Relay stopped: {}", err);
+        }
+    });
+
+    alice
+        .send_to(relay.get_uid(), b"Hello Bob".to_vec())
+        .expect("unable to send");
+    let message = bob
+        .recv_timeout_from(relay.get_uid(), RECV_TIMEOUT)
+        .expect("message not relayed");
+    println!("Bob received: {}", String::from_utf8_lossy(&message));
+
+    bob.send_to(relay.get_uid(), b"Hello Alice".to_vec())
+        .expect("unable to send");
+    let message = alice
+        .recv_timeout_from(relay.get_uid(), RECV_TIMEOUT)
+        .expect("message not relayed");
+    println!("Alice received: {}", String::from_utf8_lossy(&message));
+}
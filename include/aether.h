@@ -0,0 +1,74 @@
+/*
+ * C interface to Aether clients, provided by aether_lib with the `ffi` feature.
+ * Refer to the documentation of the `ffi` module for details.
+ */
+
+#ifndef AETHER_H
+#define AETHER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The call succeeded */
+#define AETHER_OK 0
+/* A pointer is null, a string is not UTF-8 or an address cannot be parsed */
+#define AETHER_ERR_INVALID_ARGUMENT -1
+/* The peer is not connected */
+#define AETHER_ERR_NOT_CONNECTED -2
+/* Nothing was received within the timeout */
+#define AETHER_ERR_TIMEOUT -3
+/* The link to the peer is closed, the peer has to be connected again */
+#define AETHER_ERR_LINK_CLOSED -4
+/* The message is larger than the maximum message size agreed on with the peer */
+#define AETHER_ERR_TOO_LARGE -5
+/* Another connection would exceed the memory budget of the client */
+#define AETHER_ERR_BUDGET -6
+/* The call panicked */
+#define AETHER_ERR_PANIC -7
+/* Any other error, logged by the library */
+#define AETHER_ERR_OTHER -8
+
+typedef struct AetherClient AetherClient;
+
+/*
+ * Create and start a client using the tracker server at `tracker_addr`, such as
+ * "127.0.0.1:8982". `identity` is an identity exported without a passphrase, or NULL
+ * to use the identity stored in the config dir. Returns NULL on failure.
+ */
+AetherClient *aether_new(const char *tracker_addr, const char *identity);
+
+/* Stop and free a client. NULL is ignored */
+void aether_free(AetherClient *client);
+
+/* Returns the UID of the client, valid until the client is freed */
+const char *aether_uid(const AetherClient *client);
+
+/* Connect to the peer `uid` in the background */
+int aether_connect(const AetherClient *client, const char *uid);
+
+/* Returns 1 if the peer `uid` is connected, 0 otherwise */
+int aether_is_connected(const AetherClient *client, const char *uid);
+
+/* Send `len` bytes from `data` to the connected peer `uid` */
+int aether_send(const AetherClient *client, const char *uid, const uint8_t *data, size_t len);
+
+/*
+ * Receive bytes from the connected peer `uid`, waiting at most `timeout_ms`
+ * milliseconds or until bytes are received if it is 0. The bytes are stored in a
+ * buffer `*data` of `*len` bytes, to be freed with aether_free_bytes.
+ */
+int aether_recv(const AetherClient *client, const char *uid, uint32_t timeout_ms,
+                uint8_t **data, size_t *len);
+
+/* Free a buffer returned by aether_recv. NULL is ignored */
+void aether_free_bytes(uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AETHER_H */
@@ -2,16 +2,20 @@
 //! sending
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
+use crate::sequence::{AckWindow, Seq};
+
 /// Structure to reperesent the Acknowledgement format
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Acknowledgement {
     /// The sequence number of the packet from which the Acknowledgement begins
-    pub ack_begin: u32,
+    pub ack_begin: Seq,
 
     /// The number of packets that this Acknowledgement includes. ACK number of
     /// the last packet to be acknowledged relative to the `ack_begin`
     /// > Note: If the sequence number of a packet is `ack`, the relative sequence
-    ///   number to `ack_begin` would be `ack - ack_begin`.
+    /// > number to `ack_begin` would be `ack - ack_begin`.
     pub ack_end: u16,
 
     /// Number of packets from `ack_begin` till `ack_begin + ack_end` that are
@@ -21,6 +25,23 @@ pub struct Acknowledgement {
     /// Vector of ack numbers (relative to `ack_begin`) which are missing.
     /// Length of the vector is `miss_count`.
     pub miss: Vec<u16>,
+
+    /// Time at which the packet `ack_begin + ack_end` was received by the other peer
+    /// in microseconds, relative to the start of the other peer's link.
+    /// > Note: Only sent on the wire from protocol version 2 onwards, `0` otherwise
+    pub recv_time_us: u32,
+
+    /// Set if the other peer experienced congestion (its receive queues are above
+    /// the threshold)
+    /// > Note: Only sent on the wire from protocol version 3 onwards
+    pub congestion: bool,
+}
+
+impl Acknowledgement {
+    /// Returns the window of sequence numbers covered by this Acknowledgement
+    pub fn window(&self) -> AckWindow {
+        AckWindow::with_end(self.ack_begin, self.ack_end)
+    }
 }
 
 impl Clone for Acknowledgement {
@@ -30,12 +51,21 @@ impl Clone for Acknowledgement {
             ack_end: self.ack_end,
             miss_count: self.miss_count,
             miss: self.miss.clone(),
+            recv_time_us: self.recv_time_us,
+            congestion: self.congestion,
         }
     }
 }
 
+/// Largest number of sequence numbers an [`AcknowledgementList`] accepts after the first
+/// packet not received yet. Receivers can advertise smaller windows, see
+/// [`LinkConfig::max_window`][crate::config::LinkConfig::max_window]
 pub const MAX_WINDOW: u16 = 65000;
 
+/// Largest number of missing sequence numbers carried in a single [`Acknowledgement`].
+/// Keeps the acknowledgement header bounded even when the window is large
+pub const MAX_MISS_COUNT: u16 = 1024;
+
 /// A checklist to store all Acknowledgements received.
 /// * Used by sending module to test if a packet has already been acknowledged
 ///   before sending it.
@@ -44,11 +74,11 @@ pub const MAX_WINDOW: u16 = 65000;
 pub struct AcknowledgementCheck {
     /// The sequence number of begining of the list. All sequence numbers below
     /// this have been acknowledged already.
-    begin: u32,
+    begin: Seq,
 
     /// A HashMap to determine what all numbers have been acknowledged that are
     /// greater than `begin`
-    list: HashMap<u32, bool>,
+    list: HashMap<Seq, bool>,
 }
 
 impl AcknowledgementCheck {
@@ -57,7 +87,7 @@ impl AcknowledgementCheck {
     /// # Arguments
     ///
     /// * `begin`   -   Initial value of begin sequence number
-    pub fn new(begin: u32) -> AcknowledgementCheck {
+    pub fn new(begin: Seq) -> AcknowledgementCheck {
         AcknowledgementCheck {
             begin,
             list: HashMap::new(),
@@ -79,25 +109,28 @@ impl AcknowledgementCheck {
     /// # Arguments
     ///
     /// * `ack` -   The Acknowledgement which is instance of [`Acknowledgement`].
-    ///             This will be obtained from the [`Packet`][crate::packet::Packet] received.
+    ///   This will be obtained from the [`Packet`][crate::packet::Packet] received.
     pub fn acknowledge(&mut self, ack: Acknowledgement) {
         // acknowledge everythin below ack.ack_begin
         if self.begin < ack.ack_begin {
-            for i in self.begin..(ack.ack_begin + 1) {
-                self.insert(i);
-            }
+            let begin = ack.ack_begin;
+            self.begin = begin;
+            self.list.retain(|seq, _| *seq > begin);
+            self.update_begin();
         }
 
+        let window = ack.window();
+
         let mut missing: HashMap<u16, bool> = HashMap::new();
 
         for i in ack.miss {
             missing.insert(i, true);
         }
 
-        for i in 0..(ack.ack_end + 1) {
+        for i in 0..(window.end() + 1) {
             match missing.get(&i) {
-                None => self.insert(i as u32 + ack.ack_begin),
-                Some(false) => self.insert(i as u32 + ack.ack_begin),
+                None => self.insert(window.at(i)),
+                Some(false) => self.insert(window.at(i)),
                 Some(true) => (),
             }
         }
@@ -108,8 +141,8 @@ impl AcknowledgementCheck {
     /// # Arguments
     ///
     /// * `ack` -   The Acknowledgement number that was received from the other
-    ///             peer
-    pub fn insert(&mut self, ack: u32) {
+    ///   peer
+    pub fn insert(&mut self, ack: Seq) {
         if ack > self.begin {
             self.list.insert(ack, true);
         }
@@ -121,8 +154,8 @@ impl AcknowledgementCheck {
     /// # Arguments
     ///
     /// * `ack` -   The sequence number which needs to be matched and check if
-    ///             it is present in the list (acknowledged).
-    pub fn check(&self, ack: &u32) -> bool {
+    ///   it is present in the list (acknowledged).
+    pub fn check(&self, ack: &Seq) -> bool {
         if *ack <= self.begin {
             return true;
         }
@@ -139,18 +172,22 @@ impl AcknowledgementCheck {
 /// * Used by sending module to get Acknowledgements to be sent with the next packet
 #[derive(Debug)]
 pub struct AcknowledgementList {
-    /// A `HashMap` to store the sequence numbers of packets from `ack_begin` to
-    /// `ack_begin + ack_end` that have been received and need to be acknowledged
-    list: HashMap<u32, bool>,
+    /// A `HashMap` to store the sequence numbers of packets in `window` that have
+    /// been received and need to be acknowledged
+    list: HashMap<Seq, bool>,
 
-    /// The sequence number of the first packet included in this Acknowledgement
-    ack_begin: u32,
+    /// The sequence numbers included in this Acknowledgement, from the first packet
+    /// to the last one received
+    window: AckWindow,
 
-    /// The sequence number (relative to `ack_begin`) of the last packet in this
-    /// Acknowledgement.
-    /// > Note: If the sequence number of a packet is `ack`, the relative sequence
-    /// number to `ack_begin` would be `ack - ack_begin`.
-    ack_end: u16,
+    /// Receive time (in microseconds) of the last packet of `window`
+    recv_time_us: u32,
+
+    /// Set if congestion is being experienced by the receiver
+    congestion: bool,
+
+    /// Set if packets have been added since the last [`Acknowledgement`] was taken
+    pending: bool,
 }
 
 impl AcknowledgementList {
@@ -159,14 +196,29 @@ impl AcknowledgementList {
     /// # Arguments
     ///
     /// * `ack_begin`   -   The `ack_begin` value from which this Acknowledgement
-    ///                     begins
-    pub fn new(ack_begin: u32) -> AcknowledgementList {
-        let mut list: HashMap<u32, bool> = HashMap::new();
+    ///   begins
+    #[cfg(any(test, feature = "raw"))]
+    pub fn new(ack_begin: Seq) -> AcknowledgementList {
+        Self::with_window(ack_begin, MAX_WINDOW)
+    }
+
+    /// Creates a new instance of [`AcknowledgementList`] accepting at most `max_window`
+    /// sequence numbers after the first packet not received yet
+    ///
+    /// # Arguments
+    ///
+    /// * `ack_begin`   -   The `ack_begin` value from which this Acknowledgement
+    ///   begins
+    /// * `max_window`  -   Largest window advertised to the other peer
+    pub fn with_window(ack_begin: Seq, max_window: u16) -> AcknowledgementList {
+        let mut list: HashMap<Seq, bool> = HashMap::new();
         list.insert(ack_begin, true);
         AcknowledgementList {
             list,
-            ack_begin,
-            ack_end: 0,
+            window: AckWindow::with_limit(ack_begin, max_window),
+            recv_time_us: 0,
+            congestion: false,
+            pending: false,
         }
     }
 
@@ -175,10 +227,10 @@ impl AcknowledgementList {
     /// # Arguments
     ///
     /// * `ack` -   The sequence number of the packet to check
-    pub fn check(&self, ack: &u32) -> bool {
-        if *ack <= self.ack_begin {
+    pub fn check(&self, ack: &Seq) -> bool {
+        if *ack <= self.window.begin() {
             true
-        } else if self.ack_begin < *ack && *ack <= (self.ack_begin + self.ack_end as u32) {
+        } else if self.window.contains(*ack) {
             match self.list.get(ack) {
                 None => false,
                 Some(v) => *v,
@@ -193,30 +245,71 @@ impl AcknowledgementList {
     /// # Arguments
     ///
     /// * `ack` -   Sequence number of the packet to be added to the Acknowledgement
-    ///             list
-    pub fn insert(&mut self, ack: u32) {
-        if ack > (MAX_WINDOW as u32 + self.ack_begin) {
-            panic!("ack too large {}\t Diff: {}", ack, ack - self.ack_begin);
-        } else if ack > self.ack_begin {
-            let n = (ack - self.ack_begin) as u16;
-
-            if n > self.ack_end {
-                self.ack_end = n;
+    ///   list
+    ///
+    /// # Returns
+    ///
+    /// * `bool`    -   False if `ack` is more than the window of the list (by default
+    ///   [`MAX_WINDOW`]) packets ahead of the first packet not received yet, in which
+    ///   case it is not added
+    pub fn insert(&mut self, ack: Seq) -> bool {
+        if ack > self.window.begin() {
+            if !self.window.extend(ack) {
+                return false;
             }
 
             self.list.insert(ack, true);
             self.update_begin();
         }
+        self.pending = true;
+        true
+    }
+
+    /// Insert a sequence number into the Acknowledgement list along with the time
+    /// at which the packet was received
+    ///
+    /// # Arguments
+    ///
+    /// * `ack` -   Sequence number of the packet to be added to the Acknowledgement
+    ///   list
+    /// * `recv_time_us`    -   Time at which the packet was received in microseconds
+    ///   relative to the start of the link
+    ///
+    /// # Returns
+    ///
+    /// * `bool`    -   False if `ack` is too far ahead to be added, see
+    ///   [`AcknowledgementList::insert`]
+    pub fn insert_with_time(&mut self, ack: Seq, recv_time_us: u32) -> bool {
+        let latest = self.window.last();
+
+        if !self.insert(ack) {
+            return false;
+        }
+
+        // Only the receive time of the latest packet is sent with the acknowledgement
+        if ack >= latest {
+            self.recv_time_us = recv_time_us;
+        }
+        true
+    }
+
+    /// Set if congestion is currently being experienced. This is signalled to the
+    /// other peer in the next [`Acknowledgement`]
+    ///
+    /// # Arguments
+    ///
+    /// * `congestion`  -   Boolean representing if there is congestion or not
+    pub fn set_congestion(&mut self, congestion: bool) {
+        self.congestion = congestion;
     }
 
     /// Update value of begin if consequitive values in `list` after begin have
     /// been acknowledged.
     /// This helps keep `check()` more efficient
     fn update_begin(&mut self) {
-        while self.check(&(self.ack_begin + 1)) {
-            self.list.remove(&(self.ack_begin + 1));
-            self.ack_begin += 1;
-            self.ack_end -= 1;
+        while self.check(&(self.window.begin() + 1)) {
+            self.list.remove(&(self.window.begin() + 1));
+            self.window.advance();
         }
     }
 
@@ -224,26 +317,52 @@ impl AcknowledgementList {
     /// * Used to add the Acknowledgement to the next outgoing packet
     pub fn get(&self) -> Acknowledgement {
         let mut miss: Vec<u16> = Vec::new();
-
-        for i in 1..(self.ack_end + 1) {
-            match self.list.get(&(i as u32 + self.ack_begin)) {
-                None => miss.push(i),
-                Some(false) => miss.push(i),
-                Some(true) => (),
+        let mut ack_end = self.window.end();
+        let mut recv_time_us = self.recv_time_us;
+
+        for i in 1..(self.window.end() + 1) {
+            let missing = !matches!(self.list.get(&self.window.at(i)), Some(true));
+
+            if missing {
+                if miss.len() == MAX_MISS_COUNT as usize {
+                    // Acknowledge only up to the last packet before this one, the
+                    // rest is acknowledged by later acknowledgements.
+                    // The receive time does not belong to the new end anymore
+                    ack_end = i - 1;
+                    recv_time_us = 0;
+                    break;
+                }
+                miss.push(i);
             }
         }
 
         Acknowledgement {
-            ack_begin: self.ack_begin,
-            ack_end: self.ack_end,
+            ack_begin: self.window.begin(),
+            ack_end,
             miss_count: miss.len() as u16,
             miss,
+            recv_time_us,
+            congestion: self.congestion,
         }
     }
 
+    /// Get an [`Acknowledgement`] structure out of this [`AcknowledgementList`] and
+    /// mark it as sent. Used when the Acknowledgement is actually sent to the other peer
+    pub fn take(&mut self) -> Acknowledgement {
+        self.pending = false;
+        self.get()
+    }
+
+    /// Check if packets have been added to the list since the last
+    /// [`Acknowledgement`] was taken with [`AcknowledgementList::take`]
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
     /// Check if the [`AcknowledgementList`] is complete. The list is complete when
-    /// there are not missing packets between `ack_begin` to `ack_begin + ack_end`.
+    /// there are not missing packets in its window.
     /// Thus, all packets within that window have been acknowledged
+    #[cfg(any(test, feature = "raw"))]
     pub fn is_complete(&self) -> bool {
         self.get().miss_count == 0
     }
@@ -253,20 +372,21 @@ impl AcknowledgementList {
 mod tests {
     mod ack_check {
         use crate::acknowledgement::{AcknowledgementCheck, AcknowledgementList};
+        use crate::sequence::Seq;
         #[test]
         fn false_positive_raw() {
             let values = [16, 1024, 99, 45];
 
             let check = [19, 32, 63, 6000];
 
-            let mut ack_check = AcknowledgementCheck::new(16);
+            let mut ack_check = AcknowledgementCheck::new(Seq(16));
 
             for v in values {
-                ack_check.insert(v);
+                ack_check.insert(Seq(v));
             }
 
             for c in check {
-                assert!(!ack_check.check(&c));
+                assert!(!ack_check.check(&Seq(c)));
             }
         }
 
@@ -274,14 +394,14 @@ mod tests {
         fn true_negatives_raw() {
             let values = [16, 1024, 99, 45];
 
-            let mut ack_check = AcknowledgementCheck::new(16);
+            let mut ack_check = AcknowledgementCheck::new(Seq(16));
 
             for v in values {
-                ack_check.insert(v);
+                ack_check.insert(Seq(v));
             }
 
             for c in values {
-                assert!(ack_check.check(&c));
+                assert!(ack_check.check(&Seq(c)));
             }
         }
 
@@ -291,19 +411,19 @@ mod tests {
 
             let check = [19, 21, 63];
 
-            let mut ack_list = AcknowledgementList::new(16);
+            let mut ack_list = AcknowledgementList::new(Seq(16));
 
             for v in values {
-                ack_list.insert(v);
+                ack_list.insert(Seq(v));
             }
 
-            let mut ack_check = AcknowledgementCheck::new(16);
+            let mut ack_check = AcknowledgementCheck::new(Seq(16));
 
             let ack = ack_list.get();
 
             ack_check.acknowledge(ack);
             for c in check {
-                assert!(!ack_check.check(&c));
+                assert!(!ack_check.check(&Seq(c)));
             }
         }
 
@@ -311,70 +431,71 @@ mod tests {
         fn true_negatives() {
             let values = [16, 17, 18, 20, 21, 22, 32];
 
-            let mut ack_list = AcknowledgementList::new(16);
+            let mut ack_list = AcknowledgementList::new(Seq(16));
 
             for v in values {
-                ack_list.insert(v);
+                ack_list.insert(Seq(v));
             }
 
-            let mut ack_check = AcknowledgementCheck::new(16);
+            let mut ack_check = AcknowledgementCheck::new(Seq(16));
 
             let ack = ack_list.get();
 
             ack_check.acknowledge(ack);
             for c in values {
-                assert!(ack_check.check(&c));
+                assert!(ack_check.check(&Seq(c)));
             }
         }
     }
 
     mod ack_list {
-        use crate::acknowledgement::AcknowledgementList;
+        use crate::acknowledgement::{AcknowledgementList, MAX_MISS_COUNT};
+        use crate::sequence::Seq;
 
         #[test]
         fn false_positives() {
             let sequence = 10;
-            let mut ack_list = AcknowledgementList::new(sequence);
+            let mut ack_list = AcknowledgementList::new(Seq(sequence));
 
             let values = [10, 20, 30, 40];
 
             let check = [12, 15, 320, 44, 39];
 
             for v in values {
-                ack_list.insert(v);
+                ack_list.insert(Seq(v));
             }
 
             for c in check {
-                assert!(!ack_list.check(&c));
+                assert!(!ack_list.check(&Seq(c)));
             }
         }
 
         #[test]
         fn true_negatives() {
             let sequence = 10;
-            let mut ack_list = AcknowledgementList::new(sequence);
+            let mut ack_list = AcknowledgementList::new(Seq(sequence));
 
             let values = [10, 20, 30, 40];
 
             for v in values {
-                ack_list.insert(v);
+                ack_list.insert(Seq(v));
             }
 
             for c in values {
-                assert!(ack_list.check(&c));
+                assert!(ack_list.check(&Seq(c)));
             }
         }
 
         #[test]
         fn missing_test() {
             let sequence = 10;
-            let mut ack_list = AcknowledgementList::new(sequence);
+            let mut ack_list = AcknowledgementList::new(Seq(sequence));
 
             let misses = [11, 14, 22, 28];
 
             for v in sequence..(sequence + 20) {
                 if !misses.contains(&v) {
-                    ack_list.insert(v);
+                    ack_list.insert(Seq(v));
                 }
             }
 
@@ -388,15 +509,45 @@ mod tests {
         #[test]
         fn check_complete_test() {
             let sequence = 10;
-            let mut ack_list = AcknowledgementList::new(sequence);
+            let mut ack_list = AcknowledgementList::new(Seq(sequence));
 
             let values = sequence..(sequence + 20);
 
             for v in values {
-                ack_list.insert(v);
+                ack_list.insert(Seq(v));
             }
 
             assert!(ack_list.is_complete());
         }
+
+        #[test]
+        fn pending_test() {
+            let mut ack_list = AcknowledgementList::new(Seq(10));
+            assert!(!ack_list.is_pending());
+
+            ack_list.insert(Seq(11));
+            assert!(ack_list.is_pending());
+
+            let ack = ack_list.take();
+            assert_eq!(ack.ack_begin, Seq(11));
+            assert!(!ack_list.is_pending());
+        }
+
+        #[test]
+        fn miss_limit_test() {
+            let sequence = 10;
+            let mut ack_list = AcknowledgementList::new(Seq(sequence));
+
+            // Every other packet is missing
+            for v in 0..(MAX_MISS_COUNT as u32 * 4) {
+                ack_list.insert(Seq(sequence + 2 * v));
+            }
+
+            let ack = ack_list.get();
+
+            assert_eq!(ack.miss_count, MAX_MISS_COUNT);
+            assert_eq!(ack.ack_end, MAX_MISS_COUNT * 2);
+            assert_eq!(ack.recv_time_us, 0);
+        }
     }
 }
@@ -5,17 +5,27 @@
 //!
 //! ## Configuration file
 //! The default configuration file is to be stored in `$HOME/.config/aether/config.yaml` and must
-//! be in [YAML](https://yaml.org/) format
+//! be in [YAML](https://yaml.org/) format. A different file can be used by setting the
+//! `AETHER_CONFIG` environment variable to its path
 //!
 //! Note that any missing values will be replaced with default values. It is not recommended to
 //! leave any missing values in the configuration file as the values need to follow certain
 //! constaints. For example, `handshake_timeout` cannot be smaller than `peer_poll_time` because in
 //! such a case, the handshake would timeout before even a single poll is complete.
-use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, default::Default, fs, path::Path};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{convert::TryFrom, default::Default, env, fs, path::Path};
+use tracing::{info, warn};
 
+use crate::acknowledgement::MAX_WINDOW;
 use crate::error::AetherError;
+use crate::packet::MAX_PAYLOAD_SIZE;
+
+/// Environment variable holding the path of the configuration file to be used instead of
+/// the default one
+pub const CONFIG_ENV: &str = "AETHER_CONFIG";
 
 /// Structure to represent configuration options for `aether_lib`
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
@@ -27,6 +37,8 @@ pub struct Config {
     pub handshake: HandshakeConfig,
     /// Configuration for [`link`][crate::link] module
     pub link: LinkConfig,
+    /// Configuration for [`telemetry`][crate::telemetry] module
+    pub telemetry: TelemetryConfig,
 }
 
 /// Structure to represent configuration for [`peer`][crate::peer] module
@@ -37,17 +49,145 @@ pub struct AetherConfig {
     pub server_retry_delay: u64,
     /// How often to poll server for new connections
     pub server_poll_time: u64,
-    /// How long to wait to retry handshake after a failed attempt
-    /// Also used as duration to wait to receive nonce from other peer during
-    /// authentication
+    /// Duration to wait to receive nonce from other peer during authentication (in ms).
+    /// Failed handshakes are retried as configured in `retry`
     pub handshake_retry_delay: u64,
     /// Poll time to check if connection has been established
     pub connection_check_delay: u64,
-    /// Magnitude by which to randomize retry delay
+    /// Magnitude by which to randomize the duration to wait during authentication
     pub delta_time: u64,
     /// General poll time to be used to check for updates to lists shared by threads
     /// (in us)
+    ///
+    /// No longer used, threads are woken up when the lists are updated instead. Kept for
+    /// compatibility
     pub poll_time_us: u64,
+    /// How often to check if the route to the tracker server changed. `0` disables
+    /// the check
+    pub network_poll_time: u64,
+    /// Only exchange addresses with peers that requested a connection to this client
+    /// and that this client requested a connection to (using
+    /// [`Aether::connect`][crate::peer::Aether::connect]). Requests from other peers are
+    /// ignored
+    pub mutual_intent: bool,
+    /// Queue connection requests from peers this client did not request a connection
+    /// to until the application accepts them (refer
+    /// [`Aether::incoming`][crate::peer::Aether::incoming]), instead of connecting
+    /// immediately
+    pub manual_accept: bool,
+    /// Number of polls in a row a tracker may leave unanswered before packets to it
+    /// are sent in plaintext, for trackers that do not support encryption. `0` never
+    /// falls back. Only used if the public key of the tracker is set using
+    /// [`Aether::set_tracker_key`][crate::peer::Aether::set_tracker_key]
+    pub tracker_fallback_attempts: u32,
+    /// Number of polls in a row the tracker server may leave unanswered before it is
+    /// reported as unreachable (refer
+    /// [`AetherEvent::TrackerUnreachable`][crate::peer::AetherEvent::TrackerUnreachable]).
+    /// `0` never reports it
+    pub tracker_failure_threshold: u32,
+    /// Local address to bind the sockets of this client to. The default `0.0.0.0` binds
+    /// all interfaces
+    pub bind_address: IpAddr,
+    /// Local port of the socket used to communicate with the tracker server. `0` binds
+    /// any free port
+    pub tracker_port: u16,
+    /// First local port of the sockets used for links to peers. `0` binds any free
+    /// port. Connections bind within the range from their first attempt, through the
+    /// handshake and any retries, so that firewall rules can be limited to it (refer
+    /// [`Aether::bound_ports`][crate::peer::Aether::bound_ports])
+    pub peer_port_min: u16,
+    /// Last local port of the sockets used for links to peers, so that one link can be
+    /// open per port from [`peer_port_min`][AetherConfig::peer_port_min]. Smaller values
+    /// only allow `peer_port_min`
+    pub peer_port_max: u16,
+    /// Memory the links of this client may use (in bytes). Connecting fails if the
+    /// [projected memory][crate::memory::projected_memory] of another connection would
+    /// exceed it. `0` does not limit the memory
+    pub memory_budget: usize,
+    /// Number of threads calling the message handlers of this client (refer
+    /// [`Aether::on_message`][crate::peer::Aether::on_message]). They are only started
+    /// once a handler is registered
+    pub handler_threads: usize,
+    /// How failed handshakes are retried, unless overridden by the
+    /// [`ConnectOptions`][crate::peer::connect::ConnectOptions] of a connection
+    pub retry: RetryPolicy,
+    /// Limits of the messages kept for peers that are not connected (refer
+    /// [`Aether::send_to_or_queue`][crate::peer::Aether::send_to_or_queue])
+    pub outbox: OutboxConfig,
+}
+
+/// How connecting to a peer is retried after a failed handshake
+///
+/// The `n`-th retry is delayed by `retry_delay * backoff^(n - 1)`, at most `max_delay`,
+/// plus a random delay of up to `jitter` so that peers failing at once do not retry in
+/// lockstep
+///
+/// # Examples
+///
+/// ```
+/// use aether_lib::config::{Config, GiveUp, RetryPolicy};
+///
+/// let mut config = Config::default();
+/// // Retry after 1s, 2s, 4s, 8s and 16s, then keep the connection failed
+/// config.aether.retry = RetryPolicy {
+///     max_retries: Some(5),
+///     retry_delay: 1_000,
+///     backoff: 2,
+///     give_up: GiveUp::KeepFailed,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Give up connecting after this many handshakes failed after the first one.
+    /// [`None`] keeps retrying until connected
+    pub max_retries: Option<u32>,
+    /// Delay before the first retry (in ms)
+    pub retry_delay: u64,
+    /// Factor the delay is multiplied by after every further failure. `1` retries with
+    /// a fixed delay
+    pub backoff: u32,
+    /// Largest delay before a retry, excluding jitter (in ms)
+    pub max_delay: u64,
+    /// Largest random delay added to every delay (in ms)
+    pub jitter: u64,
+    /// What happens to the connection once connecting is given up
+    pub give_up: GiveUp,
+}
+
+/// What happens to a connection once connecting is given up (refer [`RetryPolicy`])
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiveUp {
+    /// The connection is removed, as if the peer was never connected to
+    Remove,
+    /// The connection is kept in failed state without being retried, until the peer is
+    /// connected to again
+    KeepFailed,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retrying after `failures` failed handshakes, excluding
+    /// jitter
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = (self.backoff.max(1) as u64).saturating_pow(failures.saturating_sub(1));
+        let delay = self.retry_delay.saturating_mul(factor).min(self.max_delay);
+        Duration::from_millis(delay)
+    }
+}
+
+/// Limits of the [`Outbox`][crate::peer::outbox::Outbox] of a client, which apply to
+/// every peer separately
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct OutboxConfig {
+    /// Largest number of messages queued for a peer. `0` does not limit them
+    pub max_messages: usize,
+    /// Largest number of bytes queued for a peer. `0` does not limit them
+    pub max_bytes: usize,
+    /// Time after which queued messages are dropped instead of delivered (in ms). `0`
+    /// keeps them until the peer is connected
+    pub max_age: u64,
 }
 
 /// Structure to represent configuration for [`handshake`][crate::peer::handshake] module
@@ -60,13 +200,23 @@ pub struct HandshakeConfig {
     pub peer_poll_time: u64,
     /// Timeout after which handshake can be declared failed if not complete (in ms)
     pub handshake_timeout: u64,
+    /// Number of leading zero bits required in the proof-of-work of the other peer
+    /// during high load. `0` disables the proof-of-work
+    pub pow_difficulty: u8,
+    /// Number of handshakes in progress above which proof-of-work is required
+    pub pow_load_threshold: usize,
+    /// Largest number of handshakes in progress at once. Further connection requests
+    /// wait until a handshake completes. `0` does not limit handshakes
+    pub max_handshakes: usize,
 }
 
 /// Structure to represent configuration for [`link`][crate::link] module
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 #[serde(default)]
 pub struct LinkConfig {
-    /// Window size for the link. Determines how many packets are sent in a single burst
+    /// Initial window size for the link. Determines how many packets are sent in a single
+    /// burst until the link adapts the window to the loss observed (up to the
+    /// `max_window` advertised by the other peer)
     pub window_size: u16,
     /// Time to wait for acknowledgement to be received
     pub ack_wait_time: u64,
@@ -76,11 +226,84 @@ pub struct LinkConfig {
     pub timeout: u64,
     /// Time to wait for acknowledgment before sending packets again
     pub retry_delay: u64,
-    /// Time to wait before sending another acknowledgment only packet when primary queue is empty
-    /// i.e. no more packets to be sent
+    /// Interval at which acknowledgment only packets are sent for newly received packets,
+    /// or to keep the link alive when there are no more packets to be sent
     pub ack_only_time: u64,
     /// Number of times a packet can be retried before link is declared as broken
     pub max_retries: i16,
+    /// Number of received packets waiting to be read after which the link signals
+    /// congestion to the other peer
+    pub congestion_threshold: usize,
+    /// Interval at which keepalive packets are sent on an idle link (in ms). `0` disables
+    /// keepalives, acknowledgment only packets are sent instead
+    pub keepalive_interval: u64,
+    /// Number of keepalive intervals without receiving any packet after which the link is
+    /// declared as broken (if sooner than `timeout`)
+    pub keepalive_misses: u32,
+    /// Packets with payloads (after encryption) smaller than this many bytes are sent
+    /// immediately by [`Link::send`][crate::link::Link::send] if nothing else is waiting to
+    /// be sent, instead of waiting for the send thread. `0` disables this
+    pub fast_path_size: usize,
+    /// Largest message accepted from the other peer (in bytes). The smaller of the limits
+    /// of both peers is agreed on during the handshake and applies in both directions.
+    /// Messages larger than a single packet
+    /// ([`MAX_PAYLOAD_SIZE`][crate::packet::MAX_PAYLOAD_SIZE]) are split into fragments,
+    /// unless the other peer does not support them (refer
+    /// [`has_fragments`][crate::packet::has_fragments])
+    pub max_message_size: usize,
+    /// Largest number of packets accepted ahead of the first packet not received yet,
+    /// which bounds the packets held for reordering. Advertised to the other peer
+    /// during the handshake, which keeps its congestion window below it. Packets
+    /// further ahead are dropped and resent later
+    /// (at most [`MAX_WINDOW`][crate::acknowledgement::MAX_WINDOW])
+    pub max_window: u16,
+    /// Time [`Link::stop`][crate::link::Link::stop] waits for the other peer to answer
+    /// the close packets, which are resent every `retry_delay` (in ms). Extended by at
+    /// most `linger_timeout` while the other peer still delivers its queued messages.
+    /// `0` stops links without telling the other peer, which then waits for the link to
+    /// time out
+    pub close_timeout: u64,
+    /// Longest time a closing link keeps running to deliver the messages still queued
+    /// (in ms). Applies to both ends: the end stopping the link first waits for its own
+    /// messages to be acknowledged, the other end does the same before answering the
+    /// close packets
+    pub linger_timeout: u64,
+    /// Request packets missing before a received packet from the other peer right away
+    /// with a NACK packet, instead of waiting for it to send its window again after
+    /// `retry_delay`. Only used if both peers support it
+    /// (refer [`has_nack`][crate::packet::has_nack])
+    pub nack: bool,
+    /// CPUs the worker threads of links run on, as a mask with bit `i` set for CPU `i`.
+    /// `0` lets them run on any CPU (refer [`worker`][crate::link::worker])
+    pub worker_cpus: u64,
+    /// Nice value of the worker threads of links, positive values lowering their
+    /// priority below the threads of the application. `0` leaves it unchanged (refer
+    /// [`worker`][crate::link::worker])
+    pub worker_nice: i8,
+    /// Largest number of packets waiting to be sent on a link, counting each fragment of
+    /// a message larger than a packet. Once full, [`Link::send`][crate::link::Link::send]
+    /// blocks until the send thread takes some and
+    /// [`Link::try_send`][crate::link::Link::try_send] fails, so that a fast producer
+    /// cannot grow the queue without bound on a slow link. `0` leaves the queue unbounded
+    pub send_queue_size: usize,
+}
+
+/// Structure to represent configuration for [`telemetry`][crate::telemetry] module
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// Number of messages logged per `log_interval` by a hot path, such as for every
+    /// malformed packet received or retransmission. Further messages are suppressed and
+    /// counted (refer [`LogLimiter`][crate::telemetry::LogLimiter]). `0` disables the
+    /// limit
+    pub log_burst: u32,
+    /// Interval in which at most `log_burst` messages are logged by a hot path. The
+    /// number of messages suppressed is logged along with the first message of a later
+    /// interval
+    pub log_interval: u64,
+    /// Log every `log_sample`-th message that would be suppressed anyway. `0` logs none
+    /// of them
+    pub log_sample: u32,
 }
 
 impl Config {
@@ -111,9 +334,10 @@ impl Config {
         }
     }
 
-    /// Returns configuration read from the default configuration file
+    /// Returns configuration read from the file at the path in [`CONFIG_ENV`] if it is
+    /// set, or from the default configuration file otherwise
     /// If default configuration file is not found, the default internal configuration
-    /// is returned
+    /// is returned. A file given in [`CONFIG_ENV`] has to exist
     ///
     /// # Examples
     ///
@@ -122,12 +346,20 @@ impl Config {
     /// let config = Config::get_config();
     /// ```
     pub fn get_config() -> Result<Config, AetherError> {
-        match home::home_dir() {
-            Some(mut path_buf) => {
-                path_buf.push(".config");
-                path_buf.push("aether");
-                path_buf.push("config.yaml");
+        if let Some(path) = env::var_os(CONFIG_ENV) {
+            let path = PathBuf::from(path);
+
+            info!(
+                "Reading configuration from {} (set by {})",
+                path.to_str().unwrap_or("Cannot parse path"),
+                CONFIG_ENV
+            );
+
+            return Config::from_file(&path);
+        }
 
+        match Config::default_path() {
+            Some(path_buf) => {
                 let path = path_buf.as_path();
 
                 info!(
@@ -149,6 +381,17 @@ impl Config {
             None => Ok(Config::default()),
         }
     }
+
+    /// Returns the path of the default configuration file, or [`None`] if the home
+    /// directory is unknown
+    pub fn default_path() -> Option<PathBuf> {
+        home::home_dir().map(|mut path_buf| {
+            path_buf.push(".config");
+            path_buf.push("aether");
+            path_buf.push("config.yaml");
+            path_buf
+        })
+    }
 }
 
 impl TryFrom<String> for Config {
@@ -175,6 +418,44 @@ impl Default for AetherConfig {
             connection_check_delay: 1_000,
             delta_time: 1000,
             poll_time_us: 100,
+            network_poll_time: 5_000,
+            mutual_intent: false,
+            manual_accept: false,
+            tracker_fallback_attempts: 0,
+            tracker_failure_threshold: 3,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tracker_port: 0,
+            peer_port_min: 0,
+            peer_port_max: 0,
+            memory_budget: 0,
+            handler_threads: 4,
+            retry: RetryPolicy::default(),
+            outbox: OutboxConfig::default(),
+        }
+    }
+}
+
+/// Default values for [`OutboxConfig`]
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 256,
+            max_bytes: 1_048_576,
+            max_age: 604_800_000,
+        }
+    }
+}
+
+/// Default values for [`RetryPolicy`]
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: None,
+            retry_delay: 1_500,
+            backoff: 1,
+            max_delay: 60_000,
+            jitter: 1_000,
+            give_up: GiveUp::Remove,
         }
     }
 }
@@ -185,6 +466,9 @@ impl Default for HandshakeConfig {
         Self {
             peer_poll_time: 100,
             handshake_timeout: 2_500,
+            pow_difficulty: 0,
+            pow_load_threshold: 16,
+            max_handshakes: 0,
         }
     }
 }
@@ -200,14 +484,38 @@ impl Default for LinkConfig {
             retry_delay: 100,
             ack_only_time: 50,
             max_retries: 10,
+            congestion_threshold: 1_000,
+            keepalive_interval: 1_000,
+            keepalive_misses: 3,
+            fast_path_size: 256,
+            max_message_size: MAX_PAYLOAD_SIZE * 32,
+            max_window: MAX_WINDOW,
+            close_timeout: 500,
+            linger_timeout: 2_000,
+            nack: true,
+            worker_cpus: 0,
+            worker_nice: 0,
+            send_queue_size: 1_024,
+        }
+    }
+}
+
+/// Default values for [`TelemetryConfig`]
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_burst: 10,
+            log_interval: 1_000,
+            log_sample: 1_000,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::Config;
-    use std::{convert::TryFrom, fs, path::Path};
+    use crate::config::{Config, RetryPolicy, CONFIG_ENV};
+    use std::time::Duration;
+    use std::{convert::TryFrom, env, fs, path::Path};
 
     #[test]
     fn read_test() {
@@ -223,4 +531,44 @@ mod tests {
 
         assert_eq!(config, default);
     }
+
+    #[test]
+    fn env_test() {
+        let mut custom = Config::default();
+        custom.link.window_size = 42;
+
+        let path = "./tmp/env_config.yaml";
+
+        fs::create_dir_all("./tmp").unwrap();
+
+        fs::write(path, String::try_from(custom).unwrap()).unwrap();
+
+        env::set_var(CONFIG_ENV, path);
+        let config = Config::get_config();
+        env::set_var(CONFIG_ENV, "./tmp/missing_config.yaml");
+        let missing = Config::get_config();
+        env::remove_var(CONFIG_ENV);
+
+        assert_eq!(config.unwrap(), custom);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        let fixed = RetryPolicy::default();
+        assert_eq!(fixed.delay(1), Duration::from_millis(1_500));
+        assert_eq!(fixed.delay(100), Duration::from_millis(1_500));
+
+        let backoff = RetryPolicy {
+            retry_delay: 1_000,
+            backoff: 2,
+            max_delay: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(1_000));
+        assert_eq!(backoff.delay(2), Duration::from_millis(2_000));
+        assert_eq!(backoff.delay(4), Duration::from_millis(8_000));
+        assert_eq!(backoff.delay(5), Duration::from_millis(10_000));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(10_000));
+    }
 }
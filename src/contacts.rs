@@ -0,0 +1,247 @@
+//! Address book of known peers.
+//!
+//! [`Contacts`] maps peer UIDs to human-readable aliases and a [`Trust`] status. The
+//! aliases can be used instead of UIDs when connecting (see
+//! [`Aether::connect`][crate::peer::Aether::connect]) and connection requests from
+//! [`Trust::Blocked`] contacts are ignored.
+//!
+//! Contacts are stored in [YAML](https://yaml.org/) format, by default in
+//! `$HOME/.config/aether/contacts.yaml`. Only clients using the identity stored in a
+//! config dir load the contacts stored next to it, other clients start with an empty
+//! address book (refer [`Aether::set_contacts`][crate::peer::Aether::set_contacts]).
+//!
+//! # Examples
+//!
+//! ```
+//! use aether_lib::contacts::{Contacts, Trust};
+//!
+//! let mut contacts = Contacts::new();
+//! contacts.add_contact("<peer-uid>", "alice", Trust::Trusted).unwrap();
+//!
+//! assert_eq!(contacts.alias_of("<peer-uid>"), Some("alice"));
+//! assert_eq!(contacts.resolve("alice"), "<peer-uid>");
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::identity::Id;
+
+/// Name of the file the contacts are stored in, inside the config dir
+pub const CONTACTS_FILE: &str = "contacts.yaml";
+
+/// Trust status of a [`Contact`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Trust {
+    /// Not verified by the user
+    #[default]
+    Unknown,
+    /// Verified by the user
+    Trusted,
+    /// Connection requests are ignored
+    Blocked,
+}
+
+/// A known peer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Contact {
+    /// Human-readable alias of the peer, unique among all contacts
+    pub alias: String,
+    /// Trust status of the peer
+    #[serde(default)]
+    pub trust: Trust,
+}
+
+/// Address book of [`Contact`]s by UID
+#[derive(Debug, Default)]
+pub struct Contacts {
+    /// File the contacts are stored in, if any
+    path: Option<PathBuf>,
+    contacts: HashMap<String, Contact>,
+}
+
+impl Contacts {
+    /// Creates an empty address book that is not stored on the filesystem
+    pub fn new() -> Contacts {
+        Contacts::default()
+    }
+
+    /// Returns the default location of the contacts on the filesystem
+    pub fn default_path() -> PathBuf {
+        let mut path = Id::get_config_dir();
+        path.push(CONTACTS_FILE);
+        path
+    }
+
+    /// Load the contacts stored in `path`. A missing file is an empty address book.
+    /// Changes are saved to the same file
+    /// # Errors
+    /// * [`AetherError::FileRead`] - If the file exists but cannot be read
+    /// * [`AetherError::YamlParse`] - If the file does not contain valid contacts
+    pub fn load(path: &Path) -> Result<Contacts, AetherError> {
+        let contacts = match fs::read_to_string(path) {
+            Ok(data) => serde_yaml::from_str(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(AetherError::FileRead(err)),
+        };
+
+        Ok(Contacts {
+            path: Some(path.to_path_buf()),
+            contacts,
+        })
+    }
+
+    /// Write the contacts to their file, if any
+    pub fn save(&self) -> Result<(), AetherError> {
+        match &self.path {
+            Some(path) => {
+                let data = serde_yaml::to_string(&self.contacts)?;
+                fs::write(path, data).map_err(AetherError::FileWrite)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Add or update the contact with `uid` and save the contacts
+    /// # Errors
+    /// * [`AetherError::AliasTaken`] - If another contact already has `alias`
+    ///
+    /// Errors from [`Contacts::save`] are returned as well
+    pub fn add_contact(&mut self, uid: &str, alias: &str, trust: Trust) -> Result<(), AetherError> {
+        if matches!(self.uid_of(alias), Some(other) if other != uid) {
+            return Err(AetherError::AliasTaken(alias.to_string()));
+        }
+
+        self.contacts.insert(
+            uid.to_string(),
+            Contact {
+                alias: alias.to_string(),
+                trust,
+            },
+        );
+
+        self.save()
+    }
+
+    /// Remove the contact with `uid` and save the contacts
+    pub fn remove_contact(&mut self, uid: &str) -> Result<Option<Contact>, AetherError> {
+        let removed = self.contacts.remove(uid);
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Change the trust status of the contact with `uid` and save the contacts
+    /// # Errors
+    /// * [`AetherError::UnknownContact`] - If there is no contact with `uid`
+    pub fn set_trust(&mut self, uid: &str, trust: Trust) -> Result<(), AetherError> {
+        match self.contacts.get_mut(uid) {
+            Some(contact) => contact.trust = trust,
+            None => return Err(AetherError::UnknownContact(uid.to_string())),
+        }
+
+        self.save()
+    }
+
+    /// Returns the contact with `uid`
+    pub fn get(&self, uid: &str) -> Option<&Contact> {
+        self.contacts.get(uid)
+    }
+
+    /// Returns the alias of the contact with `uid`
+    pub fn alias_of(&self, uid: &str) -> Option<&str> {
+        self.contacts.get(uid).map(|contact| contact.alias.as_str())
+    }
+
+    /// Returns the UID of the contact with `alias`
+    pub fn uid_of(&self, alias: &str) -> Option<&str> {
+        self.contacts
+            .iter()
+            .find(|(_, contact)| contact.alias == alias)
+            .map(|(uid, _)| uid.as_str())
+    }
+
+    /// Returns the trust status of the peer with `uid`, [`Trust::Unknown`] if it is not
+    /// a contact
+    pub fn trust_of(&self, uid: &str) -> Trust {
+        self.contacts
+            .get(uid)
+            .map(|contact| contact.trust)
+            .unwrap_or_default()
+    }
+
+    /// Returns the UID of the contact if `name` is an alias, else `name` itself
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.uid_of(name).unwrap_or(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{Contacts, Trust};
+    use crate::error::AetherError;
+
+    #[test]
+    fn alias_test() {
+        let mut contacts = Contacts::new();
+        contacts
+            .add_contact("uid1", "alice", Trust::Trusted)
+            .unwrap();
+        contacts.add_contact("uid2", "bob", Trust::Unknown).unwrap();
+
+        assert_eq!(contacts.alias_of("uid1"), Some("alice"));
+        assert_eq!(contacts.uid_of("bob"), Some("uid2"));
+        assert_eq!(contacts.resolve("alice"), "uid1");
+        assert_eq!(contacts.resolve("uid3"), "uid3");
+
+        assert!(matches!(
+            contacts.add_contact("uid2", "alice", Trust::Unknown),
+            Err(AetherError::AliasTaken(_))
+        ));
+
+        // Renaming keeps the contact unique
+        contacts
+            .add_contact("uid2", "robert", Trust::Unknown)
+            .unwrap();
+        assert_eq!(contacts.uid_of("bob"), None);
+        assert_eq!(contacts.resolve("robert"), "uid2");
+
+        contacts.set_trust("uid2", Trust::Blocked).unwrap();
+        assert_eq!(contacts.trust_of("uid2"), Trust::Blocked);
+        assert_eq!(contacts.trust_of("uid3"), Trust::Unknown);
+        assert!(contacts.set_trust("uid3", Trust::Trusted).is_err());
+
+        assert!(contacts.remove_contact("uid2").unwrap().is_some());
+        assert_eq!(contacts.alias_of("uid2"), None);
+    }
+
+    #[test]
+    fn persist_test() {
+        fs::create_dir_all("./tmp").unwrap();
+        let path = Path::new("./tmp/contacts.yaml");
+        let _ = fs::remove_file(path);
+
+        let mut contacts = Contacts::load(path).unwrap();
+        contacts
+            .add_contact("uid1", "alice", Trust::Trusted)
+            .unwrap();
+
+        let loaded = Contacts::load(path).unwrap();
+        assert_eq!(loaded.get("uid1"), contacts.get("uid1"));
+
+        // Missing trust status defaults to unknown
+        fs::write(path, "uid2:\n  alias: bob\n").unwrap();
+        let loaded = Contacts::load(path).unwrap();
+        assert_eq!(loaded.trust_of("uid2"), Trust::Unknown);
+        assert_eq!(loaded.alias_of("uid2"), Some("bob"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
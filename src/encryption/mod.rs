@@ -1,29 +1,56 @@
 //! Primitives for symmetric encryption for Aether.
 //! Makes use of AES-256-GCM cipher. Implementation built on top of OpenSSL.
 
+use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
 
 use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
     sha::sha256,
+    sign::Signer,
     symm::{decrypt_aead, encrypt_aead, Cipher},
 };
 
-use crate::{error::AetherError, util::gen_nonce};
+use crate::{
+    error::{AetherError, PacketError},
+    util::gen_nonce,
+};
 
 const EMPTY_BYTES: [u8; 0] = [];
 pub const IV_SIZE: usize = 16;
 pub const KEY_SIZE: usize = 32;
 pub const TAG_SIZE: usize = 16;
 
+/// Name of the cipher used by [`AetherCipher`]
+pub const CIPHER_NAME: &str = "AES-256-GCM";
+
+/// Number of bytes added to each payload by encryption
+pub const ENCRYPTION_OVERHEAD: usize = TAG_SIZE + IV_SIZE;
+
+/// Number of decimal digits of a short authentication string
+pub const SAS_DIGITS: u32 = 6;
+
+/// Prefix of the digest short authentication strings are derived from, so that they
+/// reveal nothing about the key
+const SAS_CONTEXT: &[u8] = b"aether sas";
+
+/// Prefix of the digest the key of packet tags is derived from, so that it differs
+/// from the key of the cipher
+const PACKET_TAG_CONTEXT: &[u8] = b"aether packet tag";
+
 #[derive(Clone)]
 pub struct AetherCipher {
     cipher: Cipher,
     key: [u8; KEY_SIZE],
+    /// Key of the tags authenticating packets (refer [`AetherCipher::packet_tag`])
+    tag_key: PKey<Private>,
 }
 
 pub struct Encrypted {
     pub cipher_text: Vec<u8>,
     pub tag: Vec<u8>,
+    /// Nonce the message was encrypted with, unique to the message. Sent along with it
     pub iv: Vec<u8>,
     pub aad: Vec<u8>,
 }
@@ -32,10 +59,36 @@ impl AetherCipher {
     pub fn new(shared_secret: Vec<u8>) -> AetherCipher {
         let cipher = Cipher::aes_256_gcm();
         let key = sha256(&shared_secret);
+        let tag_key = PKey::hmac(&sha256(&[PACKET_TAG_CONTEXT, &key].concat()))
+            .expect("Unable to create key of packet tags");
 
-        AetherCipher { cipher, key }
+        AetherCipher {
+            cipher,
+            key,
+            tag_key,
+        }
     }
 
+    /// Returns the tag authenticating the packet `bytes`, the HMAC-SHA-256 of the bytes
+    /// truncated to [`TAG_SIZE`] bytes (refer
+    /// [`has_packet_tags`][crate::packet::has_packet_tags]). The key of the tags is
+    /// derived from the key of the cipher
+    pub fn packet_tag(&self, bytes: &[u8]) -> [u8; TAG_SIZE] {
+        let hmac = Signer::new(MessageDigest::sha256(), &self.tag_key)
+            .and_then(|mut signer| {
+                signer.update(bytes)?;
+                signer.sign_to_vec()
+            })
+            .expect("Unable to compute packet tag");
+
+        let mut tag = [0; TAG_SIZE];
+        tag.copy_from_slice(&hmac[..TAG_SIZE]);
+        tag
+    }
+
+    /// Encrypt `plain_text` with a new random nonce of [`IV_SIZE`] bytes. Nonces are never
+    /// reused for the same key, which would break the confidentiality and authenticity
+    /// of AES-GCM, so each message carries its own in [`Encrypted::iv`]
     pub fn encrypt_bytes(&self, plain_text: Vec<u8>) -> Result<Encrypted, AetherError> {
         let mut tag = vec![0u8; TAG_SIZE];
         let iv = gen_nonce(IV_SIZE);
@@ -66,6 +119,37 @@ impl AetherCipher {
             &cipher_text.tag,
         )?)
     }
+
+    /// Derive the short authentication string of the session between the peers `uid`
+    /// and `peer_uid`: [`SAS_DIGITS`] decimal digits in groups of 3, the same for both
+    /// peers. Users reading it out to each other know that nobody intercepted the
+    /// session if it matches, since an attacker would have agreed on different keys or
+    /// UIDs with each of them
+    pub fn short_auth_string(&self, uid: &str, peer_uid: &str) -> String {
+        let (first, second) = if uid <= peer_uid {
+            (uid, peer_uid)
+        } else {
+            (peer_uid, uid)
+        };
+
+        let mut data = SAS_CONTEXT.to_vec();
+        for part in [first.as_bytes(), second.as_bytes(), &self.key] {
+            data.push(0);
+            data.extend(part);
+        }
+        let digest = sha256(&data);
+
+        let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+            % 10u32.pow(SAS_DIGITS);
+        let digits = format!("{:0width$}", value, width = SAS_DIGITS as usize);
+
+        digits
+            .as_bytes()
+            .chunks(3)
+            .map(|group| String::from_utf8_lossy(group))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
 impl From<Encrypted> for Vec<u8> {
@@ -79,21 +163,30 @@ impl From<Encrypted> for Vec<u8> {
     }
 }
 
-impl From<Vec<u8>> for Encrypted {
-    fn from(mut bytes: Vec<u8>) -> Self {
-        Encrypted {
+impl TryFrom<Vec<u8>> for Encrypted {
+    type Error = PacketError;
+
+    /// Split encrypted bytes into their tag, nonce and cipher text
+    /// # Errors
+    /// * [`PacketError::Truncated`] - The bytes are shorter than [`ENCRYPTION_OVERHEAD`]
+    fn try_from(mut bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        if bytes.len() < ENCRYPTION_OVERHEAD {
+            return Err(PacketError::Truncated);
+        }
+
+        Ok(Encrypted {
             aad: EMPTY_BYTES.to_vec(),
             tag: bytes.drain(0..TAG_SIZE).collect(),
             iv: bytes.drain(0..IV_SIZE).collect(),
             cipher_text: bytes,
-        }
+        })
     }
 }
 
 impl Debug for AetherCipher {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AetherCipher")
-            .field("cipher", &"AES-256-GCM")
+            .field("cipher", &CIPHER_NAME)
             .field("key", &base64::encode(self.key))
             .finish()
     }
@@ -101,12 +194,17 @@ impl Debug for AetherCipher {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
+    use openssl::{hash::MessageDigest, pkey::PKey, sha::sha256, sign::Signer};
+
     use crate::{
-        encryption::{Encrypted, KEY_SIZE},
+        encryption::{Encrypted, ENCRYPTION_OVERHEAD, KEY_SIZE, TAG_SIZE},
+        error::PacketError,
         util::gen_nonce,
     };
 
-    use super::AetherCipher;
+    use super::{AetherCipher, PACKET_TAG_CONTEXT};
 
     #[test]
     fn encryption_test() {
@@ -133,10 +231,82 @@ mod tests {
         let encrypted_raw: Vec<u8> = Vec::from(encrypted);
 
         // Other end receives sequence of bytes as encrypted text
-        let received = Encrypted::from(encrypted_raw);
+        let received = Encrypted::try_from(encrypted_raw).unwrap();
 
         let decrypted = cipher.decrypt_bytes(received).unwrap();
 
         assert_eq!(data, decrypted);
+
+        // Bytes too short to hold the tag and nonce are rejected
+        assert!(matches!(
+            Encrypted::try_from(vec![0; ENCRYPTION_OVERHEAD - 1]),
+            Err(PacketError::Truncated)
+        ));
+        let empty = Encrypted::try_from(vec![0; ENCRYPTION_OVERHEAD]).unwrap();
+        assert!(cipher.decrypt_bytes(empty).is_err());
+    }
+
+    #[test]
+    fn nonce_test() {
+        let data = gen_nonce(64);
+        let cipher = AetherCipher::new(gen_nonce(KEY_SIZE));
+
+        // Every message is encrypted with its own nonce
+        let first = cipher.encrypt_bytes(data.clone()).unwrap();
+        let second = cipher.encrypt_bytes(data.clone()).unwrap();
+        assert_ne!(first.iv, second.iv);
+        assert_ne!(first.cipher_text, second.cipher_text);
+
+        // which is authenticated along with the message
+        let mut tampered = Vec::from(first);
+        tampered[TAG_SIZE] ^= 1;
+        assert!(cipher
+            .decrypt_bytes(Encrypted::try_from(tampered).unwrap())
+            .is_err());
+
+        assert_eq!(cipher.decrypt_bytes(second).unwrap(), data);
+    }
+
+    #[test]
+    fn packet_tag_test() {
+        let secret = gen_nonce(KEY_SIZE);
+        let cipher = AetherCipher::new(secret.clone());
+
+        // Both peers compute the same tag
+        let tag = cipher.packet_tag(b"packet");
+        assert_eq!(AetherCipher::new(secret.clone()).packet_tag(b"packet"), tag);
+
+        // which changes with the packet and the key
+        assert_ne!(cipher.packet_tag(b"packed"), tag);
+        assert_ne!(
+            AetherCipher::new(gen_nonce(KEY_SIZE)).packet_tag(b"packet"),
+            tag
+        );
+
+        // and is the HMAC-SHA-256 of the packet with the key derived from the secret
+        let tag_key = sha256(&[PACKET_TAG_CONTEXT, &sha256(&secret)].concat());
+        let pkey = PKey::hmac(&tag_key).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+        signer.update(b"packet").unwrap();
+        assert_eq!(tag[..], signer.sign_to_vec().unwrap()[..TAG_SIZE]);
+    }
+
+    #[test]
+    fn short_auth_string_test() {
+        let secret = gen_nonce(KEY_SIZE);
+        let cipher = AetherCipher::new(secret.clone());
+
+        let sas = cipher.short_auth_string("alice", "bob");
+        assert_eq!(sas.len(), 7);
+        assert!(sas.chars().all(|c| c.is_ascii_digit() || c == ' '));
+
+        // Both peers derive the same string
+        let other = AetherCipher::new(secret);
+        assert_eq!(other.short_auth_string("bob", "alice"), sas);
+
+        // Other keys or peers lead to other strings (with high probability)
+        let intercepted = AetherCipher::new(gen_nonce(KEY_SIZE));
+        assert_ne!(intercepted.short_auth_string("alice", "bob"), sas);
+        assert_ne!(cipher.short_auth_string("alice", "mallory"), sas);
     }
 }
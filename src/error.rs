@@ -7,8 +7,35 @@ use thiserror::Error;
 
 use crossbeam::channel::{RecvError, RecvTimeoutError, SendError};
 
+use crate::link::delivery::MessageId;
 use crate::packet::Packet;
 
+/// Invariants violated when building a [`Packet`] with a
+/// [`PacketBuilder`][crate::packet::PacketBuilder]
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PacketError {
+    #[error("Payload of {size} bytes exceeds the limit of {max} bytes for the packet type")]
+    PayloadTooLarge { size: usize, max: usize },
+    #[error("Packet type requires an acknowledgement")]
+    MissingAck,
+    #[error("Acknowledgement is inconsistent")]
+    InvalidAck,
+    #[error("Packet type cannot be encrypted")]
+    InvalidEncryption,
+    #[error("Packet type cannot be a fragment of a message")]
+    InvalidFragment,
+    #[error("Packet is shorter than its header")]
+    Truncated,
+    #[error("Reserved flag bits {0:#04b} are set")]
+    ReservedFlags(u8),
+    #[error("Protocol version {0} of the packet is not supported by the link")]
+    UnsupportedVersion(u8),
+    #[error("Checksum of the packet does not match its contents")]
+    ChecksumMismatch,
+    #[error("Packet is not authenticated by the key of the link")]
+    Unauthenticated,
+}
+
 #[derive(Error, Debug)]
 pub enum AetherError {
     #[error("Current time is from future so cannot calculate elapsed time")]
@@ -23,8 +50,14 @@ pub enum AetherError {
     LinkTimeout,
     #[error("Failed to set read timeout on socket")]
     SetReadTimeout,
+    #[error("Unable to bind a socket for the link to the peer")]
+    SocketBind(std::io::Error),
     #[error("User not connected")]
     NotConnected(String),
+    #[error("Link to user is broken")]
+    LinkBroken(String),
+    #[error("Session with user has expired")]
+    SessionExpired(String),
     #[error("Error parsing yaml string")]
     YamlParse(#[from] serde_yaml::Error),
     #[error("Error reading file")]
@@ -35,6 +68,14 @@ pub enum AetherError {
     AuthenticationInvalid(String),
     #[error("Other peer cannot be reached when authenticating")]
     AuthenticationFailed(String),
+    #[error("Other peer presented a new key that cannot be verified")]
+    IdentityChanged(String),
+    #[error("Private key cannot be decrypted with the given passphrase")]
+    InvalidPassphrase,
+    #[error("Not an exported identity")]
+    InvalidIdentity,
+    #[error("Display name is invalid or not signed by the peer")]
+    InvalidName(String),
     #[error("OpenSSL Error")]
     OpenSSLError(#[from] ErrorStack),
     #[error("Error parsing utf8 string")]
@@ -44,7 +85,47 @@ pub enum AetherError {
     #[error("Handshake couldn't complete")]
     HandshakeError,
     #[error("Error sending on channel")]
-    ChannelSendError(#[from] SendError<Packet>),
+    ChannelSendError(#[from] Box<SendError<Packet>>),
     #[error("Error receiving on channel")]
     ChannelRecvError(#[from] RecvError),
+    #[error("Invalid packet")]
+    InvalidPacket(#[from] PacketError),
+    #[error("Invalid tracker packet")]
+    TrackerPacket(&'static str),
+    #[error("Tracker packet is not signed by its sender")]
+    TrackerSignature(&'static str),
+    #[error("Tracker server did not answer in time")]
+    TrackerTimeout,
+    #[error("Unable to communicate with the tracker server")]
+    TrackerIo(std::io::Error),
+    #[error("Message is larger than the maximum message size of the link")]
+    MessageTooLarge(usize),
+    #[error("Send queue of the link is full")]
+    SendQueueFull(usize),
+    #[error("Message was cancelled")]
+    MessageCancelled(MessageId),
+    #[error("Outbox of the peer is full")]
+    OutboxFull(String),
+    #[error("Invalid control frame")]
+    ControlFrame(&'static str),
+    #[error("Message headers are invalid")]
+    InvalidHeaders(&'static str),
+    #[error("Handler is already registered for the extended packet subtype")]
+    ExtensionTaken(u8),
+    #[error("Other peer does not support extended packets")]
+    ExtensionUnsupported(u8),
+    #[error("Alias is already used by another contact")]
+    AliasTaken(String),
+    #[error("No contact with the given uid")]
+    UnknownContact(String),
+    #[error("Schema version of the config dir is not supported")]
+    InvalidSchemaVersion(String),
+    #[error("Connection needs {required} bytes but only {available} bytes of the memory budget are left")]
+    ResourceBudgetExceeded { required: usize, available: usize },
+}
+
+impl From<SendError<Packet>> for AetherError {
+    fn from(err: SendError<Packet>) -> AetherError {
+        AetherError::ChannelSendError(Box::new(err))
+    }
 }
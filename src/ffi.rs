@@ -0,0 +1,354 @@
+//! C interface to [`Aether`] clients. Requires the `ffi` feature.
+//!
+//! A client is created with [`aether_new`] and used through the returned pointer until
+//! it is freed with [`aether_free`]. Other functions return [`AETHER_OK`] or one of the
+//! negative error codes below. They never unwind into the caller, a panic is reported
+//! as [`AETHER_ERR_PANIC`]. Strings, such as UIDs, are NUL-terminated UTF-8.
+//!
+//! The declarations for C are in `include/aether.h`. The library is built for C by
+//! choosing a C crate type, for example
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! # Examples
+//!
+//! ```c
+//! #include "aether.h"
+//!
+//! AetherClient *aether = aether_new("127.0.0.1:8982", NULL);
+//! aether_connect(aether, peer_uid);
+//! while (aether_is_connected(aether, peer_uid) == 0) {
+//!     sleep(1);
+//! }
+//!
+//! aether_send(aether, peer_uid, (const uint8_t *)"Hello", 5);
+//!
+//! uint8_t *data;
+//! size_t len;
+//! if (aether_recv(aether, peer_uid, 5000, &data, &len) == AETHER_OK) {
+//!     fwrite(data, 1, len, stdout);
+//!     aether_free_bytes(data, len);
+//! }
+//!
+//! aether_free(aether);
+//! ```
+
+use std::ffi::{CStr, CString};
+use std::net::SocketAddr;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::error::AetherError;
+use crate::identity::Id;
+use crate::peer::Aether;
+use crate::util::catch_panic;
+
+/// The call succeeded
+pub const AETHER_OK: c_int = 0;
+/// A pointer is null, a string is not UTF-8 or an address cannot be parsed
+pub const AETHER_ERR_INVALID_ARGUMENT: c_int = -1;
+/// The peer is not connected
+pub const AETHER_ERR_NOT_CONNECTED: c_int = -2;
+/// Nothing was received within the timeout
+pub const AETHER_ERR_TIMEOUT: c_int = -3;
+/// The link to the peer is closed, the peer has to be connected again
+pub const AETHER_ERR_LINK_CLOSED: c_int = -4;
+/// The message is larger than the maximum message size agreed on with the peer
+pub const AETHER_ERR_TOO_LARGE: c_int = -5;
+/// Another connection would exceed the memory budget of the client
+pub const AETHER_ERR_BUDGET: c_int = -6;
+/// The call panicked
+pub const AETHER_ERR_PANIC: c_int = -7;
+/// Any other error, logged by the library
+pub const AETHER_ERR_OTHER: c_int = -8;
+
+/// [`Aether`] client used through the C interface
+pub struct AetherClient {
+    aether: Aether,
+    /// UID of the client returned by [`aether_uid`]
+    uid: CString,
+}
+
+/// Returns the error code reported to C for `err`
+pub fn error_code(err: &AetherError) -> c_int {
+    match err {
+        AetherError::NotConnected(_) => AETHER_ERR_NOT_CONNECTED,
+        AetherError::RecvTimeout(_) => AETHER_ERR_TIMEOUT,
+        AetherError::LinkStopped(_)
+        | AetherError::LinkBroken(_)
+        | AetherError::LinkTimeout
+        | AetherError::SessionExpired(_)
+        | AetherError::IdentityChanged(_) => AETHER_ERR_LINK_CLOSED,
+        AetherError::MessageTooLarge(_) => AETHER_ERR_TOO_LARGE,
+        AetherError::ResourceBudgetExceeded { .. } => AETHER_ERR_BUDGET,
+        _ => AETHER_ERR_OTHER,
+    }
+}
+
+/// Run `f`, reporting its error or a panic as an error code
+fn guard<F: FnOnce() -> Result<c_int, c_int>>(f: F) -> c_int {
+    match catch_panic(f) {
+        Ok(Ok(code)) | Ok(Err(code)) => code,
+        Err(_) => AETHER_ERR_PANIC,
+    }
+}
+
+/// Returns the client `client` points to
+unsafe fn client_arg<'a>(client: *const AetherClient) -> Result<&'a AetherClient, c_int> {
+    client.as_ref().ok_or(AETHER_ERR_INVALID_ARGUMENT)
+}
+
+/// Returns the string `s` points to
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(AETHER_ERR_INVALID_ARGUMENT);
+    }
+
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| AETHER_ERR_INVALID_ARGUMENT)
+}
+
+/// Returns the error code of `result`
+fn code<T>(result: Result<T, AetherError>) -> Result<T, c_int> {
+    result.map_err(|err| {
+        let code = error_code(&err);
+        if code == AETHER_ERR_OTHER {
+            warn!("Aether call failed: {}", err);
+        }
+        code
+    })
+}
+
+/// Create and start a client using the tracker server at `tracker_addr`, such as
+/// `"127.0.0.1:8982"`. Returns null if the client cannot be created
+///
+/// `identity` is an identity exported without a passphrase (refer [`Id::export`]), or
+/// null to use the identity stored in the config dir like [`Aether::new`]
+/// # Safety
+/// `tracker_addr` and `identity` must be null or point to NUL-terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn aether_new(
+    tracker_addr: *const c_char,
+    identity: *const c_char,
+) -> *mut AetherClient {
+    let result = catch_panic(|| {
+        let tracker_addr: SocketAddr = str_arg(tracker_addr)?
+            .parse()
+            .map_err(|_| AETHER_ERR_INVALID_ARGUMENT)?;
+
+        let aether = if identity.is_null() {
+            Aether::new(tracker_addr)
+        } else {
+            let id = code(Id::import(str_arg(identity)?, None))?;
+            Aether::new_with_id(id, tracker_addr)
+        };
+        let uid = CString::new(aether.get_uid()).map_err(|_| AETHER_ERR_OTHER)?;
+
+        aether.start();
+        Ok::<_, c_int>(Box::into_raw(Box::new(AetherClient { aether, uid })))
+    });
+
+    match result {
+        Ok(Ok(client)) => client,
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Stop and free a client created by [`aether_new`]. Null is ignored
+/// # Safety
+/// `client` must be null or returned by [`aether_new`], and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn aether_free(client: *mut AetherClient) {
+    if client.is_null() {
+        return;
+    }
+
+    let client = Box::from_raw(client);
+    let _ = catch_panic(move || {
+        if let Err(err) = client.aether.stop() {
+            warn!("Unable to stop client: {}", err);
+        }
+    });
+}
+
+/// Returns the UID of the client, valid until the client is freed. Null if `client` is
+/// null
+/// # Safety
+/// `client` must be null or returned by [`aether_new`]
+#[no_mangle]
+pub unsafe extern "C" fn aether_uid(client: *const AetherClient) -> *const c_char {
+    match client_arg(client) {
+        Ok(client) => client.uid.as_ptr(),
+        Err(_) => ptr::null(),
+    }
+}
+
+/// Connect to the peer `uid`. Returns once connecting started, the connection is
+/// established in the background (refer [`aether_is_connected`])
+/// # Safety
+/// `client` must be null or returned by [`aether_new`], and `uid` must be null or
+/// point to a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn aether_connect(client: *const AetherClient, uid: *const c_char) -> c_int {
+    guard(|| {
+        let client = client_arg(client)?;
+        code(client.aether.connect(str_arg(uid)?))?;
+        Ok(AETHER_OK)
+    })
+}
+
+/// Returns 1 if the peer `uid` is connected, 0 otherwise
+/// # Safety
+/// Same as [`aether_connect`]
+#[no_mangle]
+pub unsafe extern "C" fn aether_is_connected(
+    client: *const AetherClient,
+    uid: *const c_char,
+) -> c_int {
+    guard(|| {
+        let client = client_arg(client)?;
+        Ok(client.aether.is_connected(str_arg(uid)?) as c_int)
+    })
+}
+
+/// Send `len` bytes from `data` to the connected peer `uid`
+/// # Safety
+/// Same as [`aether_connect`], and `data` must point to `len` readable bytes unless
+/// `len` is 0
+#[no_mangle]
+pub unsafe extern "C" fn aether_send(
+    client: *const AetherClient,
+    uid: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let client = client_arg(client)?;
+        let uid = str_arg(uid)?;
+        let buf = match len {
+            0 => Vec::new(),
+            _ if data.is_null() => return Err(AETHER_ERR_INVALID_ARGUMENT),
+            _ => slice::from_raw_parts(data, len).to_vec(),
+        };
+
+        code(client.aether.send_to(uid, buf))?;
+        Ok(AETHER_OK)
+    })
+}
+
+/// Receive bytes from the connected peer `uid`, waiting at most `timeout_ms`
+/// milliseconds or until bytes are received if it is 0. The bytes are stored in a
+/// buffer `*data` of `*len` bytes, to be freed with [`aether_free_bytes`]
+/// # Safety
+/// Same as [`aether_connect`], and `data` and `len` must be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn aether_recv(
+    client: *const AetherClient,
+    uid: *const c_char,
+    timeout_ms: u32,
+    data: *mut *mut u8,
+    len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let client = client_arg(client)?;
+        let uid = str_arg(uid)?;
+        if data.is_null() || len.is_null() {
+            return Err(AETHER_ERR_INVALID_ARGUMENT);
+        }
+
+        let bytes = code(match timeout_ms {
+            0 => client.aether.recv_from(uid),
+            timeout => client
+                .aether
+                .recv_timeout_from(uid, Duration::from_millis(timeout as u64)),
+        })?;
+
+        let bytes = bytes.into_boxed_slice();
+        *len = bytes.len();
+        *data = Box::into_raw(bytes) as *mut u8;
+        Ok(AETHER_OK)
+    })
+}
+
+/// Free a buffer of `len` bytes returned by [`aether_recv`]. Null is ignored
+/// # Safety
+/// `data` must be null or returned by [`aether_recv`] along with `len`, and not be
+/// used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn aether_free_bytes(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use crossbeam::channel::RecvTimeoutError;
+
+    use super::{
+        aether_connect, aether_free, aether_free_bytes, aether_new, aether_recv, aether_send,
+        aether_uid, error_code, AETHER_ERR_INVALID_ARGUMENT, AETHER_ERR_LINK_CLOSED,
+        AETHER_ERR_NOT_CONNECTED, AETHER_ERR_OTHER, AETHER_ERR_TIMEOUT,
+    };
+    use crate::error::AetherError;
+
+    #[test]
+    fn error_code_test() {
+        let uid = String::from("peer");
+        assert_eq!(
+            error_code(&AetherError::NotConnected(uid.clone())),
+            AETHER_ERR_NOT_CONNECTED
+        );
+        assert_eq!(
+            error_code(&AetherError::RecvTimeout(RecvTimeoutError::Timeout)),
+            AETHER_ERR_TIMEOUT
+        );
+        assert_eq!(
+            error_code(&AetherError::LinkBroken(uid)),
+            AETHER_ERR_LINK_CLOSED
+        );
+        assert_eq!(error_code(&AetherError::HandshakeError), AETHER_ERR_OTHER);
+    }
+
+    #[test]
+    fn invalid_argument_test() {
+        let uid = CString::new("peer").unwrap();
+        let invalid = CString::new("not an address").unwrap();
+
+        unsafe {
+            assert!(aether_new(ptr::null(), ptr::null()).is_null());
+            assert!(aether_new(invalid.as_ptr(), ptr::null()).is_null());
+            assert!(aether_uid(ptr::null()).is_null());
+
+            assert_eq!(
+                aether_connect(ptr::null(), uid.as_ptr()),
+                AETHER_ERR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                aether_send(ptr::null(), uid.as_ptr(), ptr::null(), 0),
+                AETHER_ERR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                aether_recv(
+                    ptr::null(),
+                    uid.as_ptr(),
+                    0,
+                    ptr::null_mut(),
+                    ptr::null_mut()
+                ),
+                AETHER_ERR_INVALID_ARGUMENT
+            );
+
+            // Null pointers are ignored when freeing
+            aether_free(ptr::null_mut());
+            aether_free_bytes(ptr::null_mut(), 0);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Human-readable names of peers, bound to their UIDs.
+//!
+//! UIDs are base64 encoded public keys, which are unfit to be shown to users. A peer
+//! can instead publish a display name of its choice as a [`SignedName`]: the name along
+//! with a signature of the [`name_message`] of its UID and the name, made with its
+//! private key. Other peers only accept the name if the signature verifies against the
+//! UID of the peer it was received from, so names cannot be forged for other peers or
+//! replayed by them.
+//!
+//! Names are published with [`Aether::set_display_name`] in frames of their own (refer
+//! [`frames`]) and kept by every receive path of the other peer, which shows them in
+//! its [`PeerInfo`].
+//!
+//! Names are chosen by the peers themselves and are therefore not unique. Only
+//! [aliases][crate::contacts] given by the user tell peers apart reliably.
+//!
+//! # Examples
+//!
+//! ```
+//! use aether_lib::identity::Id;
+//!
+//! let id = Id::new().unwrap();
+//! let uid = id.public_key_to_base64().unwrap();
+//!
+//! let signed = id.sign_name("Alice").unwrap();
+//! assert_eq!(signed.verify(&uid).unwrap(), "Alice");
+//! ```
+//!
+//! [`Aether::set_display_name`]: crate::peer::Aether::set_display_name
+//! [`frames`]: crate::peer::frames
+//! [`PeerInfo`]: crate::peer::PeerInfo
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::identity::{Id, PublicId};
+use crate::wire::control::Control;
+
+/// Longest display name accepted (in bytes)
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Prefix of the [`name_message`], so that names cannot be mistaken for signatures of
+/// other data
+const NAME_CONTEXT: &[u8] = b"aether display name";
+
+/// Display name of a peer signed with its private key. Also the control message
+/// publishing the name to connected peers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedName {
+    /// The display name
+    pub name: String,
+    /// [`name_message`] signed by the peer the name belongs to
+    pub signature: Vec<u8>,
+}
+
+impl Control for SignedName {
+    const KIND: u16 = 0x0006;
+}
+
+/// Returns the message signed by the peer `uid` to publish `name`
+pub fn name_message(uid: &str, name: &str) -> Vec<u8> {
+    let mut message = NAME_CONTEXT.to_vec();
+    message.push(0);
+    message.extend(uid.as_bytes());
+    message.push(0);
+    message.extend(name.as_bytes());
+    message
+}
+
+/// Check if `name` can be shown to users: not blank, at most [`MAX_NAME_LENGTH`] bytes
+/// and free of control and bidirectional formatting characters, which could disguise
+/// it as another name
+pub fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.chars().any(|c| {
+            c.is_control()
+                || matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+        })
+}
+
+impl Id {
+    /// Sign `name` to be published as the display name of this identity
+    /// # Errors
+    /// * [`AetherError::InvalidName`] - If the name is not valid (refer [`is_valid_name`])
+    pub fn sign_name(&self, name: &str) -> Result<SignedName, AetherError> {
+        let uid = self.public_key_to_base64()?;
+        if !is_valid_name(name) {
+            return Err(AetherError::InvalidName(uid));
+        }
+
+        let signature = self.sign(&name_message(&uid, name))?;
+        Ok(SignedName {
+            name: name.to_string(),
+            signature,
+        })
+    }
+}
+
+impl SignedName {
+    /// Verify that the name was signed by the peer `uid`. Returns the name if it was
+    /// # Errors
+    /// * [`AetherError::InvalidName`] - If the name was not signed by `uid` or is not
+    ///   valid (refer [`is_valid_name`])
+    pub fn verify(&self, uid: &str) -> Result<&str, AetherError> {
+        let public_id = PublicId::from_base64(uid)?;
+
+        match public_id.verify(&name_message(uid, &self.name), &self.signature) {
+            Ok(true) if is_valid_name(&self.name) => Ok(&self.name),
+            _ => Err(AetherError::InvalidName(uid.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_name, SignedName, MAX_NAME_LENGTH};
+    use crate::error::AetherError;
+    use crate::identity::Id;
+
+    #[test]
+    fn verify_test() {
+        let id = Id::new().unwrap();
+        let uid = id.public_key_to_base64().unwrap();
+
+        let signed = id.sign_name("Alice").unwrap();
+        assert_eq!(signed.verify(&uid).unwrap(), "Alice");
+
+        // Names are bound to the UID that signed them
+        let mallory = Id::new().unwrap();
+        let mallory_uid = mallory.public_key_to_base64().unwrap();
+        assert!(matches!(
+            signed.verify(&mallory_uid),
+            Err(AetherError::InvalidName(_))
+        ));
+
+        // and cannot be changed
+        let changed = SignedName {
+            name: "Mallory".to_string(),
+            ..signed
+        };
+        assert!(matches!(
+            changed.verify(&uid),
+            Err(AetherError::InvalidName(_))
+        ));
+
+        assert!(is_valid_name("Bob 🚀"));
+        assert!(!is_valid_name(" "));
+        assert!(!is_valid_name("Bob\u{202e}"));
+        assert!(!is_valid_name(&"a".repeat(MAX_NAME_LENGTH + 1)));
+        assert!(matches!(
+            id.sign_name("Alice\n"),
+            Err(AetherError::InvalidName(_))
+        ));
+    }
+}
@@ -52,7 +52,7 @@
 //! let peer_uid = String::from("<peer-uid-here>");
 //!
 //! // connect to the other peer
-//! aether.connect(&peer_uid);
+//! aether.connect(&peer_uid).unwrap();
 //! ```
 //!
 //! ## Sending bytes to another peer
@@ -80,7 +80,7 @@
 //! let peer_uid = String::from("<peer-uid-here>");
 //!
 //! // connect to the other peer
-//! aether.connect(&peer_uid);
+//! aether.connect(&peer_uid).unwrap();
 //!
 //! // message to be sent
 //! let message = String::from("Hello");
@@ -113,7 +113,7 @@
 //! let peer_uid = String::from("<peer-uid-here>");
 //!
 //! // connect to the other peer
-//! aether.connect(&peer_uid);
+//! aether.connect(&peer_uid).unwrap();
 //!
 //! // receive bytes from peer with peer_uid
 //! let bytes = aether.recv_from(&peer_uid).unwrap();
@@ -122,16 +122,69 @@
 //! let message = String::from_utf8(bytes).unwrap();
 //! ```
 //!
+//! ## Imports
+//!
+//! The types used in the examples above, and most others needed to use a client, can
+//! also be imported at once from the [prelude].
+//!
+//! ## Stability
+//!
+//! The documented modules make up the public API, covered by semantic versioning. The
+//! implementation of the protocol (links, packets, acknowledgements, encryption and the
+//! tracker client) is private and may change in any release. It is made public with
+//! the `raw` feature for advanced users that need to work with links or packets
+//! directly, without the stability guarantees of the public API.
+//!
 //! [Aether]: crate::peer::Aether
 //! [identity]: crate::identity
+//! [prelude]: crate::prelude
 
-pub mod acknowledgement;
 pub mod config;
-pub mod encryption;
+pub mod contacts;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod identity;
+pub mod memory;
+pub mod migration;
+pub mod peer;
+pub mod prelude;
+pub mod pubsub;
+pub mod stats;
+pub mod sync;
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod transport;
+pub mod wire;
+
+// Protocol internals, only public with the `raw` feature (refer "Stability"). Items of
+// their API only used by advanced users and tests are only compiled with it.
+#[cfg(feature = "raw")]
+pub mod acknowledgement;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod acknowledgement;
+#[cfg(feature = "raw")]
+pub mod encryption;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod encryption;
+#[cfg(feature = "raw")]
 pub mod link;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod link;
+#[cfg(feature = "raw")]
 pub mod packet;
-pub mod peer;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod packet;
+#[cfg(feature = "raw")]
+pub mod sequence;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod sequence;
+#[cfg(feature = "raw")]
 pub mod tracker;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod tracker;
+#[cfg(feature = "raw")]
 pub mod util;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod util;
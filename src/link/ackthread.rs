@@ -0,0 +1,233 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::acknowledgement::{Acknowledgement, AcknowledgementList};
+use crate::config::Config;
+use crate::link::delay::DelayEstimator;
+use crate::link::pool::BufferPool;
+use crate::link::tags::PacketTags;
+use crate::link::take_ack;
+use crate::packet::has_keepalive;
+use crate::packet::PType;
+use crate::packet::Packet;
+use crate::packet::PacketBuilder;
+use crate::sequence::Seq;
+use crate::stats::{Histograms, LinkCounters};
+use crate::sync::Mutex;
+use crate::transport::Transport;
+use crate::util::Wakeup;
+
+/// Data structure to group data used by the acknowledgement thread. The thread sends
+/// acknowledgement only packets on a timer, independent of the batch queue of the
+/// [`SendThread`][crate::link::sendthread::SendThread], so that incoming packets are
+/// acknowledged promptly even when a large backlog of outgoing packets exists
+///
+/// The thread sleeps until a packet to be acknowledged is received. Acknowledgement only
+/// packets are sent at most once every
+/// [`ack_only_time`][crate::config::LinkConfig::ack_only_time], packets received in the
+/// meantime are acknowledged together
+pub struct AckThread {
+    /// The socket used to send packets
+    socket: Arc<dyn Transport>,
+    /// Address of the other peer
+    peer_addr: SocketAddr,
+    /// Reference to the stop flag from [`crate::link::Link`]
+    stop_flag: Arc<Mutex<bool>>,
+    /// Notified when packets to be acknowledged are received or the link is stopped
+    wakeup: Arc<Wakeup>,
+    /// Reference to the [`AcknowledgementList`] from [`crate::link::Link`]
+    ack_list: Arc<Mutex<AcknowledgementList>>,
+    /// Reference to send sequence from [`crate::link::Link`]
+    send_seq: Arc<Mutex<Seq>>,
+    /// Reference to the batch empty flag from [`crate::link::Link`]
+    batch_empty: Arc<Mutex<bool>>,
+    /// Reference to the [`DelayEstimator`] from [`crate::link::Link`]
+    delay: Arc<Mutex<DelayEstimator>>,
+    /// Reference to the [`Histograms`] from [`crate::link::Link`]
+    stats: Arc<Mutex<Histograms>>,
+    /// Reference to the [`LinkCounters`] from [`crate::link::Link`]
+    counters: Arc<LinkCounters>,
+    /// Reference to the [`BufferPool`] from [`crate::link::Link`]
+    buffers: Arc<BufferPool>,
+    /// Reference to the [`PacketTags`] from [`crate::link::Link`]
+    tags: Arc<PacketTags>,
+    /// Protocol version used to communicate with the other peer
+    version: u8,
+    /// Current configuration for Aether
+    config: Config,
+}
+
+impl AckThread {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        socket: Arc<dyn Transport>,
+        peer_addr: SocketAddr,
+        stop_flag: Arc<Mutex<bool>>,
+        wakeup: Arc<Wakeup>,
+        ack_list: Arc<Mutex<AcknowledgementList>>,
+        send_seq: Arc<Mutex<Seq>>,
+        batch_empty: Arc<Mutex<bool>>,
+        delay: Arc<Mutex<DelayEstimator>>,
+        stats: Arc<Mutex<Histograms>>,
+        counters: Arc<LinkCounters>,
+        buffers: Arc<BufferPool>,
+        tags: Arc<PacketTags>,
+        version: u8,
+        config: Config,
+    ) -> AckThread {
+        AckThread {
+            socket,
+            peer_addr,
+            stop_flag,
+            wakeup,
+            ack_list,
+            send_seq,
+            batch_empty,
+            delay,
+            stats,
+            counters,
+            buffers,
+            tags,
+            version,
+            config,
+        }
+    }
+
+    pub fn start(&self) {
+        let keepalive = self.config.link.keepalive_interval > 0 && has_keepalive(self.version);
+        let keepalive_interval = Duration::from_millis(self.config.link.keepalive_interval);
+        let ack_only_time = Duration::from_millis(self.config.link.ack_only_time);
+        let mut last_keepalive = Instant::now();
+        // Time the last acknowledgement only packet was sent
+        let mut last_ack: Option<Instant> = None;
+        // Time at which the acknowledgements waiting to be sent are due
+        let mut ack_due: Option<Instant> = None;
+        // Packets needing acknowledgement sent as of the last wakeup
+        let mut reliable_sent = self.counters.reliable_sent();
+
+        loop {
+            let now = Instant::now();
+            let timeout = match ack_due {
+                Some(due) => due.saturating_duration_since(now),
+                None if keepalive => {
+                    (last_keepalive + keepalive_interval).saturating_duration_since(now)
+                }
+                None => ack_only_time,
+            };
+            self.wakeup.wait_timeout(timeout);
+
+            // If stop flag is set stop the thread
+            let flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
+            if *flag_lock {
+                break;
+            }
+
+            drop(flag_lock);
+
+            let empty_lock = self.batch_empty.lock().expect("Unable to lock empty bool");
+            let idle = *empty_lock;
+            drop(empty_lock);
+
+            let ack_lock = self.ack_list.lock().expect("Unable to lock ack list");
+            let pending = (*ack_lock).is_pending();
+            drop(ack_lock);
+
+            let sent = self.counters.reliable_sent();
+            let sending = sent != reliable_sent;
+            reliable_sent = sent;
+
+            if pending {
+                let now = Instant::now();
+                let due = *ack_due.get_or_insert_with(|| match last_ack {
+                    // Packets sent by the send thread carry the acknowledgements, so
+                    // they are only sent on their own if none is sent for a while
+                    _ if sending => now + ack_only_time,
+                    Some(last_ack) => now.max(last_ack + ack_only_time),
+                    None => now,
+                });
+                if now < due {
+                    continue;
+                }
+            }
+            // Acknowledgements may have been sent along with other packets instead
+            ack_due = None;
+
+            if !pending && idle && keepalive {
+                // Keep the link (and NAT bindings) alive when there is nothing to send
+                if last_keepalive.elapsed() >= keepalive_interval {
+                    last_keepalive = Instant::now();
+                    let packet = PacketBuilder::new(PType::Keepalive)
+                        .build()
+                        .expect("Invalid keepalive packet");
+                    self.send(packet);
+                }
+                continue;
+            }
+
+            // Without keepalives, acknowledgement only packets are sent when there is
+            // nothing else to send, so the other peer knows the link is still alive
+            if !pending && !idle {
+                // Packets being sent keep the link alive as well
+                last_keepalive = Instant::now();
+                continue;
+            }
+
+            let ack = take_ack(&self.ack_list, &self.delay, &self.stats)
+                .expect("Unable to take acknowledgements");
+            let packet = self.ack_packet(ack);
+            if self.send(packet) {
+                self.counters.ack_only();
+            }
+            last_ack = Some(Instant::now());
+        }
+    }
+
+    pub fn ack_packet(&self, ack: Acknowledgement) -> Packet {
+        // Lock seq number
+        let seq_lock = self.send_seq.lock().expect("Unable to lock seq");
+
+        let seq: Seq = *seq_lock;
+
+        // Create a new packet to be sent
+        PacketBuilder::new(PType::AckOnly)
+            .sequence(seq)
+            .ack(ack)
+            .build()
+            .expect("Invalid acknowledgement only packet")
+    }
+
+    /// Send `packet` to the other peer. Returns false if it could not be sent, which
+    /// stops the link
+    pub fn send(&self, mut packet: Packet) -> bool {
+        packet.version = self.version;
+        let mut data = self.buffers.take();
+        self.tags.compile_into(&packet, &mut data);
+
+        let size = loop {
+            match self.socket.send_to(&data, self.peer_addr) {
+                Ok(size) => break size,
+                Err(err) => match err.kind() {
+                    ErrorKind::PermissionDenied => continue,
+                    _ => {
+                        // Same as the send thread, the link is broken
+                        warn!("Unable to send acknowledgement, stopping link: {}", err);
+                        let mut flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
+                        *flag_lock = true;
+                        return false;
+                    }
+                },
+            }
+        };
+
+        let mut stats_lock = self.stats.lock().expect("Unable to lock stats");
+        stats_lock.packet_size.record(size as u64);
+        drop(stats_lock);
+
+        self.counters.sent(size, false);
+        true
+    }
+}
@@ -0,0 +1,149 @@
+//! Congestion control for a [`Link`][crate::link::Link].
+//!
+//! The controller decides how many packets the send thread fetches into a single
+//! window. The window starts at [`LinkConfig::window_size`][crate::config::LinkConfig]
+//! and grows after every window that was delivered without retransmissions, doubling
+//! until the slow start threshold and by one packet afterwards.
+//!
+//! The window is halved when packets of a window had to be retransmitted or when
+//! the other peer signals congestion by setting the congestion experienced flag in
+//! its acknowledgements (its receive queues crossed a threshold). Congestion signals
+//! are acted upon at most once per round trip.
+
+use crate::sequence::Seq;
+
+/// Data structure to keep track of the congestion window of a link
+#[derive(Debug)]
+pub struct CongestionController {
+    /// Current congestion window (number of packets per window)
+    window: u16,
+    /// Largest window the controller is allowed to grow to
+    max_window: u16,
+    /// Window below which the window is doubled instead of increased by one
+    slow_start_threshold: u16,
+    /// Set if packets of the current window had to be retransmitted
+    loss: bool,
+    /// Sequence number sent last when the window was reduced. No further reduction
+    /// happens until it has been acknowledged
+    recovery_seq: Option<Seq>,
+}
+
+impl CongestionController {
+    /// Creates a new [`CongestionController`]
+    ///
+    /// # Arguments
+    ///
+    /// * `initial_window`  -   Window to start with
+    /// * `max_window`  -   Largest window to be used
+    pub fn new(initial_window: u16, max_window: u16) -> CongestionController {
+        let max_window = max_window.max(1);
+        CongestionController {
+            window: initial_window.clamp(1, max_window),
+            max_window,
+            slow_start_threshold: max_window,
+            loss: false,
+            recovery_seq: None,
+        }
+    }
+
+    /// Returns the current congestion window
+    pub fn window(&self) -> u16 {
+        self.window
+    }
+
+    /// Called when the other peer signals that it experienced congestion
+    ///
+    /// # Arguments
+    ///
+    /// * `ack_begin`   -   `ack_begin` of the acknowledgement which carried the signal
+    /// * `send_seq`    -   Sequence number of the latest packet sent
+    pub fn on_congestion(&mut self, ack_begin: Seq, send_seq: Seq) {
+        // Only react once per round trip
+        if let Some(seq) = self.recovery_seq {
+            if ack_begin < seq {
+                return;
+            }
+        }
+
+        self.reduce();
+        self.recovery_seq = Some(send_seq);
+    }
+
+    /// Called when packets of the current window are about to be retransmitted
+    pub fn on_retransmit(&mut self) {
+        self.loss = true;
+    }
+
+    /// Called when a window of packets has been sent and acknowledged completely.
+    /// Shrinks the window if the window had retransmissions and grows it otherwise
+    pub fn on_window_complete(&mut self) {
+        if self.loss {
+            self.loss = false;
+            self.reduce();
+        } else if self.window < self.slow_start_threshold {
+            self.window = self
+                .window
+                .saturating_mul(2)
+                .min(self.slow_start_threshold)
+                .min(self.max_window);
+        } else if self.window < self.max_window {
+            self.window += 1;
+        }
+    }
+
+    /// Halve the window and stop slow start at the new window
+    fn reduce(&mut self) {
+        self.window = (self.window / 2).max(1);
+        self.slow_start_threshold = self.window;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CongestionController;
+    use crate::sequence::Seq;
+
+    #[test]
+    fn congestion_test() {
+        let mut controller = CongestionController::new(20, 20);
+
+        controller.on_congestion(Seq(100), Seq(120));
+        assert_eq!(controller.window(), 10);
+
+        // Still within the same round trip
+        controller.on_congestion(Seq(110), Seq(125));
+        assert_eq!(controller.window(), 10);
+
+        controller.on_congestion(Seq(120), Seq(130));
+        assert_eq!(controller.window(), 5);
+
+        for _ in 0..30 {
+            controller.on_window_complete();
+        }
+        assert_eq!(controller.window(), 20);
+    }
+
+    #[test]
+    fn adaptive_window_test() {
+        let mut controller = CongestionController::new(20, 1000);
+
+        // Slow start doubles the window
+        controller.on_window_complete();
+        assert_eq!(controller.window(), 40);
+        controller.on_window_complete();
+        assert_eq!(controller.window(), 80);
+
+        // Retransmissions halve the window and end slow start
+        controller.on_retransmit();
+        controller.on_window_complete();
+        assert_eq!(controller.window(), 40);
+
+        controller.on_window_complete();
+        assert_eq!(controller.window(), 41);
+
+        for _ in 0..2000 {
+            controller.on_window_complete();
+        }
+        assert_eq!(controller.window(), 1000);
+    }
+}
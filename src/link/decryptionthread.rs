@@ -1,17 +1,24 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
+use std::{convert::TryFrom, sync::Arc, time::Duration};
 
 use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
 
-use crate::{config::Config, encryption::AetherCipher, error::AetherError, packet::Packet};
+use crate::{
+    config::Config,
+    encryption::{AetherCipher, Encrypted},
+    error::AetherError,
+    packet::Packet,
+    stats::LinkCounters,
+    sync::Mutex,
+    telemetry::{limited, LogLimiter},
+};
 
 pub struct DecryptionThread {
     cipher: AetherCipher,
     receiver: Receiver<Packet>,
     sender: Sender<Packet>,
     stop_flag: Arc<Mutex<bool>>,
+    counters: Arc<LinkCounters>,
+    log_limiter: LogLimiter,
     config: Config,
 }
 
@@ -21,6 +28,7 @@ impl DecryptionThread {
         receiver: Receiver<Packet>,
         sender: Sender<Packet>,
         stop_flag: Arc<Mutex<bool>>,
+        counters: Arc<LinkCounters>,
         config: Config,
     ) -> DecryptionThread {
         DecryptionThread {
@@ -28,34 +36,119 @@ impl DecryptionThread {
             receiver,
             sender,
             stop_flag,
+            counters,
+            log_limiter: LogLimiter::new(config.telemetry),
             config,
         }
     }
-    pub fn start(&self) -> Result<(), AetherError> {
+    pub fn start(&mut self) -> Result<(), AetherError> {
+        // Received packets wake the thread up, the timeout only bounds how long it takes
+        // to notice that the link was stopped
+        let stop_poll_time = Duration::from_millis(self.config.link.ack_only_time);
+
         loop {
-            match self
-                .receiver
-                .recv_timeout(Duration::from_micros(self.config.link.poll_time_us))
-            {
-                Ok(mut packet) => {
-                    let encrypted = packet.payload;
-                    let decrypted = self.cipher.decrypt_bytes(encrypted.into())?;
-                    packet.payload = decrypted;
-                    packet.set_enc(false);
-                    self.sender.send(packet)?;
-                }
+            match self.receiver.recv_timeout(stop_poll_time) {
+                Ok(packet) => self.decrypt(packet)?,
                 Err(RecvTimeoutError::Timeout) => {}
-                Err(err) => {
-                    return Err(AetherError::from(err));
-                }
+                // The receive thread stopped, stopping this thread disconnects the
+                // output queue as well
+                Err(RecvTimeoutError::Disconnected) => break,
             };
 
             let flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
             if *flag_lock {
+                drop(flag_lock);
+
+                // Packets received until the receive thread stops are still delivered
+                while let Ok(packet) = self.receiver.recv() {
+                    self.decrypt(packet)?;
+                }
                 break;
             }
         }
 
         Ok(())
     }
+
+    /// Decrypt `packet` and push it onto the output queue. Packets that cannot be
+    /// decrypted, as when they were altered or sent by someone else on links without
+    /// [`has_packet_tags`][crate::packet::has_packet_tags], are dropped and counted in
+    /// [`LinkStats::forgeries_dropped`][crate::stats::LinkStats::forgeries_dropped]
+    fn decrypt(&mut self, mut packet: Packet) -> Result<(), AetherError> {
+        let decrypted = Encrypted::try_from(packet.payload)
+            .map_err(AetherError::from)
+            .and_then(|encrypted| self.cipher.decrypt_bytes(encrypted));
+        match decrypted {
+            Ok(decrypted) => {
+                packet.payload = decrypted;
+                packet.set_enc(false);
+                self.sender.send(packet)?;
+            }
+            Err(err) => {
+                self.counters.forgery();
+                limited!(
+                    warn,
+                    self.log_limiter,
+                    "Dropping packet that cannot be decrypted: {}",
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam::channel::unbounded;
+
+    use crate::config::Config;
+    use crate::encryption::{AetherCipher, KEY_SIZE};
+    use crate::link::decryptionthread::DecryptionThread;
+    use crate::packet::{PType, Packet, PacketBuilder};
+    use crate::stats::LinkCounters;
+    use crate::sync::Mutex;
+    use crate::util::gen_nonce;
+
+    fn encrypted_packet(payload: Vec<u8>) -> Packet {
+        PacketBuilder::new(PType::Data)
+            .encrypted(true)
+            .payload(payload)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn undecryptable_test() {
+        let cipher = AetherCipher::new(gen_nonce(KEY_SIZE));
+        let (input, receiver) = unbounded();
+        let (sender, output) = unbounded();
+        let counters = Arc::new(LinkCounters::new());
+        let stop_flag = Arc::new(Mutex::new("link.stop_flag", false));
+        let mut thread = DecryptionThread::new(
+            cipher.clone(),
+            receiver,
+            sender,
+            stop_flag,
+            counters.clone(),
+            Config::default(),
+        );
+
+        // Payloads too short to be encrypted and garbage are dropped, the thread keeps
+        // decrypting the packets after them
+        input.send(encrypted_packet(vec![1, 2, 3])).unwrap();
+        input.send(encrypted_packet(gen_nonce(64))).unwrap();
+        let valid = cipher.encrypt_bytes(b"Hello".to_vec()).unwrap();
+        input.send(encrypted_packet(valid.into())).unwrap();
+        drop(input);
+
+        thread.start().unwrap();
+        let packet = output.try_recv().unwrap();
+        assert_eq!(packet.payload, b"Hello".to_vec());
+        assert!(!packet.flags.enc);
+        assert!(output.try_recv().is_err());
+        assert_eq!(counters.snapshot(0).forgeries_dropped, 2);
+    }
 }
@@ -0,0 +1,177 @@
+//! Estimation of round trip time and one-way delay from the receive timestamps
+//! carried in acknowledgements.
+//!
+//! The clocks of both peers are not synchronized, so the absolute one-way delay
+//! cannot be known. Instead, the smallest one-way sample seen so far is used as the
+//! base delay (propagation delay plus clock offset) and the difference from it is
+//! reported as the queueing delay.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::acknowledgement::Acknowledgement;
+use crate::sequence::Seq;
+
+/// Weight of a new sample in the smoothed round trip time (1/8 as in RFC 6298)
+const RTT_GAIN: f64 = 0.125;
+
+/// Snapshot of the delay estimates for a [`Link`][crate::link::Link]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DelayEstimate {
+    /// Smoothed round trip time (in us)
+    pub rtt_us: u64,
+    /// Latest one-way delay sample including the clock offset between the peers (in us)
+    pub one_way_delay_us: i64,
+    /// Queueing delay above the smallest one-way delay observed (in us)
+    pub queueing_delay_us: u64,
+}
+
+/// Data structure to keep track of send times of packets and derive delay estimates
+/// from the acknowledgements received
+#[derive(Debug)]
+pub struct DelayEstimator {
+    /// Start of the link, all timestamps are relative to this
+    epoch: Instant,
+    /// Time at which each unacknowledged packet was last sent (in us)
+    send_times: HashMap<Seq, u32>,
+    /// Smallest one-way delay sample observed
+    base_one_way_us: Option<i64>,
+    /// Current estimates
+    estimate: DelayEstimate,
+}
+
+impl DelayEstimator {
+    /// Creates a new [`DelayEstimator`] with the current time as its epoch
+    pub fn new() -> DelayEstimator {
+        DelayEstimator {
+            epoch: Instant::now(),
+            send_times: HashMap::new(),
+            base_one_way_us: None,
+            estimate: DelayEstimate::default(),
+        }
+    }
+
+    /// Current time in microseconds relative to the epoch. Wraps around after
+    /// roughly 71 minutes, which is fine since only differences are used
+    pub fn now_us(&self) -> u32 {
+        self.epoch.elapsed().as_micros() as u32
+    }
+
+    /// Record that the packet with the given sequence number has been sent
+    pub fn on_send(&mut self, sequence: Seq) {
+        let now = self.now_us();
+        self.send_times.insert(sequence, now);
+    }
+
+    /// Update the estimates from an acknowledgement received from the other peer
+    ///
+    /// # Arguments
+    ///
+    /// * `ack` -   The [`Acknowledgement`] received
+    /// * `timestamps`  -   If the acknowledgement carries receive timestamps
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` -   The round trip time sample (in us) if the acknowledgement
+    ///   provided one
+    pub fn on_ack(&mut self, ack: &Acknowledgement, timestamps: bool) -> Option<u64> {
+        let latest = ack.window().last();
+        let mut sample = None;
+
+        // Each packet only provides a single sample
+        if let Some(sent_us) = self.send_times.remove(&latest) {
+            let rtt = self.now_us().wrapping_sub(sent_us) as f64;
+            sample = Some(rtt as u64);
+            self.estimate.rtt_us = if self.estimate.rtt_us == 0 {
+                rtt as u64
+            } else {
+                ((1.0 - RTT_GAIN) * self.estimate.rtt_us as f64 + RTT_GAIN * rtt) as u64
+            };
+
+            // A receive time of 0 means the acknowledgement was cut short and
+            // carries no receive time
+            if timestamps && ack.recv_time_us != 0 {
+                let one_way = ack.recv_time_us.wrapping_sub(sent_us) as i32 as i64;
+                let base = match self.base_one_way_us {
+                    Some(base) if base <= one_way => base,
+                    _ => one_way,
+                };
+
+                self.base_one_way_us = Some(base);
+                self.estimate.one_way_delay_us = one_way;
+                self.estimate.queueing_delay_us = (one_way - base) as u64;
+            }
+        }
+
+        // Send times of packets that have been acknowledged are not needed anymore
+        let ack_begin = ack.ack_begin;
+        self.send_times.retain(|sequence, _| *sequence >= ack_begin);
+
+        sample
+    }
+
+    /// Returns the current [`DelayEstimate`]
+    pub fn estimate(&self) -> DelayEstimate {
+        self.estimate
+    }
+
+    /// Time after which an unacknowledged packet is considered lost: twice the
+    /// smoothed round trip time, but never less than `min`
+    pub fn retransmit_timeout(&self, min: Duration) -> Duration {
+        Duration::from_micros(2 * self.estimate.rtt_us).max(min)
+    }
+}
+
+impl Default for DelayEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::acknowledgement::AcknowledgementList;
+    use crate::sequence::Seq;
+
+    use std::time::Duration;
+
+    use super::DelayEstimator;
+
+    #[test]
+    fn queueing_delay_test() {
+        let mut estimator = DelayEstimator::new();
+
+        estimator.on_send(Seq(11));
+        estimator.on_send(Seq(12));
+
+        let mut ack_list = AcknowledgementList::new(Seq(10));
+        ack_list.insert_with_time(Seq(11), 5_000);
+        estimator.on_ack(&ack_list.get(), true);
+
+        // Second packet took 2ms longer to arrive
+        ack_list.insert_with_time(Seq(12), 7_000);
+        estimator.on_ack(&ack_list.get(), true);
+
+        let estimate = estimator.estimate();
+        assert!(estimate.queueing_delay_us >= 1_000);
+        assert!(estimate.one_way_delay_us > 0);
+    }
+
+    #[test]
+    fn retransmit_timeout_test() {
+        let mut estimator = DelayEstimator::new();
+        let min = Duration::from_millis(100);
+
+        // No round trip time sample yet
+        assert_eq!(estimator.retransmit_timeout(min), min);
+
+        estimator.estimate.rtt_us = 300_000;
+        assert_eq!(
+            estimator.retransmit_timeout(min),
+            Duration::from_millis(600)
+        );
+
+        estimator.estimate.rtt_us = 1_000;
+        assert_eq!(estimator.retransmit_timeout(min), min);
+    }
+}
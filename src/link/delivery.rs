@@ -0,0 +1,335 @@
+//! Delivery receipts of messages sent on a [`Link`][super::Link].
+//!
+//! A message is delivered once the other peer acknowledged the packets carrying it,
+//! which it does as soon as they are received, before the application reads it.
+//!
+//! Messages can be cancelled until then (refer [`Link::cancel`][super::Link::cancel]),
+//! such as when a user aborts sending a file. The packets of a cancelled message are
+//! replaced by [`PType::Expired`][crate::packet::PType::Expired] packets the same way
+//! as packets whose deadline passed, and the other peer drops the parts of the message
+//! it received.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::acknowledgement::AcknowledgementCheck;
+use crate::error::AetherError;
+use crate::sequence::Seq;
+use crate::sync::Mutex;
+
+/// Number identifying the next [`Cancellations`], and thereby the link they belong to
+static NEXT_LINK: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a message sent with [`Link::send_tracked`][super::Link::send_tracked] on
+/// its link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    /// Number of the link the message was sent on
+    link: u64,
+    /// Sequence number of the first packet carrying the message
+    seq: Seq,
+    /// Number of packets carrying the message, more than one if it was split into
+    /// fragments
+    packets: u32,
+}
+
+impl MessageId {
+    /// Returns the sequence numbers of the packets carrying the message
+    fn sequences(&self) -> impl Iterator<Item = Seq> {
+        let seq = self.seq;
+        (0..self.packets).map(move |offset| seq + offset)
+    }
+}
+
+/// Messages cancelled on a [`Link`][super::Link]
+#[derive(Debug)]
+pub struct Cancellations {
+    /// Number of the link, messages sent on other links are not cancelled on this one
+    link: u64,
+    /// Sequence numbers of the packets the send thread has not replaced yet
+    pending: HashSet<Seq>,
+    /// Messages that were cancelled
+    messages: HashSet<MessageId>,
+}
+
+impl Cancellations {
+    /// Creates a new [`Cancellations`] without any cancelled messages
+    pub fn new() -> Cancellations {
+        Cancellations {
+            link: NEXT_LINK.fetch_add(1, Ordering::Relaxed),
+            pending: HashSet::new(),
+            messages: HashSet::new(),
+        }
+    }
+
+    /// Returns the [`MessageId`] of the message carried by `packets` packets from `seq`
+    /// on the link
+    pub fn message(&self, seq: Seq, packets: u32) -> MessageId {
+        MessageId {
+            link: self.link,
+            seq,
+            packets,
+        }
+    }
+
+    /// Cancel the message `id`, unless it was sent on another link or every packet
+    /// carrying it was acknowledged according to `ack_check`. Returns false if it was
+    pub fn cancel(&mut self, id: MessageId, ack_check: &AcknowledgementCheck) -> bool {
+        if id.link != self.link || id.sequences().all(|seq| ack_check.check(&seq)) {
+            return false;
+        }
+
+        // Packets acknowledged before the send thread replaced them are not sent again
+        self.pending.retain(|seq| !ack_check.check(seq));
+        self.pending
+            .extend(id.sequences().filter(|seq| !ack_check.check(seq)));
+        self.messages.insert(id);
+        true
+    }
+
+    /// Returns true if the packet `seq` carries a cancelled message and has to be
+    /// replaced, which the caller does
+    pub fn take(&mut self, seq: Seq) -> bool {
+        self.pending.remove(&seq)
+    }
+
+    /// Returns true if the message `id` was cancelled
+    pub fn is_cancelled(&self, id: &MessageId) -> bool {
+        self.messages.contains(id)
+    }
+}
+
+impl Default for Cancellations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle of a message sent with [`Link::send_tracked`][super::Link::send_tracked],
+/// telling whether the other peer received it
+#[derive(Debug, Clone)]
+pub struct DeliveryHandle {
+    /// Message the handle is for
+    id: MessageId,
+    /// Acknowledgements received by the link
+    ack_check: Arc<Mutex<AcknowledgementCheck>>,
+    /// Messages cancelled on the link
+    cancellations: Arc<Mutex<Cancellations>>,
+    /// Stop flag of the link
+    stop_flag: Arc<Mutex<bool>>,
+    /// Time to wait between checks while waiting for the acknowledgement
+    poll_time: Duration,
+}
+
+impl DeliveryHandle {
+    pub(crate) fn new(
+        id: MessageId,
+        ack_check: Arc<Mutex<AcknowledgementCheck>>,
+        cancellations: Arc<Mutex<Cancellations>>,
+        stop_flag: Arc<Mutex<bool>>,
+        poll_time: Duration,
+    ) -> DeliveryHandle {
+        DeliveryHandle {
+            id,
+            ack_check,
+            cancellations,
+            stop_flag,
+            poll_time,
+        }
+    }
+
+    /// Returns the sequence number of the first packet carrying the message
+    pub fn sequence(&self) -> Seq {
+        self.id.seq
+    }
+
+    /// Returns the [`MessageId`] of the message, for example to cancel it
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Returns true if the other peer acknowledged the message. Cancelled messages are
+    /// never delivered
+    pub fn is_delivered(&self) -> Result<bool, AetherError> {
+        if self.is_cancelled()? {
+            return Ok(false);
+        }
+
+        match self.ack_check.lock() {
+            Ok(check_lock) => Ok(self.id.sequences().all(|seq| (*check_lock).check(&seq))),
+            Err(_) => Err(AetherError::MutexLock("ack check")),
+        }
+    }
+
+    /// Returns true if the message was cancelled before it was delivered
+    pub fn is_cancelled(&self) -> Result<bool, AetherError> {
+        match self.cancellations.lock() {
+            Ok(cancellations_lock) => Ok((*cancellations_lock).is_cancelled(&self.id)),
+            Err(_) => Err(AetherError::MutexLock("cancellations")),
+        }
+    }
+
+    /// Wait until the other peer acknowledged the message
+    /// # Errors
+    /// * [`AetherError::LinkStopped`] - The link stopped before the message was
+    ///   acknowledged, it may or may not have been received
+    /// * [`AetherError::MessageCancelled`] - The message was cancelled
+    pub fn wait(&self) -> Result<(), AetherError> {
+        while !self.is_delivered()? {
+            self.check_stopped()?;
+            thread::sleep(self.poll_time);
+        }
+        Ok(())
+    }
+
+    /// Wait until the other peer acknowledged the message, at most `timeout`. Returns
+    /// false if it was not acknowledged in time
+    /// # Errors
+    /// * [`AetherError::LinkStopped`] - The link stopped before the message was
+    ///   acknowledged, it may or may not have been received
+    /// * [`AetherError::MessageCancelled`] - The message was cancelled
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<bool, AetherError> {
+        let start = Instant::now();
+        while !self.is_delivered()? {
+            self.check_stopped()?;
+            if start.elapsed() >= timeout {
+                return Ok(false);
+            }
+            thread::sleep(self.poll_time);
+        }
+        Ok(true)
+    }
+
+    fn check_stopped(&self) -> Result<(), AetherError> {
+        if self.is_cancelled()? {
+            return Err(AetherError::MessageCancelled(self.id));
+        }
+
+        match self.stop_flag.lock() {
+            Ok(stop_lock) if *stop_lock => Err(AetherError::LinkStopped("delivery")),
+            Ok(_) => Ok(()),
+            Err(_) => Err(AetherError::MutexLock("stop flag")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Cancellations, DeliveryHandle};
+    use crate::acknowledgement::AcknowledgementCheck;
+    use crate::error::AetherError;
+    use crate::sequence::Seq;
+    use crate::sync::Mutex;
+
+    #[test]
+    fn delivery_test() {
+        let ack_check = Arc::new(Mutex::new("ack_check", AcknowledgementCheck::new(Seq(10))));
+        let cancellations = Arc::new(Mutex::new("cancellations", Cancellations::new()));
+        let stop_flag = Arc::new(Mutex::new("stop_flag", false));
+        let handle = |seq| {
+            DeliveryHandle::new(
+                cancellations.lock().unwrap().message(Seq(seq), 1),
+                ack_check.clone(),
+                cancellations.clone(),
+                stop_flag.clone(),
+                Duration::from_millis(1),
+            )
+        };
+
+        assert!(handle(10).is_delivered().unwrap());
+        let pending = handle(12);
+        assert!(!pending.is_delivered().unwrap());
+        assert!(!pending.wait_timeout(Duration::from_millis(5)).unwrap());
+
+        ack_check.lock().unwrap().insert(Seq(12));
+        assert!(pending.is_delivered().unwrap());
+        assert!(pending.wait_timeout(Duration::from_millis(5)).unwrap());
+        assert!(!handle(11).is_delivered().unwrap());
+
+        *stop_flag.lock().unwrap() = true;
+        assert!(matches!(
+            handle(11).wait(),
+            Err(AetherError::LinkStopped(_))
+        ));
+        pending.wait().unwrap();
+
+        // Fragmented messages are delivered once every fragment is acknowledged
+        let fragmented = DeliveryHandle::new(
+            cancellations.lock().unwrap().message(Seq(12), 3),
+            ack_check.clone(),
+            cancellations.clone(),
+            stop_flag.clone(),
+            Duration::from_millis(1),
+        );
+        ack_check.lock().unwrap().insert(Seq(14));
+        assert!(!fragmented.is_delivered().unwrap());
+        ack_check.lock().unwrap().insert(Seq(13));
+        assert!(fragmented.is_delivered().unwrap());
+    }
+
+    #[test]
+    fn cancel_test() {
+        let ack_check = Arc::new(Mutex::new("ack_check", AcknowledgementCheck::new(Seq(10))));
+        let cancellations = Arc::new(Mutex::new("cancellations", Cancellations::new()));
+        let stop_flag = Arc::new(Mutex::new("stop_flag", false));
+        let handle = |seq, packets| {
+            DeliveryHandle::new(
+                cancellations.lock().unwrap().message(Seq(seq), packets),
+                ack_check.clone(),
+                cancellations.clone(),
+                stop_flag.clone(),
+                Duration::from_millis(1),
+            )
+        };
+
+        // Delivered messages cannot be cancelled
+        let delivered = handle(10, 1);
+        let check_lock = ack_check.lock().unwrap();
+        assert!(!cancellations
+            .lock()
+            .unwrap()
+            .cancel(delivered.id(), &check_lock));
+        drop(check_lock);
+        assert!(!delivered.is_cancelled().unwrap());
+        assert!(delivered.is_delivered().unwrap());
+
+        // Only the packets not acknowledged yet are replaced
+        let pending = handle(11, 3);
+        ack_check.lock().unwrap().insert(Seq(12));
+        let check_lock = ack_check.lock().unwrap();
+        assert!(cancellations
+            .lock()
+            .unwrap()
+            .cancel(pending.id(), &check_lock));
+        drop(check_lock);
+        {
+            let mut cancellations_lock = cancellations.lock().unwrap();
+            assert!(cancellations_lock.take(Seq(11)));
+            assert!(!cancellations_lock.take(Seq(11)));
+            assert!(!cancellations_lock.take(Seq(12)));
+            assert!(cancellations_lock.take(Seq(13)));
+        }
+
+        // Cancelled messages are never delivered
+        ack_check.lock().unwrap().insert(Seq(11));
+        ack_check.lock().unwrap().insert(Seq(13));
+        assert!(pending.is_cancelled().unwrap());
+        assert!(!pending.is_delivered().unwrap());
+        assert!(matches!(
+            pending.wait(),
+            Err(AetherError::MessageCancelled(id)) if id == pending.id()
+        ));
+        assert!(!handle(11, 1).is_cancelled().unwrap());
+
+        // Messages of other links are not cancelled
+        let other = Cancellations::new().message(Seq(14), 1);
+        let check_lock = ack_check.lock().unwrap();
+        assert!(!cancellations.lock().unwrap().cancel(other, &check_lock));
+    }
+}
@@ -0,0 +1,237 @@
+//! Control packets defined by applications.
+//!
+//! Protocols built on Aether can define their own control frames as
+//! [`PType::Extended`] packets, told apart by a subtype of their choice. They are sent
+//! with [`Link::send_extended`] and delivered reliably and in order like messages,
+//! encrypted once the link is. Instead of being returned by [`Link::recv`], each
+//! packet is passed to the handler registered for its subtype with
+//! [`Link::register_extension`]. Packets of subtypes without a handler are dropped.
+//!
+//! Handlers are called on the receive thread of the link, so they should return
+//! quickly. They are called without holding the lock of the registered handlers, so
+//! they may register or unregister handlers of the same link.
+//!
+//! Extended packets are only exchanged with peers supporting them (refer
+//! [`has_extensions`][crate::packet::has_extensions]).
+//!
+//! The subtypes in [`RESERVED_SUBTYPES`] carry the frames of the library itself (refer
+//! [`crate::peer::frames`]). No handler can be registered for them, their packets are
+//! received in order with messages from
+//! [`Link::get_receiver`][crate::link::Link::get_receiver] instead.
+//!
+//! [`Link::send_extended`]: crate::link::Link::send_extended
+//! [`Link::recv`]: crate::link::Link::recv
+//! [`Link::register_extension`]: crate::link::Link::register_extension
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::encryption::{AetherCipher, Encrypted};
+use crate::error::{AetherError, PacketError};
+use crate::packet::{PType, Packet};
+use crate::sync::Mutex;
+
+/// Subtypes of the extended packets reserved for the frames of the library (refer
+/// [`has_reserved_subtypes`][crate::packet::has_reserved_subtypes])
+pub const RESERVED_SUBTYPES: RangeInclusive<u8> = 0xF0..=0xFF;
+
+/// Check if `subtype` is one of the [`RESERVED_SUBTYPES`]
+pub fn is_reserved(subtype: u8) -> bool {
+    RESERVED_SUBTYPES.contains(&subtype)
+}
+
+/// Handler of the extended packets of a subtype, called with the payload of every
+/// packet received
+pub type ExtensionHandler = Box<dyn FnMut(Vec<u8>) + Send>;
+
+/// Extended packet routed to the handler of its subtype (refer [`Extensions::route`])
+pub struct Routed {
+    handler: Arc<Mutex<ExtensionHandler>>,
+    payload: Vec<u8>,
+}
+
+impl Routed {
+    /// Call the handler with the payload of the packet. Call it after releasing the lock
+    /// of the [`Extensions`] the packet was routed with, so that the handler cannot
+    /// block registering other handlers
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - If the handler panicked before
+    pub fn deliver(self) -> Result<(), AetherError> {
+        match self.handler.lock() {
+            Ok(mut handler) => {
+                (*handler)(self.payload);
+                Ok(())
+            }
+            Err(_) => Err(AetherError::MutexLock("extension handler")),
+        }
+    }
+}
+
+/// Handlers registered on a [`Link`][crate::link::Link] by the subtype they handle
+#[derive(Default)]
+pub struct Extensions {
+    handlers: HashMap<u8, Arc<Mutex<ExtensionHandler>>>,
+    /// Cipher of the link once encryption is enabled, to decrypt payloads
+    cipher: Option<AetherCipher>,
+}
+
+impl Extensions {
+    /// Creates a new [`Extensions`] without any handlers
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Register `handler` for the packets of `subtype`
+    /// # Errors
+    /// * [`AetherError::ExtensionTaken`] - If a handler is already registered for
+    ///   `subtype` or it is reserved (refer [`RESERVED_SUBTYPES`])
+    pub fn register(&mut self, subtype: u8, handler: ExtensionHandler) -> Result<(), AetherError> {
+        if is_reserved(subtype) || self.handlers.contains_key(&subtype) {
+            return Err(AetherError::ExtensionTaken(subtype));
+        }
+
+        self.handlers.insert(
+            subtype,
+            Arc::new(Mutex::new("link.extension_handler", handler)),
+        );
+        Ok(())
+    }
+
+    /// Remove the handler of `subtype`. Returns false if there was none
+    pub fn unregister(&mut self, subtype: u8) -> bool {
+        self.handlers.remove(&subtype).is_some()
+    }
+
+    /// Check if a handler is registered for `subtype`
+    #[cfg(any(test, feature = "raw"))]
+    pub fn is_registered(&self, subtype: u8) -> bool {
+        self.handlers.contains_key(&subtype)
+    }
+
+    /// Set the cipher encrypted payloads are decrypted with
+    pub fn set_cipher(&mut self, cipher: AetherCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    /// Route the payload of an extended `packet` to the handler of its subtype, to be
+    /// passed to it with [`Routed::deliver`]. Returns [`None`] if there is no handler
+    /// for it
+    /// # Errors
+    /// * [`AetherError::InvalidPacket`] - If the payload is encrypted but encryption is
+    ///   not enabled yet
+    ///
+    /// Other errors might occur when decrypting the payload (refer to [`AetherError`])
+    pub fn route(&self, packet: Packet) -> Result<Option<Routed>, AetherError> {
+        let handler = match packet.flags.p_type {
+            PType::Extended(subtype) => match self.handlers.get(&subtype) {
+                Some(handler) => handler.clone(),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let payload = if packet.flags.enc {
+            match self.cipher {
+                Some(ref cipher) => cipher.decrypt_bytes(Encrypted::try_from(packet.payload)?)?,
+                None => return Err(PacketError::InvalidEncryption.into()),
+            }
+        } else {
+            packet.payload
+        };
+
+        Ok(Some(Routed { handler, payload }))
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut subtypes: Vec<&u8> = self.handlers.keys().collect();
+        subtypes.sort();
+
+        f.debug_struct("Extensions")
+            .field("subtypes", &subtypes)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use super::{Extensions, RESERVED_SUBTYPES};
+    use crate::error::AetherError;
+    use crate::packet::{PType, Packet};
+    use crate::sequence::Seq;
+
+    #[test]
+    fn dispatch_test() {
+        let mut extensions = Extensions::new();
+        let (sender, receiver) = mpsc::channel();
+        extensions
+            .register(7, Box::new(move |payload| sender.send(payload).unwrap()))
+            .unwrap();
+        assert!(matches!(
+            extensions.register(7, Box::new(|_| ())),
+            Err(AetherError::ExtensionTaken(7))
+        ));
+
+        let mut packet = Packet::new(PType::Extended(7), Seq(1));
+        packet.append_payload(b"ping".to_vec());
+        extensions
+            .route(packet)
+            .unwrap()
+            .unwrap()
+            .deliver()
+            .unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), b"ping".to_vec());
+
+        // Other subtypes are not handled
+        let packet = Packet::new(PType::Extended(8), Seq(2));
+        assert!(extensions.route(packet).unwrap().is_none());
+
+        // Encrypted payloads cannot be read before encryption is enabled
+        let mut packet = Packet::new(PType::Extended(7), Seq(3));
+        packet.append_payload(b"ping".to_vec());
+        packet.set_enc(true);
+        assert!(extensions.route(packet).is_err());
+
+        assert!(extensions.unregister(7));
+        assert!(!extensions.is_registered(7));
+
+        // Reserved subtypes are taken by the library
+        assert!(matches!(
+            extensions.register(*RESERVED_SUBTYPES.start(), Box::new(|_| ())),
+            Err(AetherError::ExtensionTaken(_))
+        ));
+    }
+
+    #[test]
+    fn reentrant_test() {
+        let extensions = Arc::new(Mutex::new(Extensions::new()));
+        let (sender, receiver) = mpsc::channel();
+
+        // A handler unregistering itself once called
+        let handler_extensions = extensions.clone();
+        let handler = move |payload| {
+            sender.send(payload).unwrap();
+            handler_extensions.lock().unwrap().unregister(7);
+        };
+        extensions
+            .lock()
+            .unwrap()
+            .register(7, Box::new(handler))
+            .unwrap();
+
+        let mut packet = Packet::new(PType::Extended(7), Seq(1));
+        packet.append_payload(b"ping".to_vec());
+        let routed = extensions.lock().unwrap().route(packet).unwrap().unwrap();
+        routed.deliver().unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), b"ping".to_vec());
+        assert!(!extensions.lock().unwrap().is_registered(7));
+    }
+}
@@ -1,34 +1,76 @@
 //! Structure for representing a reliable [`Link`] between 2 peers.
 
+pub mod ackthread;
+pub mod congestion;
 pub mod decryptionthread;
+pub mod delay;
+pub mod delivery;
+pub mod extension;
+pub mod pool;
 pub mod receivethread;
+pub mod replay;
 pub mod sendthread;
+pub mod tags;
+pub mod worker;
 
+use std::io;
 use std::net::SocketAddr;
-use std::net::UdpSocket;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossbeam::channel::unbounded;
 use crossbeam::channel::Receiver;
+use crossbeam::channel::RecvTimeoutError;
 use crossbeam::channel::Sender;
+use crossbeam::channel::TryRecvError;
+use crossbeam::channel::{bounded, SendError, SendTimeoutError};
+use tracing::{debug, debug_span, error, info_span, Span};
 
-use crate::acknowledgement::{AcknowledgementCheck, AcknowledgementList};
+use crate::acknowledgement::{
+    Acknowledgement, AcknowledgementCheck, AcknowledgementList, MAX_WINDOW,
+};
 use crate::config::Config;
 use crate::encryption::AetherCipher;
-use crate::encryption::KEY_SIZE;
-use crate::error::AetherError;
+use crate::encryption::{CIPHER_NAME, ENCRYPTION_OVERHEAD, KEY_SIZE};
+use crate::error::{AetherError, PacketError};
 use crate::identity::Id;
 use crate::identity::PublicId;
+use crate::link::ackthread::AckThread;
+use crate::link::congestion::CongestionController;
+use crate::link::delay::{DelayEstimate, DelayEstimator};
+use crate::link::delivery::{Cancellations, DeliveryHandle, MessageId};
+use crate::link::extension::Extensions;
+use crate::link::pool::BufferPool;
 use crate::link::receivethread::ReceiveThread;
 use crate::link::sendthread::SendThread;
+use crate::link::tags::PacketTags;
+use crate::memory::{packet_memory, MemoryBudget, MemoryCharge, CIPHER_MEMORY, LINK_MEMORY};
+use crate::packet::has_close;
+use crate::packet::has_deadlines;
+use crate::packet::has_extensions;
+use crate::packet::has_fragments;
+use crate::packet::has_message_length;
+use crate::packet::has_reserved_subtypes;
+use crate::packet::max_packet_size;
+use crate::packet::Capabilities;
 use crate::packet::PType;
 use crate::packet::Packet;
+use crate::packet::PacketBuilder;
+use crate::packet::MAX_PAYLOAD_SIZE;
+use crate::packet::MESSAGE_LENGTH_SIZE;
+use crate::packet::PROTOCOL_VERSION;
+use crate::sequence::Seq;
+use crate::stats::{Histograms, LinkCounters, LinkStats};
+use crate::sync::Mutex;
+use crate::telemetry::{self, NoopTelemetry, Telemetry, TelemetryEvent, SPAN_KEY_EXCHANGE};
+use crate::transport::Transport;
+use crate::util::catch_panic;
 use crate::util::gen_nonce;
 use crate::util::xor;
+use crate::util::Wakeup;
 
 use self::decryptionthread::DecryptionThread;
 
@@ -37,11 +79,131 @@ pub fn needs_ack(packet: &Packet) -> bool {
     match packet.flags.p_type {
         PType::Data => true,
         PType::KeyExchange => true,
+        PType::Extended(_) => true,
+        PType::Expired => true,
         PType::AckOnly => false,
         _ => false,
     }
 }
 
+/// Returns `max_message_size` limited to what a link using the given protocol version
+/// can send, which is a single packet unless messages are split into fragments (refer
+/// [`has_fragments`])
+fn message_limit(max_message_size: usize, version: u8) -> usize {
+    if has_fragments(version) {
+        max_message_size
+    } else {
+        max_message_size.min(MAX_PAYLOAD_SIZE)
+    }
+}
+
+/// Take the acknowledgements waiting to be sent from `ack_list`, to be sent in a packet
+/// right away. Records how long the oldest of them waited in `stats`
+pub(crate) fn take_ack(
+    ack_list: &Mutex<AcknowledgementList>,
+    delay: &Mutex<DelayEstimator>,
+    stats: &Mutex<Histograms>,
+) -> Result<Acknowledgement, AetherError> {
+    let mut ack_lock = match ack_list.lock() {
+        Ok(lock) => lock,
+        Err(_) => return Err(AetherError::MutexLock("ack list")),
+    };
+    let pending = (*ack_lock).is_pending();
+    let ack = (*ack_lock).take();
+    drop(ack_lock);
+
+    if pending && ack.recv_time_us != 0 {
+        let now_us = match delay.lock() {
+            Ok(delay_lock) => (*delay_lock).now_us(),
+            Err(_) => return Err(AetherError::MutexLock("delay estimator")),
+        };
+
+        match stats.lock() {
+            Ok(mut stats_lock) => stats_lock
+                .ack_delay_us
+                .record(now_us.wrapping_sub(ack.recv_time_us) as u64),
+            Err(_) => return Err(AetherError::MutexLock("stats")),
+        }
+    }
+
+    Ok(ack)
+}
+
+/// Check if the other peer acknowledged every packet sent up to `send_seq`, so that no
+/// message is lost by closing the link
+pub(crate) fn is_drained(
+    send_seq: &Mutex<Seq>,
+    ack_check: &Mutex<AcknowledgementCheck>,
+) -> Result<bool, AetherError> {
+    let seq = match send_seq.lock() {
+        Ok(seq_lock) => *seq_lock,
+        Err(_) => return Err(AetherError::MutexLock("send seq")),
+    };
+
+    match ack_check.lock() {
+        Ok(check_lock) => Ok((*check_lock).check(&seq)),
+        Err(_) => Err(AetherError::MutexLock("ack check")),
+    }
+}
+
+/// Reason a [`Link`] was closed, with the numeric code reported to applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CloseReason {
+    /// Stopped locally using [`Link::stop`]
+    Stopped = 1,
+    /// The other peer could not be reached any more
+    Broken = 2,
+    /// The session granted with [`Link::set_expiry`] ran out
+    Expired = 3,
+    /// The other peer violated the protocol, such as by sending a message larger than
+    /// the maximum message size
+    ProtocolError = 4,
+    /// The other peer closed the link using [`Link::stop`]
+    Closed = 5,
+    /// A thread of the link failed, for example because a packet could not be
+    /// decrypted. Refer [`Link::failure`]
+    Failed = 6,
+}
+
+impl CloseReason {
+    /// Returns the numeric code of the reason
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Parameters of a [`Link`] as agreed on with the other peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Protocol version used by both peers
+    pub version: u8,
+    /// Name of the cipher used for end-to-end encryption, [`None`] if not enabled yet
+    pub cipher: Option<&'static str>,
+    /// Largest message that can be sent in a single packet (in bytes)
+    pub mtu: usize,
+    /// Largest message accepted by both peers (in bytes)
+    pub max_message_size: usize,
+    /// Initial congestion window (in packets)
+    pub initial_window: u16,
+    /// Current congestion window (in packets)
+    pub window: u16,
+    /// Largest congestion window, the window accepted by the other peer (in packets)
+    pub max_window: u16,
+    /// Optional protocol features available on the link
+    pub capabilities: Capabilities,
+}
+
+/// Progress of a message larger than a packet being received, whose fragments arrived
+/// in part (refer [`Link::incoming_progress`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncomingProgress {
+    /// Bytes of the message received so far
+    pub received: usize,
+    /// Size of the whole message in bytes
+    pub total: usize,
+}
+
 /// Represents a single reliable [`Link`] to another peer
 #[derive(Debug)]
 pub struct Link {
@@ -55,28 +217,86 @@ pub struct Link {
     ack_list: Arc<Mutex<AcknowledgementList>>,
     /// List of the acknowledgments received from the other peer
     ack_check: Arc<Mutex<AcknowledgementCheck>>,
-    /// UDP socket used to communicate with the other peer
-    socket: Arc<UdpSocket>,
+    /// Transport used to communicate with the other peer
+    socket: Arc<dyn Transport>,
     /// The address of the other peer
     peer_addr: SocketAddr,
     /// Queue of packets to be sent to the other peer
     primary_queue: (Sender<Packet>, Receiver<Packet>),
+    /// Held while a packet is numbered and pushed onto the primary queue
+    queue_lock: Mutex<()>,
     /// Queue of packets received from the other peer
-    receive_queue: (Sender<Packet>, Receiver<Packet>),
+    receive_queue: Receiver<Packet>,
+    /// Sending end of the receive queue until it is handed to the receive thread. Only
+    /// the threads of the link send to its queues, so the queues disconnect and
+    /// receivers unblock once the threads stop
+    receive_sender: Option<Sender<Packet>>,
     /// Queue of packets to be output
-    output_queue: (Sender<Packet>, Receiver<Packet>),
-    /// [`JoinHandle`] for threads created by [`Link`] module
-    thread_handles: Vec<JoinHandle<()>>,
+    output_queue: Receiver<Packet>,
+    /// Sending end of the output queue until it is handed to the decryption thread
+    output_sender: Option<Sender<Packet>>,
+    /// [`JoinHandle`] for threads created by [`Link`] module, locked so that the link
+    /// can be stopped while it is shared
+    thread_handles: Mutex<Vec<JoinHandle<()>>>,
     /// Sequence number for the next packet to be sent
-    send_seq: Arc<Mutex<u32>>,
+    send_seq: Arc<Mutex<Seq>>,
     /// Keeps track of sequence number of received packets [ Not used yet ]
-    recv_seq: Arc<Mutex<u32>>,
+    recv_seq: Arc<Mutex<Seq>>,
     /// Flag to indicate if the [`Link`] is currently active or not
     stop_flag: Arc<Mutex<bool>>,
     /// Flag to indicate if the batch queue is empty or not
     batch_empty: Arc<Mutex<bool>>,
+    /// Wakes the send thread up instead of it polling for work
+    send_wakeup: Arc<Wakeup>,
+    /// Wakes the acknowledgement thread up instead of it polling for work
+    ack_wakeup: Arc<Wakeup>,
     /// Timeout for receiving packets from the other peer
     read_timeout: Option<Duration>,
+    /// Protocol version used to communicate with the other peer
+    version: u8,
+    /// Largest message accepted by both peers
+    max_message_size: usize,
+    /// Largest window accepted by the other peer
+    max_window: u16,
+    /// Delay estimates derived from acknowledgements
+    delay: Arc<Mutex<DelayEstimator>>,
+    /// Congestion window used by the send thread
+    congestion: Arc<Mutex<CongestionController>>,
+    /// Histograms recorded for this link
+    stats: Arc<Mutex<Histograms>>,
+    /// Counters of the traffic on this link
+    counters: Arc<LinkCounters>,
+    /// Reason for closing the link, set by whoever stops it for a specific reason
+    close_reason: Arc<Mutex<Option<CloseReason>>>,
+    /// Error a thread of the link failed with, which closed it
+    failure: Arc<Mutex<Option<String>>>,
+    /// Set while [`Link::stop`] waits for the other peer to answer the close packets
+    closing: Arc<AtomicBool>,
+    /// Wakes [`Link::stop`] up when the other peer answered the close packets
+    close_wakeup: Arc<Wakeup>,
+    /// Sequence numbers of the packets the other peer requested again with NACK
+    /// packets, sent again by the send thread
+    nacked: Arc<Mutex<Vec<Seq>>>,
+    /// Handlers of the extended packets received from the other peer
+    extensions: Arc<Mutex<Extensions>>,
+    /// Messages cancelled with [`Link::cancel`], whose packets the send thread replaces
+    cancellations: Arc<Mutex<Cancellations>>,
+    /// Progress of the message being received, set by the receive thread
+    incoming: Arc<Mutex<Option<IncomingProgress>>>,
+    /// Buffers packets are compiled into and received into, shared by the threads
+    buffers: Arc<BufferPool>,
+    /// Key of the tags authenticating the packets of this link once it is encrypted,
+    /// shared by the threads
+    tags: Arc<PacketTags>,
+    /// Span entered by the threads of this link, a child of the span the link was
+    /// created in (such as the connection to a peer)
+    span: Span,
+    /// Receiver of the telemetry of this link
+    telemetry: Arc<dyn Telemetry>,
+    /// Budget the memory held by this link is charged to
+    memory: Arc<MemoryBudget>,
+    /// Memory charged for the state of this link, given back when it is dropped
+    state_charges: Vec<MemoryCharge>,
     /// Current configuration for Aether
     config: Config,
 }
@@ -85,22 +305,23 @@ impl Link {
     /// Creates a new [`Link`] to another peer
     /// # Arguments
     /// * `id` - [`Id`] of the user that is creating this link
-    /// * `socket` - UDP socket used to communicate with the other peer
+    /// * `socket` - [`Transport`] used to communicate with the other peer, usually a
+    ///   [`UdpSocket`][std::net::UdpSocket]
     /// * `peer_addr` - Address of the other peer
     /// * `peer_id` - Public Id of the other peer
     /// * `send_seq` - Sending Sequence number that the Link needs to be initialised with
     /// * `recv_seq` - Receiving Sequence number that the Link needs to be initialised with
     /// * `config` - Configuration for Aether
-    pub fn new(
+    pub fn new<T: Transport + 'static>(
         id: Id,
-        socket: UdpSocket,
+        socket: T,
         peer_addr: SocketAddr,
         peer_id: PublicId,
-        send_seq: u32,
-        recv_seq: u32,
+        send_seq: Seq,
+        recv_seq: Seq,
         config: Config,
     ) -> Result<Link, AetherError> {
-        let socket = Arc::new(socket);
+        let socket: Arc<dyn Transport> = Arc::new(socket);
 
         // if - let for errors
         if socket
@@ -110,85 +331,250 @@ impl Link {
             return Err(AetherError::SetReadTimeout);
         }
 
-        let primary_queue = unbounded();
-        let receive_queue = unbounded();
-        let output_queue = unbounded();
+        let primary_queue = match config.link.send_queue_size {
+            0 => unbounded(),
+            size => bounded(size),
+        };
+        let (receive_sender, receive_queue) = unbounded();
+        let (output_sender, output_queue) = unbounded();
 
-        let stop_flag = Arc::new(Mutex::new(false));
-        let batch_empty = Arc::new(Mutex::new(false));
+        let stop_flag = Arc::new(Mutex::new("link.stop_flag", false));
+        let batch_empty = Arc::new(Mutex::new("link.batch_empty", false));
         Ok(Link {
             private_id: id,
-            ack_list: Arc::new(Mutex::new(AcknowledgementList::new(recv_seq))),
-            ack_check: Arc::new(Mutex::new(AcknowledgementCheck::new(send_seq))),
+            ack_list: Arc::new(Mutex::new(
+                "link.ack_list",
+                AcknowledgementList::with_window(recv_seq, config.link.max_window),
+            )),
+            ack_check: Arc::new(Mutex::new(
+                "link.ack_check",
+                AcknowledgementCheck::new(send_seq),
+            )),
             peer_addr,
             peer_id,
             cipher: None,
             socket,
             primary_queue,
+            queue_lock: Mutex::new("link.queue", ()),
             receive_queue,
+            receive_sender: Some(receive_sender),
             output_queue,
-            send_seq: Arc::new(Mutex::new(send_seq)),
-            recv_seq: Arc::new(Mutex::new(recv_seq)),
-            thread_handles: Vec::new(),
+            output_sender: Some(output_sender),
+            send_seq: Arc::new(Mutex::new("link.send_seq", send_seq)),
+            recv_seq: Arc::new(Mutex::new("link.recv_seq", recv_seq)),
+            thread_handles: Mutex::new("link.thread_handles", Vec::new()),
             stop_flag,
             batch_empty,
+            send_wakeup: Arc::new(Wakeup::new()),
+            ack_wakeup: Arc::new(Wakeup::new()),
             read_timeout: None,
+            version: PROTOCOL_VERSION,
+            max_message_size: message_limit(config.link.max_message_size, PROTOCOL_VERSION),
+            max_window: MAX_WINDOW,
+            delay: Arc::new(Mutex::new("link.delay", DelayEstimator::new())),
+            congestion: Arc::new(Mutex::new(
+                "link.congestion",
+                CongestionController::new(config.link.window_size, MAX_WINDOW),
+            )),
+            stats: Arc::new(Mutex::new("link.stats", Histograms::new())),
+            counters: Arc::new(LinkCounters::new()),
+            close_reason: Arc::new(Mutex::new("link.close_reason", None)),
+            failure: Arc::new(Mutex::new("link.failure", None)),
+            closing: Arc::new(AtomicBool::new(false)),
+            close_wakeup: Arc::new(Wakeup::new()),
+            nacked: Arc::new(Mutex::new("link.nacked", Vec::new())),
+            extensions: Arc::new(Mutex::new("link.extensions", Extensions::new())),
+            cancellations: Arc::new(Mutex::new("link.cancellations", Cancellations::new())),
+            incoming: Arc::new(Mutex::new("link.incoming", None)),
+            buffers: Arc::new(BufferPool::new(max_packet_size())),
+            tags: Arc::new(PacketTags::new()),
+            span: info_span!("link", peer_addr = %peer_addr),
+            telemetry: Arc::new(NoopTelemetry),
+            memory: Arc::new(MemoryBudget::new(0)),
+            state_charges: Vec::new(),
             config,
         })
     }
 
     /// Starts the [`Link`] to the other peer
+    /// # Panics
+    /// If the [`Link`] has already been started
     pub fn start(&mut self) {
+        let receive_sender = self.receive_sender.take().expect("Link already started");
+        self.state_charges.push(self.memory.charge(LINK_MEMORY));
+
         // Create data structure for the send thread
         let mut send_thread_data = SendThread::new(
             self.socket.clone(),
             self.peer_addr,
             self.primary_queue.1.clone(),
             self.stop_flag.clone(),
+            self.send_wakeup.clone(),
             self.ack_check.clone(),
             self.ack_list.clone(),
-            self.send_seq.clone(),
             self.batch_empty.clone(),
+            self.delay.clone(),
+            self.congestion.clone(),
+            self.stats.clone(),
+            self.counters.clone(),
+            self.telemetry.clone(),
+            self.nacked.clone(),
+            self.cancellations.clone(),
+            self.buffers.clone(),
+            self.tags.clone(),
+            self.version,
             self.config,
         );
 
         // Start the send thread
         // Check for arc self if stable : https://stackoverflow.com/questions/25462935/what-types-are-valid-for-the-self-parameter-of-a-method
-        let send_thread = thread::spawn(move || {
+        let span = debug_span!(parent: &self.span, "send");
+        let send_thread = self.spawn_worker(span, move || {
             send_thread_data.start();
+            Ok(())
         });
 
         // Create data strcuture for the receive thread
         let mut recv_thread_data = ReceiveThread::new(
             self.socket.clone(),
             self.peer_addr,
-            self.receive_queue.0.clone(),
+            receive_sender,
             self.stop_flag.clone(),
+            self.send_wakeup.clone(),
+            self.ack_wakeup.clone(),
             self.ack_check.clone(),
             self.ack_list.clone(),
             self.recv_seq.clone(),
+            self.send_seq.clone(),
+            self.delay.clone(),
+            self.congestion.clone(),
+            self.output_queue.clone(),
+            self.stats.clone(),
+            self.counters.clone(),
+            self.close_reason.clone(),
+            self.closing.clone(),
+            self.close_wakeup.clone(),
+            self.nacked.clone(),
+            self.extensions.clone(),
+            self.incoming.clone(),
+            self.memory.clone(),
+            self.buffers.clone(),
+            self.tags.clone(),
+            self.version,
+            self.max_message_size,
             self.config,
         );
 
         // Start the receive thread
-        let recv_thread = thread::spawn(move || {
+        let span = debug_span!(parent: &self.span, "receive");
+        let recv_thread = self.spawn_worker(span, move || {
             recv_thread_data.start();
+            Ok(())
+        });
+
+        // Create data structure for the acknowledgement thread
+        let ack_thread_data = AckThread::new(
+            self.socket.clone(),
+            self.peer_addr,
+            self.stop_flag.clone(),
+            self.ack_wakeup.clone(),
+            self.ack_list.clone(),
+            self.send_seq.clone(),
+            self.batch_empty.clone(),
+            self.delay.clone(),
+            self.stats.clone(),
+            self.counters.clone(),
+            self.buffers.clone(),
+            self.tags.clone(),
+            self.version,
+            self.config,
+        );
+
+        // Start the acknowledgement thread
+        let span = debug_span!(parent: &self.span, "ack");
+        let ack_thread = self.spawn_worker(span, move || {
+            ack_thread_data.start();
+            Ok(())
         });
 
         // Push the threads' join handles to join when stopping the link
-        self.thread_handles.push(send_thread);
-        self.thread_handles.push(recv_thread);
+        self.push_thread(send_thread);
+        self.push_thread(recv_thread);
+        self.push_thread(ack_thread);
+
+        debug!(parent: &self.span, version = self.version, "Link started");
+    }
+
+    /// Spawn a thread of the link running `work` in `span`. If it fails, the link is
+    /// closed with [`CloseReason::Failed`] and the error kept for [`Link::failure`]
+    fn spawn_worker<F>(&self, span: Span, work: F) -> JoinHandle<()>
+    where
+        F: FnOnce() -> Result<(), AetherError> + Send + 'static,
+    {
+        let link_config = self.config.link;
+        let failure = self.failure.clone();
+        let close_reason = self.close_reason.clone();
+        let stop_flag = self.stop_flag.clone();
+        let send_wakeup = self.send_wakeup.clone();
+        let ack_wakeup = self.ack_wakeup.clone();
+
+        thread::spawn(move || {
+            let _enter = span.enter();
+            worker::apply_hints(&link_config);
+
+            let error = match catch_panic(work) {
+                Ok(Ok(())) => return,
+                Ok(Err(err)) => err.to_string(),
+                Err(panic) => panic,
+            };
+            error!("Link thread failed: {}", error);
+
+            // The thread may have failed while holding any of the locks
+            failure
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_or_insert(error);
+            close_reason
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_or_insert(CloseReason::Failed);
+            *stop_flag.lock().unwrap_or_else(PoisonError::into_inner) = true;
+
+            send_wakeup.notify();
+            ack_wakeup.notify();
+        })
+    }
+
+    /// Report the telemetry of this link to `telemetry`. Must be called before
+    /// [`Link::start`]
+    pub fn set_telemetry(&mut self, telemetry: Arc<dyn Telemetry>) {
+        self.telemetry = telemetry;
+    }
+
+    /// Charge the memory held by this link to `memory`, shared with other links. Must be
+    /// called before [`Link::start`]
+    pub fn set_memory_budget(&mut self, memory: Arc<MemoryBudget>) {
+        self.memory = memory;
     }
 
+    /// Exchange a secret with the other peer and encrypt all packets sent from now on.
+    /// Does nothing if encryption is already enabled
     pub fn enable_encryption(&mut self) -> Result<(), AetherError> {
+        if self.is_encrypted() {
+            return Ok(());
+        }
+
+        let _span = telemetry::span(&self.telemetry, SPAN_KEY_EXCHANGE);
+
         // Generate a secret
         let own_secret = gen_nonce(KEY_SIZE);
 
         // Encrypt secret with other's public key
         let encrypted_secret = self.peer_id.public_encrypt(&own_secret)?;
         // Send encrypted secret
-        let mut packet = Packet::new(PType::KeyExchange, 0);
-        packet.append_payload(encrypted_secret);
+        let packet = PacketBuilder::new(PType::KeyExchange)
+            .payload(encrypted_secret)
+            .build()?;
         self.send_packet(packet)?;
 
         // Receive encrypted secret
@@ -201,20 +587,35 @@ impl Link {
 
         // Instantiate a new cipher with the shared secret
         let cipher = AetherCipher::new(shared_secret);
-        let decryption_thread_data = DecryptionThread::new(
+        let mut decryption_thread_data = DecryptionThread::new(
             cipher.clone(),
-            self.receive_queue.1.clone(),
-            self.output_queue.0.clone(),
+            self.receive_queue.clone(),
+            self.output_sender
+                .take()
+                .expect("Output queue taken before enabling encryption"),
             self.stop_flag.clone(),
+            self.counters.clone(),
             self.config,
         );
 
-        let decryption_thread = thread::spawn(move || {
-            decryption_thread_data.start().unwrap();
-        });
+        let span = debug_span!(parent: &self.span, "decryption");
+        let decryption_thread = self.spawn_worker(span, move || decryption_thread_data.start());
 
-        self.thread_handles.push(decryption_thread);
+        self.push_thread(decryption_thread);
+        self.state_charges.push(self.memory.charge(CIPHER_MEMORY));
 
+        match self.extensions.lock() {
+            Ok(mut extensions_lock) => extensions_lock.set_cipher(cipher.clone()),
+            Err(_) => return Err(AetherError::MutexLock("extensions")),
+        }
+
+        debug!(parent: &self.span, "Encryption enabled");
+        self.telemetry.event(&TelemetryEvent::KeyExchange {
+            peer_addr: self.peer_addr,
+            cipher: CIPHER_NAME,
+        });
+
+        self.tags.enable(cipher.clone());
         self.cipher = Some(cipher);
 
         Ok(())
@@ -224,8 +625,131 @@ impl Link {
         self.cipher.is_some()
     }
 
-    /// Stops the [`Link`] to the other peer
-    pub fn stop(&mut self) -> Result<(), AetherError> {
+    /// Returns the short authentication string of the encrypted session, which is the
+    /// same on both ends unless the key exchange was intercepted (refer
+    /// [`AetherCipher::short_auth_string`]). [`None`] if encryption is not enabled
+    pub fn short_auth_string(&self) -> Result<Option<String>, AetherError> {
+        let cipher = match self.cipher {
+            Some(ref cipher) => cipher,
+            None => return Ok(None),
+        };
+
+        let uid = self.private_id.public_key_to_base64()?;
+        let peer_uid = self.peer_id.public_key_to_base64()?;
+        Ok(Some(cipher.short_auth_string(&uid, &peer_uid)))
+    }
+
+    /// Sets the protocol version to be used with the other peer. Must be called
+    /// before the [`Link`] is started
+    /// # Arguments
+    /// * `version` - Protocol version negotiated with the other peer
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+        self.max_message_size = message_limit(self.max_message_size, version);
+    }
+
+    /// Returns the protocol version used to communicate with the other peer
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    /// Sets the largest message accepted by both peers. Must be called before the
+    /// [`Link`] is started
+    /// # Arguments
+    /// * `max_message_size` - Maximum message size negotiated with the other peer (in
+    ///   bytes)
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = message_limit(max_message_size, self.version);
+    }
+
+    /// Sets the largest window accepted by the other peer, which the congestion window
+    /// never exceeds. Must be called before the [`Link`] is started
+    /// # Arguments
+    /// * `max_window` - Window advertised by the other peer (in packets)
+    pub fn set_max_window(&mut self, max_window: u16) {
+        self.max_window = max_window.clamp(1, MAX_WINDOW);
+        self.congestion = Arc::new(Mutex::new(
+            "link.congestion",
+            CongestionController::new(self.config.link.window_size, self.max_window),
+        ));
+    }
+
+    /// Returns the current congestion window of the [`Link`]
+    pub fn congestion_window(&self) -> Result<u16, AetherError> {
+        match self.congestion.lock() {
+            Ok(congestion_lock) => Ok(congestion_lock.window()),
+            Err(_) => Err(AetherError::MutexLock("congestion controller")),
+        }
+    }
+
+    /// Returns the [`Negotiated`] parameters of the [`Link`]
+    pub fn negotiated(&self) -> Result<Negotiated, AetherError> {
+        let (cipher, mtu) = match self.cipher {
+            Some(_) => (Some(CIPHER_NAME), MAX_PAYLOAD_SIZE - ENCRYPTION_OVERHEAD),
+            None => (None, MAX_PAYLOAD_SIZE),
+        };
+
+        Ok(Negotiated {
+            version: self.version,
+            cipher,
+            mtu,
+            max_message_size: self.max_message_size,
+            initial_window: self.config.link.window_size,
+            window: self.congestion_window()?,
+            max_window: self.max_window,
+            capabilities: Capabilities::for_version(self.version),
+        })
+    }
+
+    /// Returns a snapshot of the [`Histograms`] recorded for the [`Link`]
+    pub fn histograms(&self) -> Result<Histograms, AetherError> {
+        match self.stats.lock() {
+            Ok(stats_lock) => Ok(stats_lock.clone()),
+            Err(_) => Err(AetherError::MutexLock("stats")),
+        }
+    }
+
+    /// Returns the [`LinkStats`] of the traffic on the [`Link`] so far
+    pub fn stats(&self) -> Result<LinkStats, AetherError> {
+        let rtt_us = self.delay_estimate()?.rtt_us;
+        Ok(self.counters.snapshot(rtt_us))
+    }
+
+    /// Returns the current [`DelayEstimate`] for the [`Link`]
+    pub fn delay_estimate(&self) -> Result<DelayEstimate, AetherError> {
+        match self.delay.lock() {
+            Ok(delay_lock) => Ok(delay_lock.estimate()),
+            Err(_) => Err(AetherError::MutexLock("delay estimator")),
+        }
+    }
+
+    /// Stops the [`Link`] to the other peer. Messages already sent are delivered first,
+    /// waiting at most `linger_timeout` for them to be acknowledged. If the protocol
+    /// version supports it, the other peer is then told so that it delivers its own
+    /// queued messages and closes its end of the link (refer [`CloseReason::Closed`])
+    pub fn stop(&self) -> Result<(), AetherError> {
+        let started = match self.thread_handles.lock() {
+            Ok(handles_lock) => !(*handles_lock).is_empty(),
+            Err(_) => return Err(AetherError::MutexLock("thread handles")),
+        };
+        if started && !self.is_stopped()? {
+            self.linger()?;
+
+            if has_close(self.version) {
+                self.close_exchange()?;
+            }
+        }
+
+        match self.close_reason.lock() {
+            Ok(mut reason_lock) => {
+                // Keep the reason if the link was already closed by someone else
+                if reason_lock.is_none() && !self.is_stopped()? {
+                    *reason_lock = Some(CloseReason::Stopped);
+                }
+            }
+            Err(_) => return Err(AetherError::MutexLock("close reason")),
+        }
+
         // Set the stop flag
         match self.stop_flag.lock() {
             Ok(mut flag_lock) => {
@@ -234,72 +758,521 @@ impl Link {
                 // Unlock stop flag
                 drop(flag_lock);
 
+                self.send_wakeup.notify();
+                self.ack_wakeup.notify();
+
                 // Join each thread
-                while match self.thread_handles.pop() {
-                    Some(handle) => {
-                        handle.join().expect("Thread failed to join");
-                        true
-                    }
-                    None => false,
-                } {}
+                let handles = match self.thread_handles.lock() {
+                    Ok(mut handles_lock) => std::mem::take(&mut *handles_lock),
+                    Err(_) => return Err(AetherError::MutexLock("thread handles")),
+                };
+                for handle in handles.into_iter().rev() {
+                    handle.join().expect("Thread failed to join");
+                }
                 Ok(())
             }
             Err(_) => Err(AetherError::MutexLock("stop flag")),
         }
     }
 
+    /// Wait until the other peer acknowledged every packet sent, at most
+    /// `linger_timeout`
+    fn linger(&self) -> Result<(), AetherError> {
+        let timeout = Duration::from_millis(self.config.link.linger_timeout);
+        let poll_time = Duration::from_micros(self.config.link.poll_time_us);
+
+        let start = Instant::now();
+        while !is_drained(&self.send_seq, &self.ack_check)? {
+            if self.is_stopped()? {
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                debug!(parent: &self.span, "Closing link with unacknowledged packets");
+                return Ok(());
+            }
+            thread::sleep(poll_time);
+        }
+
+        Ok(())
+    }
+
+    /// Send close packets to the other peer until it answers with one, waiting at most
+    /// `close_timeout`. The other peer answers once its own queued messages are
+    /// delivered, so the wait is extended while it is still sending, by at most
+    /// `linger_timeout`
+    fn close_exchange(&self) -> Result<(), AetherError> {
+        let timeout = Duration::from_millis(self.config.link.close_timeout);
+        if timeout.is_zero() {
+            return Ok(());
+        }
+
+        let packet = PacketBuilder::new(PType::Close)
+            .version(self.version)
+            .build()?;
+        let mut data = self.buffers.take();
+        self.tags.compile_into(&packet, &mut data);
+
+        self.closing.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        let latest = start + timeout + Duration::from_millis(self.config.link.linger_timeout);
+        let mut deadline = start + timeout;
+        let mut received = self.counters.packets_received();
+        while Instant::now() < deadline && !self.is_stopped()? {
+            if let Err(err) = self.socket.send_to(&data, self.peer_addr) {
+                debug!(parent: &self.span, "Unable to send close packet: {}", err);
+                break;
+            }
+
+            let wait = deadline
+                .saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(self.config.link.retry_delay));
+            if self.close_wakeup.wait_timeout(wait) {
+                debug!(parent: &self.span, "Other peer answered close");
+                break;
+            }
+
+            let now_received = self.counters.packets_received();
+            if now_received != received {
+                received = now_received;
+                deadline = latest.min(Instant::now() + timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the [`Link`] has been stopped, either locally or because the
+    /// other peer could not be reached
+    pub fn is_stopped(&self) -> Result<bool, AetherError> {
+        match self.stop_flag.lock() {
+            Ok(flag_lock) => Ok(*flag_lock),
+            Err(_) => Err(AetherError::MutexLock("stop flag")),
+        }
+    }
+
+    /// Returns why the [`Link`] was closed, [`None`] if it is still running
+    pub fn close_reason(&self) -> Result<Option<CloseReason>, AetherError> {
+        if !self.is_stopped()? {
+            return Ok(None);
+        }
+
+        match self.close_reason.lock() {
+            Ok(reason_lock) => Ok(Some(reason_lock.unwrap_or(CloseReason::Broken))),
+            Err(_) => Err(AetherError::MutexLock("close reason")),
+        }
+    }
+
+    /// Returns the error a thread of the [`Link`] failed with, if it was closed with
+    /// [`CloseReason::Failed`]
+    pub fn failure(&self) -> Result<Option<String>, AetherError> {
+        match self.failure.lock() {
+            Ok(failure_lock) => Ok(failure_lock.clone()),
+            Err(_) => Err(AetherError::MutexLock("failure")),
+        }
+    }
+
+    /// Close the [`Link`] automatically with [`CloseReason::Expired`] after `expiry`,
+    /// for sessions granted only for a limited time
+    pub fn set_expiry(&mut self, expiry: Duration) {
+        let deadline = Instant::now() + expiry;
+        let stop_flag = self.stop_flag.clone();
+        let close_reason = self.close_reason.clone();
+        let poll_time = Duration::from_millis(self.config.link.ack_only_time);
+
+        let span = debug_span!(parent: &self.span, "expiry");
+        let expiry_thread = thread::spawn(move || {
+            let _enter = span.enter();
+
+            loop {
+                let flag_lock = stop_flag.lock().expect("Error locking stop flag");
+                if *flag_lock {
+                    break;
+                }
+                drop(flag_lock);
+
+                let now = Instant::now();
+                if now >= deadline {
+                    let mut reason_lock = close_reason.lock().expect("Error locking close reason");
+                    *reason_lock = Some(CloseReason::Expired);
+                    drop(reason_lock);
+
+                    let mut flag_lock = stop_flag.lock().expect("Error locking stop flag");
+                    *flag_lock = true;
+
+                    debug!("Session expired");
+                    break;
+                }
+
+                thread::sleep(poll_time.min(deadline - now));
+            }
+        });
+
+        self.push_thread(expiry_thread);
+    }
+
+    /// Keep the [`JoinHandle`] of a thread of the link, joined by [`Link::stop`]
+    fn push_thread(&self, handle: JoinHandle<()>) {
+        if let Ok(mut handles_lock) = self.thread_handles.lock() {
+            (*handles_lock).push(handle);
+        }
+    }
+
     /// Get the [`SocketAddr`] of the peer
     pub fn get_addr(&self) -> SocketAddr {
         self.peer_addr
     }
 
-    /// Sends bytes to the other peer
+    /// Returns the local [`SocketAddr`] the [`Link`] sends from
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the [`SocketAddr`] of the other peer
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Sends bytes to the other peer. Blocks while the send queue is full (refer
+    /// [`send_queue_size`][crate::config::LinkConfig::send_queue_size]). Bytes larger
+    /// than a packet are split into fragments, which the other peer joins again (refer
+    /// [`has_fragments`])
     /// # Arguments
     /// * `buf` - Buffer containing the bytes to be sent
+    /// # Errors
+    /// * [`AetherError::MessageTooLarge`] - The bytes are larger than the maximum message
+    ///   size negotiated with the other peer
+    /// * [`AetherError::InvalidPacket`] - The (encrypted) bytes do not fit in a single
+    ///   packet, and the other peer does not support fragments
+    /// * [`AetherError::LinkStopped`] - [`Link`] has been stopped, also while waiting for
+    ///   the send queue
+    ///
+    /// Other general errors might occur (refer to [`AetherError`])
     pub fn send(&self, buf: Vec<u8>) -> Result<(), AetherError> {
-        // Create a new packet to be sent
-        let mut packet = Packet::new(PType::Data, 0);
+        self.send_payload(PType::Data, buf, None, true).map(|_| ())
+    }
+
+    /// Sends bytes to the other peer without blocking
+    /// # Errors
+    /// * [`AetherError::SendQueueFull`] - The send queue does not have space for all the
+    ///   packets carrying the bytes, they are not sent
+    ///
+    /// Otherwise the same as [`Link::send`]
+    pub fn try_send(&self, buf: Vec<u8>) -> Result<(), AetherError> {
+        self.send_payload(PType::Data, buf, None, false).map(|_| ())
+    }
+
+    /// Sends bytes to the other peer, returning a [`DeliveryHandle`] telling once the
+    /// other peer received them (refer [`delivery`])
+    /// # Errors
+    /// The same as [`Link::send`]
+    pub fn send_tracked(&self, buf: Vec<u8>) -> Result<DeliveryHandle, AetherError> {
+        let (seq, packets) = self.send_payload(PType::Data, buf, None, true)?;
+        let id = match self.cancellations.lock() {
+            Ok(cancellations_lock) => (*cancellations_lock).message(seq, packets),
+            Err(_) => return Err(AetherError::MutexLock("cancellations")),
+        };
+        Ok(DeliveryHandle::new(
+            id,
+            self.ack_check.clone(),
+            self.cancellations.clone(),
+            self.stop_flag.clone(),
+            Duration::from_micros(self.config.link.poll_time_us),
+        ))
+    }
+
+    /// Cancel the message `id` sent with [`Link::send_tracked`], such as when a user
+    /// aborts sending a file. The packets carrying it that the other peer did not
+    /// acknowledge yet are not sent (again), and the other peer drops the parts of the
+    /// message it received (refer [`delivery`]). Returns false if the other peer
+    /// already acknowledged the whole message, or it was sent on another link
+    ///
+    /// Packets on their way to the other peer may still arrive, so a message cancelled
+    /// while only its last packets were in flight can be received nonetheless
+    pub fn cancel(&self, id: MessageId) -> Result<bool, AetherError> {
+        let check_lock = match self.ack_check.lock() {
+            Ok(check_lock) => check_lock,
+            Err(_) => return Err(AetherError::MutexLock("ack check")),
+        };
+        let cancelled = match self.cancellations.lock() {
+            Ok(mut cancellations_lock) => (*cancellations_lock).cancel(id, &check_lock),
+            Err(_) => return Err(AetherError::MutexLock("cancellations")),
+        };
+        drop(check_lock);
+
+        self.send_wakeup.notify();
+        Ok(cancelled)
+    }
+
+    /// Returns the progress of the message larger than a packet the other peer is
+    /// sending, such as to show the progress of receiving a file. [`None`] if no such
+    /// message is partially received, or the other peer does not tell the size of its
+    /// messages (refer [`has_message_length`])
+    pub fn incoming_progress(&self) -> Result<Option<IncomingProgress>, AetherError> {
+        match self.incoming.lock() {
+            Ok(incoming_lock) => Ok(*incoming_lock),
+            Err(_) => Err(AetherError::MutexLock("incoming progress")),
+        }
+    }
+
+    /// Sends bytes to the other peer, giving up on them if they could not be delivered
+    /// by `deadline`, such as for real-time data that is useless once stale. Bytes not
+    /// sent in time or waiting to be sent again are dropped and counted in
+    /// [`LinkStats::expired`][crate::stats::LinkStats::expired], the other peer receives
+    /// the messages following them as usual
+    ///
+    /// Deadlines are ignored if the other peer does not support them (refer
+    /// [`has_deadlines`])
+    /// # Errors
+    /// The same as [`Link::send`]
+    pub fn send_with_deadline(&self, buf: Vec<u8>, deadline: Instant) -> Result<(), AetherError> {
+        let deadline = Some(deadline).filter(|_| has_deadlines(self.version));
+        self.send_payload(PType::Data, buf, deadline, true)
+            .map(|_| ())
+    }
+
+    /// Sends bytes to the other peer in an extended packet of `subtype`, which is
+    /// passed to the handler the other peer registered for it instead of being
+    /// received as a message (refer [`extension`])
+    /// # Arguments
+    /// * `subtype` - Subtype of the extended packet
+    /// * `buf` - Buffer containing the bytes to be sent
+    /// # Errors
+    /// * [`AetherError::ExtensionUnsupported`] - The other peer does not support
+    ///   extended packets, or the reserved subtype `subtype` (refer
+    ///   [`has_reserved_subtypes`])
+    ///
+    /// Otherwise the same as [`Link::send`]
+    pub fn send_extended(&self, subtype: u8, buf: Vec<u8>) -> Result<(), AetherError> {
+        if !has_extensions(self.version)
+            || (extension::is_reserved(subtype) && !has_reserved_subtypes(self.version))
+        {
+            return Err(AetherError::ExtensionUnsupported(self.version));
+        }
+
+        self.send_payload(PType::Extended(subtype), buf, None, true)
+            .map(|_| ())
+    }
+
+    /// Send `buf` in a packet of `p_type`, encrypted if encryption is enabled and
+    /// dropped once `deadline` passed. Data larger than a packet is split into fragments
+    /// (refer [`has_fragments`] and [`has_reserved_subtypes`]). Fails instead of waiting for the send queue unless
+    /// `block` is set. Returns the sequence number of the first packet and the number of
+    /// packets
+    fn send_payload(
+        &self,
+        p_type: PType,
+        buf: Vec<u8>,
+        deadline: Option<Instant>,
+        block: bool,
+    ) -> Result<(Seq, u32), AetherError> {
+        if buf.len() > self.max_message_size {
+            return Err(AetherError::MessageTooLarge(self.max_message_size));
+        }
+
         // if a cipher is present, encrypt the payload
-        let data: Vec<u8> = match self.cipher {
-            Some(ref cipher) => {
-                packet.set_enc(true);
-                cipher.encrypt_bytes(buf)?.into()
-            }
-            None => buf,
+        let (data, enc): (Vec<u8>, bool) = match self.cipher {
+            Some(ref cipher) => (cipher.encrypt_bytes(buf)?.into(), true),
+            None => (buf, false),
+        };
+
+        // Messages are encrypted as a whole, so fragments are not encrypted again
+        let max_payload = PacketBuilder::max_payload_size(&p_type);
+        let fragmented = match p_type {
+            PType::Data => true,
+            PType::Extended(_) => has_reserved_subtypes(self.version),
+            _ => false,
+        };
+        let mut packets = if has_fragments(self.version) && fragmented && data.len() > max_payload {
+            // The first fragment tells the size of the message (refer
+            // has_message_length)
+            let data = if has_message_length(self.version) {
+                let mut framed = Vec::with_capacity(MESSAGE_LENGTH_SIZE + data.len());
+                framed.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                framed.extend_from_slice(&data);
+                framed
+            } else {
+                data
+            };
+            let count = (data.len() + max_payload - 1) / max_payload;
+            data.chunks(max_payload)
+                .enumerate()
+                .map(|(index, fragment)| {
+                    PacketBuilder::new(p_type.clone())
+                        .encrypted(enc)
+                        .more_fragments(index + 1 < count)
+                        .payload(fragment.to_vec())
+                        .build()
+                })
+                .collect::<Result<Vec<Packet>, PacketError>>()?
+        } else {
+            vec![PacketBuilder::new(p_type)
+                .encrypted(enc)
+                .payload(data)
+                .build()?]
         };
-        packet.append_payload(data);
-        self.send_packet(packet)
+
+        for packet in &mut packets {
+            packet.deadline = deadline;
+        }
+        let count = packets.len() as u32;
+        Ok((self.queue_packets(packets, block)?, count))
+    }
+
+    /// Register `handler` to be called with the payload of every extended packet of
+    /// `subtype` received from the other peer (refer [`extension`])
+    /// # Errors
+    /// * [`AetherError::ExtensionTaken`] - A handler is already registered for
+    ///   `subtype`
+    pub fn register_extension<F>(&self, subtype: u8, handler: F) -> Result<(), AetherError>
+    where
+        F: FnMut(Vec<u8>) + Send + 'static,
+    {
+        match self.extensions.lock() {
+            Ok(mut extensions_lock) => extensions_lock.register(subtype, Box::new(handler)),
+            Err(_) => Err(AetherError::MutexLock("extensions")),
+        }
+    }
+
+    /// Remove the handler of `subtype`, later packets of `subtype` are dropped. Returns
+    /// false if no handler was registered
+    pub fn unregister_extension(&self, subtype: u8) -> Result<bool, AetherError> {
+        match self.extensions.lock() {
+            Ok(mut extensions_lock) => Ok(extensions_lock.unregister(subtype)),
+            Err(_) => Err(AetherError::MutexLock("extensions")),
+        }
     }
 
     /// Send a `packet` to the other peer
     /// > This alter's the `packet.sequence` number of the `packet` argument. Rest
-    /// of the packet is sent as it is
+    /// > of the packet is sent as it is
     ///
     /// # Arguments
     ///
     /// * `packet` - The [`Packet`] to be sent
-    pub fn send_packet(&self, mut packet: Packet) -> Result<(), AetherError> {
-        // Lock seq number
-        match self.send_seq.lock() {
-            Ok(mut seq_lock) => {
-                // Increase sequence number
-                (*seq_lock) += 1;
+    pub fn send_packet(&self, packet: Packet) -> Result<(), AetherError> {
+        self.queue_packets(vec![packet], true).map(|_| ())
+    }
 
-                let seq: u32 = *seq_lock;
+    /// Push `packets` onto the primary queue with consecutive sequence numbers, waiting
+    /// for space in it if `block` is set. Returns the sequence number assigned to the
+    /// first one
+    fn queue_packets(&self, packets: Vec<Packet>, block: bool) -> Result<Seq, AetherError> {
+        if self.is_stopped()? {
+            return Err(AetherError::LinkStopped("send packet"));
+        }
 
-                // Unlock seq
-                drop(seq_lock);
+        // Held until the packets are queued, so that packets are queued in the order of
+        // their sequence numbers. The other threads of the link lock the sequence
+        // number, which is therefore not held while waiting for space in the queue
+        let queue_lock = match self.queue_lock.lock() {
+            Ok(queue_lock) => queue_lock,
+            Err(_) => return Err(AetherError::MutexLock("send queue")),
+        };
 
-                // set sequence number on packet
-                packet.sequence = seq;
+        // Only the send thread takes packets off the queue while the lock is held, so
+        // the packets fit if there is space now. Packets always fit an unbounded queue
+        if !block {
+            if let Some(capacity) = self.primary_queue.0.capacity() {
+                if capacity - self.primary_queue.0.len() < packets.len() {
+                    return Err(AetherError::SendQueueFull(capacity));
+                }
+            }
+        }
 
-                // Push the new packet onto the primary queue
-                self.primary_queue.0.send(packet)?;
+        let mut first = Seq(0);
+        for (index, mut packet) in packets.into_iter().enumerate() {
+            // Lock seq number
+            match self.send_seq.lock() {
+                Ok(mut seq_lock) => {
+                    // Increase sequence number
+                    (*seq_lock) += 1;
 
-                Ok(())
+                    // set sequence number on packet
+                    packet.sequence = *seq_lock;
+                }
+                Err(_) => return Err(AetherError::MutexLock("send seq")),
+            }
+            packet.charge = Some(Box::new(self.memory.charge(packet_memory(&packet))));
+            if index == 0 {
+                first = packet.sequence;
             }
-            Err(_) => Err(AetherError::MutexLock("send queue")),
+
+            // The send thread still handles acknowledgements and retransmissions
+            if self.can_send_now(&packet) {
+                packet.sent_at = self.send_now(&mut packet);
+            }
+
+            // Push the new packet onto the primary queue, the link may be stopped while
+            // waiting for space in it
+            let stop_poll_time = Duration::from_millis(self.config.link.ack_only_time);
+            loop {
+                match self.primary_queue.0.send_timeout(packet, stop_poll_time) {
+                    Ok(()) => break,
+                    Err(SendTimeoutError::Timeout(unsent)) => {
+                        if self.is_stopped()? {
+                            return Err(AetherError::LinkStopped("send packet"));
+                        }
+                        packet = unsent;
+                    }
+                    Err(SendTimeoutError::Disconnected(unsent)) => {
+                        return Err(SendError(unsent).into())
+                    }
+                }
+            }
+            // Wake the send thread up for every packet, the queue may hold fewer
+            // packets than a fragmented message
+            self.send_wakeup.notify();
         }
+        drop(queue_lock);
+
+        Ok(first)
+    }
+
+    /// Check if `packet` can skip waiting for the send thread, which is the case for
+    /// small packets while nothing else is waiting to be sent
+    fn can_send_now(&self, packet: &Packet) -> bool {
+        if packet.payload.len() >= self.config.link.fast_path_size || !needs_ack(packet) {
+            return false;
+        }
+
+        // Expired packets are replaced by the send thread
+        if matches!(packet.deadline, Some(deadline) if deadline <= Instant::now()) {
+            return false;
+        }
+
+        let batch_empty = match self.batch_empty.lock() {
+            Ok(empty_lock) => *empty_lock,
+            Err(_) => false,
+        };
+
+        batch_empty && self.primary_queue.0.is_empty()
+    }
+
+    /// Send `packet` on the caller's thread. Returns the time it was sent, or [`None`] if
+    /// it could not be sent and has to be sent by the send thread instead
+    fn send_now(&self, packet: &mut Packet) -> Option<Instant> {
+        packet.add_ack(take_ack(&self.ack_list, &self.delay, &self.stats).ok()?);
+        packet.version = self.version;
+        let mut data = self.buffers.take();
+        self.tags.compile_into(packet, &mut data);
+
+        let size = match self.socket.send_to(&data, self.peer_addr) {
+            Ok(size) if size > 0 => size,
+            _ => return None,
+        };
+        let sent_at = Instant::now();
+
+        if let Ok(mut stats_lock) = self.stats.lock() {
+            stats_lock.packet_size.record(size as u64);
+        }
+        self.counters.sent(size, true);
+
+        match self.delay.lock() {
+            Ok(mut delay_lock) => (*delay_lock).on_send(packet.sequence),
+            Err(_) => return None,
+        }
+
+        Some(sent_at)
     }
 
     /// Sets the read timeout for the [`Link`]
@@ -321,7 +1294,10 @@ impl Link {
     /// Other general errors might occur (refer to [`AetherError`])
     pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>, AetherError> {
         let receiver = self.get_receiver()?;
-        let packet = receiver.recv_timeout(timeout)?;
+        let packet = match receiver.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Disconnected) => return Err(AetherError::LinkStopped("recv")),
+            result => result?,
+        };
         Ok(packet.payload)
     }
 
@@ -336,30 +1312,67 @@ impl Link {
     pub fn recv(&self) -> Result<Vec<u8>, AetherError> {
         let receiver = self.get_receiver()?;
         let packet = if let Some(time) = self.read_timeout {
-            receiver.recv_timeout(time)?
+            match receiver.recv_timeout(time) {
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(AetherError::LinkStopped("recv"))
+                }
+                result => result?,
+            }
         } else {
-            receiver.recv()?
+            receiver
+                .recv()
+                .map_err(|_| AetherError::LinkStopped("recv"))?
         };
 
         Ok(packet.payload)
     }
 
-    /// Returns a [`Receiver`] to receive packets from the output queue
+    /// Receive bytes from the other peer if there are any, without waiting
+    /// # Returns
+    /// * `Option<Vec<u8>>` - Buffer containing the received bytes, [`None`] if nothing
+    ///   was received yet
+    /// # Errors
+    /// * [`AetherError::LinkStopped`] - [`Link`] stopped and all bytes received before
+    ///   were read
+    ///
+    /// Other general errors might occur (refer to [`AetherError`])
+    pub fn try_recv(&self) -> Result<Option<Vec<u8>>, AetherError> {
+        let receiver = self.get_receiver()?;
+        match receiver.try_recv() {
+            Ok(packet) => Ok(Some(packet.payload)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(AetherError::LinkStopped("recv")),
+        }
+    }
+
+    /// Returns an iterator over the bytes received from the other peer, waiting for
+    /// each like [`Link::recv`]. It ends once receiving fails, usually because the
+    /// [`Link`] stopped
+    pub fn messages(&self) -> Messages<'_> {
+        Messages { link: self }
+    }
+
+    /// Returns a [`Receiver`] to receive packets from the output queue. The [`Receiver`]
+    /// disconnects once the [`Link`] has stopped and all packets received before were read.
+    /// Once stopped, it is only returned while such packets are left
     pub fn get_receiver(&self) -> Result<Receiver<Packet>, AetherError> {
         match self.stop_flag.lock() {
             Ok(flag_lock) => {
                 let stop = *flag_lock;
                 drop(flag_lock);
 
-                if stop {
+                // Packets still waiting to be decrypted end up in the output queue
+                let pending = !self.output_queue.is_empty() || !self.receive_queue.is_empty();
+
+                if stop && !pending {
                     Err(AetherError::LinkStopped("get receiver"))
                 } else {
                     // if encrypted receive from output queue
                     if self.is_encrypted() {
-                        Ok(self.output_queue.1.clone())
+                        Ok(self.output_queue.clone())
                     } else {
                         // if not encrypted receive directly from receive queue
-                        Ok(self.receive_queue.1.clone())
+                        Ok(self.receive_queue.clone())
                     }
                 }
             }
@@ -400,12 +1413,35 @@ impl Link {
     }
 }
 
+impl<'a> IntoIterator for &'a Link {
+    type Item = Vec<u8>;
+    type IntoIter = Messages<'a>;
+
+    fn into_iter(self) -> Messages<'a> {
+        self.messages()
+    }
+}
+
+/// Iterator over the bytes received on a [`Link`] (refer [`Link::messages`])
+#[derive(Debug)]
+pub struct Messages<'a> {
+    link: &'a Link,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.link.recv().ok()
+    }
+}
+
 impl Drop for Link {
     fn drop(&mut self) {
         match self.stop() {
             Ok(_) => {}
             Err(aether_error) => {
-                log::error!("{}", aether_error)
+                error!("{}", aether_error)
             }
         }
     }
@@ -0,0 +1,138 @@
+//! Buffers reused by the threads of a [`Link`][crate::link::Link].
+//!
+//! Every packet sent is compiled into a buffer, and every datagram is received into
+//! one. Instead of allocating a new buffer for each packet, the threads of a link take
+//! them from its [`BufferPool`]. A [`PooledBuffer`] goes back to the pool when dropped,
+//! unless the pool already holds [`MAX_POOLED_BUFFERS`] buffers.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::sync::Mutex;
+
+/// Largest number of free buffers kept by a [`BufferPool`]. Enough for the threads of a
+/// link to send and receive at the same time
+pub const MAX_POOLED_BUFFERS: usize = 4;
+
+/// Free buffers shared by the threads of a link
+#[derive(Debug)]
+pub struct BufferPool {
+    /// Capacity of newly allocated buffers (in bytes)
+    buffer_size: usize,
+    /// Buffers given back and not taken again yet
+    free: Mutex<Vec<Vec<u8>>>,
+    /// Number of buffers allocated by the pool
+    allocated: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Creates a new empty [`BufferPool`] allocating buffers of `buffer_size` bytes
+    pub fn new(buffer_size: usize) -> BufferPool {
+        BufferPool {
+            buffer_size,
+            free: Mutex::new("link.buffers", Vec::new()),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take an empty buffer from the pool, allocating one if there is none free. The
+    /// buffer goes back to the pool when the returned [`PooledBuffer`] is dropped
+    pub fn take(self: &Arc<Self>) -> PooledBuffer {
+        let free = self.free.lock().ok().and_then(|mut free| free.pop());
+        let buf = match free {
+            Some(mut buf) => {
+                buf.clear();
+                buf
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(self.buffer_size)
+            }
+        };
+
+        PooledBuffer {
+            buf,
+            pool: self.clone(),
+        }
+    }
+
+    /// Returns the number of buffers allocated by the pool so far
+    #[cfg(any(test, feature = "raw"))]
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of free buffers in the pool
+    #[cfg(any(test, feature = "raw"))]
+    pub fn free(&self) -> usize {
+        self.free.lock().map(|free| free.len()).unwrap_or(0)
+    }
+
+    /// Give `buf` back to the pool, dropping it if the pool is full
+    fn give_back(&self, buf: Vec<u8>) {
+        if let Ok(mut free) = self.free.lock() {
+            if free.len() < MAX_POOLED_BUFFERS {
+                free.push(buf);
+            }
+        }
+    }
+}
+
+/// Buffer taken from a [`BufferPool`], given back when dropped
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        self.pool.give_back(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{BufferPool, MAX_POOLED_BUFFERS};
+
+    #[test]
+    fn pool_test() {
+        let pool = Arc::new(BufferPool::new(64));
+
+        let mut buf = pool.take();
+        assert!(buf.capacity() >= 64);
+        buf.extend_from_slice(b"Hello");
+        drop(buf);
+        assert_eq!(pool.free(), 1);
+
+        // Buffers are reused and handed out empty
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(pool.allocated(), 1);
+        drop(buf);
+
+        // At most MAX_POOLED_BUFFERS are kept
+        let bufs: Vec<_> = (0..MAX_POOLED_BUFFERS + 2).map(|_| pool.take()).collect();
+        assert_eq!(pool.allocated(), MAX_POOLED_BUFFERS + 2);
+        drop(bufs);
+        assert_eq!(pool.free(), MAX_POOLED_BUFFERS);
+    }
+}
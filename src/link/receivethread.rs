@@ -1,32 +1,57 @@
 //use rand::{thread_rng, Rng};
-use std::cmp::{Ord, Ordering};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::net::SocketAddr;
-use std::net::UdpSocket;
+use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
-use crossbeam::channel::Sender;
+use crossbeam::channel::{Receiver, Sender};
+use tracing::{trace, warn};
 
 use crate::acknowledgement::{AcknowledgementCheck, AcknowledgementList};
 use crate::config::Config;
-use crate::link::needs_ack;
+use crate::encryption::ENCRYPTION_OVERHEAD;
+use crate::error::PacketError;
+use crate::link::congestion::CongestionController;
+use crate::link::delay::DelayEstimator;
+use crate::link::extension::{is_reserved, Extensions, Routed};
+use crate::link::pool::BufferPool;
+use crate::link::replay::ReplayWindow;
+use crate::link::tags::PacketTags;
+use crate::link::{is_drained, needs_ack};
+use crate::link::{CloseReason, IncomingProgress};
+use crate::memory::{packet_memory, MemoryBudget};
+use crate::packet::has_ack_timestamps;
+use crate::packet::has_keepalive;
+use crate::packet::has_message_length;
+use crate::packet::has_nack;
+use crate::packet::max_packet_size;
 use crate::packet::PType;
 use crate::packet::Packet;
+use crate::packet::PacketBuilder;
+use crate::packet::MAX_NACK_COUNT;
+use crate::packet::MESSAGE_LENGTH_SIZE;
+use crate::packet::{decode_nack, encode_nack};
+use crate::sequence::Seq;
+use crate::stats::{Histograms, LinkCounters};
+use crate::sync::Mutex;
+use crate::telemetry::{limited, LogLimiter};
+use crate::transport::Transport;
+use crate::util::Wakeup;
 
 /// Data structure to facilitate ordering of incoming packets by their sequence number.
 pub struct OrderList {
     /// Last sequence number till which the packets are ordered.
-    seq: u32,
+    seq: Seq,
     /// [`HashMap`] of packets by their sequence numbers
-    list: HashMap<u32, Packet>,
+    list: HashMap<Seq, Packet>,
 }
 
 impl OrderList {
     /// Creates a new [`OrderList`] with the starting sequence number `seq`.
-    pub fn new(seq: u32) -> OrderList {
+    pub fn new(seq: Seq) -> OrderList {
         OrderList {
             seq,
             list: HashMap::new(),
@@ -42,12 +67,12 @@ impl OrderList {
     /// * [`Err(0)`] - If the packet received has already been sequenced before
     /// * [`Err(1)`] - If no sequnce of packets can be returned till now ???.
     pub fn insert(&mut self, packet: Packet) -> Result<VecDeque<Packet>, u8> {
-        match (self.seq).cmp(&(packet.sequence - 1)) {
-            Ordering::Less => {
+        match (self.seq).partial_cmp(&(packet.sequence - 1)) {
+            Some(Ordering::Less) => {
                 self.list.insert(packet.sequence, packet);
                 Err(1)
             }
-            Ordering::Equal => {
+            Some(Ordering::Equal) => {
                 let mut result: VecDeque<Packet> = VecDeque::new();
                 result.push_back(packet);
 
@@ -71,21 +96,79 @@ impl OrderList {
 /// Data structure to group data used by the receive thread
 pub struct ReceiveThread {
     /// The socket used to receive packets
-    socket: Arc<UdpSocket>,
+    socket: Arc<dyn Transport>,
     /// Address of the other peer
-    _peer_addr: SocketAddr,
+    peer_addr: SocketAddr,
     /// Reference to the output queue from [`crate::link::Link`]
     receive_queue: Sender<Packet>,
     /// Reference to the stop flag from [`crate::link::Link`]
     stop_flag: Arc<Mutex<bool>>,
+    /// Wakes the send thread up when acknowledgements are received
+    send_wakeup: Arc<Wakeup>,
+    /// Wakes the acknowledgement thread up when packets to be acknowledged are received
+    ack_wakeup: Arc<Wakeup>,
     /// Reference to the [`AcknowledgementList`] from [`crate::link::Link`]
     ack_list: Arc<Mutex<AcknowledgementList>>,
     /// Reference to the [`AcknowledgementCheck`] from [`crate::link::Link`]
     ack_check: Arc<Mutex<AcknowledgementCheck>>,
     /// [`OrderList`] used to order received packets by their sequence number
     order_list: OrderList,
+    /// Highest sequence number received so far. Packets skipped by a later packet are
+    /// requested with a NACK packet
+    highest: Seq,
+    /// [`ReplayWindow`] of the packets received, to drop packets injected again
+    replay: ReplayWindow,
     /// Reference to receive sequence from [`crate::link::Link`]
-    _recv_seq: Arc<Mutex<u32>>,
+    _recv_seq: Arc<Mutex<Seq>>,
+    /// Reference to send sequence from [`crate::link::Link`]
+    send_seq: Arc<Mutex<Seq>>,
+    /// Reference to the [`DelayEstimator`] from [`crate::link::Link`]
+    delay: Arc<Mutex<DelayEstimator>>,
+    /// Reference to the [`CongestionController`] from [`crate::link::Link`]
+    congestion: Arc<Mutex<CongestionController>>,
+    /// Reference to the output queue from [`crate::link::Link`], used to measure
+    /// the number of packets waiting to be read
+    output_queue: Receiver<Packet>,
+    /// Reference to the [`Histograms`] from [`crate::link::Link`]
+    stats: Arc<Mutex<Histograms>>,
+    /// Reference to the [`LinkCounters`] from [`crate::link::Link`]
+    counters: Arc<LinkCounters>,
+    /// Reference to the close reason from [`crate::link::Link`]
+    close_reason: Arc<Mutex<Option<CloseReason>>>,
+    /// Set while [`crate::link::Link::stop`] waits for the other peer to answer close
+    /// packets
+    closing: Arc<AtomicBool>,
+    /// Wakes [`crate::link::Link::stop`] up when the other peer answered
+    close_wakeup: Arc<Wakeup>,
+    /// Reference to the packets requested by the other peer from
+    /// [`crate::link::Link`], sent again by the send thread
+    nacked: Arc<Mutex<Vec<Seq>>>,
+    /// Reference to the handlers of extended packets from [`crate::link::Link`]
+    extensions: Arc<Mutex<Extensions>>,
+    /// Reference to the progress of the message being received from
+    /// [`crate::link::Link`]
+    incoming: Arc<Mutex<Option<IncomingProgress>>>,
+    /// Budget the memory of packets waiting to be read is charged to
+    memory: Arc<MemoryBudget>,
+    /// Reference to the [`BufferPool`] from [`crate::link::Link`]
+    buffers: Arc<BufferPool>,
+    /// Reference to the [`PacketTags`] from [`crate::link::Link`]
+    tags: Arc<PacketTags>,
+    /// Protocol version used to communicate with the other peer
+    version: u8,
+    /// Largest message accepted from the other peer
+    max_message_size: usize,
+    /// Fragments of the message being received, joined into the first of them (refer
+    /// [`has_fragments`][crate::packet::has_fragments])
+    fragments: Option<Packet>,
+    /// Size of the message whose fragments are being joined, if the other peer told it
+    /// (refer [`has_message_length`])
+    message_length: Option<usize>,
+    /// Set while the remaining fragments of a message that cannot be delivered are
+    /// dropped
+    skipping: bool,
+    /// Limits the messages logged for every packet dropped
+    log_limiter: LogLimiter,
     /// Current configuration for Aether
     config: Config,
 }
@@ -93,13 +176,32 @@ pub struct ReceiveThread {
 impl ReceiveThread {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        socket: Arc<UdpSocket>,
+        socket: Arc<dyn Transport>,
         peer_addr: SocketAddr,
         receive_queue: Sender<Packet>,
         stop_flag: Arc<Mutex<bool>>,
+        send_wakeup: Arc<Wakeup>,
+        ack_wakeup: Arc<Wakeup>,
         ack_check: Arc<Mutex<AcknowledgementCheck>>,
         ack_list: Arc<Mutex<AcknowledgementList>>,
-        recv_seq: Arc<Mutex<u32>>,
+        recv_seq: Arc<Mutex<Seq>>,
+        send_seq: Arc<Mutex<Seq>>,
+        delay: Arc<Mutex<DelayEstimator>>,
+        congestion: Arc<Mutex<CongestionController>>,
+        output_queue: Receiver<Packet>,
+        stats: Arc<Mutex<Histograms>>,
+        counters: Arc<LinkCounters>,
+        close_reason: Arc<Mutex<Option<CloseReason>>>,
+        closing: Arc<AtomicBool>,
+        close_wakeup: Arc<Wakeup>,
+        nacked: Arc<Mutex<Vec<Seq>>>,
+        extensions: Arc<Mutex<Extensions>>,
+        incoming: Arc<Mutex<Option<IncomingProgress>>>,
+        memory: Arc<MemoryBudget>,
+        buffers: Arc<BufferPool>,
+        tags: Arc<PacketTags>,
+        version: u8,
+        max_message_size: usize,
         config: Config,
     ) -> ReceiveThread {
         let recv_lock = recv_seq.lock().expect("Unable to lock recv_seq");
@@ -109,21 +211,60 @@ impl ReceiveThread {
 
         ReceiveThread {
             socket,
-            _peer_addr: peer_addr,
+            peer_addr,
             receive_queue,
             stop_flag,
+            send_wakeup,
+            ack_wakeup,
             ack_check,
             ack_list,
             _recv_seq: recv_seq,
             order_list: OrderList::new(seq),
+            highest: seq,
+            replay: ReplayWindow::new(seq, config.link.max_window),
+            send_seq,
+            delay,
+            congestion,
+            output_queue,
+            stats,
+            counters,
+            close_reason,
+            closing,
+            close_wakeup,
+            nacked,
+            extensions,
+            incoming,
+            memory,
+            buffers,
+            tags,
+            version,
+            max_message_size,
+            fragments: None,
+            message_length: None,
+            skipping: false,
+            log_limiter: LogLimiter::new(config.telemetry),
             config,
         }
     }
 
     pub fn start(&mut self) {
-        let buf_size = Packet::get_max_header_size(self.config.link.window_size) + 2048;
-        let mut buf: Vec<u8> = vec![0; buf_size];
+        let mut buf = self.buffers.take();
+        buf.resize(max_packet_size(), 0);
         let mut now = SystemTime::now();
+
+        // Peers sending keepalives can be declared dead after a few missed keepalives
+        let mut timeout = self.config.link.timeout;
+        if self.config.link.keepalive_interval > 0 && has_keepalive(self.version) {
+            let keepalive_timeout =
+                self.config.link.keepalive_interval * self.config.link.keepalive_misses as u64;
+            timeout = timeout.min(keepalive_timeout);
+        }
+
+        // Set once the other peer asked to close the link, which is answered after the
+        // packets sent by this end are acknowledged
+        let mut lingering: Option<Instant> = None;
+        let linger_timeout = Duration::from_millis(self.config.link.linger_timeout);
+
         loop {
             // If stop flag is set stop the thread
             let flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
@@ -134,63 +275,316 @@ impl ReceiveThread {
             // Unlock flag
             drop(flag_lock);
 
+            if let Some(since) = lingering {
+                let drained = is_drained(&self.send_seq, &self.ack_check).unwrap_or(true);
+                if drained || since.elapsed() >= linger_timeout {
+                    self.send_close();
+                    self.close(CloseReason::Closed);
+                    break;
+                }
+            }
+
             /* Simulate packet loss
             if thread_rng().gen_range(0..100) < 99 {
                 continue;
             }*/
 
-            let size = match self.socket.recv(&mut buf) {
-                Ok(result) => result,
-                _ => 0,
-            };
+            let size = self.socket.recv(&mut buf).unwrap_or_default();
 
             if size > 0 {
+                // Malformed packets are dropped and do not count as activity on the link
+                // as are packets not authenticated by the key of an encrypted link
+                let packet = match self.tags.decode_slice(&buf[..size], self.version) {
+                    Ok(packet) => packet,
+                    Err(PacketError::Unauthenticated) => {
+                        self.counters.forgery();
+                        limited!(warn, self.log_limiter, "Dropping unauthenticated packet");
+                        continue;
+                    }
+                    Err(err) => {
+                        limited!(warn, self.log_limiter, "Dropping malformed packet: {}", err);
+                        continue;
+                    }
+                };
+
+                trace!(packet = %packet, "Received packet");
+
+                if packet.flags.p_type == PType::Close {
+                    if self.closing.load(atomic::Ordering::SeqCst) {
+                        // Answer to the close packets sent by this end
+                        self.close_wakeup.notify();
+                        continue;
+                    }
+
+                    // The other peer is closing the link and waits for an answer, which
+                    // is sent once the packets queued on this end are delivered
+                    lingering.get_or_insert_with(Instant::now);
+                    continue;
+                }
+
+                // A message larger than agreed on is never delivered. Dropping it
+                // alone would hold up all later messages, so the link is closed
+                if self.exceeds_limit(&packet) {
+                    warn!(
+                        "Closing link, packet {} exceeds the maximum message size of {} bytes",
+                        packet, self.max_message_size
+                    );
+                    self.close(CloseReason::ProtocolError);
+                    break;
+                }
+
                 now = SystemTime::now();
-                let packet = Packet::from(buf[..size].to_vec());
+
+                let mut stats_lock = self.stats.lock().expect("Unable to lock stats");
+                stats_lock.packet_size.record(size as u64);
+                drop(stats_lock);
+
+                self.counters.received(size);
+
+                if packet.flags.p_type == PType::Nack {
+                    self.recv_nack(&packet);
+                    continue;
+                }
+
                 let exists = self.check_ack(&packet);
                 self.recv_ack(&packet);
-                self.send_ack(&packet);
-                if !exists {
+                if !self.send_ack(&packet) {
+                    limited!(
+                        warn,
+                        self.log_limiter,
+                        "Dropping packet {} too far ahead of the acknowledgement window",
+                        packet
+                    );
+                } else if !exists {
+                    // Packets received before and no longer waiting to be acknowledged
+                    // were captured and injected again
+                    if needs_ack(&packet) && !self.replay.insert(packet.sequence) {
+                        self.counters.replay();
+                        limited!(
+                            warn,
+                            self.log_limiter,
+                            "Dropping replayed packet {}",
+                            packet
+                        );
+                        continue;
+                    }
+
+                    self.send_nack(&packet);
                     self.output(packet);
                 }
             } else {
                 let elapsed = now.elapsed().expect("unable to get system time");
-                if elapsed.as_millis() > self.config.link.timeout.into() {
-                    let mut flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
-                    *flag_lock = true;
+                if elapsed.as_millis() > timeout.into() {
+                    self.close(CloseReason::Broken);
                 }
             }
         }
     }
 
+    /// Check if the message carried by `packet` is larger than the maximum message size
+    fn exceeds_limit(&self, packet: &Packet) -> bool {
+        if !matches!(packet.flags.p_type, PType::Data | PType::Extended(_)) {
+            return false;
+        }
+
+        self.exceeds_size(packet.payload.len(), packet.flags.enc)
+    }
+
+    /// Check if a message of `size` bytes, encrypted if `enc` is set, is larger than the
+    /// maximum message size
+    fn exceeds_size(&self, size: usize, enc: bool) -> bool {
+        let overhead = if enc { ENCRYPTION_OVERHEAD } else { 0 };
+        size.saturating_sub(overhead) > self.max_message_size
+    }
+
+    /// Stop the link for `reason`, unless it is already being closed for another reason
+    fn close(&self, reason: CloseReason) {
+        let mut reason_lock = self
+            .close_reason
+            .lock()
+            .expect("Error locking close reason");
+        reason_lock.get_or_insert(reason);
+        drop(reason_lock);
+
+        let mut flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
+        *flag_lock = true;
+        drop(flag_lock);
+
+        self.send_wakeup.notify();
+        self.ack_wakeup.notify();
+    }
+
+    /// Answer the close packet of the other peer
+    fn send_close(&self) {
+        let packet = PacketBuilder::new(PType::Close)
+            .version(self.version)
+            .build()
+            .expect("Invalid close packet");
+
+        let mut data = self.buffers.take();
+        self.tags.compile_into(&packet, &mut data);
+
+        if let Err(err) = self.socket.send_to(&data, self.peer_addr) {
+            warn!("Unable to answer close: {}", err);
+        }
+    }
+
+    /// Request the packets skipped by `packet` with a NACK packet, if it is the first
+    /// packet received after a gap in the sequence numbers
+    fn send_nack(&mut self, packet: &Packet) {
+        if !needs_ack(packet)
+            || packet.sequence.partial_cmp(&self.highest) != Some(Ordering::Greater)
+        {
+            return;
+        }
+
+        let first = self.highest + 1;
+        let gap = packet.sequence.distance(first);
+        self.highest = packet.sequence;
+
+        if gap == 0 || !self.config.link.nack || !has_nack(self.version) {
+            return;
+        }
+
+        let missing: Vec<Seq> = (0..gap.min(MAX_NACK_COUNT as u32))
+            .map(|offset| first + offset)
+            .collect();
+        let nack = PacketBuilder::new(PType::Nack)
+            .payload(encode_nack(&missing))
+            .version(self.version)
+            .build()
+            .expect("Invalid NACK packet");
+
+        let mut data = self.buffers.take();
+        self.tags.compile_into(&nack, &mut data);
+
+        match self.socket.send_to(&data, self.peer_addr) {
+            Ok(size) => {
+                trace!(packet = %nack, "Sent packet");
+                self.counters.sent(size, false);
+                self.counters.nack();
+            }
+            Err(err) => limited!(warn, self.log_limiter, "Unable to send NACK: {}", err),
+        }
+    }
+
+    /// Queue the packets requested by the other peer to be sent again
+    fn recv_nack(&mut self, packet: &Packet) {
+        let missing = match decode_nack(&packet.payload) {
+            Ok(missing) => missing,
+            Err(err) => {
+                limited!(warn, self.log_limiter, "Dropping malformed NACK: {}", err);
+                return;
+            }
+        };
+
+        let mut nacked_lock = self.nacked.lock().expect("Unable to lock nacked");
+        (*nacked_lock).extend(missing.into_iter().take(MAX_NACK_COUNT));
+        drop(nacked_lock);
+
+        self.send_wakeup.notify();
+    }
+
     fn check_ack(&self, packet: &Packet) -> bool {
         let ack_lock = self.ack_list.lock().expect("Unable to lack ack list");
         (*ack_lock).check(&packet.sequence)
     }
 
-    fn send_ack(&self, packet: &Packet) {
+    /// Add `packet` to the acknowledgements to be sent. Returns false if it is too far
+    /// ahead to be acknowledged, in which case it has to be sent again later
+    fn send_ack(&self, packet: &Packet) -> bool {
         if needs_ack(packet) {
+            let delay_lock = self.delay.lock().expect("Unable to lock delay estimator");
+            let recv_time_us = (*delay_lock).now_us();
+            drop(delay_lock);
+
+            // Signal congestion if too many packets are waiting to be read
+            let queued = self.receive_queue.len() + self.output_queue.len();
+
             let mut ack_lock = self.ack_list.lock().expect("Unable to lack ack list");
-            (*ack_lock).insert(packet.sequence);
+            let pending = (*ack_lock).is_pending();
+            if !(*ack_lock).insert_with_time(packet.sequence, recv_time_us) {
+                return false;
+            }
+            (*ack_lock).set_congestion(queued > self.config.link.congestion_threshold);
+            drop(ack_lock);
+
+            // The acknowledgement thread is already waiting to send earlier ones
+            if !pending {
+                self.ack_wakeup.notify();
+            }
         }
+        true
     }
 
     fn recv_ack(&self, packet: &Packet) {
         let mut ack_lock = self.ack_check.lock().expect("unable to lock ack check");
         (*ack_lock).acknowledge(packet.ack.clone());
+        drop(ack_lock);
+
+        if packet.flags.ack {
+            self.send_wakeup.notify();
+
+            let mut delay_lock = self.delay.lock().expect("Unable to lock delay estimator");
+            let rtt_sample = (*delay_lock).on_ack(&packet.ack, has_ack_timestamps(self.version));
+            drop(delay_lock);
+
+            if let Some(rtt_us) = rtt_sample {
+                let mut stats_lock = self.stats.lock().expect("Unable to lock stats");
+                stats_lock.rtt_us.record(rtt_us);
+            }
+
+            if packet.ack.congestion {
+                let seq_lock = self.send_seq.lock().expect("Unable to lock seq");
+                let send_seq = *seq_lock;
+                drop(seq_lock);
+
+                let mut congestion_lock = self
+                    .congestion
+                    .lock()
+                    .expect("Unable to lock congestion controller");
+                (*congestion_lock).on_congestion(packet.ack.ack_begin, send_seq);
+            }
+        }
     }
 
     fn output(&mut self, packet: Packet) {
         match packet.flags.p_type {
             PType::AckOnly => (),
+            PType::Keepalive => (),
+            PType::Reserved(_) => {
+                limited!(
+                    warn,
+                    self.log_limiter,
+                    "Dropping packet of unknown type: {}",
+                    packet
+                )
+            }
             _ => self.order_output(packet),
         }
     }
 
-    fn order_output(&mut self, packet: Packet) {
+    fn order_output(&mut self, mut packet: Packet) {
+        // Held in the reorder buffer and queues until read
+        packet.charge = Some(Box::new(self.memory.charge(packet_memory(&packet))));
+
         match self.order_list.insert(packet) {
             Ok(mut packets) => {
                 while let Some(p) = packets.pop_front() {
+                    let p = match self.join_fragments(p) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+
+                    // Packets of reserved subtypes are received with messages
+                    match p.flags.p_type {
+                        PType::Extended(subtype) if !is_reserved(subtype) => {
+                            self.dispatch(p);
+                            continue;
+                        }
+                        _ => (),
+                    }
+
                     self.receive_queue
                         .send(p)
                         .expect("Unable to push to output queue");
@@ -201,4 +595,138 @@ impl ReceiveThread {
             _ => panic!("Unexpected error"),
         }
     }
+
+    /// Join `packet` with the fragments of the same message received before it, in
+    /// order. Returns the message once its last fragment is received
+    fn join_fragments(&mut self, mut packet: Packet) -> Option<Packet> {
+        // Expired packets only kept the place of a message, or a fragment of one, the
+        // other peer gave up on. The rest of the message is dropped as well
+        if self.skipping || packet.flags.p_type == PType::Expired {
+            self.drop_fragments(packet.flags.more);
+            return None;
+        }
+
+        let mut message = match self.fragments.take() {
+            Some(mut message) => {
+                message.payload.extend_from_slice(&packet.payload);
+                message.flags.more = packet.flags.more;
+                message
+            }
+            None if packet.flags.more => {
+                if has_message_length(self.version) {
+                    self.message_length = self.take_length(&mut packet);
+                    if self.message_length.is_none() {
+                        warn!("Closing link, first fragment {} is invalid", packet);
+                        self.close(CloseReason::ProtocolError);
+                        self.drop_fragments(true);
+                        return None;
+                    }
+                }
+                packet
+            }
+            None => return Some(packet),
+        };
+
+        // Fragments are limited by the size of the message, not of a single packet
+        if self.exceeds_limit(&message) || self.exceeds_length(&message) {
+            warn!(
+                "Closing link, message {} exceeds the maximum message size of {} bytes or \
+                 its announced size",
+                message, self.max_message_size
+            );
+            self.close(CloseReason::ProtocolError);
+            self.drop_fragments(message.flags.more);
+            return None;
+        }
+
+        message.charge = Some(Box::new(self.memory.charge(packet_memory(&message))));
+        if message.flags.more {
+            if let Some(total) = self.message_length {
+                self.set_progress(Some(IncomingProgress {
+                    received: message.payload.len(),
+                    total,
+                }));
+            }
+            self.fragments = Some(message);
+            None
+        } else {
+            self.message_length = None;
+            self.set_progress(None);
+            Some(message)
+        }
+    }
+
+    /// Remove the size of the message from its first fragment `packet` (refer
+    /// [`has_message_length`]). Returns [`None`] if the fragment is too short or the
+    /// message would exceed the maximum message size
+    fn take_length(&self, packet: &mut Packet) -> Option<usize> {
+        if packet.payload.len() < MESSAGE_LENGTH_SIZE {
+            return None;
+        }
+
+        let mut bytes = [0; MESSAGE_LENGTH_SIZE];
+        bytes.copy_from_slice(&packet.payload[..MESSAGE_LENGTH_SIZE]);
+        packet.payload.drain(..MESSAGE_LENGTH_SIZE);
+        let length = u32::from_be_bytes(bytes) as usize;
+        if self.exceeds_size(length, packet.flags.enc) {
+            return None;
+        }
+        Some(length)
+    }
+
+    /// Check if the fragments joined into `message` are larger than the size the other
+    /// peer announced, or all of them are joined and they are smaller
+    fn exceeds_length(&self, message: &Packet) -> bool {
+        match self.message_length {
+            Some(length) if message.flags.more => message.payload.len() > length,
+            Some(length) => message.payload.len() != length,
+            None => false,
+        }
+    }
+
+    /// Drop the fragments of the message being joined, and those following until the
+    /// last one if `more` is set
+    fn drop_fragments(&mut self, more: bool) {
+        self.fragments = None;
+        self.message_length = None;
+        self.skipping = more;
+        self.set_progress(None);
+    }
+
+    /// Publish the progress of the message being received to the [`crate::link::Link`]
+    fn set_progress(&self, progress: Option<IncomingProgress>) {
+        let mut incoming_lock = self
+            .incoming
+            .lock()
+            .expect("Unable to lock incoming progress");
+        *incoming_lock = progress;
+    }
+
+    /// Pass an extended packet to the handler registered for its subtype
+    fn dispatch(&mut self, packet: Packet) {
+        let p_type = packet.flags.p_type.clone();
+
+        let extensions_lock = self.extensions.lock().expect("Unable to lock extensions");
+        let routed = (*extensions_lock).route(packet);
+        drop(extensions_lock);
+
+        // The handler is called without holding the lock, so that it can register or
+        // unregister handlers
+        match routed.and_then(|routed| routed.map(Routed::deliver).transpose()) {
+            Ok(Some(())) => (),
+            Ok(None) => limited!(
+                warn,
+                self.log_limiter,
+                "Dropping {} packet without a handler",
+                p_type
+            ),
+            Err(err) => limited!(
+                warn,
+                self.log_limiter,
+                "Dropping {} packet: {}",
+                p_type,
+                err
+            ),
+        }
+    }
 }
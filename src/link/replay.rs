@@ -0,0 +1,140 @@
+//! Detection of packets received more than once.
+//!
+//! An attacker capturing the encrypted packets of a link can inject them again later.
+//! Packets still waiting to be acknowledged are recognised by the
+//! [`AcknowledgementList`][crate::acknowledgement::AcknowledgementList], but older ones
+//! would be delivered a second time. The receive thread keeps a [`ReplayWindow`] of
+//! the sequence numbers received recently and drops packets whose sequence number was
+//! already received or is too old to tell.
+//!
+//! The window spans the largest acknowledgement window of the link, since the other
+//! peer never sends packets further ahead of the first one not received yet.
+
+use std::cmp::Ordering;
+
+use crate::sequence::Seq;
+
+/// Sliding window of the sequence numbers received recently
+#[derive(Debug, Clone)]
+pub struct ReplayWindow {
+    /// Latest sequence number received
+    highest: Seq,
+    /// Bit `i` is set if `highest - i` was received
+    bits: Vec<u64>,
+    /// Number of sequence numbers up to `highest` in the window
+    size: u32,
+}
+
+impl ReplayWindow {
+    /// Creates a new [`ReplayWindow`] of `size` sequence numbers where every sequence
+    /// number up to `seq` was received
+    pub fn new(seq: Seq, size: u16) -> ReplayWindow {
+        let size = size.max(1) as u32;
+        let words = (size as usize + 63) / 64;
+
+        ReplayWindow {
+            highest: seq,
+            bits: vec![u64::MAX; words],
+            size,
+        }
+    }
+
+    /// Check if a packet with sequence number `seq` was not received yet
+    pub fn check(&self, seq: Seq) -> bool {
+        match seq.partial_cmp(&self.highest) {
+            Some(Ordering::Greater) => true,
+            Some(Ordering::Less) => {
+                let offset = self.highest.distance(seq);
+                offset < self.size && !self.get(offset)
+            }
+            _ => false,
+        }
+    }
+
+    /// Record a packet with sequence number `seq` as received. Returns false if it was
+    /// received before or is too old to tell, in which case it should be dropped
+    pub fn insert(&mut self, seq: Seq) -> bool {
+        if !self.check(seq) {
+            return false;
+        }
+
+        if seq > self.highest {
+            self.shift(seq.distance(self.highest));
+            self.highest = seq;
+        }
+
+        let offset = self.highest.distance(seq);
+        self.bits[offset as usize / 64] |= 1 << (offset % 64);
+        true
+    }
+
+    /// Returns the bit of `highest - offset`
+    fn get(&self, offset: u32) -> bool {
+        self.bits[offset as usize / 64] & (1 << (offset % 64)) != 0
+    }
+
+    /// Move the window `count` sequence numbers ahead, the sequence numbers it now
+    /// covers were not received
+    fn shift(&mut self, count: u32) {
+        if count >= self.size {
+            self.bits.iter_mut().for_each(|word| *word = 0);
+            return;
+        }
+
+        let words = count as usize / 64;
+        let bits = count % 64;
+        for i in (0..self.bits.len()).rev() {
+            let mut word = if i >= words {
+                self.bits[i - words] << bits
+            } else {
+                0
+            };
+            if bits > 0 && i > words {
+                word |= self.bits[i - words - 1] >> (64 - bits);
+            }
+            self.bits[i] = word;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayWindow;
+    use crate::sequence::Seq;
+
+    #[test]
+    fn replay_test() {
+        let mut window = ReplayWindow::new(Seq(10), 100);
+
+        // Packets up to the initial sequence number were received
+        assert!(!window.insert(Seq(10)));
+        assert!(!window.insert(Seq(3)));
+
+        assert!(window.insert(Seq(11)));
+        assert!(!window.insert(Seq(11)));
+
+        // Packets received out of order are accepted once
+        assert!(window.insert(Seq(80)));
+        assert!(window.insert(Seq(12)));
+        assert!(window.insert(Seq(79)));
+        assert!(!window.insert(Seq(12)));
+        assert!(!window.insert(Seq(79)));
+        assert!(window.check(Seq(13)));
+
+        // Packets older than the window are dropped
+        assert!(window.insert(Seq(200)));
+        assert!(!window.check(Seq(13)));
+        assert!(!window.insert(Seq(100)));
+        assert!(window.insert(Seq(101)));
+        assert!(!window.insert(Seq(80)));
+
+        // The window keeps working across the wrap around of sequence numbers
+        let mut window = ReplayWindow::new(Seq(u32::MAX - 1), 100);
+        assert!(window.insert(Seq(3)));
+        assert!(window.insert(Seq(u32::MAX)));
+        assert!(window.insert(Seq(0)));
+        assert!(!window.insert(Seq(u32::MAX)));
+        assert!(!window.insert(Seq(3)));
+        assert!(window.insert(Seq(1)));
+    }
+}
@@ -1,35 +1,78 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::ErrorKind;
+use std::mem;
 use std::net::SocketAddr;
-use std::net::UdpSocket;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossbeam::channel::Receiver;
 use crossbeam::channel::TryRecvError;
+use tracing::{debug_span, trace, warn, Span};
 
 use crate::acknowledgement::{AcknowledgementCheck, AcknowledgementList};
 use crate::config::Config;
-use crate::link::needs_ack;
+use crate::link::congestion::CongestionController;
+use crate::link::delay::DelayEstimator;
+use crate::link::delivery::Cancellations;
+use crate::link::pool::BufferPool;
+use crate::link::tags::PacketTags;
+use crate::link::{needs_ack, take_ack};
 use crate::packet::PType;
 use crate::packet::Packet;
 use crate::packet::PacketMeta;
+use crate::packet::META_TYPE;
+use crate::sequence::Seq;
+use crate::stats::{Histograms, LinkCounters};
+use crate::sync::Mutex;
+use crate::telemetry::{
+    limited, LogLimiter, Telemetry, TelemetryEvent, COUNTER_EXPIRED, COUNTER_RETRANSMISSIONS,
+};
+use crate::transport::Transport;
+use crate::util::Wakeup;
+
+/// Longest time an idle send thread waits before checking for packets and the stop
+/// flag again, in case the link was stopped without waking it up
+const MAX_IDLE_WAIT: Duration = Duration::from_secs(1);
 
 pub struct SendThread {
     batch_queue: VecDeque<Packet>,
-    socket: Arc<UdpSocket>,
+    socket: Arc<dyn Transport>,
     peer_addr: SocketAddr,
     primary_queue: Receiver<Packet>,
     stop_flag: Arc<Mutex<bool>>,
+    /// Notified when packets are queued, acknowledgements are received or the link is
+    /// stopped
+    wakeup: Arc<Wakeup>,
 
     is_empty: Arc<Mutex<bool>>,
 
     ack_list: Arc<Mutex<AcknowledgementList>>,
     ack_check: Arc<Mutex<AcknowledgementCheck>>,
 
-    send_seq: Arc<Mutex<u32>>,
+    /// Set while packets of the current window are being sent again
+    retransmitting: bool,
+    /// Span of the window of packets currently in the batch queue
+    batch_span: Span,
+    /// Time each unacknowledged packet was last sent, to avoid sending it again
+    /// while it may still be in flight
+    last_sent: HashMap<Seq, Instant>,
+
+    delay: Arc<Mutex<DelayEstimator>>,
+    congestion: Arc<Mutex<CongestionController>>,
+    stats: Arc<Mutex<Histograms>>,
+    counters: Arc<LinkCounters>,
+    telemetry: Arc<dyn Telemetry>,
+    /// Sequence numbers of the packets requested again by the other peer
+    nacked: Arc<Mutex<Vec<Seq>>>,
+    /// Messages cancelled by the user, whose packets are replaced like expired ones
+    cancellations: Arc<Mutex<Cancellations>>,
+    version: u8,
+    /// Limits the messages logged for every retransmitted batch
+    log_limiter: LogLimiter,
+    /// Buffers packets are compiled into before they are sent
+    buffers: Arc<BufferPool>,
+    /// Tags packets once the link is encrypted
+    tags: Arc<PacketTags>,
 
     config: Config,
 }
@@ -37,14 +80,24 @@ pub struct SendThread {
 impl SendThread {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        socket: Arc<UdpSocket>,
+        socket: Arc<dyn Transport>,
         peer_addr: SocketAddr,
         primary_queue: Receiver<Packet>,
         stop_flag: Arc<Mutex<bool>>,
+        wakeup: Arc<Wakeup>,
         ack_check: Arc<Mutex<AcknowledgementCheck>>,
         ack_list: Arc<Mutex<AcknowledgementList>>,
-        send_seq: Arc<Mutex<u32>>,
         is_empty: Arc<Mutex<bool>>,
+        delay: Arc<Mutex<DelayEstimator>>,
+        congestion: Arc<Mutex<CongestionController>>,
+        stats: Arc<Mutex<Histograms>>,
+        counters: Arc<LinkCounters>,
+        telemetry: Arc<dyn Telemetry>,
+        nacked: Arc<Mutex<Vec<Seq>>>,
+        cancellations: Arc<Mutex<Cancellations>>,
+        buffers: Arc<BufferPool>,
+        tags: Arc<PacketTags>,
+        version: u8,
         config: Config,
     ) -> SendThread {
         SendThread {
@@ -53,10 +106,24 @@ impl SendThread {
             peer_addr,
             primary_queue,
             stop_flag,
+            wakeup,
             ack_check,
             ack_list,
-            send_seq,
+            retransmitting: false,
+            batch_span: Span::none(),
+            last_sent: HashMap::new(),
             is_empty,
+            delay,
+            congestion,
+            stats,
+            counters,
+            telemetry,
+            nacked,
+            cancellations,
+            version,
+            log_limiter: LogLimiter::new(config.telemetry),
+            buffers,
+            tags,
             config,
         }
     }
@@ -71,12 +138,17 @@ impl SendThread {
 
             drop(flag_lock);
 
+            self.retransmit_nacked();
+
+            let batch_span = self.batch_span.clone();
+            let _enter = batch_span.enter();
+
             match self.batch_queue.pop_front() {
                 Some(mut packet) => {
                     if packet.is_meta {
                         // If this is a meta packet check if it requires a delay
                         if packet.meta.delay_ms > 0 {
-                            thread::sleep(Duration::from_millis(packet.meta.delay_ms));
+                            self.wait(Duration::from_millis(packet.meta.delay_ms));
                         }
 
                         // only increase retries if batch queue still has packets to send
@@ -84,6 +156,15 @@ impl SendThread {
                             // Increase retry count since after this same packets
                             // will be sent again
                             let retry_count = packet.meta.retry_count + 1;
+                            self.retransmitting = true;
+
+                            limited!(
+                                trace,
+                                self.log_limiter,
+                                retry_count,
+                                pending = self.batch_queue.len(),
+                                "Retransmitting batch"
+                            );
 
                             if retry_count >= self.config.link.max_retries {
                                 // Stop connection if too many retries
@@ -91,7 +172,8 @@ impl SendThread {
                                     self.stop_flag.lock().expect("Error locking stop flag");
                                 *flag_lock = true;
                             } else {
-                                let mut meta_packet = Packet::new(PType::Extended, 0);
+                                let mut meta_packet =
+                                    Packet::new(PType::Reserved(META_TYPE), Seq(0));
 
                                 meta_packet.set_meta(PacketMeta {
                                     retry_count,
@@ -102,8 +184,33 @@ impl SendThread {
                             }
                         }
                     } else if !self.check_ack(&packet) {
+                        // Packets sent by Link::send are only tracked until acknowledged
+                        if let Some(sent_at) = packet.sent_at.take() {
+                            self.last_sent.insert(packet.sequence, sent_at);
+                            self.batch_queue.push_back(packet);
+                            continue;
+                        }
+
+                        let expired = self.expire(&mut packet);
+                        if self.retransmitting && needs_ack(&packet) {
+                            // Wait for the acknowledgement of a recently sent packet
+                            // instead of flooding a slow link with duplicates
+                            if self.in_flight(&packet) {
+                                self.counters.suppress();
+                                self.batch_queue.push_back(packet);
+                                continue;
+                            }
+
+                            if !expired {
+                                self.count_retransmit(&packet);
+                            }
+                        }
+
                         self.add_ack(&mut packet);
                         self.send(packet);
+                    } else {
+                        // Acknowledged packets are dropped from the batch queue
+                        self.last_sent.remove(&packet.sequence);
                     }
                 }
                 None => {
@@ -113,10 +220,10 @@ impl SendThread {
                     let mut retry_delay = self.config.link.retry_delay;
                     // If still empty
                     if self.batch_queue.is_empty() {
+                        // Acknowledgement only packets are sent by the ack thread, so
+                        // wait until woken up by new packets
                         (*empty_lock) = true;
-                        // Send a ack only packet (with empty payload)
-                        self.batch_queue.push_back(self.ack_packet());
-                        retry_delay = self.config.link.ack_only_time;
+                        retry_delay = MAX_IDLE_WAIT.as_millis() as u64;
                     } else {
                         (*empty_lock) = false;
                     }
@@ -125,7 +232,7 @@ impl SendThread {
 
                     // At end of each window push a meta packet
                     // This is to keep track of number of retries
-                    let mut meta_packet = Packet::new(PType::Extended, 0);
+                    let mut meta_packet = Packet::new(PType::Reserved(META_TYPE), Seq(0));
 
                     // Retry count here is -1 so after trying once it is set to 0
                     meta_packet.set_meta(PacketMeta {
@@ -139,30 +246,82 @@ impl SendThread {
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        let empty_lock = self.is_empty.lock().expect("Unable to lock empty bool");
-        *empty_lock
+    /// Wait for up to `delay`, unless there is something to do before that: the
+    /// packets waiting for acknowledgements have all been acknowledged, or new packets
+    /// were queued while idle
+    fn wait(&self, delay: Duration) {
+        let deadline = Instant::now() + delay;
+
+        loop {
+            if self.has_work() || self.is_stopped() {
+                break;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            self.wakeup.wait_timeout(deadline - now);
+        }
     }
 
-    pub fn ack_packet(&self) -> Packet {
-        // Lock seq number
-        let seq_lock = self.send_seq.lock().expect("Unable to lock seq");
-        // Increase sequence number
+    fn has_work(&self) -> bool {
+        if self.batch_queue.is_empty() {
+            return !self.primary_queue.is_empty();
+        }
 
-        let seq: u32 = *seq_lock;
+        let nacked_lock = self.nacked.lock().expect("Unable to lock nacked");
+        if !(*nacked_lock).is_empty() {
+            return true;
+        }
+        drop(nacked_lock);
 
-        // Create a new packet to be sent
-        Packet::new(PType::AckOnly, seq)
+        let ack_lock = self.ack_check.lock().expect("Unable to lock ack list");
+        self.batch_queue
+            .iter()
+            .all(|packet| packet.is_meta || (*ack_lock).check(&packet.sequence))
+    }
+
+    fn is_stopped(&self) -> bool {
+        let flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
+        *flag_lock
+    }
+
+    #[cfg(feature = "raw")]
+    pub fn is_empty(&self) -> bool {
+        let empty_lock = self.is_empty.lock().expect("Unable to lock empty bool");
+        *empty_lock
     }
 
     pub fn fetch_window(&mut self) {
-        for _ in 0..self.config.link.window_size {
+        let mut congestion_lock = self
+            .congestion
+            .lock()
+            .expect("Unable to lock congestion controller");
+        // Batch queue is empty so the whole previous window has been acknowledged
+        (*congestion_lock).on_window_complete();
+        let window = (*congestion_lock).window();
+        drop(congestion_lock);
+        self.retransmitting = false;
+
+        for _ in 0..window {
             match self.primary_queue.try_recv() {
                 Ok(packet) => self.batch_queue.push_back(packet),
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => panic!("Primary queue disconnected"),
             }
         }
+
+        self.batch_span = match self.batch_queue.front() {
+            Some(packet) => debug_span!(
+                "batch",
+                window,
+                first = %packet,
+                size = self.batch_queue.len()
+            ),
+            None => Span::none(),
+        };
     }
 
     pub fn check_ack(&self, packet: &Packet) -> bool {
@@ -174,14 +333,122 @@ impl SendThread {
         }
     }
 
+    /// Send the packets requested by the other peer again right away, if they still wait
+    /// for acknowledgement
+    fn retransmit_nacked(&mut self) {
+        let mut nacked_lock = self.nacked.lock().expect("Unable to lock nacked");
+        let nacked = mem::take(&mut *nacked_lock);
+        drop(nacked_lock);
+
+        for sequence in nacked {
+            let position = self.batch_queue.iter().position(|packet| {
+                !packet.is_meta && needs_ack(packet) && packet.sequence == sequence
+            });
+
+            // Packets no longer in the batch queue have been acknowledged since
+            let mut packet = match position.and_then(|index| self.batch_queue.remove(index)) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            if self.check_ack(&packet) {
+                self.last_sent.remove(&packet.sequence);
+                continue;
+            }
+
+            packet.sent_at = None;
+            if !self.expire(&mut packet) {
+                self.count_retransmit(&packet);
+                self.counters.nack_retransmit();
+            }
+
+            self.add_ack(&mut packet);
+            self.send(packet);
+        }
+    }
+
+    /// Replace `packet` with an empty [`PType::Expired`] packet of the same sequence
+    /// number if its deadline passed or its message was cancelled, so that the other
+    /// peer does not wait for it. Returns true if it was replaced
+    fn expire(&self, packet: &mut Packet) -> bool {
+        let mut cancellations_lock = self
+            .cancellations
+            .lock()
+            .expect("Unable to lock cancellations");
+        let cancelled = (*cancellations_lock).take(packet.sequence);
+        drop(cancellations_lock);
+
+        if !cancelled {
+            match packet.deadline {
+                Some(deadline) if deadline <= Instant::now() => (),
+                _ => return false,
+            }
+        }
+
+        packet.flags.p_type = PType::Expired;
+        packet.flags.enc = false;
+        packet.payload.clear();
+        packet.deadline = None;
+        // Releases the memory held by the payload
+        packet.charge = None;
+
+        if cancelled {
+            trace!(sequence = %packet.sequence, "Dropping cancelled packet");
+            return true;
+        }
+
+        trace!(sequence = %packet.sequence, "Dropping expired packet");
+        self.counters.expire();
+        self.telemetry.event(&TelemetryEvent::Expired {
+            peer_addr: self.peer_addr,
+            sequence: packet.sequence,
+        });
+        self.telemetry.counter(COUNTER_EXPIRED, 1);
+        true
+    }
+
+    /// Record that `packet` is being sent again
+    fn count_retransmit(&self, packet: &Packet) {
+        let mut congestion_lock = self
+            .congestion
+            .lock()
+            .expect("Unable to lock congestion controller");
+        (*congestion_lock).on_retransmit();
+        drop(congestion_lock);
+
+        self.counters.retransmit();
+        self.telemetry.event(&TelemetryEvent::Retransmit {
+            peer_addr: self.peer_addr,
+            sequence: packet.sequence,
+        });
+        self.telemetry.counter(COUNTER_RETRANSMISSIONS, 1);
+    }
+
+    /// Check if a packet was sent again within its retransmit timeout
+    pub fn in_flight(&self, packet: &Packet) -> bool {
+        match self.last_sent.get(&packet.sequence) {
+            Some(sent) => {
+                let delay_lock = self.delay.lock().expect("Unable to lock delay estimator");
+                let timeout = (*delay_lock)
+                    .retransmit_timeout(Duration::from_millis(self.config.link.retry_delay));
+                drop(delay_lock);
+
+                sent.elapsed() < timeout
+            }
+            None => false,
+        }
+    }
+
     pub fn add_ack(&self, packet: &mut Packet) {
-        let ack_lock = self.ack_list.lock().expect("Unable to lock ack list");
-        let ack = (*ack_lock).get();
+        let ack = take_ack(&self.ack_list, &self.delay, &self.stats)
+            .expect("Unable to take acknowledgements");
         packet.add_ack(ack);
     }
 
-    pub fn send(&mut self, packet: Packet) {
-        let data = packet.compile();
+    pub fn send(&mut self, mut packet: Packet) {
+        packet.version = self.version;
+        let mut data = self.buffers.take();
+        self.tags.compile_into(&packet, &mut data);
 
         let result = loop {
             match self.socket.send_to(&data, self.peer_addr) {
@@ -190,7 +457,14 @@ impl SendThread {
                 }
                 Err(err) => match err.kind() {
                     ErrorKind::PermissionDenied => continue,
-                    _ => panic!("Unable to send data: {}", err),
+                    _ => {
+                        // The other peer cannot be reached any more, such as a closed
+                        // stream transport
+                        warn!("Unable to send data, stopping link: {}", err);
+                        let mut flag_lock = self.stop_flag.lock().expect("Error locking stop flag");
+                        *flag_lock = true;
+                        return;
+                    }
                 },
             }
         };
@@ -199,7 +473,22 @@ impl SendThread {
             panic!("Cannot sent");
         }
 
-        if needs_ack(&packet) {
+        trace!(packet = %packet, "Sent packet");
+
+        let mut stats_lock = self.stats.lock().expect("Unable to lock stats");
+        stats_lock.packet_size.record(result as u64);
+        drop(stats_lock);
+
+        let reliable = needs_ack(&packet);
+        self.counters.sent(result, reliable);
+
+        if reliable {
+            self.last_sent.insert(packet.sequence, Instant::now());
+
+            let mut delay_lock = self.delay.lock().expect("Unable to lock delay estimator");
+            (*delay_lock).on_send(packet.sequence);
+            drop(delay_lock);
+
             self.batch_queue.push_back(packet);
         }
     }
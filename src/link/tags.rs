@@ -0,0 +1,133 @@
+//! Authentication tags of the packets of a [`Link`][crate::link::Link].
+//!
+//! Messages are encrypted as a whole before they are split into packets, so the cipher
+//! does not cover the sequence numbers and headers of the packets carrying them. Once
+//! the key of the link is agreed on, every packet is sent with a tag authenticating all
+//! of it and packets without a valid tag are dropped before they are acknowledged or
+//! recorded in the [`ReplayWindow`][crate::link::replay::ReplayWindow] (refer
+//! [`has_packet_tags`]).
+//!
+//! Each peer starts tagging its packets as soon as it knows the key, which may be
+//! before the other peer does. Packets received before the key is known are accepted
+//! without verifying their tag, like any packet of an unencrypted link, and untagged
+//! packets received after are dropped and sent again by the other peer once it tags
+//! them as well.
+
+use crate::encryption::AetherCipher;
+use crate::error::PacketError;
+use crate::packet::{has_packet_tags, Packet};
+use crate::sync::Mutex;
+
+/// Key of the tags of a link, shared by the threads sending and receiving its packets
+#[derive(Debug)]
+pub struct PacketTags {
+    cipher: Mutex<Option<AetherCipher>>,
+}
+
+impl PacketTags {
+    /// Creates a new [`PacketTags`] without key, packets are neither tagged nor
+    /// verified until [`PacketTags::enable`] is called
+    pub fn new() -> PacketTags {
+        PacketTags {
+            cipher: Mutex::new("link.tags", None),
+        }
+    }
+
+    /// Tag and verify packets with the key of `cipher` from now on
+    pub fn enable(&self, cipher: AetherCipher) {
+        if let Ok(mut lock) = self.cipher.lock() {
+            *lock = Some(cipher);
+        }
+    }
+
+    fn cipher(&self) -> Option<AetherCipher> {
+        self.cipher.lock().ok().and_then(|lock| lock.clone())
+    }
+
+    /// Compile `packet` into `buf`, ending it with its tag once the key is known and
+    /// the protocol version of the packet has [`has_packet_tags`]
+    pub fn compile_into(&self, packet: &Packet, buf: &mut Vec<u8>) {
+        match self.cipher() {
+            Some(cipher) => packet.compile_tagged_into(buf, &cipher),
+            None => packet.compile_into(buf),
+        }
+    }
+
+    /// Decode the packet in `bytes` received on a link of protocol version `version`,
+    /// verifying its tag once the key is known and `version` has [`has_packet_tags`]
+    ///
+    /// # Errors
+    ///
+    /// Refer [`Packet::decode_tagged`]
+    pub fn decode_slice(&self, bytes: &[u8], version: u8) -> Result<Packet, PacketError> {
+        match self.cipher() {
+            Some(cipher) if has_packet_tags(version) => {
+                Packet::decode_tagged(bytes, version, &cipher)
+            }
+            _ => Packet::decode_slice(bytes, version),
+        }
+    }
+}
+
+impl Default for PacketTags {
+    fn default() -> Self {
+        PacketTags::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption::{AetherCipher, KEY_SIZE};
+    use crate::error::PacketError;
+    use crate::link::tags::PacketTags;
+    use crate::packet::{PType, PacketBuilder, CHECKSUM_SIZE, PROTOCOL_VERSION};
+    use crate::sequence::Seq;
+    use crate::util::{crc32, gen_nonce};
+
+    #[test]
+    fn tags_test() {
+        let secret = gen_nonce(KEY_SIZE);
+        let sender = PacketTags::new();
+        let receiver = PacketTags::new();
+
+        let packet = PacketBuilder::new(PType::Data)
+            .sequence(Seq(42))
+            .payload(b"Hello".to_vec())
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        // Untagged packets are accepted before the key is known
+        let mut untagged = Vec::new();
+        sender.compile_into(&packet, &mut untagged);
+        assert!(receiver.decode_slice(&untagged, PROTOCOL_VERSION).is_ok());
+
+        // and so are tagged ones, without verifying them
+        sender.enable(AetherCipher::new(secret.clone()));
+        let mut tagged = Vec::new();
+        sender.compile_into(&packet, &mut tagged);
+        let decoded = receiver.decode_slice(&tagged, PROTOCOL_VERSION).unwrap();
+        assert_eq!(decoded.payload, b"Hello".to_vec());
+
+        // Once the key is known only packets with a valid tag are accepted
+        receiver.enable(AetherCipher::new(secret));
+        let decoded = receiver.decode_slice(&tagged, PROTOCOL_VERSION).unwrap();
+        assert_eq!(decoded.sequence, Seq(42));
+        assert_eq!(decoded.payload, b"Hello".to_vec());
+
+        assert!(matches!(
+            receiver.decode_slice(&untagged, PROTOCOL_VERSION),
+            Err(PacketError::Unauthenticated)
+        ));
+
+        // The sequence number is authenticated even with a valid checksum
+        let end = tagged.len() - CHECKSUM_SIZE;
+        tagged[3] ^= 1;
+        let checksum = crc32(&tagged[..end]);
+        tagged[end..].copy_from_slice(&checksum.to_be_bytes());
+        assert!(matches!(
+            receiver.decode_slice(&tagged, PROTOCOL_VERSION),
+            Err(PacketError::Unauthenticated)
+        ));
+    }
+}
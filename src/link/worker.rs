@@ -0,0 +1,117 @@
+//! Scheduling hints for the worker threads of a [`Link`][crate::link::Link].
+//!
+//! Every link runs its own send, receive, acknowledgement and decryption threads. On
+//! busy links these compete for the CPU with the threads of the application, which
+//! matters to applications with latency sensitive threads of their own (such as
+//! real-time audio). [`worker_cpus`][LinkConfig::worker_cpus] restricts the link threads
+//! to some CPUs and [`worker_nice`][LinkConfig::worker_nice] lowers their priority, so
+//! the application can keep the remaining CPUs or priority to itself.
+//!
+//! The hints are only applied on Linux. Hints that cannot be applied (such as a negative
+//! nice value without the privileges for it) are logged and otherwise ignored.
+
+use std::io;
+
+use tracing::warn;
+
+use crate::config::LinkConfig;
+
+/// Apply the scheduling hints of `config` to the calling thread
+pub fn apply_hints(config: &LinkConfig) {
+    if config.worker_cpus != 0 {
+        if let Err(err) = set_affinity(config.worker_cpus) {
+            warn!("Unable to set CPU affinity of link thread: {}", err);
+        }
+    }
+
+    if config.worker_nice != 0 {
+        if let Err(err) = set_nice(config.worker_nice) {
+            warn!("Unable to set priority of link thread: {}", err);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: u64) -> io::Result<()> {
+    // SAFETY: `set` is a valid CPU set that outlives the calls using it
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in (0..u64::BITS as usize).filter(|cpu| cpus & (1 << cpu) != 0) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_nice(nice: i8) -> io::Result<()> {
+    // The nice value applies to a single thread when set for its thread id
+    // SAFETY: Neither call takes pointers
+    let result = unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, nice as libc::c_int)
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU affinity is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_nice(_nice: i8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread priorities are only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::thread;
+
+    use super::apply_hints;
+    use crate::config::LinkConfig;
+
+    #[test]
+    fn hints_test() {
+        let config = LinkConfig {
+            worker_cpus: 1,
+            worker_nice: 1,
+            ..LinkConfig::default()
+        };
+
+        // Hints only apply to the thread they are applied on
+        let (cpu, nice) = thread::spawn(move || {
+            apply_hints(&config);
+            // SAFETY: Neither call takes pointers
+            unsafe {
+                let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+                (
+                    libc::sched_getcpu(),
+                    libc::getpriority(libc::PRIO_PROCESS, tid),
+                )
+            }
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(cpu, 0);
+        assert_eq!(nice, 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Accounting of the memory used by the links of an [`Aether`][crate::peer::Aether]
+//! client, for devices with little memory.
+//!
+//! Links charge the memory they hold to a shared [`MemoryBudget`]: a fixed amount for
+//! their own state ([`LINK_MEMORY`], [`CIPHER_MEMORY`] once encrypted) and every packet
+//! waiting in their queues or reorder buffer. A charge is a [`MemoryCharge`] which
+//! gives its memory back when dropped, so packets release theirs wherever they end up
+//! being dropped.
+//!
+//! If [`memory_budget`][crate::config::AetherConfig::memory_budget] is set, new
+//! connections are only admitted while the [`projected_memory`] of each connection
+//! still fits into the budget.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::config::LinkConfig;
+use crate::error::AetherError;
+use crate::packet::{Packet, MAX_PAYLOAD_SIZE};
+
+/// Memory used by the state of a [`Link`][crate::link::Link] and the buffers of its
+/// threads, including a full [`BufferPool`][crate::link::pool::BufferPool] and the
+/// largest [`ReplayWindow`][crate::link::replay::ReplayWindow] (in bytes)
+pub const LINK_MEMORY: usize = 40 * 1024;
+
+/// Memory used by the cipher and decryption thread of an encrypted
+/// [`Link`][crate::link::Link] (in bytes)
+pub const CIPHER_MEMORY: usize = 1024;
+
+/// Largest amount of memory held by a single queued packet (in bytes)
+pub const PACKET_MEMORY: usize = mem::size_of::<Packet>() + MAX_PAYLOAD_SIZE;
+
+/// Returns the memory held by `packet` while it is queued
+pub fn packet_memory(packet: &Packet) -> usize {
+    mem::size_of::<Packet>() + packet.payload.len()
+}
+
+/// Returns the memory a connection is expected to use with `config`: the state of its
+/// link, its cipher and a full window of packets both in its send queues and its
+/// reorder buffer
+pub fn projected_memory(config: &LinkConfig) -> usize {
+    LINK_MEMORY + CIPHER_MEMORY + 2 * config.window_size as usize * PACKET_MEMORY
+}
+
+/// Memory shared by the links of a client
+#[derive(Debug)]
+pub struct MemoryBudget {
+    /// Largest amount of memory to be used (in bytes), `0` if unlimited
+    limit: usize,
+    /// Memory currently charged (in bytes)
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    /// Creates a new [`MemoryBudget`] of `limit` bytes. `0` never rejects anything, but
+    /// the memory used is still counted
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the limit of the budget (in bytes), `0` if unlimited
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the memory currently charged to the budget (in bytes)
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Charge `bytes` to the budget until the returned [`MemoryCharge`] is dropped.
+    /// Charges are never rejected, the budget is only enforced by [`MemoryBudget::admit`]
+    pub fn charge(self: &Arc<Self>, bytes: usize) -> MemoryCharge {
+        self.used.fetch_add(bytes, Ordering::SeqCst);
+        MemoryCharge {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    /// Check if `required` more bytes fit into the budget
+    ///
+    /// # Errors
+    ///
+    /// * [`AetherError::ResourceBudgetExceeded`] - If the memory used would exceed the
+    ///   limit
+    pub fn admit(&self, required: usize) -> Result<(), AetherError> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+
+        let available = self.limit.saturating_sub(self.used());
+        if required > available {
+            Err(AetherError::ResourceBudgetExceeded {
+                required,
+                available,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Memory charged to a [`MemoryBudget`], given back when dropped
+#[derive(Debug)]
+pub struct MemoryCharge {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl MemoryCharge {
+    /// Returns the number of bytes charged
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{projected_memory, MemoryBudget, LINK_MEMORY};
+    use crate::config::LinkConfig;
+    use crate::error::AetherError;
+
+    #[test]
+    fn budget_test() {
+        let budget = Arc::new(MemoryBudget::new(1000));
+
+        let charge = budget.charge(600);
+        assert_eq!(budget.used(), 600);
+        assert!(budget.admit(400).is_ok());
+
+        match budget.admit(401) {
+            Err(AetherError::ResourceBudgetExceeded {
+                required,
+                available,
+            }) => {
+                assert_eq!(required, 401);
+                assert_eq!(available, 400);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        drop(charge);
+        assert_eq!(budget.used(), 0);
+        assert!(budget.admit(1000).is_ok());
+
+        // Unlimited budgets only count
+        let unlimited = Arc::new(MemoryBudget::new(0));
+        let _charge = unlimited.charge(usize::MAX / 2);
+        assert!(unlimited.admit(usize::MAX / 2).is_ok());
+
+        assert!(projected_memory(&LinkConfig::default()) > LINK_MEMORY);
+    }
+}
@@ -0,0 +1,271 @@
+//! Upgrades of the files stored in the aether config dir.
+//!
+//! The layout of the config dir (identity, configuration, contacts and peer cache) is
+//! versioned by a schema version recorded in its `schema_version` file. A config dir
+//! without that file predates versioning and is at [`BASE_SCHEMA_VERSION`].
+//!
+//! Changes to the layout or the format of any of the files have to bump
+//! [`SCHEMA_VERSION`] and add a [`Migration`] to [`MIGRATIONS`] that upgrades the files
+//! in place. Before a migration runs, each file it touches is copied to
+//! `<file>.v<version>.bak`, where `<version>` is the schema version it is upgraded
+//! from.
+//!
+//! Only the versioning and the backups are provided so far, [`MIGRATIONS`] is empty
+//! because no released layout needs upgrading: the keys have always been stored as
+//! [`PRIVATE_KEY_FILE`][crate::identity::PRIVATE_KEY_FILE] and
+//! [`PUBLIC_KEY_FILE`][crate::identity::PUBLIC_KEY_FILE], and no field of the
+//! configuration file was renamed. Configuration files written by older versions still
+//! load, since fields they lack take their defaults. Formats used on the wire, such as
+//! acknowledgements, are not stored and are negotiated per link instead (refer
+//! [`PROTOCOL_VERSION`][crate::packet::PROTOCOL_VERSION]).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+use crate::error::AetherError;
+
+/// Schema version of config dirs created before the schema version was recorded
+pub const BASE_SCHEMA_VERSION: u32 = 1;
+/// Schema version of the config dir written by this version of the crate
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Name of the file the schema version is recorded in
+pub const SCHEMA_VERSION_FILE: &str = "schema_version";
+
+/// Upgrade of the config dir to a schema version
+pub struct Migration {
+    /// Schema version after the migration
+    pub version: u32,
+    /// Description of the change, for logs
+    pub description: &'static str,
+    /// Names of the files in the config dir changed by the migration, which are backed
+    /// up first
+    pub files: &'static [&'static str],
+    /// Upgrade the files in the given config dir
+    pub run: fn(&Path) -> Result<(), AetherError>,
+}
+
+/// Migrations in order of their versions
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// Returns the schema version recorded in the config dir `dir`
+pub fn schema_version(dir: &Path) -> Result<u32, AetherError> {
+    match fs::read_to_string(dir.join(SCHEMA_VERSION_FILE)) {
+        Ok(data) => data
+            .trim()
+            .parse()
+            .map_err(|_| AetherError::InvalidSchemaVersion(data.trim().to_string())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BASE_SCHEMA_VERSION),
+        Err(err) => Err(AetherError::FileRead(err)),
+    }
+}
+
+/// Upgrade the config dir `dir` to [`SCHEMA_VERSION`] using [`MIGRATIONS`]. Returns the
+/// schema version the config dir was upgraded from
+/// # Errors
+/// * [`AetherError::InvalidSchemaVersion`] - If the recorded schema version cannot be
+///   read or is newer than [`SCHEMA_VERSION`]
+///
+/// Errors from failed migrations are returned as well, leaving the schema version at
+/// the last migration that succeeded
+pub fn migrate(dir: &Path) -> Result<u32, AetherError> {
+    migrate_with(dir, MIGRATIONS, SCHEMA_VERSION)
+}
+
+fn migrate_with(dir: &Path, migrations: &[Migration], target: u32) -> Result<u32, AetherError> {
+    let initial = schema_version(dir)?;
+
+    if initial > target {
+        return Err(AetherError::InvalidSchemaVersion(initial.to_string()));
+    }
+
+    let mut current = initial;
+    // Migrations beyond the target are left for a later version of the crate
+    let pending = migrations
+        .iter()
+        .filter(|m| m.version > initial && m.version <= target);
+    for migration in pending {
+        info!(
+            "Migrating {} from schema version {} to {}: {}",
+            dir.display(),
+            current,
+            migration.version,
+            migration.description
+        );
+
+        for file in migration.files {
+            let path = dir.join(file);
+            if path.exists() {
+                fs::copy(&path, backup_path(&path, current)).map_err(AetherError::FileWrite)?;
+            }
+        }
+
+        (migration.run)(dir)?;
+
+        current = migration.version;
+        write_schema_version(dir, current)?;
+    }
+
+    if !dir.join(SCHEMA_VERSION_FILE).exists() || current != target {
+        write_schema_version(dir, target)?;
+    }
+
+    Ok(initial)
+}
+
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+fn write_schema_version(dir: &Path, version: u32) -> Result<(), AetherError> {
+    fs::write(dir.join(SCHEMA_VERSION_FILE), version.to_string()).map_err(AetherError::FileWrite)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{
+        migrate, migrate_with, schema_version, Migration, BASE_SCHEMA_VERSION, SCHEMA_VERSION,
+    };
+    use crate::config::Config;
+    use crate::error::AetherError;
+    use crate::identity::Id;
+
+    fn config_dir(name: &str) -> &Path {
+        let dir = Path::new(name);
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        dir
+    }
+
+    fn rename_field(dir: &Path) -> Result<(), AetherError> {
+        let path = dir.join("config.yaml");
+        let data = fs::read_to_string(&path).map_err(AetherError::FileRead)?;
+        fs::write(&path, data.replace("old_field", "new_field")).map_err(AetherError::FileWrite)
+    }
+
+    fn fail(_: &Path) -> Result<(), AetherError> {
+        Err(AetherError::InvalidSchemaVersion("failed".to_string()))
+    }
+
+    #[test]
+    fn record_test() {
+        let dir = config_dir("./tmp/migration_record");
+
+        assert_eq!(migrate(dir).unwrap(), SCHEMA_VERSION);
+        assert_eq!(schema_version(dir).unwrap(), SCHEMA_VERSION);
+
+        fs::write(dir.join("schema_version"), "1000").unwrap();
+        assert!(migrate(dir).is_err());
+
+        fs::write(dir.join("schema_version"), "invalid").unwrap();
+        assert!(migrate(dir).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn legacy_layout_test() {
+        let dir = config_dir("./tmp/migration_legacy");
+
+        // Layout written before the schema version was recorded
+        let config = "---\naether:\n  server_retry_delay: 1000\n  server_poll_time: 1000\n\
+                      link:\n  window_size: 20\n  timeout: 10000\n";
+        fs::write(dir.join("config.yaml"), config).unwrap();
+        let id = Id::new().unwrap();
+        id.save_to(dir).unwrap();
+
+        assert_eq!(migrate(dir).unwrap(), BASE_SCHEMA_VERSION);
+        assert_eq!(schema_version(dir).unwrap(), SCHEMA_VERSION);
+
+        // Nothing needed upgrading, so the files are unchanged and still load
+        assert_eq!(fs::read_to_string(dir.join("config.yaml")).unwrap(), config);
+        let loaded = Config::from_file(&dir.join("config.yaml")).unwrap();
+        assert_eq!(loaded.link.window_size, 20);
+        assert_eq!(
+            loaded.link.keepalive_interval,
+            Config::default().link.keepalive_interval
+        );
+        assert_eq!(
+            Id::load_from(dir).unwrap().public_key_to_base64().unwrap(),
+            id.public_key_to_base64().unwrap()
+        );
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 4);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_test() {
+        let dir = config_dir("./tmp/migration_migrate");
+        fs::write(dir.join("config.yaml"), "aether:\n  old_field: 1\n").unwrap();
+
+        let migrations = [
+            Migration {
+                version: 2,
+                description: "Rename old_field",
+                files: &["config.yaml", "missing.yaml"],
+                run: rename_field,
+            },
+            Migration {
+                version: 3,
+                description: "Fail",
+                files: &[],
+                run: fail,
+            },
+        ];
+
+        assert!(migrate_with(dir, &migrations, 3).is_err());
+
+        // The successful migration is recorded and its files backed up
+        assert_eq!(schema_version(dir).unwrap(), 2);
+        let config = fs::read_to_string(dir.join("config.yaml")).unwrap();
+        assert_eq!(config, "aether:\n  new_field: 1\n");
+        let backup = fs::read_to_string(dir.join("config.yaml.v1.bak")).unwrap();
+        assert_eq!(backup, "aether:\n  old_field: 1\n");
+        assert!(!dir.join("missing.yaml.v1.bak").exists());
+
+        // Migrations already applied are skipped
+        assert_eq!(migrate_with(dir, &migrations[..1], 2).unwrap(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn target_test() {
+        let dir = config_dir("./tmp/migration_target");
+        fs::write(dir.join("config.yaml"), "aether:\n  old_field: 1\n").unwrap();
+
+        let migrations = [
+            Migration {
+                version: 2,
+                description: "Rename old_field",
+                files: &["config.yaml"],
+                run: rename_field,
+            },
+            Migration {
+                version: 3,
+                description: "Fail",
+                files: &[],
+                run: fail,
+            },
+        ];
+
+        // Migrations to versions after the target are not run
+        assert_eq!(
+            migrate_with(dir, &migrations, 2).unwrap(),
+            BASE_SCHEMA_VERSION
+        );
+        assert_eq!(schema_version(dir).unwrap(), 2);
+        let config = fs::read_to_string(dir.join("config.yaml")).unwrap();
+        assert_eq!(config, "aether:\n  new_field: 1\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
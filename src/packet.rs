@@ -1,20 +1,424 @@
 //! Primitives for representing a unit of packet in Aether.
 
-use crate::acknowledgement::Acknowledgement;
-use crate::util::compile_u16;
-use crate::util::compile_u32;
+use crate::acknowledgement::{Acknowledgement, MAX_MISS_COUNT};
+use crate::encryption::{AetherCipher, TAG_SIZE};
+use crate::error::PacketError;
+use crate::memory::MemoryCharge;
+use crate::sequence::Seq;
+use crate::util::crc32;
+
+use openssl::memcmp;
+use serde::{Deserialize, Serialize};
 
 use std::convert::From;
+use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+use std::time::Instant;
 use std::vec::Vec;
 
-#[derive(Debug, Clone)]
+/// Largest payload of a packet sent over a [`Link`][crate::link::Link] in bytes
+pub const MAX_PAYLOAD_SIZE: usize = 2048;
+
+/// Largest payload of an initiation packet in bytes. Handshake packets are received in
+/// 1024 byte buffers with a 13 byte header
+pub const MAX_INITIATION_PAYLOAD_SIZE: usize = 1011;
+
+/// Base version of the Aether protocol. Packets exchanged before a version has been
+/// negotiated (such as handshake packets) are always encoded with this version
+pub const BASE_VERSION: u8 = 1;
+
+/// Latest version of the Aether protocol supported by this implementation
+///
+/// * Version 1 - Base packet format
+/// * Version 2 - Acknowledgements carry the receive timestamp of the latest packet
+/// * Version 3 - Acknowledgements carry a flags byte (congestion experienced)
+/// * Version 4 - Handshake hello carries a proof-of-work puzzle
+/// * Version 5 - Handshake hello carries a cookie to be echoed by the other peer
+/// * Version 6 - Idle links exchange [`PType::Keepalive`] packets
+/// * Version 7 - Handshake hello carries the largest message accepted by the sender
+/// * Version 8 - Links are closed by exchanging [`PType::Close`] packets
+/// * Version 9 - Handshake hello carries the largest window accepted by the sender
+/// * Version 10 - Packet headers carry the protocol version of the packet
+/// * Version 11 - Receivers request missing packets with [`PType::Nack`] packets
+/// * Version 12 - [`PType::Extended`] packets carry a subtype and are delivered to the
+///   handlers registered on the link
+/// * Version 13 - Packets end with a CRC-32 checksum, corrupted packets are dropped
+/// * Version 14 - Handshake hello carries a digest of the UID and is padded to a fixed
+///   size
+/// * Version 15 - Authentication challenges are answered with a digest of the nonce
+/// * Version 16 - Messages whose deadline passed are replaced with [`PType::Expired`]
+///   packets
+/// * Version 17 - Messages larger than a packet are split into fragments
+/// * Version 18 - The first fragment of a message starts with the size of the message
+/// * Version 19 - Display names, key rotations and headers are sent as
+///   [`PType::Extended`] packets of subtypes reserved for the library
+/// * Version 20 - Packets of encrypted links end with an authentication tag
+pub const PROTOCOL_VERSION: u8 = 20;
+
+/// Largest size of the acknowledgement extension in bytes
+pub const ACK_EXTENSION_SIZE: usize = 5;
+
+/// Size of the header without the missing list and extensions in bytes
+pub const BASE_HEADER_SIZE: usize = 13;
+
+/// Size of the version field following the base header in bytes, in protocol versions
+/// that have it (refer [`has_header_version`])
+pub const VERSION_FIELD_SIZE: usize = 1;
+
+/// Size of the subtype of [`PType::Extended`] packets, which precedes their payload in
+/// bytes
+pub const SUBTYPE_SIZE: usize = 1;
+
+/// Size of the checksum ending packets in bytes, in protocol versions that have it
+/// (refer [`has_checksum`])
+pub const CHECKSUM_SIZE: usize = 4;
+
+/// Size of the authentication tag preceding the checksum of packets sent on encrypted
+/// links in bytes, in protocol versions that have it (refer [`has_packet_tags`])
+pub const PACKET_TAG_SIZE: usize = TAG_SIZE;
+
+/// Largest number of sequence numbers requested by a single [`PType::Nack`] packet
+pub const MAX_NACK_COUNT: usize = 64;
+
+/// Bits of the flags byte that are reserved (must be zero) in the base version
+pub const FLAG_RESERVED_MASK: u8 = 0b11;
+
+/// Bit of the flags byte set on every fragment of a message but the last, in protocol
+/// versions that have it (refer [`has_fragments`])
+pub const FLAG_MORE_FRAGMENTS: u8 = 0b10;
+
+/// Bit of the flags byte set on packets ending with an authentication tag, in protocol
+/// versions that have it (refer [`has_packet_tags`])
+pub const FLAG_TAGGED: u8 = 0b01;
+
+/// Size of the message size preceding the payload of the first fragment of a message
+/// in bytes, in protocol versions that have it (refer [`has_message_length`])
+pub const MESSAGE_LENGTH_SIZE: usize = 4;
+
+/// Bit of the acknowledgement flags byte set when the receiver experienced congestion
+pub const ACK_FLAG_CONGESTION: u8 = 1;
+
+/// Bits of the flags byte that are reserved in the given protocol version. Packets with
+/// any of these bits set are rejected, so the bits can be allocated by later versions
+pub fn reserved_flags(version: u8) -> u8 {
+    let mut reserved = FLAG_RESERVED_MASK;
+    if has_fragments(version) {
+        reserved &= !FLAG_MORE_FRAGMENTS;
+    }
+    if has_packet_tags(version) {
+        reserved &= !FLAG_TAGGED;
+    }
+    reserved
+}
+
+/// Size of the acknowledgement extension in the given protocol version in bytes
+pub fn ack_extension_size(version: u8) -> usize {
+    let mut size = 0;
+    if has_ack_timestamps(version) {
+        size += 4;
+    }
+    if has_ack_flags(version) {
+        size += 1;
+    }
+    size
+}
+
+/// Check if acknowledgements carry receive timestamps in the given protocol version
+pub fn has_ack_timestamps(version: u8) -> bool {
+    version >= 2
+}
+
+/// Check if acknowledgements carry the acknowledgement flags byte in the given protocol
+/// version
+pub fn has_ack_flags(version: u8) -> bool {
+    version >= 3
+}
+
+/// Check if the handshake hello carries a proof-of-work puzzle in the given protocol
+/// version
+pub fn has_handshake_puzzle(version: u8) -> bool {
+    version >= 4
+}
+
+/// Check if the handshake hello carries an address validation cookie in the given
+/// protocol version
+pub fn has_handshake_cookie(version: u8) -> bool {
+    version >= 5
+}
+
+/// Check if idle links exchange keepalive packets in the given protocol version
+pub fn has_keepalive(version: u8) -> bool {
+    version >= 6
+}
+
+/// Check if the handshake hello carries the maximum message size of the sender in the
+/// given protocol version. Peers on older versions accept messages up to
+/// [`MAX_PAYLOAD_SIZE`]
+pub fn has_message_size(version: u8) -> bool {
+    version >= 7
+}
+
+/// Check if links are closed by exchanging close packets in the given protocol version,
+/// instead of the other peer waiting for the link to time out
+pub fn has_close(version: u8) -> bool {
+    version >= 8
+}
+
+/// Check if the handshake hello carries the largest window accepted by the sender in
+/// the given protocol version. Peers on older versions accept windows up to
+/// [`MAX_WINDOW`][crate::acknowledgement::MAX_WINDOW]
+pub fn has_window_size(version: u8) -> bool {
+    version >= 9
+}
+
+/// Check if packet headers carry the protocol version the packet was compiled with in
+/// the given protocol version. Packets are decoded with the version they carry, so a
+/// later version can change the format of packets on links that negotiated it while
+/// packets of a version the link does not support are rejected instead of misread
+pub fn has_header_version(version: u8) -> bool {
+    version >= 10
+}
+
+/// Check if receivers request packets missing before a received packet with
+/// [`PType::Nack`] packets in the given protocol version, so that the sender does not
+/// have to wait for the retransmission of its window to send them again
+pub fn has_nack(version: u8) -> bool {
+    version >= 11
+}
+
+/// Check if [`PType::Extended`] packets carry a subtype and are delivered reliably to
+/// the handlers registered on the link (refer [`crate::link::extension`]) in the given
+/// protocol version. Peers on older versions drop them
+pub fn has_extensions(version: u8) -> bool {
+    version >= 12
+}
+
+/// Check if packets end with a CRC-32 checksum of the preceding bytes in the given
+/// protocol version. Packets failing the check were corrupted on the way and are
+/// rejected instead of being delivered
+pub fn has_checksum(version: u8) -> bool {
+    version >= 13
+}
+
+/// Check if the handshake hello identifies the sender by the SHA-256 digest of its UID
+/// and is padded to [`HELLO_SIZE`][crate::peer::handshake::HELLO_SIZE] in the given
+/// protocol version. Initiation packets then have the same size whatever the key of the
+/// sender, so they neither reveal it nor get fragmented. Both peers know the UID of the
+/// other from the tracker, and the key is verified by the authentication following the
+/// handshake
+pub fn has_handshake_padding(version: u8) -> bool {
+    version >= 14
+}
+
+/// Check if peers answer authentication challenges with a digest of the decrypted nonce
+/// (refer [`challenge_answer`][crate::peer::authentication::challenge_answer]) in the
+/// given protocol version, instead of the nonce itself. Peers answering with the nonce
+/// would decrypt anything sent to them as a challenge, so peers on older versions are
+/// not authenticated
+pub fn has_challenge_digest(version: u8) -> bool {
+    version >= 15
+}
+
+/// Check if senders give up on messages whose deadline passed (refer
+/// [`Link::send_with_deadline`][crate::link::Link::send_with_deadline]) by sending a
+/// [`PType::Expired`] packet in their place in the given protocol version. Peers on
+/// older versions would wait for the dropped message forever, so deadlines are ignored
+/// on links to them
+pub fn has_deadlines(version: u8) -> bool {
+    version >= 16
+}
+
+/// Check if messages larger than [`MAX_PAYLOAD_SIZE`] are split into fragments sent in
+/// consecutive packets in the given protocol version. Every fragment but the last has
+/// [`FLAG_MORE_FRAGMENTS`] set, and the receiver joins them before delivering the
+/// message. Peers on older versions accept messages up to [`MAX_PAYLOAD_SIZE`]
+pub fn has_fragments(version: u8) -> bool {
+    version >= 17
+}
+
+/// Check if the first fragment of a message starts with the size of the whole message
+/// in bytes, [`MESSAGE_LENGTH_SIZE`] bytes big-endian, in the given protocol version.
+/// The size counts the fragments as sent, without itself. Receivers report the progress
+/// of messages being received with it (refer
+/// [`Link::incoming_progress`][crate::link::Link::incoming_progress])
+pub fn has_message_length(version: u8) -> bool {
+    version >= 18
+}
+
+/// Check if display names, key rotations and messages with headers are sent as
+/// [`PType::Extended`] packets of the subtypes reserved for the library (refer
+/// [`crate::peer::frames`]) in the given protocol version, so that they are never
+/// mistaken for messages. Packets of these subtypes are received in order with
+/// messages instead of being passed to handlers. Extended payloads larger than a packet
+/// are split into fragments like messages (refer [`has_fragments`])
+pub fn has_reserved_subtypes(version: u8) -> bool {
+    version >= 19
+}
+
+/// Check if packets sent on encrypted links end with an authentication tag in the
+/// given protocol version (refer [`AetherCipher::packet_tag`]). The tag of
+/// [`PACKET_TAG_SIZE`] bytes covers everything preceding it, sequence number and header
+/// included, is followed by the checksum and is flagged with [`FLAG_TAGGED`]. Once the
+/// key of the link is agreed on, receivers drop packets without a valid tag, so that
+/// altered or forged packets never take the place of the packets of the other peer
+pub fn has_packet_tags(version: u8) -> bool {
+    version >= 20
+}
+
+/// Size of the fixed part of the header in the given protocol version in bytes, which
+/// is followed by the missing list
+pub fn header_size(version: u8) -> usize {
+    if has_header_version(version) {
+        BASE_HEADER_SIZE + VERSION_FIELD_SIZE
+    } else {
+        BASE_HEADER_SIZE
+    }
+}
+
+/// Largest size of a packet sent over a [`Link`][crate::link::Link] in bytes: the
+/// header with the longest missing list and all extensions, the largest payload, the
+/// authentication tag and the checksum
+pub fn max_packet_size() -> usize {
+    Packet::get_max_header_size(MAX_MISS_COUNT)
+        + VERSION_FIELD_SIZE
+        + ACK_EXTENSION_SIZE
+        + MAX_PAYLOAD_SIZE
+        + PACKET_TAG_SIZE
+        + CHECKSUM_SIZE
+}
+
+/// Optional features of the protocol available in a protocol version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// See [`has_ack_timestamps`]
+    pub ack_timestamps: bool,
+    /// See [`has_ack_flags`]
+    pub ack_flags: bool,
+    /// See [`has_handshake_puzzle`]
+    pub handshake_puzzle: bool,
+    /// See [`has_handshake_cookie`]
+    pub handshake_cookie: bool,
+    /// See [`has_keepalive`]
+    pub keepalive: bool,
+    /// See [`has_message_size`]
+    pub message_size: bool,
+    /// See [`has_close`]
+    pub close: bool,
+    /// See [`has_window_size`]
+    pub window_size: bool,
+    /// See [`has_header_version`]
+    pub header_version: bool,
+    /// See [`has_nack`]
+    pub nack: bool,
+    /// See [`has_extensions`]
+    pub extensions: bool,
+    /// See [`has_checksum`]
+    pub checksum: bool,
+    /// See [`has_handshake_padding`]
+    pub handshake_padding: bool,
+    /// See [`has_challenge_digest`]
+    pub challenge_digest: bool,
+    /// See [`has_deadlines`]
+    pub deadlines: bool,
+    /// See [`has_fragments`]
+    pub fragments: bool,
+    /// See [`has_message_length`]
+    pub message_length: bool,
+    /// See [`has_reserved_subtypes`]
+    pub reserved_subtypes: bool,
+    /// See [`has_packet_tags`]
+    pub packet_tags: bool,
+}
+
+impl Capabilities {
+    /// Returns the [`Capabilities`] of the given protocol version
+    pub fn for_version(version: u8) -> Capabilities {
+        Capabilities {
+            ack_timestamps: has_ack_timestamps(version),
+            ack_flags: has_ack_flags(version),
+            handshake_puzzle: has_handshake_puzzle(version),
+            handshake_cookie: has_handshake_cookie(version),
+            keepalive: has_keepalive(version),
+            message_size: has_message_size(version),
+            close: has_close(version),
+            window_size: has_window_size(version),
+            header_version: has_header_version(version),
+            nack: has_nack(version),
+            extensions: has_extensions(version),
+            checksum: has_checksum(version),
+            handshake_padding: has_handshake_padding(version),
+            challenge_digest: has_challenge_digest(version),
+            deadlines: has_deadlines(version),
+            fragments: has_fragments(version),
+            message_length: has_message_length(version),
+            reserved_subtypes: has_reserved_subtypes(version),
+            packet_tags: has_packet_tags(version),
+        }
+    }
+}
+
+/// Returns the payload of a [`PType::Nack`] packet requesting the packets `missing`:
+/// their sequence numbers, 4 bytes each. At most [`MAX_NACK_COUNT`] are included
+pub fn encode_nack(missing: &[Seq]) -> Vec<u8> {
+    missing
+        .iter()
+        .take(MAX_NACK_COUNT)
+        .flat_map(|seq| seq.0.to_be_bytes())
+        .collect()
+}
+
+/// Returns the sequence numbers requested by the payload of a [`PType::Nack`] packet
+/// # Errors
+/// * [`PacketError::Truncated`] - The payload is not a list of sequence numbers
+pub fn decode_nack(payload: &[u8]) -> Result<Vec<Seq>, PacketError> {
+    if payload.len() % 4 != 0 {
+        return Err(PacketError::Truncated);
+    }
+
+    Ok(payload
+        .chunks_exact(4)
+        .map(|bytes| Seq(u32::from_be_bytes(bytes.try_into().unwrap())))
+        .collect())
+}
+
+/// Type value of [`PType::Extended`] packets, which are told apart by their subtype
+pub const EXTENDED_TYPE: u8 = 8;
+
+/// Type value of [`PType::Reserved`] packets used internally by a
+/// [`Link`][crate::link::Link] (meta packets). These are never sent on the wire
+pub const META_TYPE: u8 = 15;
+
+/// Type of a [`Packet`], sent as the upper 4 bits of the flags byte
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PType {
+    /// Carries a message, or a fragment of one
     Data,
+    /// Carries only an acknowledgement, and is not acknowledged itself
     AckOnly,
+    /// Carries the handshake hello of a peer initiating a link
     Initiation,
+    /// Sent on idle links to keep them open (refer [`has_keepalive`])
+    Keepalive,
+    /// Sent by a peer closing the link, and answered with the same type by the other peer
+    Close,
+    /// Sent by a receiver to request the packets missing before a received packet again.
+    /// Carries their sequence numbers (refer [`encode_nack`])
+    Nack,
+    /// Takes the place of a message the sender gave up on because its deadline passed,
+    /// so that the messages following it can be delivered. Carries no payload and is
+    /// acknowledged but not delivered (refer [`has_deadlines`])
+    Expired,
+    /// Carries the secret of a peer encrypted with the public key of the other, from
+    /// which both derive the key of the link
     KeyExchange,
-    Extended,
+    /// Packet defined by an application, carrying its subtype. The subtype precedes
+    /// the payload on the wire (refer [`has_extensions`])
+    Extended(u8),
+    /// Any type value not assigned to the other variants. Carries the raw 4 bit
+    /// type value
+    Reserved(u8),
 }
 
 impl From<PType> for u8 {
@@ -23,8 +427,23 @@ impl From<PType> for u8 {
             PType::Data => 0,
             PType::AckOnly => 1,
             PType::Initiation => 2,
+            PType::Keepalive => 3,
+            PType::Close => 4,
+            PType::Nack => 5,
+            PType::Expired => 6,
             PType::KeyExchange => 7,
-            PType::Extended => 15,
+            PType::Extended(_) => EXTENDED_TYPE,
+            PType::Reserved(p_type) => p_type & 0x0F,
+        }
+    }
+}
+
+impl fmt::Display for PType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PType::Extended(subtype) => write!(f, "Extended({})", subtype),
+            PType::Reserved(p_type) => write!(f, "Reserved({})", p_type),
+            p_type => fmt::Debug::fmt(p_type, f),
         }
     }
 }
@@ -35,23 +454,25 @@ impl From<u8> for PType {
             0 => PType::Data,
             1 => PType::AckOnly,
             2 => PType::Initiation,
+            3 => PType::Keepalive,
+            4 => PType::Close,
+            5 => PType::Nack,
+            6 => PType::Expired,
             7 => PType::KeyExchange,
-            _ => PType::Extended,
+            // The subtype is read along with the payload
+            EXTENDED_TYPE => PType::Extended(0),
+            other => PType::Reserved(other),
         }
     }
 }
 
-impl PartialEq for PType {
-    fn eq(&self, other: &Self) -> bool {
-        (self.clone() as u8) == (other.clone() as u8)
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PacketFlags {
     pub p_type: PType,
     pub ack: bool,
     pub enc: bool,
+    /// Set on every fragment of a message but the last (refer [`has_fragments`])
+    pub more: bool,
 }
 
 impl PacketFlags {
@@ -65,46 +486,74 @@ impl PacketFlags {
         if self.enc {
             byte |= 1 << 2;
         }
+        if self.more {
+            byte |= FLAG_MORE_FRAGMENTS;
+        }
         byte
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PacketMeta {
     pub delay_ms: u64,
     pub retry_count: i16,
 }
 
-#[derive(Debug)]
+/// Packets can be serialized with [`serde`], for example to record and replay the
+/// traffic of a link. Unlike [`Packet::compile`], every field is kept except for the
+/// local [`sent_at`][Packet::sent_at], [`deadline`][Packet::deadline] and
+/// [`charge`][Packet::charge]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Packet {
     pub flags: PacketFlags,
-    pub sequence: u32,
+    pub sequence: Seq,
     pub ack: Acknowledgement,
     pub payload: Vec<u8>,
     pub is_meta: bool,
     pub meta: PacketMeta,
+    /// Protocol version used to compile this packet. Both peers agree on it during the
+    /// handshake, it is only sent on the wire in versions that have
+    /// [`has_header_version`]
+    pub version: u8,
+    /// Time the packet was already sent, if it was sent before reaching the send thread.
+    /// Not sent on the wire
+    #[serde(skip)]
+    pub sent_at: Option<Instant>,
+    /// Time after which the packet is no longer worth sending (refer [`has_deadlines`]).
+    /// Not sent on the wire
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+    /// Memory held by the packet while it is queued, given back when it is dropped. Boxed
+    /// to keep packets small
+    #[serde(skip)]
+    pub charge: Option<Box<MemoryCharge>>,
 }
 
 impl Packet {
     /// Create a new Packet
+    /// > Note: No invariants are checked, prefer [`PacketBuilder`] when creating
+    /// > packets to be sent
     ///
     /// # Arguments
     ///
     /// * `id`    -   A u32 representing the id of the packet
-    /// * `sequence` - A u32 representing the sequence number of the packet
-    pub fn new(p_type: PType, sequence: u32) -> Packet {
+    /// * `sequence` - The sequence number of the packet
+    pub fn new(p_type: PType, sequence: Seq) -> Packet {
         Packet {
             flags: PacketFlags {
                 p_type,
                 ack: false,
                 enc: false,
+                more: false,
             },
             sequence,
             ack: Acknowledgement {
-                ack_begin: 0,
+                ack_begin: Seq(0),
                 ack_end: 0,
                 miss_count: 0,
                 miss: Vec::new(),
+                recv_time_us: 0,
+                congestion: false,
             },
             payload: Vec::new(),
             is_meta: false,
@@ -112,6 +561,10 @@ impl Packet {
                 delay_ms: 0,
                 retry_count: 0,
             },
+            version: BASE_VERSION,
+            sent_at: None,
+            deadline: None,
+            charge: None,
         }
     }
 
@@ -160,47 +613,260 @@ impl Packet {
     ///
     /// * 'self' - The Packet struct
     pub fn compile(&self) -> Vec<u8> {
-        // Vector to store final compiled packet structure
-        let mut packet_vector = Vec::<u8>::new();
+        let mut packet_vector = Vec::with_capacity(
+            header_size(self.version)
+                + self.ack.miss.len() * 2
+                + ACK_EXTENSION_SIZE
+                + SUBTYPE_SIZE
+                + self.payload.len()
+                + PACKET_TAG_SIZE
+                + CHECKSUM_SIZE,
+        );
+        self.compile_into(&mut packet_vector);
+        packet_vector
+    }
 
-        // Packet ID converting u32 to u8(vector)
-        // let slice_id = compile_u32(self.id);
-        // packet_vector.extend(slice_id);
+    /// Compile the packet into `buf`, replacing its contents. Threads sending many
+    /// packets reuse the same buffer instead of allocating one for every packet
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - Buffer to write the compiled packet to
+    pub fn compile_into(&self, buf: &mut Vec<u8>) {
+        self.compile_with(buf, None);
+    }
 
-        // Packet Sequence converting u32 to u8(vector)
-        let slice_sequence = compile_u32(self.sequence);
-        packet_vector.extend(slice_sequence);
+    /// Compile the packet into `buf` like [`Packet::compile_into`], ending it with the
+    /// authentication tag computed by `cipher` in protocol versions that have
+    /// [`has_packet_tags`]
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - Buffer to write the compiled packet to
+    /// * `cipher` - Cipher of the link the packet is sent on
+    pub fn compile_tagged_into(&self, buf: &mut Vec<u8>, cipher: &AetherCipher) {
+        self.compile_with(buf, Some(cipher).filter(|_| has_packet_tags(self.version)));
+    }
 
-        // Packet Ack Begin converting u32 to u8(vector)
-        let slice_ack_begin = compile_u32(self.ack.ack_begin);
-        packet_vector.extend(slice_ack_begin);
+    fn compile_with(&self, buf: &mut Vec<u8>, cipher: Option<&AetherCipher>) {
+        buf.clear();
 
-        let slice_ack_end = compile_u16(self.ack.ack_end);
-        packet_vector.extend(slice_ack_end);
+        let mut flags = self.flags.get_byte();
+        if cipher.is_some() {
+            flags |= FLAG_TAGGED;
+        }
 
-        packet_vector.push(self.flags.get_byte());
+        buf.extend_from_slice(&self.sequence.0.to_be_bytes());
+        buf.extend_from_slice(&self.ack.ack_begin.0.to_be_bytes());
+        buf.extend_from_slice(&self.ack.ack_end.to_be_bytes());
+        buf.push(flags);
+        buf.extend_from_slice(&self.ack.miss_count.to_be_bytes());
 
-        let slice_miss_count = compile_u16(self.ack.miss_count);
-        packet_vector.extend(slice_miss_count);
+        if has_header_version(self.version) {
+            buf.push(self.version);
+        }
 
-        let mut slice_miss: Vec<u8> = Vec::new();
-        self.ack
-            .miss
-            .clone()
-            .into_iter()
-            .map(compile_u16)
-            .for_each(|slice_part| slice_miss.extend(slice_part));
-        packet_vector.extend(slice_miss);
+        for miss in &self.ack.miss {
+            buf.extend_from_slice(&miss.to_be_bytes());
+        }
 
-        let slice_payload = self.payload.clone();
-        packet_vector.extend(slice_payload);
+        if self.flags.ack && has_ack_timestamps(self.version) {
+            buf.extend_from_slice(&self.ack.recv_time_us.to_be_bytes());
+        }
 
-        // currently the packet_vector is a vector of u8 but we have to convert into string and then into bytes
-        packet_vector
+        if self.flags.ack && has_ack_flags(self.version) {
+            let mut ack_flags: u8 = 0;
+            if self.ack.congestion {
+                ack_flags |= ACK_FLAG_CONGESTION;
+            }
+            buf.push(ack_flags);
+        }
+
+        if let PType::Extended(subtype) = self.flags.p_type {
+            buf.push(subtype);
+        }
+
+        buf.extend_from_slice(&self.payload);
+
+        if let Some(cipher) = cipher {
+            let tag = cipher.packet_tag(buf);
+            buf.extend_from_slice(&tag);
+        }
+
+        if has_checksum(self.version) {
+            let checksum = crc32(buf);
+            buf.extend_from_slice(&checksum.to_be_bytes());
+        }
     }
 
     pub fn get_max_header_size(window_size: u16) -> usize {
-        (13 + window_size * 2) as usize
+        BASE_HEADER_SIZE + window_size as usize * 2
+    }
+}
+
+/// Builder for [`Packet`]s which checks the invariants of the packet type when the
+/// packet is built
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use aether_lib::packet::{PType, PacketBuilder};
+/// use aether_lib::sequence::Seq;
+///
+/// let packet = PacketBuilder::new(PType::Data)
+///     .sequence(Seq(42))
+///     .payload(b"Hello".to_vec())
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(packet.payload, b"Hello".to_vec());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PacketBuilder {
+    p_type: PType,
+    sequence: Seq,
+    ack: Option<Acknowledgement>,
+    ack_required: bool,
+    enc: bool,
+    more: bool,
+    payload: Vec<u8>,
+    version: u8,
+}
+
+impl PacketBuilder {
+    /// Start building a [`Packet`] of the given [`PType`]
+    pub fn new(p_type: PType) -> PacketBuilder {
+        PacketBuilder {
+            p_type,
+            sequence: Seq(0),
+            ack: None,
+            ack_required: false,
+            enc: false,
+            more: false,
+            payload: Vec::new(),
+            version: BASE_VERSION,
+        }
+    }
+
+    /// Set the sequence number of the packet
+    pub fn sequence(mut self, sequence: Seq) -> PacketBuilder {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Attach an [`Acknowledgement`] to the packet
+    pub fn ack(mut self, ack: Acknowledgement) -> PacketBuilder {
+        self.ack = Some(ack);
+        self
+    }
+
+    /// Declare that there are received packets to be acknowledged. Data packets
+    /// must then carry an [`Acknowledgement`]
+    #[cfg(any(test, feature = "raw"))]
+    pub fn ack_required(mut self, ack_required: bool) -> PacketBuilder {
+        self.ack_required = ack_required;
+        self
+    }
+
+    /// Set if the payload is encrypted
+    pub fn encrypted(mut self, enc: bool) -> PacketBuilder {
+        self.enc = enc;
+        self
+    }
+
+    /// Set if more fragments of the message follow the packet (refer [`has_fragments`])
+    pub fn more_fragments(mut self, more: bool) -> PacketBuilder {
+        self.more = more;
+        self
+    }
+
+    /// Append bytes to the payload of the packet
+    pub fn payload(mut self, payload: Vec<u8>) -> PacketBuilder {
+        self.payload.extend(payload);
+        self
+    }
+
+    /// Set the protocol version the packet is compiled with
+    pub fn version(mut self, version: u8) -> PacketBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Largest payload allowed for the given [`PType`]
+    pub fn max_payload_size(p_type: &PType) -> usize {
+        match p_type {
+            PType::AckOnly | PType::Keepalive | PType::Close | PType::Expired => 0,
+            PType::Initiation => MAX_INITIATION_PAYLOAD_SIZE,
+            PType::Extended(_) => MAX_PAYLOAD_SIZE - SUBTYPE_SIZE,
+            _ => MAX_PAYLOAD_SIZE,
+        }
+    }
+
+    /// Build the [`Packet`]
+    ///
+    /// # Errors
+    ///
+    /// * [`PacketError::PayloadTooLarge`] - Payload exceeds the limit of the packet type
+    /// * [`PacketError::MissingAck`] - Acknowledgement only packets and data packets
+    ///   with outstanding acknowledgements must carry an [`Acknowledgement`]
+    /// * [`PacketError::InvalidAck`] - The missing list of the [`Acknowledgement`] is
+    ///   inconsistent or too long
+    /// * [`PacketError::InvalidEncryption`] - Only data and extended packets with a
+    ///   payload can be encrypted
+    /// * [`PacketError::InvalidFragment`] - Only data packets can be fragments of a
+    ///   message
+    pub fn build(self) -> Result<Packet, PacketError> {
+        let max = Self::max_payload_size(&self.p_type);
+        if self.payload.len() > max {
+            return Err(PacketError::PayloadTooLarge {
+                size: self.payload.len(),
+                max,
+            });
+        }
+
+        let ack_required = match self.p_type {
+            PType::AckOnly => true,
+            PType::Data => self.ack_required,
+            _ => false,
+        };
+        if ack_required && self.ack.is_none() {
+            return Err(PacketError::MissingAck);
+        }
+
+        if let Some(ack) = &self.ack {
+            if ack.miss_count as usize != ack.miss.len()
+                || ack.miss_count > MAX_MISS_COUNT
+                || ack
+                    .miss
+                    .iter()
+                    .any(|miss| *miss == 0 || *miss > ack.ack_end)
+            {
+                return Err(PacketError::InvalidAck);
+            }
+        }
+
+        if self.enc
+            && (!matches!(self.p_type, PType::Data | PType::Extended(_)) || self.payload.is_empty())
+        {
+            return Err(PacketError::InvalidEncryption);
+        }
+
+        if self.more && self.p_type != PType::Data {
+            return Err(PacketError::InvalidFragment);
+        }
+
+        let mut packet = Packet::new(self.p_type, self.sequence);
+        packet.version = self.version;
+        packet.set_enc(self.enc);
+        packet.flags.more = self.more;
+        if let Some(ack) = self.ack {
+            packet.add_ack(ack);
+        }
+        packet.append_payload(self.payload);
+
+        Ok(packet)
     }
 }
 
@@ -210,6 +876,7 @@ impl From<u8> for PacketFlags {
             p_type: PType::Data,
             ack: false,
             enc: false,
+            more: false,
         };
         flags.p_type = PType::from((byte >> 4) & 0x0F);
         if (byte >> 3) & 0x01 == 1 {
@@ -218,105 +885,306 @@ impl From<u8> for PacketFlags {
         if (byte >> 2) & 0x01 == 1 {
             flags.enc = true;
         }
+        if byte & FLAG_MORE_FRAGMENTS != 0 {
+            flags.more = true;
+        }
         flags
     }
 }
 
-impl From<Vec<u8>> for Packet {
-    // Create a packet structure from the received raw bytes
-    // # Arguments
-    // *bytes - A vector of u8 representing the raw bytes of the packet
-    fn from(bytes: Vec<u8>) -> Packet {
-        let mut packet_default = Packet {
-            flags: PacketFlags {
-                p_type: PType::Data,
-                ack: false,
-                enc: false,
-            },
-            sequence: 0,
-            ack: Acknowledgement {
-                ack_begin: 0,
-                ack_end: 0,
-                miss_count: 0,
-                miss: Vec::new(),
-            },
-            payload: Vec::new(),
-            is_meta: false,
-            meta: PacketMeta {
-                delay_ms: 0,
-                retry_count: 0,
-            },
-        };
+impl TryFrom<Vec<u8>> for Packet {
+    type Error = PacketError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Packet, PacketError> {
+        Packet::decode(bytes, BASE_VERSION)
+    }
+}
+
+impl TryFrom<&[u8]> for Packet {
+    type Error = PacketError;
+
+    fn try_from(bytes: &[u8]) -> Result<Packet, PacketError> {
+        Packet::decode_slice(bytes, BASE_VERSION)
+    }
+}
 
-        // Packet ID converting u8 to u32(vector)
-        // let id_array = bytes[0..4].try_into().unwrap();
-        // packet_default.id = u32::from_be_bytes(id_array);
+impl Packet {
+    /// Returns a single line summary of the packet, as used in logs and traces. See the
+    /// [`Display`][fmt::Display] implementation of [`Packet`]
+    pub fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    /// Create a packet structure from the received raw bytes compiled with the given
+    /// protocol version. The payload is moved out of `bytes` without copying it to a
+    /// new allocation
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`   -   A vector of u8 representing the raw bytes of the packet
+    /// * `version` -   Protocol version negotiated with the other peer
+    ///
+    /// # Errors
+    ///
+    /// Refer [`Packet::decode_slice`]
+    pub fn decode(mut bytes: Vec<u8>, version: u8) -> Result<Packet, PacketError> {
+        let (mut packet, payload) = Packet::decode_header(&bytes, version)?;
+
+        bytes.truncate(payload.end);
+        bytes.drain(..payload.start);
+        packet.payload = bytes;
 
-        // Packet Sequence converting u8 to u32(vector)
-        let sequence_array = bytes[0..4].try_into().unwrap();
-        packet_default.sequence = u32::from_be_bytes(sequence_array);
+        Ok(packet)
+    }
+
+    /// Create a packet structure from raw bytes borrowed from a receive buffer, compiled
+    /// with the given protocol version. Only the payload is copied
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`   -   The raw bytes of the packet
+    /// * `version` -   Protocol version negotiated with the other peer
+    ///
+    /// # Errors
+    ///
+    /// * [`PacketError::Truncated`] - The bytes are too short for the header they describe
+    /// * [`PacketError::ReservedFlags`] - Flag bits reserved in this protocol version are set
+    /// * [`PacketError::UnsupportedVersion`] - The packet carries a version later than
+    ///   `version` (refer [`has_header_version`])
+    /// * [`PacketError::ChecksumMismatch`] - The packet was corrupted (refer
+    ///   [`has_checksum`])
+    pub fn decode_slice(bytes: &[u8], version: u8) -> Result<Packet, PacketError> {
+        let (mut packet, payload) = Packet::decode_header(bytes, version)?;
+        packet.payload = bytes[payload].to_vec();
 
-        // Packet Ack Begin converting u8 to u32(vector)
-        let ack_begin_array = bytes[4..8].try_into().unwrap();
-        packet_default.ack.ack_begin = u32::from_be_bytes(ack_begin_array);
+        Ok(packet)
+    }
 
-        let ack_end_array = bytes[8..10].try_into().unwrap();
-        packet_default.ack.ack_end = u16::from_be_bytes(ack_end_array);
+    /// Create a packet structure from raw bytes like [`Packet::decode_slice`], verifying
+    /// the authentication tag ending the packet with `cipher` (refer
+    /// [`has_packet_tags`]). The tag of a packet decoded by [`Packet::decode_slice`] is
+    /// not verified
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`   -   The raw bytes of the packet
+    /// * `version` -   Protocol version negotiated with the other peer
+    /// * `cipher`  -   Cipher of the link the packet was received on
+    ///
+    /// # Errors
+    ///
+    /// * [`PacketError::Unauthenticated`] - The packet has no tag or the tag does not
+    ///   match its contents
+    /// * Refer [`Packet::decode_slice`] for the others
+    pub fn decode_tagged(
+        bytes: &[u8],
+        version: u8,
+        cipher: &AetherCipher,
+    ) -> Result<Packet, PacketError> {
+        let (mut packet, payload) = Packet::decode_header(bytes, version)?;
+        if bytes[10] & FLAG_TAGGED == 0 {
+            return Err(PacketError::Unauthenticated);
+        }
 
-        packet_default.flags = PacketFlags::from(bytes[10]);
+        let tag = &bytes[payload.end..payload.end + PACKET_TAG_SIZE];
+        if !memcmp::eq(&cipher.packet_tag(&bytes[..payload.end]), tag) {
+            return Err(PacketError::Unauthenticated);
+        }
 
-        let miss_count_array = bytes[11..13].try_into().unwrap();
-        packet_default.ack.miss_count = u16::from_be_bytes(miss_count_array);
+        packet.payload = bytes[payload].to_vec();
 
-        packet_default.ack.miss = (13..(13 + packet_default.ack.miss_count * 2) as usize)
+        Ok(packet)
+    }
+
+    /// Decode everything but the payload of the packet in `bytes`. Returns the packet
+    /// without payload along with the range of `bytes` holding the payload
+    fn decode_header(bytes: &[u8], version: u8) -> Result<(Packet, Range<usize>), PacketError> {
+        if bytes.len() < BASE_HEADER_SIZE {
+            return Err(PacketError::Truncated);
+        }
+
+        // Reserved bits must be zero, they may be allocated by a later version
+        let reserved = bytes[10] & reserved_flags(version);
+        if reserved != 0 {
+            return Err(PacketError::ReservedFlags(reserved));
+        }
+
+        let mut packet = Packet::new(PType::Data, Seq(read_u32(bytes, 0)));
+        packet.flags = PacketFlags::from(bytes[10]);
+        packet.version = version;
+        packet.ack.ack_begin = Seq(read_u32(bytes, 4));
+        packet.ack.ack_end = read_u16(bytes, 8);
+        packet.ack.miss_count = read_u16(bytes, 11);
+
+        // The rest of the packet is decoded with the version it was compiled with
+        let mut version = version;
+        if has_header_version(version) {
+            let packet_version = *bytes.get(BASE_HEADER_SIZE).ok_or(PacketError::Truncated)?;
+            if !has_header_version(packet_version) || packet_version > version {
+                return Err(PacketError::UnsupportedVersion(packet_version));
+            }
+            version = packet_version;
+            packet.version = version;
+        }
+
+        // Nothing but the version is trusted before the checksum is verified
+        let mut end = bytes.len();
+        if has_checksum(version) {
+            if end < header_size(version) + CHECKSUM_SIZE {
+                return Err(PacketError::Truncated);
+            }
+            end -= CHECKSUM_SIZE;
+            if crc32(&bytes[..end]) != read_u32(bytes, end) {
+                return Err(PacketError::ChecksumMismatch);
+            }
+        }
+
+        // The tag is verified by the caller (refer `Packet::decode_tagged`)
+        if bytes[10] & FLAG_TAGGED != 0 {
+            if end < header_size(version) + PACKET_TAG_SIZE {
+                return Err(PacketError::Truncated);
+            }
+            end -= PACKET_TAG_SIZE;
+        }
+        let bytes = &bytes[..end];
+
+        let fixed_size = header_size(version);
+
+        let mut payload_start = fixed_size + packet.ack.miss_count as usize * 2;
+
+        let mut header_size = payload_start;
+        if packet.flags.ack {
+            header_size += ack_extension_size(version);
+        }
+
+        if bytes.len() < header_size {
+            return Err(PacketError::Truncated);
+        }
+
+        packet.ack.miss = (fixed_size..payload_start)
             .step_by(2)
-            .into_iter()
-            .map(|i| u16::from_be_bytes(bytes[i..(i + 2)].try_into().unwrap()))
+            .map(|i| read_u16(bytes, i))
             .collect();
 
-        let payload_start = 13 + (packet_default.ack.miss_count * 2) as usize;
-        let payload_length = bytes.len() - payload_start;
-        // Packet Length converting u8 to u16(vector)
-        // let length_array = bytes[11 + packet_default.ack.miss_count as usize
-        //     ..13 + packet_default.ack.miss_count as usize]
-        //     .try_into()
-        //     .unwrap();
-        // packet_default.length = u16::from_be_bytes(length_array);
+        if packet.flags.ack && has_ack_timestamps(version) {
+            packet.ack.recv_time_us = read_u32(bytes, payload_start);
+            payload_start += 4;
+        }
 
-        packet_default.payload = bytes[payload_start..payload_start + payload_length].to_vec();
+        if packet.flags.ack && has_ack_flags(version) {
+            packet.ack.congestion = bytes[payload_start] & ACK_FLAG_CONGESTION != 0;
+            payload_start += 1;
+        }
+
+        if let PType::Extended(_) = packet.flags.p_type {
+            let subtype = *bytes.get(payload_start).ok_or(PacketError::Truncated)?;
+            packet.flags.p_type = PType::Extended(subtype);
+            payload_start += SUBTYPE_SIZE;
+        }
 
-        packet_default
+        Ok((packet, payload_start..end))
+    }
+}
+
+/// Read the big endian `u32` at `offset` of `bytes`, which must hold it
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read the big endian `u16` at `offset` of `bytes`, which must hold it
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+/// Formats the packet as a single line: type, sequence number, acknowledged range,
+/// miss count, flags and payload size, such as
+/// `Data seq=42 ack=1000..=1003 miss=1 flags=ack,enc len=5`.
+///
+/// The acknowledgement is only included if the `ack` flag is set. Meta packets are
+/// formatted with their delay and retry count instead
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_meta {
+            return write!(
+                f,
+                "Meta seq={} delay={}ms retry={}",
+                self.sequence, self.meta.delay_ms, self.meta.retry_count
+            );
+        }
+
+        write!(f, "{} seq={}", self.flags.p_type, self.sequence)?;
+
+        if self.flags.ack {
+            write!(
+                f,
+                " ack={}..={} miss={}",
+                self.ack.ack_begin,
+                self.ack.ack_begin + self.ack.ack_end as u32,
+                self.ack.miss_count
+            )?;
+        }
+
+        let mut flags = Vec::new();
+        if self.flags.ack {
+            flags.push("ack");
+        }
+        if self.flags.enc {
+            flags.push("enc");
+        }
+        if self.flags.more {
+            flags.push("more");
+        }
+        if self.flags.ack && self.ack.congestion {
+            flags.push("ce");
+        }
+        if flags.is_empty() {
+            write!(f, " flags=-")?;
+        } else {
+            write!(f, " flags={}", flags.join(","))?;
+        }
+
+        write!(f, " len={}", self.payload.len())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::packet::PType;
+    use crate::error::PacketError;
+    use crate::packet::{
+        decode_nack, encode_nack, header_size, reserved_flags, Capabilities, PType, PacketBuilder,
+        BASE_HEADER_SIZE, BASE_VERSION, CHECKSUM_SIZE, EXTENDED_TYPE, FLAG_MORE_FRAGMENTS,
+        FLAG_TAGGED, MAX_NACK_COUNT, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION, SUBTYPE_SIZE,
+        VERSION_FIELD_SIZE,
+    };
+    use crate::sequence::Seq;
+    use crate::util::crc32;
     use crate::{acknowledgement::AcknowledgementList, packet};
 
     use super::Packet;
+    use rand::{thread_rng, Rng};
+    use std::convert::TryFrom;
 
     #[test]
     fn range_test() {
-        let pack = packet::Packet::new(PType::Data, 0);
-        assert!(pack.ack.ack_begin <= pack.ack.ack_end.into());
-        assert!(pack.ack.miss_count as u32 <= (pack.ack.ack_end as u32 - pack.ack.ack_begin));
+        let pack = packet::Packet::new(PType::Data, Seq(0));
+        assert!(pack.ack.window().contains(pack.ack.ack_begin));
+        assert!(pack.ack.miss_count <= pack.ack.ack_end);
     }
 
     #[test]
     fn compile_test() {
-        let mut pack = packet::Packet::new(PType::KeyExchange, 32850943);
-        let mut ack_list = AcknowledgementList::new(329965);
-        ack_list.insert(329966);
-        ack_list.insert(329967);
-        ack_list.insert(329969);
-        ack_list.insert(331000);
+        let mut pack = packet::Packet::new(PType::KeyExchange, Seq(32850943));
+        let mut ack_list = AcknowledgementList::new(Seq(329965));
+        ack_list.insert(Seq(329966));
+        ack_list.insert(Seq(329967));
+        ack_list.insert(Seq(329969));
+        ack_list.insert(Seq(331000));
 
         pack.add_ack(ack_list.get());
         pack.append_payload(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
         let compiled = pack.compile();
 
-        let pack_out = packet::Packet::from(compiled);
+        let pack_out = packet::Packet::try_from(compiled).unwrap();
 
         assert_eq!(pack.sequence, pack_out.sequence);
 
@@ -332,6 +1200,165 @@ mod tests {
         assert_eq!(pack.payload, pack_out.payload);
     }
 
+    #[test]
+    fn serde_test() {
+        let mut pack = packet::Packet::new(PType::Extended(3), Seq(4200));
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert_with_time(Seq(1002), 123456);
+
+        pack.version = PROTOCOL_VERSION;
+        pack.add_ack(ack_list.get());
+        pack.ack.congestion = true;
+        pack.set_enc(true);
+        pack.append_payload(vec![1, 2, 3]);
+        pack.sent_at = Some(std::time::Instant::now());
+        pack.deadline = pack.sent_at;
+
+        let json = serde_json::to_string(&pack).unwrap();
+        let pack_out: Packet = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(pack_out.flags.p_type, PType::Extended(3));
+        assert!(pack_out.flags.enc);
+        assert_eq!(pack_out.ack.miss, pack.ack.miss);
+        assert_eq!(pack_out.ack.recv_time_us, 123456);
+        assert!(pack_out.ack.congestion);
+        assert_eq!(pack_out.version, PROTOCOL_VERSION);
+        assert_eq!(pack_out.sent_at, None);
+        assert_eq!(pack_out.deadline, None);
+
+        // Replayed packets compile to the same bytes
+        assert_eq!(pack_out.compile(), pack.compile());
+    }
+
+    #[test]
+    fn timestamp_test() {
+        let mut pack = packet::Packet::new(PType::Data, Seq(4200));
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert_with_time(Seq(1002), 123456);
+
+        pack.version = PROTOCOL_VERSION;
+        pack.add_ack(ack_list.get());
+        pack.append_payload(vec![1, 2, 3]);
+
+        let pack_out = packet::Packet::decode(pack.compile(), PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(pack_out.ack.recv_time_us, 123456);
+        assert_eq!(pack.ack.miss, pack_out.ack.miss);
+        assert_eq!(pack.payload, pack_out.payload);
+
+        // Base version does not carry the timestamp
+        pack.version = BASE_VERSION;
+        let pack_out = packet::Packet::decode(pack.compile(), BASE_VERSION).unwrap();
+
+        assert_eq!(pack_out.ack.recv_time_us, 0);
+        assert_eq!(pack.payload, pack_out.payload);
+    }
+
+    #[test]
+    fn extended_type_test() {
+        let mut pack = packet::Packet::new(PType::Extended(9), Seq(4200));
+        pack.append_payload(vec![1, 2, 3]);
+        let compiled = pack.compile();
+        assert_eq!(compiled.len(), BASE_HEADER_SIZE + SUBTYPE_SIZE + 3);
+
+        let pack_out = packet::Packet::try_from(compiled).unwrap();
+        assert_eq!(pack_out.flags.p_type, PType::Extended(9));
+        assert_eq!(u8::from(pack_out.flags.p_type), EXTENDED_TYPE);
+        assert_eq!(pack_out.payload, vec![1, 2, 3]);
+        assert_ne!(PType::Extended(9), PType::Extended(10));
+
+        // The subtype is part of the packet
+        let mut compiled = pack.compile();
+        compiled.truncate(BASE_HEADER_SIZE);
+        let result = packet::Packet::try_from(compiled);
+        assert_eq!(result.unwrap_err(), PacketError::Truncated);
+
+        let pack = packet::Packet::new(PType::Reserved(9), Seq(4200));
+        let pack_out = packet::Packet::try_from(pack.compile()).unwrap();
+        assert_eq!(pack_out.flags.p_type, PType::Reserved(9));
+        assert_eq!(u8::from(pack_out.flags.p_type), 9);
+    }
+
+    #[test]
+    fn builder_test() {
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1002));
+
+        let pack = PacketBuilder::new(PType::Data)
+            .sequence(Seq(42))
+            .ack(ack_list.get())
+            .ack_required(true)
+            .encrypted(true)
+            .payload(vec![1, 2, 3])
+            .build()
+            .unwrap();
+
+        assert_eq!(pack.sequence, Seq(42));
+        assert!(pack.flags.ack);
+        assert!(pack.flags.enc);
+        assert_eq!(pack.ack.miss, vec![1]);
+
+        let too_large = PacketBuilder::new(PType::Data)
+            .payload(vec![0; MAX_PAYLOAD_SIZE + 1])
+            .build();
+        assert_eq!(
+            too_large.unwrap_err(),
+            PacketError::PayloadTooLarge {
+                size: MAX_PAYLOAD_SIZE + 1,
+                max: MAX_PAYLOAD_SIZE
+            }
+        );
+
+        let keepalive = PacketBuilder::new(PType::Keepalive)
+            .payload(vec![1])
+            .build();
+        assert!(matches!(
+            keepalive,
+            Err(PacketError::PayloadTooLarge { .. })
+        ));
+
+        let ack_only = PacketBuilder::new(PType::AckOnly).build();
+        assert_eq!(ack_only.unwrap_err(), PacketError::MissingAck);
+
+        let data = PacketBuilder::new(PType::Data).ack_required(true).build();
+        assert_eq!(data.unwrap_err(), PacketError::MissingAck);
+
+        let mut ack = ack_list.get();
+        ack.miss_count = 5;
+        let invalid_ack = PacketBuilder::new(PType::AckOnly).ack(ack).build();
+        assert_eq!(invalid_ack.unwrap_err(), PacketError::InvalidAck);
+
+        let key_exchange = PacketBuilder::new(PType::KeyExchange)
+            .encrypted(true)
+            .payload(vec![1])
+            .build();
+        assert_eq!(key_exchange.unwrap_err(), PacketError::InvalidEncryption);
+    }
+
+    #[test]
+    fn congestion_flag_test() {
+        let mut pack = packet::Packet::new(PType::Data, Seq(4200));
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1001));
+        ack_list.set_congestion(true);
+
+        pack.version = PROTOCOL_VERSION;
+        pack.add_ack(ack_list.get());
+        pack.append_payload(vec![1, 2, 3]);
+
+        let pack_out = packet::Packet::decode(pack.compile(), PROTOCOL_VERSION).unwrap();
+
+        assert!(pack_out.ack.congestion);
+        assert_eq!(pack.payload, pack_out.payload);
+
+        // Version 2 does not carry acknowledgement flags
+        pack.version = 2;
+        let pack_out = packet::Packet::decode(pack.compile(), 2).unwrap();
+
+        assert!(!pack_out.ack.congestion);
+        assert_eq!(pack.payload, pack_out.payload);
+    }
+
     #[test]
     fn size_test() {
         let size = Packet::get_max_header_size(10000);
@@ -340,4 +1367,309 @@ mod tests {
 
         assert_eq!(size, 20013);
     }
+
+    #[test]
+    fn capabilities_test() {
+        assert_eq!(
+            Capabilities::for_version(BASE_VERSION),
+            Capabilities::default()
+        );
+
+        let capabilities = Capabilities::for_version(PROTOCOL_VERSION);
+        assert!(capabilities.ack_timestamps);
+        assert!(capabilities.ack_flags);
+        assert!(capabilities.handshake_puzzle);
+        assert!(capabilities.handshake_cookie);
+        assert!(capabilities.keepalive);
+        assert!(capabilities.message_size);
+        assert!(capabilities.close);
+        assert!(capabilities.window_size);
+        assert!(capabilities.header_version);
+        assert!(capabilities.nack);
+        assert!(capabilities.extensions);
+        assert!(capabilities.checksum);
+        assert!(capabilities.handshake_padding);
+        assert!(capabilities.challenge_digest);
+        assert!(capabilities.deadlines);
+        assert!(capabilities.fragments);
+        assert!(capabilities.message_length);
+        assert!(capabilities.reserved_subtypes);
+        assert!(capabilities.packet_tags);
+
+        let capabilities = Capabilities::for_version(3);
+        assert!(capabilities.ack_flags);
+        assert!(!capabilities.handshake_puzzle);
+        assert!(!Capabilities::for_version(15).deadlines);
+        assert!(!Capabilities::for_version(16).fragments);
+        assert!(!Capabilities::for_version(17).message_length);
+        assert!(!Capabilities::for_version(18).reserved_subtypes);
+        assert!(!Capabilities::for_version(19).packet_tags);
+    }
+
+    #[test]
+    fn header_version_test() {
+        let pack = PacketBuilder::new(PType::Data)
+            .sequence(Seq(1))
+            .payload(vec![1, 2, 3])
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        let compiled = pack.compile();
+        assert_eq!(compiled[BASE_HEADER_SIZE], PROTOCOL_VERSION);
+
+        let pack_out = packet::Packet::decode(compiled.clone(), PROTOCOL_VERSION).unwrap();
+        assert_eq!(pack_out.version, PROTOCOL_VERSION);
+        assert_eq!(pack_out.payload, vec![1, 2, 3]);
+
+        // Packets of later versions than negotiated are rejected
+        let mut later = compiled.clone();
+        later[BASE_HEADER_SIZE] = PROTOCOL_VERSION + 1;
+        let result = packet::Packet::decode(later, PROTOCOL_VERSION);
+        assert_eq!(
+            result.unwrap_err(),
+            PacketError::UnsupportedVersion(PROTOCOL_VERSION + 1)
+        );
+
+        // Earlier versions carry no version
+        let mut pack = pack;
+        pack.version = 9;
+        let compiled = pack.compile();
+        assert_eq!(compiled.len(), header_size(9) + 3);
+        assert_eq!(
+            header_size(9) + VERSION_FIELD_SIZE,
+            header_size(PROTOCOL_VERSION)
+        );
+
+        let pack_out = packet::Packet::decode(compiled, 9).unwrap();
+        assert_eq!(pack_out.payload, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn decode_slice_test() {
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1002));
+
+        let pack = PacketBuilder::new(PType::Extended(3))
+            .sequence(Seq(7))
+            .ack(ack_list.get())
+            .payload(vec![1, 2, 3])
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        // Packets compiled into a reused buffer are the same
+        let mut buf = vec![0xFF; 4];
+        pack.compile_into(&mut buf);
+        assert_eq!(buf, pack.compile());
+        pack.compile_into(&mut buf);
+        assert_eq!(buf, pack.compile());
+
+        let borrowed = packet::Packet::decode_slice(&buf, PROTOCOL_VERSION).unwrap();
+        let owned = packet::Packet::decode(buf.clone(), PROTOCOL_VERSION).unwrap();
+        for pack_out in [borrowed, owned] {
+            assert_eq!(pack_out.sequence, Seq(7));
+            assert_eq!(pack_out.flags.p_type, PType::Extended(3));
+            assert_eq!(pack_out.ack.miss, pack.ack.miss);
+            assert_eq!(pack_out.payload, vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn checksum_test() {
+        let pack = PacketBuilder::new(PType::Data)
+            .sequence(Seq(1))
+            .payload(vec![1, 2, 3])
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        let compiled = pack.compile();
+        assert_eq!(
+            compiled.len(),
+            header_size(PROTOCOL_VERSION) + 3 + CHECKSUM_SIZE
+        );
+
+        let pack_out = packet::Packet::decode(compiled.clone(), PROTOCOL_VERSION).unwrap();
+        assert_eq!(pack_out.payload, vec![1, 2, 3]);
+
+        // Any corrupted byte is detected
+        for i in 0..compiled.len() {
+            let mut corrupted = compiled.clone();
+            corrupted[i] ^= 0x10;
+            assert!(packet::Packet::decode(corrupted, PROTOCOL_VERSION).is_err());
+        }
+
+        let mut corrupted = compiled.clone();
+        corrupted[header_size(PROTOCOL_VERSION)] ^= 0xFF;
+        assert_eq!(
+            packet::Packet::decode(corrupted, PROTOCOL_VERSION).unwrap_err(),
+            PacketError::ChecksumMismatch
+        );
+
+        // Short packets are rejected without panicking
+        for len in 0..compiled.len() {
+            assert!(packet::Packet::decode(compiled[..len].to_vec(), PROTOCOL_VERSION).is_err());
+        }
+    }
+
+    #[test]
+    fn nack_test() {
+        let missing = vec![Seq(5), Seq(6), Seq(u32::MAX)];
+        let pack = PacketBuilder::new(PType::Nack)
+            .payload(encode_nack(&missing))
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        let pack_out = packet::Packet::decode(pack.compile(), PROTOCOL_VERSION).unwrap();
+        assert_eq!(pack_out.flags.p_type, PType::Nack);
+        assert_eq!(decode_nack(&pack_out.payload).unwrap(), missing);
+
+        let missing: Vec<Seq> = (0..1000).map(Seq).collect();
+        let requested = decode_nack(&encode_nack(&missing)).unwrap();
+        assert_eq!(requested, missing[..MAX_NACK_COUNT]);
+
+        assert_eq!(decode_nack(&[0, 0, 1]).unwrap_err(), PacketError::Truncated);
+    }
+
+    #[test]
+    fn reserved_flags_test() {
+        let pack = PacketBuilder::new(PType::Data)
+            .sequence(Seq(1))
+            .payload(vec![1, 2, 3])
+            .build()
+            .unwrap();
+
+        let mut compiled = pack.compile();
+        compiled[10] |= 0b01;
+
+        // The tag flag is reserved before it was allocated
+        let result = packet::Packet::decode(compiled, 19);
+        assert_eq!(result.unwrap_err(), PacketError::ReservedFlags(FLAG_TAGGED));
+        assert_eq!(reserved_flags(PROTOCOL_VERSION), 0);
+
+        // The fragment flag is reserved before it was allocated
+        let fragment = |version| {
+            PacketBuilder::new(PType::Data)
+                .sequence(Seq(1))
+                .more_fragments(true)
+                .payload(vec![1, 2, 3])
+                .version(version)
+                .build()
+                .unwrap()
+                .compile()
+        };
+        let compiled = fragment(PROTOCOL_VERSION);
+        assert_eq!(compiled[10] & FLAG_MORE_FRAGMENTS, FLAG_MORE_FRAGMENTS);
+        let decoded = packet::Packet::decode(compiled, PROTOCOL_VERSION).unwrap();
+        assert!(decoded.flags.more);
+        assert_eq!(
+            packet::Packet::decode(fragment(16), 16).unwrap_err(),
+            PacketError::ReservedFlags(FLAG_MORE_FRAGMENTS)
+        );
+
+        assert_eq!(
+            PacketBuilder::new(PType::Keepalive)
+                .more_fragments(true)
+                .build()
+                .unwrap_err(),
+            PacketError::InvalidFragment
+        );
+    }
+
+    #[test]
+    fn truncated_test() {
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1002));
+
+        let pack = PacketBuilder::new(PType::AckOnly)
+            .ack(ack_list.get())
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        let mut compiled = pack.compile();
+        compiled.truncate(compiled.len() - CHECKSUM_SIZE - 1);
+
+        // Truncated packets fail the checksum
+        let result = packet::Packet::decode(compiled.clone(), PROTOCOL_VERSION);
+        assert_eq!(result.unwrap_err(), PacketError::ChecksumMismatch);
+
+        // or their header if it was sent truncated
+        let checksum = crc32(&compiled);
+        compiled.extend(checksum.to_be_bytes());
+        let result = packet::Packet::decode(compiled, PROTOCOL_VERSION);
+        assert_eq!(result.unwrap_err(), PacketError::Truncated);
+
+        let result = packet::Packet::try_from(vec![0; 5]);
+        assert_eq!(result.unwrap_err(), PacketError::Truncated);
+        let result = packet::Packet::try_from(&[0; 5][..]);
+        assert_eq!(result.unwrap_err(), PacketError::Truncated);
+    }
+
+    #[test]
+    fn malformed_test() {
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1002));
+        ack_list.insert(Seq(1004));
+
+        // Decoding never panics, whatever the datagram and the negotiated version
+        let mut rng = thread_rng();
+        for version in BASE_VERSION..=PROTOCOL_VERSION {
+            let compiled = PacketBuilder::new(PType::Extended(3))
+                .ack(ack_list.get())
+                .version(version)
+                .payload(b"payload".to_vec())
+                .build()
+                .unwrap()
+                .compile();
+
+            for size in 0..compiled.len() {
+                let _ = Packet::decode_slice(&compiled[..size], version);
+
+                // Datagrams claiming more missing sequence numbers than they hold
+                let mut forged = compiled[..size].to_vec();
+                if let Some(byte) = forged.get_mut(11) {
+                    *byte = 0xFF;
+                }
+                let _ = Packet::decode_slice(&forged, version);
+            }
+
+            for _ in 0..1000 {
+                let size = rng.gen_range(0..64);
+                let garbage: Vec<u8> = (0..size).map(|_| rng.gen()).collect();
+                let _ = Packet::decode_slice(&garbage, version);
+                let _ = Packet::decode(garbage, version);
+            }
+        }
+    }
+
+    #[test]
+    fn describe_test() {
+        let mut ack_list = AcknowledgementList::new(Seq(1000));
+        ack_list.insert(Seq(1001));
+        ack_list.insert(Seq(1003));
+
+        let mut pack = Packet::new(PType::Data, Seq(42));
+        pack.add_ack(ack_list.get());
+        pack.set_enc(true);
+        pack.append_payload(vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(
+            pack.describe(),
+            "Data seq=42 ack=1001..=1003 miss=1 flags=ack,enc len=5"
+        );
+        assert_eq!(pack.to_string(), pack.describe());
+
+        let pack = Packet::new(PType::Extended(9), Seq(7));
+        assert_eq!(pack.describe(), "Extended(9) seq=7 flags=- len=0");
+
+        let mut pack = Packet::new(PType::Reserved(packet::META_TYPE), Seq(7));
+        pack.set_meta(packet::PacketMeta {
+            delay_ms: 100,
+            retry_count: 2,
+        });
+        assert_eq!(pack.describe(), "Meta seq=7 delay=100ms retry=2");
+    }
 }
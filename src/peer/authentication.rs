@@ -1,68 +1,93 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::identity::PublicId;
+use crate::packet::has_challenge_digest;
 use crate::peer::Peer;
 use crate::{error::AetherError, util::gen_nonce};
-use log::info;
+use openssl::sha::Sha256;
 use rand::{thread_rng, Rng};
+use tracing::info;
 
 use crate::{config::Config, link::Link};
 
 /// Size of the nonce to be used in authentication in bytes
 pub const NONCE_SIZE: usize = 32;
 
+/// Prefix of the [`challenge_answer`], so that answers cannot be mistaken for digests
+/// of other data
+const CHALLENGE_CONTEXT: &[u8] = b"aether authentication";
+
+/// Returns the answer of the peer `uid` to a challenge of `nonce`
+///
+/// Peers answer with a digest of the nonce and their UID instead of the nonce itself
+/// (refer [`has_challenge_digest`]). Otherwise anyone could have a peer decrypt data
+/// encrypted for it, such as the secret of a key exchange, by sending it as a challenge
+pub fn challenge_answer(nonce: &[u8], uid: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(CHALLENGE_CONTEXT);
+    hasher.update(&[0]);
+    hasher.update(uid.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(nonce);
+    hasher.finish().to_vec()
+}
+
+/// Authenticate the other peer `peer_uid` on `link`
+///
+/// Each peer challenges the other with a random nonce encrypted with the public key the
+/// UID of the other encodes. Only the owner of the matching private key can decrypt it
+/// and answer with the [`challenge_answer`] to it. Peers on versions without
+/// [`has_challenge_digest`] are not authenticated, since answering their challenges
+/// would decrypt anything they send
+/// # Errors
+/// * [`AetherError::AuthenticationFailed`] - If the other peer did not answer in time
+/// * [`AetherError::AuthenticationInvalid`] - If the other peer answered wrong, sent a
+///   challenge that cannot be decrypted or does not answer with a digest
+///
+/// Other errors might occur when sending or decrypting (refer to [`AetherError`])
 pub fn authenticate(
     link: Link,
     peer_uid: String,
     identity_number: u32,
     config: Config,
 ) -> Result<Peer, AetherError> {
-    // Authentication
-    // Send own uid
     let delta = thread_rng().gen_range(0..config.aether.delta_time);
     let recv_timeout = Duration::from_millis(config.aether.handshake_retry_delay + delta);
 
+    let version = link.negotiated()?.version;
+    if !has_challenge_digest(version) {
+        return Err(AetherError::AuthenticationInvalid(peer_uid));
+    }
+
+    let own_uid = link.private_id.public_key_to_base64()?;
     let other_id = PublicId::from_base64(&peer_uid)?;
 
-    // generate nonce
+    // Challenge the other peer with a nonce only it can decrypt
     let nonce = gen_nonce(NONCE_SIZE);
+    link.send(other_id.public_encrypt(&nonce)?)?;
 
-    // encrypt nonce with public key and send to other peer
-    link.send(other_id.public_encrypt(&nonce)?).unwrap();
-
-    // receive encrypted nonce
-    let nonce_enc = match link.recv_timeout(recv_timeout) {
-        Ok(data) => data,
-        Err(err) => match err {
-            AetherError::RecvTimeout(_) => return Err(AetherError::AuthenticationFailed(peer_uid)),
-            other => return Err(other),
-        },
-    };
-
-    // TODO: Decrypt nonce received
-    let nonce_dec = link.private_id.private_decrypt(&nonce_enc)?;
-
-    // send decrypted nonce
-    link.send(nonce_dec).unwrap();
-
-    // receive decrypted nonce
-    let nonce_recv = match link.recv_timeout(recv_timeout) {
-        Ok(data) => data,
-        Err(err) => match err {
-            AetherError::RecvTimeout(_) => return Err(AetherError::AuthenticationFailed(peer_uid)),
-            other => return Err(other),
-        },
+    // Answer the challenge of the other peer
+    let challenge = recv_timeout_from(&link, recv_timeout, &peer_uid)?;
+    let other_nonce = match link.private_id.private_decrypt(&challenge) {
+        Ok(other_nonce) if other_nonce.len() == NONCE_SIZE => other_nonce,
+        _ => return Err(AetherError::AuthenticationInvalid(peer_uid)),
     };
+    link.send(challenge_answer(&other_nonce, &own_uid))?;
 
-    // if nonce received is same as nonce sent, the other peer is authenticated
-    if nonce == nonce_recv {
+    // The other peer is authenticated if it answered the challenge right
+    let answer = recv_timeout_from(&link, recv_timeout, &peer_uid)?;
+    if answer == challenge_answer(&nonce, &peer_uid) {
         info!("Authenticated: {}", peer_uid);
 
         // Create new Peer instance
         let peer = Peer {
             uid: peer_uid,
             identity_number,
-            link,
+            link: Arc::new(link),
+            failure: None,
+            name: None,
+            connected_at: Instant::now(),
         };
 
         Ok(peer)
@@ -70,3 +95,36 @@ pub fn authenticate(
         Err(AetherError::AuthenticationInvalid(peer_uid))
     }
 }
+
+/// Receive the next message of the other peer `peer_uid` during authentication
+fn recv_timeout_from(
+    link: &Link,
+    timeout: Duration,
+    peer_uid: &str,
+) -> Result<Vec<u8>, AetherError> {
+    match link.recv_timeout(timeout) {
+        Ok(data) => Ok(data),
+        Err(AetherError::RecvTimeout(_) | AetherError::LinkStopped(_)) => {
+            Err(AetherError::AuthenticationFailed(peer_uid.to_string()))
+        }
+        Err(other) => Err(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{challenge_answer, NONCE_SIZE};
+    use crate::util::gen_nonce;
+
+    #[test]
+    fn answer_test() {
+        let nonce = gen_nonce(NONCE_SIZE);
+
+        // The decrypted nonce is never revealed
+        let answer = challenge_answer(&nonce, "peer");
+        assert_ne!(answer, nonce);
+        assert_eq!(answer, challenge_answer(&nonce, "peer"));
+        assert_ne!(answer, challenge_answer(&nonce, "other"));
+        assert_ne!(answer, challenge_answer(&gen_nonce(NONCE_SIZE), "peer"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Persistent cache of the last known endpoints of peers.
+//!
+//! Peers that were connected before are stored on disk along with the address they
+//! were reached at, the local port used to reach them and the parameters negotiated
+//! with them. Connecting to a cached peer first attempts a handshake directly with the
+//! cached address, through the same local port so the other peer's cache of this
+//! client stays valid as well. The tracker server is requested in parallel, and the
+//! endpoint it answers with is attempted as soon as the direct attempt fails. This
+//! speeds up reconnects and works while the tracker is down.
+//!
+//! The cache is stored in [YAML](https://yaml.org/) format, by default in
+//! `$HOME/.config/aether/peers.yaml`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::identity::Id;
+use crate::link::Negotiated;
+use crate::tracker::ConnectionRequest;
+
+/// Last known endpoint of a peer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedPeer {
+    /// IPv4 address the peer was reached at
+    pub ip: [u8; 4],
+    /// Port the peer was reached at
+    pub port: u16,
+    /// Local port used to reach the peer
+    pub local_port: u16,
+    /// Identity number of the peer
+    pub identity_number: u32,
+    /// Time of the last successful connection in seconds since the Unix epoch
+    pub last_seen: u64,
+    /// Protocol version negotiated with the peer, `0` if not known
+    #[serde(default)]
+    pub version: u8,
+    /// Maximum message size negotiated with the peer, `0` if not known
+    #[serde(default)]
+    pub max_message_size: usize,
+}
+
+impl CachedPeer {
+    /// Returns a [`ConnectionRequest`] to hand the cached endpoint of the peer with
+    /// `uid` to the handshake, as if it had been received from the tracker
+    pub fn to_request(&self, uid: &str) -> ConnectionRequest {
+        ConnectionRequest {
+            identity_number: self.identity_number,
+            username: uid.to_string(),
+            ip: self.ip,
+            port: self.port,
+        }
+    }
+}
+
+/// Cache of [`CachedPeer`]s by uid, stored in a file
+#[derive(Debug)]
+pub struct PeerCache {
+    /// File the cache is stored in
+    path: PathBuf,
+    peers: HashMap<String, CachedPeer>,
+}
+
+impl PeerCache {
+    /// Returns the default location of the cache on the filesystem
+    pub fn default_path() -> PathBuf {
+        let mut path = Id::get_config_dir();
+        path.push("peers.yaml");
+        path
+    }
+
+    /// Load the cache stored in `path`. A missing file is an empty cache
+    /// # Errors
+    /// * [`AetherError::FileRead`] - If the file exists but cannot be read
+    /// * [`AetherError::YamlParse`] - If the file is not a valid cache
+    pub fn load(path: &Path) -> Result<PeerCache, AetherError> {
+        let peers = match fs::read_to_string(path) {
+            Ok(data) => serde_yaml::from_str(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(AetherError::FileRead(err)),
+        };
+
+        Ok(PeerCache {
+            path: path.to_path_buf(),
+            peers,
+        })
+    }
+
+    /// Write the cache to its file
+    pub fn save(&self) -> Result<(), AetherError> {
+        let data = serde_yaml::to_string(&self.peers)?;
+        fs::write(&self.path, data).map_err(AetherError::FileWrite)
+    }
+
+    /// Returns the cached endpoint of the peer with `uid`
+    pub fn get(&self, uid: &str) -> Option<&CachedPeer> {
+        self.peers.get(uid)
+    }
+
+    /// Record a successful connection to the peer with `uid` at `peer_addr` from
+    /// `local_port`. Only IPv4 addresses can be cached
+    pub fn insert(
+        &mut self,
+        uid: &str,
+        peer_addr: SocketAddr,
+        local_port: u16,
+        identity_number: u32,
+    ) {
+        let ip = match peer_addr.ip() {
+            IpAddr::V4(ip) => ip.octets(),
+            IpAddr::V6(_) => return,
+        };
+
+        let last_seen = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        self.peers.insert(
+            uid.to_string(),
+            CachedPeer {
+                ip,
+                port: peer_addr.port(),
+                local_port,
+                identity_number,
+                last_seen,
+                version: 0,
+                max_message_size: 0,
+            },
+        );
+    }
+
+    /// Record the parameters negotiated with the cached peer with `uid`
+    pub fn set_negotiated(&mut self, uid: &str, negotiated: &Negotiated) {
+        if let Some(peer) = self.peers.get_mut(uid) {
+            peer.version = negotiated.version;
+            peer.max_message_size = negotiated.max_message_size;
+        }
+    }
+
+    /// Remove the peer with `uid` from the cache
+    pub fn remove(&mut self, uid: &str) -> Option<CachedPeer> {
+        self.peers.remove(uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::path::Path;
+
+    use super::PeerCache;
+    use crate::link::Negotiated;
+    use crate::packet::Capabilities;
+
+    #[test]
+    fn cache_test() {
+        fs::create_dir_all("./tmp").unwrap();
+        let path = Path::new("./tmp/peers.yaml");
+        let _ = fs::remove_file(path);
+
+        let mut cache = PeerCache::load(path).unwrap();
+        assert!(cache.get("peer").is_none());
+
+        let peer_addr = SocketAddr::from((Ipv4Addr::new(1, 2, 3, 4), 1234));
+        cache.insert("peer", peer_addr, 4321, 1);
+        cache.insert("ipv6", "[::1]:1234".parse().unwrap(), 4321, 1);
+        cache.set_negotiated(
+            "peer",
+            &Negotiated {
+                version: 7,
+                cipher: None,
+                mtu: 2048,
+                max_message_size: 1024,
+                initial_window: 20,
+                window: 20,
+                max_window: 20,
+                capabilities: Capabilities::for_version(7),
+            },
+        );
+        cache.save().unwrap();
+
+        let loaded = PeerCache::load(path).unwrap();
+        let peer = loaded.get("peer").unwrap();
+        assert_eq!(peer, cache.get("peer").unwrap());
+        assert_eq!(
+            (peer.ip, peer.port, peer.local_port),
+            ([1, 2, 3, 4], 1234, 4321)
+        );
+        assert!(loaded.get("ipv6").is_none());
+        assert_eq!((peer.version, peer.max_message_size), (7, 1024));
+
+        let request = peer.to_request("peer");
+        assert_eq!(request.username, "peer");
+        assert_eq!((request.ip, request.port), (peer.ip, peer.port));
+
+        // Caches written before negotiated parameters were recorded
+        fs::write(
+            path,
+            "peer:\n  ip: [1, 2, 3, 4]\n  port: 1234\n  local_port: 4321\n  identity_number: 1\n  last_seen: 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            PeerCache::load(path).unwrap().get("peer").unwrap().version,
+            0
+        );
+
+        fs::write(path, "not a cache").unwrap();
+        assert!(PeerCache::load(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
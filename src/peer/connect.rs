@@ -0,0 +1,227 @@
+//! Options for connecting to a single peer, overriding the [`Config`][crate::config::Config]
+//! of the client for that peer only.
+//!
+//! For example, user initiated connections usually need to give up quickly, while
+//! peers synchronised in the background can be retried patiently.
+//!
+//! Links are always encrypted end-to-end, so there is no option to connect without
+//! encryption.
+
+use std::time::{Duration, Instant};
+
+use rand::{thread_rng, Rng};
+
+use crate::config::{GiveUp, RetryPolicy};
+
+/// Options for connecting to a peer using
+/// [`Aether::connect_with`][crate::peer::Aether::connect_with]. The defaults keep
+/// retrying until connected, like [`Aether::connect`][crate::peer::Aether::connect]
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use aether_lib::peer::connect::ConnectOptions;
+///
+/// let options = ConnectOptions {
+///     timeout: Some(Duration::from_secs(10)),
+///     retries: Some(2),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// Give up connecting this long after the connection was requested. [`None`] keeps
+    /// trying until connected
+    pub timeout: Option<Duration>,
+    /// Give up connecting after this many handshakes failed after the first one.
+    /// [`None`] uses the [`max_retries`][RetryPolicy::max_retries] of the retry policy
+    pub retries: Option<u32>,
+    /// How failed handshakes are retried, instead of
+    /// [`AetherConfig::retry`][crate::config::AetherConfig::retry]
+    pub retry: Option<RetryPolicy>,
+    /// Timeout of each handshake, instead of
+    /// [`HandshakeConfig::handshake_timeout`][crate::config::HandshakeConfig::handshake_timeout]
+    pub handshake_timeout: Option<Duration>,
+    /// Close the link this long after the connection is established, after which
+    /// sending to the peer fails with
+    /// [`AetherError::SessionExpired`][crate::error::AetherError::SessionExpired]
+    pub expiry: Option<Duration>,
+}
+
+/// Progress of connecting to a peer with [`ConnectOptions`]
+#[derive(Debug)]
+pub struct Attempts {
+    /// Options the connection was requested with
+    pub options: ConnectOptions,
+    /// Retry policy of the options, or of the client if they do not set one
+    policy: RetryPolicy,
+    /// Time the connection was requested
+    started: Instant,
+    /// Number of failed handshakes so far
+    failures: u32,
+    /// Why the latest handshake failed
+    reason: String,
+    /// Delay before retrying after the latest failure, including jitter
+    delay: Duration,
+    /// Whether connecting was given up
+    given_up: bool,
+}
+
+impl Attempts {
+    /// Start connecting with `options`
+    pub fn new(options: ConnectOptions) -> Attempts {
+        Self::with_policy(options, RetryPolicy::default())
+    }
+
+    /// Start connecting with `options`, retrying as configured by `policy` unless the
+    /// options set their own [`retry`][ConnectOptions::retry] policy
+    pub fn with_policy(options: ConnectOptions, policy: RetryPolicy) -> Attempts {
+        Attempts {
+            options,
+            policy: options.retry.unwrap_or(policy),
+            started: Instant::now(),
+            failures: 0,
+            reason: String::new(),
+            delay: Duration::ZERO,
+            given_up: false,
+        }
+    }
+
+    /// Record a handshake failed because of `reason`
+    pub fn fail(&mut self, reason: String) {
+        self.failures += 1;
+        self.reason = reason;
+
+        let jitter = thread_rng().gen_range(0..=self.policy.jitter);
+        self.delay = self.policy.delay(self.failures) + Duration::from_millis(jitter);
+    }
+
+    /// Returns the number of failed handshakes so far
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns the delay before retrying after the latest failed handshake
+    pub fn retry_delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Returns why the latest handshake failed, empty if none failed yet
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Returns true if connecting has to be given up, because the timeout ran out or
+    /// there were more failures than retries
+    pub fn exhausted(&self) -> bool {
+        let timed_out = match self.options.timeout {
+            Some(timeout) => self.started.elapsed() > timeout,
+            None => false,
+        };
+        let out_of_retries = match self.options.retries.or(self.policy.max_retries) {
+            Some(retries) => self.failures > retries,
+            None => false,
+        };
+
+        timed_out || out_of_retries
+    }
+
+    /// Give up connecting. Returns true if the connection is to be kept in failed state
+    /// (refer [`GiveUp`])
+    pub fn give_up(&mut self) -> bool {
+        self.given_up = true;
+        if self.reason.is_empty() {
+            self.reason = "Connecting timed out".to_string();
+        }
+        self.policy.give_up == GiveUp::KeepFailed
+    }
+
+    /// Returns true if connecting was given up
+    pub fn given_up(&self) -> bool {
+        self.given_up
+    }
+}
+
+impl Default for Attempts {
+    fn default() -> Self {
+        Self::new(ConnectOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Attempts, ConnectOptions};
+    use crate::config::{GiveUp, RetryPolicy};
+
+    #[test]
+    fn attempts_test() {
+        let mut attempts = Attempts::default();
+        for _ in 0..100 {
+            attempts.fail(String::new());
+        }
+        assert!(!attempts.exhausted());
+
+        let mut attempts = Attempts::new(ConnectOptions {
+            retries: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(attempts.reason(), "");
+        attempts.fail("Handshake timed out".to_string());
+        assert!(!attempts.exhausted());
+        assert_eq!(attempts.reason(), "Handshake timed out");
+        attempts.fail(String::new());
+        assert!(attempts.exhausted());
+
+        let attempts = Attempts::new(ConnectOptions {
+            timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        assert!(!attempts.exhausted());
+        thread::sleep(Duration::from_millis(30));
+        assert!(attempts.exhausted());
+    }
+
+    #[test]
+    fn retry_policy_test() {
+        let policy = RetryPolicy {
+            max_retries: Some(1),
+            retry_delay: 1_000,
+            backoff: 2,
+            jitter: 0,
+            give_up: GiveUp::KeepFailed,
+            ..Default::default()
+        };
+        let mut attempts = Attempts::with_policy(ConnectOptions::default(), policy);
+        attempts.fail("Handshake timed out".to_string());
+        assert_eq!(attempts.retry_delay(), Duration::from_millis(1_000));
+        assert!(!attempts.exhausted());
+        attempts.fail("Handshake timed out".to_string());
+        assert_eq!(attempts.retry_delay(), Duration::from_millis(2_000));
+        assert!(attempts.exhausted());
+
+        assert!(!attempts.given_up());
+        assert!(attempts.give_up());
+        assert!(attempts.given_up());
+
+        // Options override the policy of the client
+        let options = ConnectOptions {
+            retries: Some(5),
+            retry: Some(RetryPolicy {
+                jitter: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut attempts = Attempts::with_policy(options, policy);
+        attempts.fail(String::new());
+        attempts.fail(String::new());
+        assert!(!attempts.exhausted());
+        assert_eq!(attempts.retry_delay(), Duration::from_millis(1_500));
+        assert!(!attempts.give_up());
+        assert_eq!(attempts.reason(), "Connecting timed out");
+    }
+}
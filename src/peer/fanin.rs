@@ -0,0 +1,367 @@
+//! Fan-in of the messages received on all links of an [`Aether`][crate::peer::Aether]
+//! client into a single queue, for receiving from any peer without one thread per
+//! peer in the application.
+//!
+//! Messages of peers with a [`MessageHandler`] are passed to it instead of being
+//! queued. Handlers are called by a pool of threads, the messages of a peer always
+//! by the same thread in the order they were received, so that a slow handler only
+//! holds up the peers sharing its thread.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use tracing::warn;
+
+use crate::packet::Packet;
+use crate::peer::frames::Frames;
+use crate::util::catch_panic;
+
+/// A message along with the UID of the peer it was received from
+pub type Message = (String, Vec<u8>);
+
+/// Handler called with the UID of a peer and the bytes received from it
+pub type MessageHandler = Arc<dyn Fn(String, Vec<u8>) + Send + Sync>;
+
+/// Handlers registered by the peers they handle
+#[derive(Default)]
+struct Handlers {
+    peers: HashMap<String, MessageHandler>,
+    /// Handler of the peers without one of their own
+    any: Option<MessageHandler>,
+}
+
+impl Handlers {
+    /// Returns the handler of the messages of the peer `uid`
+    fn get(&self, uid: &str) -> Option<MessageHandler> {
+        self.peers.get(uid).or(self.any.as_ref()).cloned()
+    }
+}
+
+/// Message to be passed to a handler by a thread of the pool
+type Job = (MessageHandler, Message);
+
+/// Forwards the output queues of links into a single queue of [`Message`]s
+///
+/// Forwarding only starts once [`FanIn::enable`] is called, so clients that only receive
+/// from specific peers are not affected. Links of peers with a handler are forwarded
+/// regardless
+pub struct FanIn {
+    /// Set once the first application asks for messages from any peer
+    enabled: AtomicBool,
+    /// Queue of messages forwarded from all links
+    queue: (Sender<Message>, Receiver<Message>),
+    /// Output queues currently being forwarded
+    forwarded: Arc<Mutex<Vec<Receiver<Packet>>>>,
+    /// Handlers of the messages of peers
+    handlers: Arc<Mutex<Handlers>>,
+    /// Queues of the threads calling handlers, started with the first handler
+    pool: Arc<Mutex<Vec<Sender<Job>>>>,
+    /// Number of threads calling handlers
+    handler_threads: usize,
+}
+
+impl FanIn {
+    /// Creates a new disabled [`FanIn`] calling handlers on `handler_threads` threads
+    pub fn new(handler_threads: usize) -> FanIn {
+        FanIn {
+            enabled: AtomicBool::new(false),
+            queue: unbounded(),
+            forwarded: Arc::new(Mutex::new(Vec::new())),
+            handlers: Arc::new(Mutex::new(Handlers::default())),
+            pool: Arc::new(Mutex::new(Vec::new())),
+            handler_threads: handler_threads.max(1),
+        }
+    }
+
+    /// Start forwarding links passed to [`FanIn::forward`]. Returns true if forwarding
+    /// was not enabled before, in which case the links of already connected peers have
+    /// to be forwarded by the caller
+    pub fn enable(&self) -> bool {
+        !self.enabled.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns true if [`FanIn::enable`] has been called
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Register `handler` for the messages of the peer `uid`, replacing its previous
+    /// handler. The link to the peer has to be forwarded by the caller
+    pub fn set_handler(&self, uid: &str, handler: MessageHandler) {
+        self.start_pool();
+        let mut handlers_lock = self.handlers.lock().expect("unable to lock handlers");
+        handlers_lock.peers.insert(uid.to_string(), handler);
+    }
+
+    /// Register `handler` for the messages of all peers without a handler of their
+    /// own, replacing the previous one. Returns true if there was none, in which case
+    /// the links of already connected peers have to be forwarded by the caller
+    pub fn set_any_handler(&self, handler: MessageHandler) -> bool {
+        self.start_pool();
+        let mut handlers_lock = self.handlers.lock().expect("unable to lock handlers");
+        handlers_lock.any.replace(handler).is_none()
+    }
+
+    /// Check if the link to the peer `uid` has to be forwarded once it connects, which
+    /// is the case if forwarding is enabled or the peer has a handler
+    pub fn wants(&self, uid: &str) -> bool {
+        if self.is_enabled() {
+            return true;
+        }
+
+        let handlers_lock = self.handlers.lock().expect("unable to lock handlers");
+        handlers_lock.get(uid).is_some()
+    }
+
+    /// Forward the output queue of the link to the peer `uid` until it disconnects,
+    /// handling the frames of the library received on it with `frames`. A queue that is
+    /// already being forwarded is ignored
+    pub fn forward(&self, mut uid: String, receiver: Receiver<Packet>, frames: Arc<Frames>) {
+        let mut forwarded_lock = self.forwarded.lock().expect("unable to lock forwarded");
+        if forwarded_lock.iter().any(|r| r.same_channel(&receiver)) {
+            return;
+        }
+        forwarded_lock.push(receiver.clone());
+        drop(forwarded_lock);
+
+        let sender = self.queue.0.clone();
+        let forwarded = self.forwarded.clone();
+        let handlers = self.handlers.clone();
+        let pool = self.pool.clone();
+
+        thread::spawn(move || {
+            for packet in receiver.iter() {
+                let payload = match frames.receive(&mut uid, packet, &receiver) {
+                    Ok(Some(message)) => message.payload,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        warn!(peer = %uid, "Stopping to forward messages: {}", err);
+                        break;
+                    }
+                };
+
+                let handler = {
+                    let handlers_lock = handlers.lock().expect("unable to lock handlers");
+                    handlers_lock.get(&uid)
+                };
+
+                let sent = match handler {
+                    Some(handler) => {
+                        let pool_lock = pool.lock().expect("unable to lock handler pool");
+                        let worker = &pool_lock[shard(&uid, pool_lock.len())];
+                        worker.send((handler, (uid.clone(), payload))).is_ok()
+                    }
+                    None => sender.send((uid.clone(), payload)).is_ok(),
+                };
+                if !sent {
+                    break;
+                }
+            }
+
+            let mut forwarded_lock = forwarded.lock().expect("unable to lock forwarded");
+            forwarded_lock.retain(|r| !r.same_channel(&receiver));
+        });
+    }
+
+    /// Returns the [`Receiver`] of the forwarded messages
+    pub fn receiver(&self) -> &Receiver<Message> {
+        &self.queue.1
+    }
+
+    /// Start the threads calling handlers if they are not running yet. They stop once
+    /// the [`FanIn`] and the links it forwards are dropped
+    fn start_pool(&self) {
+        let mut pool_lock = self.pool.lock().expect("unable to lock handler pool");
+        if !pool_lock.is_empty() {
+            return;
+        }
+
+        for _ in 0..self.handler_threads {
+            let (sender, receiver) = unbounded::<Job>();
+            thread::spawn(move || {
+                for (handler, (uid, payload)) in receiver.iter() {
+                    if let Err(err) = catch_panic(|| handler(uid.clone(), payload)) {
+                        warn!(peer = %uid, "Message handler panicked: {}", err);
+                    }
+                }
+            });
+            pool_lock.push(sender);
+        }
+    }
+}
+
+impl fmt::Debug for FanIn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanIn")
+            .field("enabled", &self.is_enabled())
+            .field("handler_threads", &self.handler_threads)
+            .finish()
+    }
+}
+
+impl Default for FanIn {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+/// Returns the thread of a pool of `size` threads handling the messages of `uid`
+fn shard(uid: &str, size: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    uid.hash(&mut hasher);
+    (hasher.finish() % size as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crossbeam::channel::unbounded;
+
+    use super::FanIn;
+    use crate::config::Config;
+    use crate::contacts::Contacts;
+    use crate::identity::Id;
+    use crate::packet::{PType, Packet, PacketBuilder};
+    use crate::peer::frames::{Frames, NAME_SUBTYPE};
+    use crate::peer::outbox::Outbox;
+    use crate::peer::registry::ConnectionRegistry;
+    use crate::wire::control;
+
+    fn packet(payload: &[u8]) -> Packet {
+        PacketBuilder::new(PType::Data)
+            .payload(payload.to_vec())
+            .build()
+            .unwrap()
+    }
+
+    fn frames() -> Arc<Frames> {
+        Arc::new(Frames::new(
+            Arc::new(ConnectionRegistry::new()),
+            Arc::new(Mutex::new(Contacts::new())),
+            Arc::new(Mutex::new(Outbox::new())),
+            unbounded().0,
+            &Config::default(),
+        ))
+    }
+
+    #[test]
+    fn fan_in_test() {
+        let fan_in = FanIn::new(1);
+        assert!(fan_in.enable());
+        assert!(!fan_in.enable());
+
+        let (sender1, receiver1) = unbounded();
+        let (sender2, receiver2) = unbounded();
+        fan_in.forward(String::from("first"), receiver1.clone(), frames());
+        fan_in.forward(String::from("second"), receiver2, frames());
+        // Forwarding the same queue twice does not duplicate messages
+        fan_in.forward(String::from("first"), receiver1, frames());
+
+        sender1.send(packet(b"one")).unwrap();
+        sender2.send(packet(b"two")).unwrap();
+        // Frames of the library are handled instead of being forwarded
+        let signed = Id::new().unwrap().sign_name("Alice").unwrap();
+        let name = PacketBuilder::new(PType::Extended(NAME_SUBTYPE))
+            .payload(control::encode(&signed).unwrap())
+            .build()
+            .unwrap();
+        sender2.send(name).unwrap();
+        sender1.send(packet(b"three")).unwrap();
+
+        let timeout = Duration::from_secs(1);
+        let mut messages: Vec<_> = (0..3)
+            .map(|_| fan_in.receiver().recv_timeout(timeout).unwrap())
+            .collect();
+        messages.sort();
+
+        assert_eq!(
+            messages,
+            vec![
+                (String::from("first"), b"one".to_vec()),
+                (String::from("first"), b"three".to_vec()),
+                (String::from("second"), b"two".to_vec()),
+            ]
+        );
+        assert!(fan_in
+            .receiver()
+            .recv_timeout(Duration::from_millis(50))
+            .is_err());
+
+        // Disconnected queues are no longer forwarded
+        drop(sender1);
+        drop(sender2);
+        let forwarded = fan_in.forwarded.clone();
+        let start = Instant::now();
+        while !forwarded.lock().unwrap().is_empty() {
+            assert!(start.elapsed() < timeout);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn handler_test() {
+        let fan_in = FanIn::new(2);
+        let (handled_sender, handled) = unbounded();
+
+        let sender = handled_sender.clone();
+        fan_in.set_handler(
+            "first",
+            Arc::new(move |uid, payload| {
+                if payload == b"panic" {
+                    panic!("handler failed");
+                }
+                sender.send((uid, payload)).unwrap();
+            }),
+        );
+
+        let (sender1, receiver1) = unbounded();
+        let (sender2, receiver2) = unbounded();
+        assert!(fan_in.wants("first"));
+        assert!(!fan_in.wants("second"));
+        fan_in.forward(String::from("first"), receiver1, frames());
+        fan_in.forward(String::from("second"), receiver2, frames());
+
+        // Messages of peers without a handler are queued
+        let timeout = Duration::from_secs(1);
+        for payload in [&b"one"[..], b"panic", b"two"] {
+            sender1.send(packet(payload)).unwrap();
+        }
+        sender2.send(packet(b"three")).unwrap();
+        assert_eq!(
+            fan_in.receiver().recv_timeout(timeout).unwrap(),
+            (String::from("second"), b"three".to_vec())
+        );
+
+        // Handlers are called in order, a panic does not stop later messages
+        for payload in [&b"one"[..], b"two"] {
+            assert_eq!(
+                handled.recv_timeout(timeout).unwrap(),
+                (String::from("first"), payload.to_vec())
+            );
+        }
+
+        // Peers without a handler of their own are passed to the handler of any peer
+        assert!(fan_in.set_any_handler(Arc::new(move |uid, payload| {
+            handled_sender.send((uid, payload)).unwrap();
+        })));
+        assert!(fan_in.wants("second"));
+        sender2.send(packet(b"four")).unwrap();
+        assert_eq!(
+            handled.recv_timeout(timeout).unwrap(),
+            (String::from("second"), b"four".to_vec())
+        );
+        assert!(fan_in
+            .receiver()
+            .recv_timeout(Duration::from_millis(50))
+            .is_err());
+    }
+}
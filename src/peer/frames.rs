@@ -0,0 +1,346 @@
+//! Frames of the library exchanged with connected peers along with their messages.
+//!
+//! Display names (refer [`name`][crate::identity::name]), key rotations (refer
+//! [`rotation`][crate::peer::rotation]) and messages with headers (refer
+//! [`headers`]) are [`control`] frames sent as
+//! [`PType::Extended`] packets of subtypes reserved for the library (refer
+//! [`has_reserved_subtypes`]), so that the bytes of a message are never mistaken for
+//! one of them. They are received in order with messages and handled the same way by
+//! every receive path of [`Aether`][crate::peer::Aether]: [`Aether::recv_from`] and its
+//! variants, [`Aether::messages`], [`Aether::recv_any`] and the handlers of
+//! [`Aether::on_message`].
+//!
+//! Peers on older protocol versions cannot receive them. Display names are not sent to
+//! them, and sending messages with headers or announcing key rotations to them fails
+//! with [`AetherError::ExtensionUnsupported`].
+//!
+//! [`Aether::recv_from`]: crate::peer::Aether::recv_from
+//! [`Aether::messages`]: crate::peer::Aether::messages
+//! [`Aether::recv_any`]: crate::peer::Aether::recv_any
+//! [`Aether::on_message`]: crate::peer::Aether::on_message
+//! [`has_reserved_subtypes`]: crate::packet::has_reserved_subtypes
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam::channel::{Receiver, Sender};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::contacts::Contacts;
+use crate::error::AetherError;
+use crate::identity::name::SignedName;
+use crate::link::extension::RESERVED_SUBTYPES;
+use crate::packet::{PType, Packet};
+use crate::peer::authentication::NONCE_SIZE;
+use crate::peer::outbox::Outbox;
+use crate::peer::registry::ConnectionRegistry;
+use crate::peer::rotation::{KeyRotation, RotationChallenge, RotationResponse};
+use crate::peer::{closed_error, AetherEvent, Connection};
+use crate::util::gen_nonce;
+use crate::wire::control::{self, Control};
+use crate::wire::headers::{self, Headers, Message};
+
+/// Subtype of the frames publishing the display name of a peer
+pub const NAME_SUBTYPE: u8 = *RESERVED_SUBTYPES.start();
+/// Subtype of the frames of key rotations: [`KeyRotation`], [`RotationChallenge`] and
+/// [`RotationResponse`]
+pub const ROTATION_SUBTYPE: u8 = NAME_SUBTYPE + 1;
+/// Subtype of the frames carrying a message along with its headers
+pub const HEADERS_SUBTYPE: u8 = NAME_SUBTYPE + 2;
+
+/// Message or frame received from a peer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    /// Message, with the headers it was sent with if any
+    Message(Message),
+    Name(SignedName),
+    Rotation(KeyRotation),
+    Challenge(RotationChallenge),
+    Response(RotationResponse),
+}
+
+impl Frame {
+    /// Decode the message or frame received in `packet`
+    /// # Errors
+    /// * [`AetherError::ControlFrame`] - If the packet is not a message or a frame of
+    ///   the library, or the frame is invalid
+    pub fn decode(packet: Packet) -> Result<Frame, AetherError> {
+        let frame = &packet.payload;
+
+        match packet.flags.p_type {
+            PType::Data => Ok(Frame::Message(Message {
+                headers: Headers::new(),
+                payload: packet.payload,
+            })),
+            PType::Extended(NAME_SUBTYPE) => Ok(Frame::Name(control::decode(frame)?)),
+            PType::Extended(ROTATION_SUBTYPE) => match control::kind(frame)? {
+                KeyRotation::KIND => Ok(Frame::Rotation(control::decode(frame)?)),
+                RotationChallenge::KIND => Ok(Frame::Challenge(control::decode(frame)?)),
+                RotationResponse::KIND => Ok(Frame::Response(control::decode(frame)?)),
+                _ => Err(AetherError::ControlFrame("unexpected kind")),
+            },
+            PType::Extended(HEADERS_SUBTYPE) => Ok(Frame::Message(headers::decode(frame)?)),
+            _ => Err(AetherError::ControlFrame("unknown frame")),
+        }
+    }
+}
+
+/// Handles the frames received from connected peers, for the receive paths of an
+/// [`Aether`][crate::peer::Aether] client
+pub struct Frames {
+    /// Connections of the client
+    connections: Arc<ConnectionRegistry>,
+    /// Address book of the client, updated when a contact rotates its key
+    contacts: Arc<Mutex<Contacts>>,
+    /// Messages waiting for their peers, moved when a peer rotates its key
+    outbox: Arc<Mutex<Outbox>>,
+    /// Queue of the events of the client
+    events: Sender<AetherEvent>,
+    /// Time the other peer has to answer a [`RotationChallenge`]
+    handshake_timeout: Duration,
+}
+
+impl Frames {
+    /// Creates a new [`Frames`] handling the frames of the peers in `connections`
+    pub fn new(
+        connections: Arc<ConnectionRegistry>,
+        contacts: Arc<Mutex<Contacts>>,
+        outbox: Arc<Mutex<Outbox>>,
+        events: Sender<AetherEvent>,
+        config: &Config,
+    ) -> Frames {
+        Frames {
+            connections,
+            contacts,
+            outbox,
+            events,
+            handshake_timeout: Duration::from_millis(config.handshake.handshake_timeout),
+        }
+    }
+
+    /// Handle the `packet` received from the peer `uid` on `receiver`, the output queue
+    /// of its link. Returns the message it carries, or [`None`] for frames of the
+    /// library and invalid frames, which are dropped. `uid` is updated if the peer
+    /// rotated its key
+    /// # Errors
+    /// * [`AetherError::IdentityChanged`] - The peer announced a new key that could not
+    ///   be verified, the connection has been dropped
+    pub fn receive(
+        &self,
+        uid: &mut String,
+        packet: Packet,
+        receiver: &Receiver<Packet>,
+    ) -> Result<Option<Message>, AetherError> {
+        match Frame::decode(packet) {
+            Ok(Frame::Message(message)) => return Ok(Some(message)),
+            Ok(Frame::Name(signed)) => self.accept_name(uid, signed)?,
+            Ok(Frame::Rotation(rotation)) => *uid = self.reverify(uid, rotation, receiver)?,
+            Ok(_) => debug!(peer = %uid, "Dropping rotation frame outside of a key rotation"),
+            Err(err) => warn!(peer = %uid, "Dropping invalid frame: {}", err),
+        }
+
+        Ok(None)
+    }
+
+    /// Send `frame` to the connected peer `uid` in an extended packet of `subtype`
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    /// * [`AetherError::ExtensionUnsupported`] - The peer does not support the frames of
+    ///   the library (refer
+    ///   [`has_reserved_subtypes`][crate::packet::has_reserved_subtypes])
+    ///
+    /// Other errors are those of [`Aether::send_to`][crate::peer::Aether::send_to]
+    pub fn send(&self, uid: &str, subtype: u8, frame: Vec<u8>) -> Result<(), AetherError> {
+        let link = self.connections.link(uid)?;
+
+        match link.send_extended(subtype, frame) {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, link.close_reason()?)),
+            result => result,
+        }
+    }
+
+    /// Keep the display name published by the peer `uid` if it was signed by the peer.
+    /// Names that do not verify are dropped
+    fn accept_name(&self, uid: &str, signed: SignedName) -> Result<(), AetherError> {
+        let name = match signed.verify(uid) {
+            Ok(name) => name.to_string(),
+            Err(err) => {
+                warn!(peer = %uid, "Dropping display name: {}", err);
+                return Ok(());
+            }
+        };
+
+        let mut connections_lock = self.connections.lock(uid)?;
+        if let Some(Connection::Connected(peer)) = (*connections_lock).get_mut(uid) {
+            debug!(peer = %uid, name = %name, "Peer published its display name");
+            peer.name = Some(name);
+        }
+
+        Ok(())
+    }
+
+    /// Verify the key `rotation` announced by the peer `uid` and move the session to
+    /// the new UID, which is returned. Drops the connection if the new key cannot be
+    /// verified
+    fn reverify(
+        &self,
+        uid: &str,
+        rotation: KeyRotation,
+        receiver: &Receiver<Packet>,
+    ) -> Result<String, AetherError> {
+        if let Err(err) = self.challenge_rotation(uid, &rotation, receiver) {
+            warn!(peer = %uid, "Dropping peer, new key cannot be verified: {}", err);
+            self.drop_peer(uid)?;
+            return Err(AetherError::IdentityChanged(uid.to_string()));
+        }
+
+        let new_uid = rotation.new_key;
+        self.rename_peer(uid, &new_uid)?;
+        info!(peer = %uid, "Peer rotated its key");
+
+        let _ = self.events.send(AetherEvent::IdentityRotated {
+            previous: uid.to_string(),
+            current: new_uid.clone(),
+        });
+
+        Ok(new_uid)
+    }
+
+    /// Verify the transition statement of `rotation` and authenticate its new key by
+    /// challenging the peer `uid` with a nonce only the owner of the key can decrypt
+    fn challenge_rotation(
+        &self,
+        uid: &str,
+        rotation: &KeyRotation,
+        receiver: &Receiver<Packet>,
+    ) -> Result<(), AetherError> {
+        let new_id = rotation.verify(uid)?;
+
+        let nonce = gen_nonce(NONCE_SIZE);
+        let challenge = RotationChallenge {
+            nonce: new_id.public_encrypt(&nonce)?,
+        };
+        self.send(uid, ROTATION_SUBTYPE, control::encode(&challenge)?)?;
+
+        let packet = match receiver.recv_timeout(self.handshake_timeout) {
+            Ok(packet) => packet,
+            Err(_) => return Err(AetherError::AuthenticationFailed(uid.to_string())),
+        };
+
+        match Frame::decode(packet) {
+            Ok(Frame::Response(response)) if response.nonce == nonce => Ok(()),
+            _ => Err(AetherError::AuthenticationInvalid(uid.to_string())),
+        }
+    }
+
+    /// Move the connection, contact and queued messages of the peer `uid` to `new_uid`
+    fn rename_peer(&self, uid: &str, new_uid: &str) -> Result<(), AetherError> {
+        let mut connections_lock = self.connections.lock(uid)?;
+        let connection = (*connections_lock).remove(uid);
+        drop(connections_lock);
+
+        if let Some(mut connection) = connection {
+            if let Connection::Connected(peer) = &mut connection {
+                peer.uid = new_uid.to_string();
+            }
+
+            let mut connections_lock = self.connections.lock(new_uid)?;
+            (*connections_lock).insert(new_uid.to_string(), connection);
+        }
+
+        let mut contacts_lock = match self.contacts.lock() {
+            Ok(lock) => lock,
+            Err(_) => return Err(AetherError::MutexLock("contacts")),
+        };
+        if let Some(contact) = (*contacts_lock).get(uid).cloned() {
+            (*contacts_lock).remove_contact(uid)?;
+            (*contacts_lock).add_contact(new_uid, &contact.alias, contact.trust)?;
+        }
+        drop(contacts_lock);
+
+        let mut outbox_lock = match self.outbox.lock() {
+            Ok(lock) => lock,
+            Err(_) => return Err(AetherError::MutexLock("outbox")),
+        };
+        (*outbox_lock).rename(uid, new_uid);
+        (*outbox_lock).save()?;
+
+        Ok(())
+    }
+
+    /// Remove the connection to the peer `uid`, closing its link
+    fn drop_peer(&self, uid: &str) -> Result<(), AetherError> {
+        let connection = (*self.connections.lock(uid)?).remove(uid);
+
+        // The link is stopped without holding the lock, as it waits for the peer
+        if let Some(Connection::Connected(peer)) = connection {
+            if let Err(err) = peer.link.stop() {
+                warn!(peer = %uid, "Unable to stop link: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, HEADERS_SUBTYPE, NAME_SUBTYPE, ROTATION_SUBTYPE};
+    use crate::error::AetherError;
+    use crate::identity::Id;
+    use crate::packet::{PType, PacketBuilder};
+    use crate::peer::rotation::RotationResponse;
+    use crate::wire::control;
+    use crate::wire::headers::{self, Headers};
+
+    fn packet(p_type: PType, payload: Vec<u8>) -> crate::packet::Packet {
+        PacketBuilder::new(p_type).payload(payload).build().unwrap()
+    }
+
+    #[test]
+    fn decode_test() {
+        let id = Id::new().unwrap();
+        let signed = id.sign_name("Alice").unwrap();
+        let frame = control::encode(&signed).unwrap();
+
+        // Frames are only decoded from packets of their subtype
+        assert_eq!(
+            Frame::decode(packet(PType::Extended(NAME_SUBTYPE), frame.clone())).unwrap(),
+            Frame::Name(signed)
+        );
+        match Frame::decode(packet(PType::Data, frame.clone())).unwrap() {
+            Frame::Message(message) => {
+                assert!(message.headers.is_empty());
+                assert_eq!(message.payload, frame);
+            }
+            other => panic!("Unexpected frame {:?}", other),
+        }
+        assert!(matches!(
+            Frame::decode(packet(PType::Extended(ROTATION_SUBTYPE), frame)),
+            Err(AetherError::ControlFrame(_))
+        ));
+
+        let response = RotationResponse { nonce: vec![1, 2] };
+        let frame = control::encode(&response).unwrap();
+        assert_eq!(
+            Frame::decode(packet(PType::Extended(ROTATION_SUBTYPE), frame)).unwrap(),
+            Frame::Response(response)
+        );
+
+        let mut headers = Headers::new();
+        headers.insert("id".to_string(), "42".to_string());
+        let frame = headers::encode(&headers, b"Hello".to_vec()).unwrap();
+        match Frame::decode(packet(PType::Extended(HEADERS_SUBTYPE), frame)).unwrap() {
+            Frame::Message(message) => {
+                assert_eq!(message.headers, headers);
+                assert_eq!(message.payload, b"Hello".to_vec());
+            }
+            other => panic!("Unexpected frame {:?}", other),
+        }
+
+        // Messages in packets of other subtypes are not mistaken for frames
+        assert!(Frame::decode(packet(PType::Extended(HEADERS_SUBTYPE), b"Hi".to_vec())).is_err());
+        assert!(Frame::decode(packet(PType::Extended(7), Vec::new())).is_err());
+    }
+}
@@ -1,27 +1,354 @@
+use crate::acknowledgement::MAX_WINDOW;
 use crate::error::AetherError;
 use crate::identity::{Id, PublicId};
-use crate::{acknowledgement::Acknowledgement, config::Config, packet::Packet};
+use crate::memory::MemoryBudget;
+use crate::packet::{
+    has_handshake_cookie, has_handshake_padding, has_handshake_puzzle, has_message_size,
+    has_window_size, BASE_VERSION, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION,
+};
+use crate::sequence::Seq;
+use crate::stats::RejectionCounters;
+use crate::telemetry::{
+    self, HandshakePhase, NoopTelemetry, Telemetry, TelemetryEvent, SPAN_HANDSHAKE,
+};
+use crate::transport::Transport;
+use crate::{
+    acknowledgement::Acknowledgement,
+    config::Config,
+    packet::{Packet, PacketBuilder},
+};
 use crate::{link::Link, packet::PType};
+use std::convert::{TryFrom, TryInto};
 use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::{
-    net::{SocketAddr, UdpSocket},
+    net::SocketAddr,
     time::{Duration, SystemTime},
 };
 
+use openssl::sha::Sha256;
 use rand::{thread_rng, Rng};
+use tracing::{debug, trace};
+
+/// Largest proof-of-work difficulty that will be solved for the other peer. Handshakes
+/// with peers requiring more fail
+pub const MAX_POW_DIFFICULTY: u8 = 24;
+
+/// Size every [`Hello`] is padded to in protocol versions that have
+/// [`has_handshake_padding`] (in bytes). Initiation packets stay far below the MTU of
+/// any path
+pub const HELLO_SIZE: usize = 256;
+
+/// Size of the digest of the UID of the sender carried by padded hellos (in bytes)
+pub const UID_DIGEST_SIZE: usize = 32;
+
+/// Returns the SHA-256 digest of `uid`, which identifies the sender of a padded
+/// [`Hello`]
+pub fn uid_digest(uid: &str) -> [u8; UID_DIGEST_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.update(uid.as_bytes());
+    hasher.finish()
+}
+
+/// Payload of the initiation packets exchanged during the handshake
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hello {
+    /// Highest protocol version supported by the sender
+    pub version: u8,
+    /// Proof-of-work difficulty the sender requires from the receiver
+    pub difficulty: u8,
+    /// Solution of the proof-of-work puzzle required by the receiver
+    pub nonce: u64,
+    /// Random cookie to be echoed back by the receiver, proving it can receive
+    /// packets sent to its address
+    pub cookie: u64,
+    /// Cookie received from the receiver, `0` if none has been received yet
+    pub echo: u64,
+    /// Largest message the sender accepts (in bytes)
+    pub max_message_size: u32,
+    /// Largest window the sender accepts (in packets)
+    pub max_window: u16,
+    /// UID of the sender. Empty in padded hellos that were received (refer
+    /// [`has_handshake_padding`]), which only carry its digest
+    pub uid: String,
+    /// Digest of the UID of the sender received in place of it (refer [`uid_digest`]),
+    /// [`None`] for hellos of earlier versions
+    pub uid_digest: Option<[u8; UID_DIGEST_SIZE]>,
+}
+
+impl Hello {
+    /// Create a new [`Hello`] with the latest protocol version
+    pub fn new(uid: String) -> Hello {
+        Hello {
+            version: PROTOCOL_VERSION,
+            difficulty: 0,
+            nonce: 0,
+            cookie: 0,
+            echo: 0,
+            max_message_size: MAX_PAYLOAD_SIZE as u32,
+            max_window: MAX_WINDOW,
+            uid,
+            uid_digest: None,
+        }
+    }
+
+    /// Check if the hello was sent by the peer `uid`
+    pub fn is_from(&self, uid: &str) -> bool {
+        match self.uid_digest {
+            Some(digest) => digest == uid_digest(uid),
+            None => self.uid == uid,
+        }
+    }
+
+    /// Compile the [`Hello`] into bytes to be used as payload
+    pub fn compile(&self) -> Vec<u8> {
+        let mut bytes = vec![self.version];
+        if has_handshake_puzzle(self.version) {
+            bytes.push(self.difficulty);
+            bytes.extend(self.nonce.to_be_bytes());
+        }
+        if has_handshake_cookie(self.version) {
+            bytes.extend(self.cookie.to_be_bytes());
+            bytes.extend(self.echo.to_be_bytes());
+        }
+        if has_message_size(self.version) {
+            bytes.extend(self.max_message_size.to_be_bytes());
+        }
+        if has_window_size(self.version) {
+            bytes.extend(self.max_window.to_be_bytes());
+        }
+        if has_handshake_padding(self.version) {
+            bytes.extend(uid_digest(&self.uid));
+            bytes.resize(HELLO_SIZE, 0);
+        } else {
+            bytes.extend(self.uid.as_bytes());
+        }
+        bytes
+    }
+
+    /// Parse a [`Hello`] from the payload of an initiation packet
+    /// # Errors
+    /// * [`AetherError::HandshakeError`] - If the payload is not a valid [`Hello`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Hello, AetherError> {
+        let (version, mut rest) = match bytes.split_first() {
+            Some((&version, rest)) if version >= BASE_VERSION => (version, rest),
+            _ => return Err(AetherError::HandshakeError),
+        };
+
+        let mut difficulty = 0;
+        let mut nonce = 0;
+        let mut cookie = 0;
+        let mut echo = 0;
+        let mut max_message_size = MAX_PAYLOAD_SIZE as u32;
+        let mut max_window = MAX_WINDOW;
+
+        if has_handshake_puzzle(version) {
+            if rest.len() < 9 {
+                return Err(AetherError::HandshakeError);
+            }
+            difficulty = rest[0];
+            nonce = u64::from_be_bytes(rest[1..9].try_into().expect("Invalid nonce size"));
+            rest = &rest[9..];
+        }
+
+        if has_handshake_cookie(version) {
+            if rest.len() < 16 {
+                return Err(AetherError::HandshakeError);
+            }
+            cookie = u64::from_be_bytes(rest[0..8].try_into().expect("Invalid cookie size"));
+            echo = u64::from_be_bytes(rest[8..16].try_into().expect("Invalid echo size"));
+            rest = &rest[16..];
+        }
+
+        if has_message_size(version) {
+            if rest.len() < 4 {
+                return Err(AetherError::HandshakeError);
+            }
+            max_message_size =
+                u32::from_be_bytes(rest[0..4].try_into().expect("Invalid message size"));
+            rest = &rest[4..];
+        }
+
+        if has_window_size(version) {
+            if rest.len() < 2 {
+                return Err(AetherError::HandshakeError);
+            }
+            max_window = u16::from_be_bytes(rest[0..2].try_into().expect("Invalid window size"));
+            rest = &rest[2..];
+        }
+
+        // The padding following the digest is ignored
+        let (uid, uid_digest) = if has_handshake_padding(version) {
+            if rest.len() < UID_DIGEST_SIZE {
+                return Err(AetherError::HandshakeError);
+            }
+            let digest = rest[..UID_DIGEST_SIZE]
+                .try_into()
+                .expect("Invalid digest size");
+            (String::new(), Some(digest))
+        } else {
+            match String::from_utf8(rest.to_vec()) {
+                Ok(uid) => (uid, None),
+                Err(_) => return Err(AetherError::HandshakeError),
+            }
+        };
+
+        Ok(Hello {
+            version,
+            difficulty,
+            nonce,
+            cookie,
+            echo,
+            max_message_size,
+            max_window,
+            uid,
+            uid_digest,
+        })
+    }
+}
+
+/// Number of leading zero bits in the hash of a puzzle solution
+fn puzzle_zeros(challenge: u32, uid: &str, nonce: u64) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(&challenge.to_be_bytes());
+    hasher.update(uid.as_bytes());
+    hasher.update(&nonce.to_be_bytes());
+
+    let mut zeros = 0;
+    for byte in hasher.finish() {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Solve the proof-of-work puzzle required by the other peer. The puzzle is to find a
+/// nonce such that SHA-256 of the challenge (the other peer's initial sequence number),
+/// the solver's UID and the nonce starts with `difficulty` zero bits
+///
+/// # Arguments
+///
+/// * `challenge`   -   Initial sequence number of the other peer
+/// * `uid` -   UID of the peer solving the puzzle
+/// * `difficulty`  -   Number of leading zero bits required
+pub fn solve_puzzle(challenge: u32, uid: &str, difficulty: u8) -> u64 {
+    let mut nonce: u64 = 0;
+    while !verify_puzzle(challenge, uid, nonce, difficulty) {
+        nonce += 1;
+    }
+    nonce
+}
+
+/// Verify a solution of a proof-of-work puzzle (see [`solve_puzzle`])
+///
+/// # Arguments
+///
+/// * `challenge`   -   Initial sequence number of the peer that required the puzzle
+/// * `uid` -   UID of the peer that solved the puzzle
+/// * `nonce`   -   The solution to be verified
+/// * `difficulty`  -   Number of leading zero bits required
+pub fn verify_puzzle(challenge: u32, uid: &str, nonce: u64, difficulty: u8) -> bool {
+    difficulty == 0 || puzzle_zeros(challenge, uid, nonce) >= difficulty as u32
+}
+
+/// Options for a single handshake
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeOptions {
+    /// Proof-of-work difficulty required from the other peer. `0` if no proof-of-work
+    /// is required
+    pub pow_difficulty: u8,
+    /// Counters to record rejected attempts in
+    pub rejections: Option<Arc<RejectionCounters>>,
+    /// Receiver of the telemetry of the handshake and the resulting link
+    pub telemetry: Option<Arc<dyn Telemetry>>,
+    /// Budget the memory of the resulting link is charged to
+    pub memory: Option<Arc<MemoryBudget>>,
+    /// Flag to abandon the handshake while no hello has been received from the other
+    /// peer yet, which then fails like a timeout
+    pub cancel: Option<Arc<AtomicBool>>,
+}
 
-pub fn handshake(
+impl HandshakeOptions {
+    fn reject_address(&self) {
+        if let Some(rejections) = &self.rejections {
+            rejections.reject_address();
+        }
+    }
+
+    fn reject_cookie(&self) {
+        if let Some(rejections) = &self.rejections {
+            rejections.reject_cookie();
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        match &self.cancel {
+            Some(cancel) => cancel.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    fn telemetry(&self) -> Arc<dyn Telemetry> {
+        match &self.telemetry {
+            Some(telemetry) => telemetry.clone(),
+            None => Arc::new(NoopTelemetry),
+        }
+    }
+}
+
+#[cfg(feature = "raw")]
+pub fn handshake<T: Transport + 'static>(
+    private_id: Id,
+    socket: T,
+    address: SocketAddr,
+    my_uid: String,
+    peer_uid: String,
+    config: Config,
+) -> Result<Link, AetherError> {
+    handshake_with_options(
+        private_id,
+        socket,
+        address,
+        my_uid,
+        peer_uid,
+        HandshakeOptions::default(),
+        config,
+    )
+}
+
+/// Perform a handshake with the given [`HandshakeOptions`]
+///
+/// The other peer's address is validated first: packets from other addresses are
+/// dropped and the other peer has to echo the cookie sent to it. Only then is its
+/// proof-of-work puzzle solved or checked (if one is required) and the other peer
+/// accepted, so spoofed packets never lead to expensive work
+pub fn handshake_with_options<T: Transport + 'static>(
     private_id: Id,
-    socket: UdpSocket,
+    socket: T,
     address: SocketAddr,
     my_uid: String,
     peer_uid: String,
+    options: HandshakeOptions,
     config: Config,
 ) -> Result<Link, AetherError> {
-    let seq = thread_rng().gen_range(0..(1 << 16_u32)) as u32;
-    let recv_seq: u32;
+    let telemetry = options.telemetry();
+    let _span = telemetry::span(&telemetry, SPAN_HANDSHAKE);
+    telemetry.event(&TelemetryEvent::HandshakePhase {
+        peer: &peer_uid,
+        phase: HandshakePhase::Hello,
+    });
+
+    let seq = Seq(thread_rng().gen_range(0..(1 << 16_u32)));
+    let recv_seq: Seq;
+    let version: u8;
+    let max_message_size: usize;
+    let max_window: u16;
 
     let ack: bool;
+    let peer_acked: bool;
 
     if socket
         .set_read_timeout(Some(Duration::from_millis(config.handshake.peer_poll_time)))
@@ -30,17 +357,28 @@ pub fn handshake(
         return Err(AetherError::SetReadTimeout);
     }
 
-    let mut packet = Packet::new(PType::Initiation, seq);
-    packet.append_payload(my_uid.into_bytes());
+    let mut own_hello = Hello::new(my_uid.clone());
+    own_hello.difficulty = options.pow_difficulty;
+    own_hello.cookie = thread_rng().gen_range(1..u64::MAX);
+    own_hello.max_message_size = u32::try_from(config.link.max_message_size).unwrap_or(u32::MAX);
+    own_hello.max_window = config.link.max_window.min(MAX_WINDOW);
+
+    let mut packet = PacketBuilder::new(PType::Initiation)
+        .sequence(seq)
+        .payload(own_hello.compile())
+        .build()?;
 
-    let sequence_data = packet.compile();
+    let mut sequence_data = packet.compile();
+    let mut solved = false;
+    let mut ignored = false;
 
     let now = SystemTime::now();
     // Repeat sending start sequence number and ID
     loop {
         let elapsed = now.elapsed()?;
 
-        if elapsed.as_millis() > config.handshake.handshake_timeout.into() {
+        if elapsed.as_millis() > config.handshake.handshake_timeout.into() || options.is_cancelled()
+        {
             return Err(AetherError::HandshakeError);
         }
 
@@ -56,19 +394,89 @@ pub fn handshake(
 
         let mut buf: [u8; 1024] = [0; 1024];
 
-        if let Ok(size) = socket.recv(&mut buf) {
+        if let Ok((size, source)) = socket.recv_from(&mut buf) {
+            if source != address {
+                options.reject_address();
+                continue;
+            }
+
             if size > 0 {
-                let recved = Packet::from(buf[..size].to_vec());
-                let uid_recved = match String::from_utf8(recved.payload.clone()) {
-                    Ok(string) => string,
-                    Err(_) => return Err(AetherError::HandshakeError),
+                let recved = match Packet::decode_slice(&buf[..size], BASE_VERSION) {
+                    Ok(packet) => packet,
+                    Err(_) => continue,
+                };
+                // Packets from a link already started by the other peer carry no hello
+                let hello = match Hello::from_bytes(&recved.payload) {
+                    Ok(hello) => hello,
+                    Err(_) => continue,
                 };
 
                 // Verify the sender has the correct uid
-                if uid_recved == peer_uid {
+                if hello.is_from(&peer_uid) {
+                    // Echo the cookie of the other peer
+                    if has_handshake_cookie(hello.version) && own_hello.echo != hello.cookie {
+                        own_hello.echo = hello.cookie;
+                        packet.payload = own_hello.compile();
+                        sequence_data = packet.compile();
+                    }
+
+                    // Ignore the other peer until it has echoed the cookie. Peers
+                    // on older versions cannot echo it
+                    if has_handshake_cookie(hello.version) && hello.echo != own_hello.cookie {
+                        if hello.echo != 0 {
+                            options.reject_cookie();
+                        }
+                        ignored = true;
+                        continue;
+                    }
+
+                    // Solve the puzzle if the other peer requires one
+                    if hello.difficulty > 0 && !solved {
+                        if hello.difficulty > MAX_POW_DIFFICULTY {
+                            return Err(AetherError::HandshakeError);
+                        }
+
+                        trace!(difficulty = hello.difficulty, "Solving puzzle");
+                        own_hello.nonce =
+                            solve_puzzle(recved.sequence.0, &my_uid, hello.difficulty);
+                        packet.payload = own_hello.compile();
+                        sequence_data = packet.compile();
+                        solved = true;
+                    }
+
+                    // Ignore the other peer until it has solved the puzzle
+                    if !verify_puzzle(seq.0, &peer_uid, hello.nonce, options.pow_difficulty) {
+                        ignored = true;
+                        continue;
+                    }
+
                     recv_seq = recved.sequence;
 
-                    ack = recved.flags.ack && recved.ack.ack_begin == seq;
+                    // Use the highest version supported by both peers
+                    version = hello.version.min(PROTOCOL_VERSION);
+
+                    // Both peers have to accept the messages sent over the link
+                    max_message_size =
+                        config
+                            .link
+                            .max_message_size
+                            .min(if has_message_size(version) {
+                                hello.max_message_size as usize
+                            } else {
+                                MAX_PAYLOAD_SIZE
+                            });
+
+                    // Never send further ahead than the other peer accepts
+                    max_window = if has_window_size(version) {
+                        hello.max_window
+                    } else {
+                        MAX_WINDOW
+                    };
+
+                    // If earlier hellos were ignored the other peer is still waiting
+                    // for an acknowledgement
+                    peer_acked = recved.flags.ack && recved.ack.ack_begin == seq;
+                    ack = peer_acked && !ignored;
 
                     break;
                 }
@@ -76,13 +484,22 @@ pub fn handshake(
         }
     }
 
+    debug!(version, max_message_size, max_window, %recv_seq, ack, "Received hello");
+
     // If not acknowledged by other peer yet
     if !ack {
+        telemetry.event(&TelemetryEvent::HandshakePhase {
+            peer: &peer_uid,
+            phase: HandshakePhase::Acknowledge,
+        });
+
         packet.add_ack(Acknowledgement {
             ack_begin: recv_seq,
             ack_end: 0,
             miss_count: 0,
             miss: Vec::new(),
+            recv_time_us: 0,
+            congestion: false,
         });
 
         let ack_data = packet.compile();
@@ -105,18 +522,32 @@ pub fn handshake(
                 }
             }
 
+            // The other peer only waits for this acknowledgement, it will not send
+            // another one
+            if peer_acked {
+                break;
+            }
+
             let mut buf: [u8; 1024] = [0; 1024];
 
-            if let Ok(size) = socket.recv(&mut buf) {
+            if let Ok((size, source)) = socket.recv_from(&mut buf) {
+                if source != address {
+                    options.reject_address();
+                    continue;
+                }
+
                 if size > 0 {
-                    let recved = Packet::from(buf[..size].to_vec());
-                    let uid_recved = match String::from_utf8(recved.payload.clone()) {
-                        Ok(string) => string,
-                        Err(_) => return Err(AetherError::HandshakeError),
+                    let recved = match Packet::try_from(&buf[..size]) {
+                        Ok(packet) => packet,
+                        Err(_) => continue,
+                    };
+                    let hello = match Hello::from_bytes(&recved.payload) {
+                        Ok(hello) => hello,
+                        Err(_) => continue,
                     };
 
                     // Verify the sender has the correct uid
-                    if uid_recved == peer_uid
+                    if hello.is_from(&peer_uid)
                         && recved.sequence == recv_seq
                         && recved.flags.ack
                         && recved.ack.ack_begin == seq
@@ -132,6 +563,75 @@ pub fn handshake(
 
     // Start the link
     let mut link = Link::new(private_id, socket, address, peer_id, seq, recv_seq, config)?;
+    link.set_version(version);
+    link.set_max_message_size(max_message_size);
+    link.set_max_window(max_window);
+    link.set_telemetry(telemetry);
+    if let Some(memory) = options.memory {
+        link.set_memory_budget(memory);
+    }
     link.start();
     Ok(link)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{solve_puzzle, verify_puzzle, Hello, HELLO_SIZE};
+    use crate::acknowledgement::MAX_WINDOW;
+    use crate::packet::MAX_PAYLOAD_SIZE;
+
+    #[test]
+    fn hello_test() {
+        let mut hello = Hello::new(String::from("uid"));
+        hello.difficulty = 8;
+        hello.nonce = 1234;
+        hello.cookie = 42;
+        hello.echo = 24;
+        hello.max_message_size = 1024;
+        hello.max_window = 3;
+
+        // Hellos carry the digest of the UID and have the same size for every UID
+        let compiled = hello.compile();
+        assert_eq!(compiled.len(), HELLO_SIZE);
+        let long = Hello::new("uid".repeat(300));
+        assert_eq!(long.compile().len(), HELLO_SIZE);
+
+        let received = Hello::from_bytes(&compiled).unwrap();
+        assert!(received.is_from("uid"));
+        assert!(!received.is_from("other"));
+        let received = Hello {
+            uid: String::from("uid"),
+            uid_digest: None,
+            ..received
+        };
+        assert_eq!(received, hello);
+
+        // Version 1 hello carries no puzzle
+        let old = Hello::from_bytes(b"\x01uid").unwrap();
+        assert_eq!(old.uid, "uid");
+        assert_eq!(old.difficulty, 0);
+        assert_eq!(old.max_message_size, MAX_PAYLOAD_SIZE as u32);
+
+        // Version 7 hello is truncated without the message size
+        let mut bytes = hello.compile();
+        bytes.truncate(1 + 9 + 16 + 2);
+        assert!(Hello::from_bytes(&bytes).is_err());
+
+        // Version 8 hello carries no window
+        let mut old = hello.clone();
+        old.version = 8;
+        let old = Hello::from_bytes(&old.compile()).unwrap();
+        assert_eq!(old.max_message_size, 1024);
+        assert_eq!(old.max_window, MAX_WINDOW);
+    }
+
+    #[test]
+    fn puzzle_test() {
+        let nonce = solve_puzzle(4200, "uid", 12);
+
+        assert!(verify_puzzle(4200, "uid", nonce, 12));
+        assert!(verify_puzzle(4200, "uid", 0, 0));
+        // Solution is bound to the challenge
+        assert!(!(0..16).all(|c| verify_puzzle(c, "uid", nonce, 12)));
+    }
+}
@@ -0,0 +1,136 @@
+//! Connection requests waiting for the application to accept or reject them, with
+//! [`manual_accept`][crate::config::AetherConfig::manual_accept] enabled.
+//!
+//! The other peer keeps requesting the connection through the tracker server until it
+//! is accepted, so each peer is queued once with its latest request. Rejected peers are
+//! ignored until the application connects to them (refer
+//! [`Aether::reject`][crate::peer::Aether::reject]).
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::tracker::ConnectionRequest;
+
+/// Maximum number of queued requests, further peers are ignored until requests are
+/// accepted or rejected
+pub const MAX_INCOMING: usize = 64;
+
+#[derive(Debug, Default)]
+struct State {
+    /// Requests in the order the peers first requested a connection
+    pending: Vec<ConnectionRequest>,
+    /// UIDs of the rejected peers
+    rejected: HashSet<String>,
+}
+
+/// Queue of incoming connection requests
+#[derive(Debug, Default)]
+pub struct Incoming {
+    state: Mutex<State>,
+}
+
+impl Incoming {
+    /// Creates an empty queue
+    pub fn new() -> Incoming {
+        Incoming::default()
+    }
+
+    /// Queue `request`, replacing an earlier request of the same peer. Returns true if
+    /// the peer was not queued before. Requests of rejected peers and requests beyond
+    /// [`MAX_INCOMING`] are dropped
+    pub fn offer(&self, request: ConnectionRequest) -> bool {
+        let mut state = self.state.lock().expect("unable to lock incoming requests");
+        if state.rejected.contains(&request.username) {
+            return false;
+        }
+
+        let queued = state
+            .pending
+            .iter()
+            .position(|pending| pending.username == request.username);
+        match queued {
+            Some(index) => {
+                state.pending[index] = request;
+                false
+            }
+            None if state.pending.len() < MAX_INCOMING => {
+                state.pending.push(request);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the queued requests
+    pub fn pending(&self) -> Vec<ConnectionRequest> {
+        let state = self.state.lock().expect("unable to lock incoming requests");
+        state.pending.clone()
+    }
+
+    /// Remove the request of `uid` from the queue, forgetting a rejection of the peer
+    pub fn take(&self, uid: &str) -> Option<ConnectionRequest> {
+        let mut state = self.state.lock().expect("unable to lock incoming requests");
+        state.rejected.remove(uid);
+        let index = state
+            .pending
+            .iter()
+            .position(|pending| pending.username == uid)?;
+        Some(state.pending.remove(index))
+    }
+
+    /// Remove the request of `uid` from the queue and ignore further requests of the
+    /// peer until it is [taken][Incoming::take] again
+    pub fn reject(&self, uid: &str) {
+        let mut state = self.state.lock().expect("unable to lock incoming requests");
+        state.pending.retain(|pending| pending.username != uid);
+        state.rejected.insert(uid.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Incoming, MAX_INCOMING};
+    use crate::tracker::ConnectionRequest;
+
+    fn request(uid: &str, port: u16) -> ConnectionRequest {
+        ConnectionRequest {
+            username: uid.to_string(),
+            port,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn incoming_test() {
+        let incoming = Incoming::new();
+
+        // Repeated requests replace the queued one
+        assert!(incoming.offer(request("first", 1)));
+        assert!(incoming.offer(request("second", 1)));
+        assert!(!incoming.offer(request("first", 2)));
+        assert_eq!(
+            incoming.pending(),
+            vec![request("first", 2), request("second", 1)]
+        );
+
+        assert_eq!(incoming.take("first"), Some(request("first", 2)));
+        assert_eq!(incoming.take("first"), None);
+
+        // Rejected peers are ignored until taken
+        incoming.reject("second");
+        assert!(incoming.pending().is_empty());
+        assert!(!incoming.offer(request("second", 1)));
+        assert_eq!(incoming.take("second"), None);
+        assert!(incoming.offer(request("second", 1)));
+    }
+
+    #[test]
+    fn incoming_limit_test() {
+        let incoming = Incoming::new();
+        for index in 0..MAX_INCOMING {
+            assert!(incoming.offer(request(&index.to_string(), 1)));
+        }
+        assert!(!incoming.offer(request("another", 1)));
+        assert_eq!(incoming.pending().len(), MAX_INCOMING);
+    }
+}
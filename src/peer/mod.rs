@@ -1,29 +1,136 @@
 //! Structure for representing an [`Aether`] client.
 
+#[cfg(feature = "raw")]
 pub mod authentication;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod authentication;
+pub mod cache;
+pub mod connect;
+#[cfg(feature = "raw")]
+pub mod fanin;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod fanin;
+pub mod frames;
+#[cfg(feature = "raw")]
 pub mod handshake;
-
-use log::{error, trace};
+#[cfg(not(feature = "raw"))]
+pub(crate) mod handshake;
+pub mod incoming;
+#[cfg(feature = "raw")]
+pub mod network;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod network;
+pub mod outbox;
+pub mod presence;
+#[cfg(feature = "raw")]
+pub mod registry;
+#[cfg(not(feature = "raw"))]
+pub(crate) mod registry;
+pub mod rotation;
+
+use tracing::{debug, error, info, info_span, trace, warn};
 
 use std::collections::VecDeque;
-use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
-use std::thread;
-use std::time::{Duration, SystemTime};
-use std::{collections::HashMap, net::SocketAddr};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 
-use rand::{thread_rng, Rng};
-
-use crate::config::Config;
-use crate::identity::Id;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+use crate::config::{AetherConfig, Config};
+use crate::contacts::{Contacts, Trust, CONTACTS_FILE};
+use crate::identity::name::SignedName;
+use crate::identity::{Id, PublicId};
+use crate::link::delivery::{DeliveryHandle, MessageId};
+use crate::link::{CloseReason, IncomingProgress, Negotiated};
+use crate::memory::{projected_memory, MemoryBudget};
+use crate::migration;
+use crate::packet::Packet;
 use crate::peer::authentication::authenticate;
-use crate::tracker::TrackerPacket;
+use crate::stats::{
+    Histograms, LinkStats, RejectionCounters, Rejections, TrackerCounters, TrackerStats,
+};
+use crate::telemetry::{
+    self, limited, HandshakePhase, LogLimiter, NoopTelemetry, Telemetry, TelemetryEvent,
+    COUNTER_HANDSHAKES, COUNTER_HANDSHAKE_FAILURES, SPAN_AUTHENTICATION,
+};
+use crate::tracker::{TrackerChannel, TrackerClient, TrackerPacketType};
+use crate::transport::Transport;
+use crate::util::{catch_panic, Stop, Wakeup};
+use crate::wire::control;
+use crate::wire::headers::{self, Headers, Message};
 use crate::{error::AetherError, link::Link, tracker::ConnectionRequest};
 
-use self::handshake::handshake;
+use self::cache::{CachedPeer, PeerCache};
+use self::connect::{Attempts, ConnectOptions};
+use self::fanin::FanIn;
+use self::frames::{Frame, Frames, HEADERS_SUBTYPE, NAME_SUBTYPE, ROTATION_SUBTYPE};
+use self::handshake::{handshake_with_options, HandshakeOptions};
+use self::incoming::Incoming;
+use self::network::NetworkEnvironment;
+use self::outbox::Outbox;
+use self::presence::{LinkFailure, PresenceChecks};
+use self::registry::{ConnectionRegistry, Shard};
+use self::rotation::{KeyRotation, RotationResponse};
+
+/// Policy deciding whether to accept a connection request from a peer this client
+/// did not request a connection to. Refer [`Aether::set_accept_policy`]
+pub type AcceptPolicy = dyn Fn(&ConnectionRequest) -> bool + Send + Sync;
+
+/// Events reported by an [`Aether`] client
+#[derive(Debug, Clone, PartialEq)]
+pub enum AetherEvent {
+    /// The route to the tracker server changed, for example because a VPN was toggled.
+    /// Links to peers using the previous route may stop working
+    NetworkEnvironmentChanged {
+        previous: NetworkEnvironment,
+        current: NetworkEnvironment,
+    },
+    /// Connecting to the peer was given up, because the timeout or retries of its
+    /// [`ConnectOptions`] ran out
+    ConnectionFailed { uid: String },
+    /// A connection to the peer was established. `reconnected` is set if earlier
+    /// handshakes with the peer failed (refer [`AetherEvent::Reconnecting`])
+    Connected { uid: String, reconnected: bool },
+    /// A handshake with the peer failed because of `reason`, it is retried after
+    /// `retry_in` as configured by the [`RetryPolicy`][crate::config::RetryPolicy]
+    HandshakeFailed {
+        uid: String,
+        reason: String,
+        retry_in: Duration,
+    },
+    /// The peer is attempted again after a failed handshake, for the `attempt`-th time
+    Reconnecting { uid: String, attempt: u32 },
+    /// A peer this client did not request a connection to requested one, and its
+    /// request was queued until it is accepted or rejected (refer [`Aether::incoming`])
+    ConnectionRequested { uid: String },
+    /// The link to a connected peer timed out, with the cause found by asking the
+    /// tracker server whether the peer is still present, or one of its threads failed
+    /// (refer [`LinkFailure::Internal`])
+    LinkFailed { uid: String, failure: LinkFailure },
+    /// A connected peer rotated its key and the new key was verified, the session
+    /// continues under the new UID. Refer [`rotation`]
+    IdentityRotated { previous: String, current: String },
+    /// The tracker server left the configured number of polls in a row unanswered
+    /// (refer [`AetherConfig::tracker_failure_threshold`]). No new connections can be
+    /// formed until it answers again
+    TrackerUnreachable { failures: u32 },
+    /// The tracker server answered again after being reported unreachable
+    TrackerReachable,
+    /// A background thread of this client failed with `error` and stopped. Depending on
+    /// the `thread`, the client may not form new connections or notice failed links any
+    /// more. Failures of the threads of a link are reported with
+    /// [`LinkFailed`][AetherEvent::LinkFailed] instead
+    ThreadFailed { thread: &'static str, error: String },
+}
 
 /// Enumeration representing different states of a connection
 #[derive(Debug)]
@@ -31,36 +138,226 @@ pub enum Connection {
     /// Initialized state - connection has been initialized and is waiting to receive
     /// other peer's public identity
     Init(Initialized),
-    /// Handshake state - handshake with the other peer is in progress
-    Handshake,
+    /// Handshake state - handshake with the other peer is in progress, from the local
+    /// port given
+    Handshake(u16),
+    /// Direct state - handshake with the cached endpoint of the other peer is in
+    /// progress, while the other peer is still requested through the tracker server
+    Direct(Box<Direct>),
     /// Connected state - a connection to the other peer has been established
     Connected(Box<Peer>),
     /// Failed state - a connection to the other peer had failed and would be retried
     Failed(Failure),
 }
 
+impl Connection {
+    /// Returns the [`ConnectionStatus`] of this state
+    pub fn status(&self) -> ConnectionStatus {
+        match self {
+            Connection::Init(_) => ConnectionStatus::Initialized,
+            Connection::Handshake(_) | Connection::Direct(_) => ConnectionStatus::Handshaking,
+            Connection::Connected(_) => ConnectionStatus::Connected,
+            Connection::Failed(failure) => ConnectionStatus::Failed {
+                since: failure.time,
+                reason: failure.attempts.reason().to_string(),
+            },
+        }
+    }
+
+    /// Returns true if connecting was given up and the connection was kept in failed
+    /// state (refer [`GiveUp::KeepFailed`][crate::config::GiveUp::KeepFailed])
+    fn given_up(&self) -> bool {
+        matches!(self, Connection::Failed(failure) if failure.attempts.given_up())
+    }
+
+    /// Give up connecting. Returns the connection to be kept in failed state, if the
+    /// retry policy keeps it
+    fn give_up(self) -> Option<Connection> {
+        let (socket, uid, mut attempts) = match self {
+            Connection::Init(init) => (init.socket, init.uid, init.attempts),
+            Connection::Failed(failure) => (failure.socket, failure.uid, failure.attempts),
+            _ => return None,
+        };
+
+        if !attempts.give_up() {
+            return None;
+        }
+        Some(Connection::Failed(Failure {
+            time: SystemTime::now(),
+            socket,
+            uid,
+            attempts,
+        }))
+    }
+
+    /// Returns the local port used to connect to the other peer
+    pub fn local_port(&self) -> Option<u16> {
+        let addr = match self {
+            Connection::Init(init) => init.socket.local_addr(),
+            Connection::Handshake(port) => return Some(*port),
+            Connection::Direct(direct) => direct.socket.local_addr(),
+            Connection::Connected(peer) => peer.link.local_addr(),
+            Connection::Failed(failure) => failure.socket.local_addr(),
+        };
+        addr.ok().map(|addr| addr.port())
+    }
+}
+
+/// State of the connection to a peer as returned by [`Aether::connection_status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// No connection to the peer has been requested, or connecting was given up
+    Unknown,
+    /// The connection is waiting for the tracker server to send the public identity of
+    /// the peer
+    Initialized,
+    /// The handshake with the peer is in progress
+    Handshaking,
+    /// A connection to the peer has been established
+    Connected,
+    /// The latest attempt to connect failed `since` the given time because of `reason`,
+    /// and will be retried
+    Failed { since: SystemTime, reason: String },
+}
+
 #[derive(Debug)]
 pub struct Peer {
     pub uid: String,
     pub identity_number: u32,
-    link: Link,
+    /// Link to the peer, shared so that it is used without holding the lock of the
+    /// [`ConnectionRegistry`]
+    link: Arc<Link>,
+    /// Cause of the link timing out, once known
+    failure: Option<LinkFailure>,
+    /// Display name published by the peer, once verified
+    name: Option<String>,
+    /// Time the peer was authenticated
+    connected_at: Instant,
+}
+
+/// Information about a connected peer, for presenting it to users instead of its UID
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    /// UID of the peer
+    pub uid: String,
+    /// Display name published by the peer, verified to be signed by it (refer
+    /// [`name`][crate::identity::name])
+    pub name: Option<String>,
+    /// Alias of the peer in the address book of this client
+    pub alias: Option<String>,
+    /// Fingerprint of the key of the peer, for users to compare with the fingerprint
+    /// the peer shows for itself (refer [`PublicId::fingerprint`])
+    pub fingerprint: String,
+    /// Address the link to the peer sends to
+    pub address: SocketAddr,
+    /// Whether the link to the peer is encrypted
+    pub encrypted: bool,
+    /// Time since the peer was connected
+    pub uptime: Duration,
+    /// Smoothed round trip time (in us) measured by the link, 0 if no sample was taken
+    /// yet (refer [`LinkStats::rtt_us`])
+    pub rtt_us: u64,
+    /// Fraction of the packets needing acknowledgement that were retransmitted (refer
+    /// [`LinkStats::loss_rate`])
+    pub loss_rate: f64,
+}
+
+impl PeerInfo {
+    /// Returns how the peer should be shown to users: the alias given to it if it is a
+    /// contact, else the display name it published, else its UID
+    pub fn label(&self) -> &str {
+        self.alias
+            .as_deref()
+            .or(self.name.as_deref())
+            .unwrap_or(&self.uid)
+    }
 }
 
 #[derive(Debug)]
 pub struct Initialized {
     uid: String,
     socket: UdpSocket,
-    identity_number: u32,
+    /// Progress of connecting along with the options the connection was requested with
+    attempts: Box<Attempts>,
+    /// Endpoint of the [`CachedPeer`] to be attempted before the tracker server answers
+    cached_endpoint: Option<([u8; 4], u16)>,
 }
 
 impl Initialized {
-    pub fn new(uid: String) -> Initialized {
-        Initialized {
+    /// Creates a connection using any free local port
+    /// # Errors
+    /// * [`std::io::Error`] - If no socket can be bound
+    pub fn new(uid: String) -> io::Result<Initialized> {
+        Self::with_port(uid, None, &AetherConfig::default())
+    }
+
+    /// Creates a connection using the local `port` if it is given and still free, such
+    /// as the port of a [`CachedPeer`]. The socket is bound as configured in `config`,
+    /// see [`bind_peer_socket`]
+    /// # Errors
+    /// * [`std::io::Error`] - If no port of the configured peer port range is free
+    pub fn with_port(
+        uid: String,
+        port: Option<u16>,
+        config: &AetherConfig,
+    ) -> io::Result<Initialized> {
+        let socket = bind_peer_socket(config, port)?;
+
+        Ok(Initialized {
             uid,
-            socket: UdpSocket::bind(("0.0.0.0", 0)).expect("unable to create socket"),
-            identity_number: 1,
+            socket,
+            attempts: Box::new(Attempts::with_policy(
+                ConnectOptions::default(),
+                config.retry,
+            )),
+            cached_endpoint: None,
+        })
+    }
+}
+
+/// Bind a socket for a link to a peer to the
+/// [`bind_address`][AetherConfig::bind_address] in `config`. The `preferred` port is
+/// used if it is free and in the configured peer port range, otherwise the first free
+/// port of the range. Without a range any free port is used
+/// # Errors
+/// * [`std::io::Error`] - If no port of the range is free
+pub fn bind_peer_socket(config: &AetherConfig, preferred: Option<u16>) -> io::Result<UdpSocket> {
+    let address = config.bind_address;
+
+    if config.peer_port_min == 0 {
+        return preferred
+            .and_then(|port| UdpSocket::bind((address, port)).ok())
+            .map_or_else(|| UdpSocket::bind((address, 0)), Ok);
+    }
+
+    let range = config.peer_port_min..=config.peer_port_max.max(config.peer_port_min);
+    let preferred = preferred.filter(|port| range.contains(port));
+
+    let mut error = None;
+    for port in preferred.into_iter().chain(range) {
+        match UdpSocket::bind((address, port)) {
+            Ok(socket) => return Ok(socket),
+            Err(err) => error = Some(err),
         }
     }
+
+    Err(error.unwrap_or_else(|| io::Error::from(io::ErrorKind::AddrInUse)))
+}
+
+/// Connection attempted with the cached endpoint of a peer
+#[derive(Debug)]
+pub struct Direct {
+    uid: String,
+    /// Clone of the socket used by the handshake, to keep requesting the peer through
+    /// the tracker server from the same local port
+    socket: UdpSocket,
+    /// Cached endpoint being attempted
+    endpoint: ([u8; 4], u16),
+    /// Latest request for a different endpoint received from the tracker server, to be
+    /// attempted immediately once the attempt of the cached endpoint is cancelled
+    request: Option<ConnectionRequest>,
+    /// Cancels the attempt of the cached endpoint while the other peer has not answered
+    cancel: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
@@ -68,6 +365,7 @@ pub struct Failure {
     time: SystemTime,
     socket: UdpSocket,
     uid: String,
+    attempts: Box<Attempts>,
 }
 
 /// [`Aether`] is an interface used to connect to other peers as well as communicate
@@ -77,103 +375,1298 @@ pub struct Aether {
     uid: String,
     /// Identity of user
     private_id: Id,
-    /// The [`UdpSocket`] to be used for communication
-    socket: Arc<UdpSocket>,
     /// Queue of connection requests received
     requests: Arc<Mutex<VecDeque<ConnectionRequest>>>,
-    /// Address of the tracker server
-    tracker_addr: SocketAddr,
+    /// Wakes the thread handling connection requests up when requests are queued
+    requests_wakeup: Arc<Wakeup>,
+    /// Client of the tracker server, communicating through the [`Transport`] given
+    tracker: TrackerClient,
+    /// Diagnostics of the polls sent to the tracker server
+    tracker_counters: Arc<TrackerCounters>,
     /// List of peers related to this peer
-    connections: Arc<Mutex<HashMap<String, Connection>>>,
+    connections: Arc<ConnectionRegistry>,
+    /// Messages received from all peers, used by [`Aether::recv_any`]
+    fan_in: Arc<FanIn>,
+    /// Presence queries for peers whose link timed out
+    presence: Arc<PresenceChecks>,
+    /// Histograms recorded outside of the links (such as handshake durations)
+    stats: Arc<Mutex<Histograms>>,
+    /// Number of handshakes currently in progress
+    handshakes: Arc<AtomicUsize>,
+    /// Handshake attempts rejected before the other peer's address was validated
+    rejections: Arc<RejectionCounters>,
+    /// Queue of [`AetherEvent`]s to be read by the user
+    events: (Sender<AetherEvent>, Receiver<AetherEvent>),
+    /// Last known endpoints of peers, if enabled
+    peer_cache: Option<Arc<Mutex<PeerCache>>>,
+    /// Receiver of the telemetry of this client and its links
+    telemetry: Arc<dyn Telemetry>,
+    /// Memory used by the links of this client
+    memory: Arc<MemoryBudget>,
+    /// Address book of known peers
+    contacts: Arc<Mutex<Contacts>>,
+    /// Messages waiting for their peers to be connected
+    outbox: Arc<Mutex<Outbox>>,
+    /// Display name of this client published to peers, if set
+    display_name: Arc<Mutex<Option<SignedName>>>,
+    /// Policy for connection requests from other peers
+    accept_policy: Arc<AcceptPolicy>,
+    /// Connection requests waiting to be accepted, with manual accept enabled
+    incoming: Arc<Incoming>,
+    /// Signals the threads of this client to stop
+    stop: Arc<Stop>,
+    /// Handles of the threads started by [`Aether::start`]
+    thread_handles: Mutex<Vec<JoinHandle<()>>>,
     /// Configuration
     config: Config,
 }
 
 impl Aether {
+    /// Creates a client using the identity stored in the default config dir, which is
+    /// upgraded to the current [`migration::SCHEMA_VERSION`] first
     pub fn new(tracker_addr: SocketAddr) -> Self {
-        let private_id = Id::load_or_generate().expect("Error loading identity");
+        let config_dir = Id::get_config_dir();
+        Self::prepare_config_dir(&config_dir);
+        let config = Config::get_config().expect("Error getting config");
 
-        Self::new_with_id(private_id, tracker_addr)
+        Self::load_from_config_dir(config, &config_dir, tracker_addr)
+    }
+
+    /// Creates a client with `config` instead of the configuration file, using the
+    /// identity and contacts stored in `config_dir` instead of the default config dir
+    /// (refer [`Id::get_config_dir`]). A new identity is generated and saved there if
+    /// there is none, and `config_dir` is upgraded like the one of [`Aether::new`].
+    /// To not store anything on the filesystem, pass the identity to
+    /// [`Aether::new_with_config`] instead
+    pub fn with_config(config: Config, config_dir: &Path, tracker_addr: SocketAddr) -> Self {
+        Self::prepare_config_dir(config_dir);
+
+        Self::load_from_config_dir(config, config_dir, tracker_addr)
+    }
+
+    /// Creates `config_dir` if it does not exist yet and upgrades it to the current
+    /// [`migration::SCHEMA_VERSION`], before any of its files are read
+    fn prepare_config_dir(config_dir: &Path) {
+        fs::create_dir_all(config_dir).expect("Error creating config dir");
+        migration::migrate(config_dir).expect("Error migrating config dir");
+    }
+
+    /// Creates a client using the identity and contacts stored in the prepared
+    /// `config_dir` (refer [`Aether::prepare_config_dir`])
+    fn load_from_config_dir(config: Config, config_dir: &Path, tracker_addr: SocketAddr) -> Self {
+        let private_id = Id::load_or_generate_in(config_dir).expect("Error loading identity");
+
+        let mut aether = Self::new_with_config(private_id, tracker_addr, config);
+        aether.set_contacts(
+            Contacts::load(&config_dir.join(CONTACTS_FILE)).unwrap_or_else(|err| {
+                warn!("Unable to load contacts: {}", err);
+                Contacts::new()
+            }),
+        );
+        aether
     }
 
     pub fn new_with_id(id: Id, tracker_addr: SocketAddr) -> Self {
         let config = Config::get_config().expect("Error getting config");
 
-        let uid = id.public_key_to_base64().expect("Error getting public key");
+        Self::new_with_config(id, tracker_addr, config)
+    }
+
+    /// Creates a client using `config` instead of the configuration file. The socket to
+    /// the tracker server is bound to the configured
+    /// [`bind_address`][AetherConfig::bind_address] and
+    /// [`tracker_port`][AetherConfig::tracker_port]
+    pub fn new_with_config(id: Id, tracker_addr: SocketAddr, config: Config) -> Self {
+        let socket = UdpSocket::bind((config.aether.bind_address, config.aether.tracker_port))
+            .expect("Unable to bind tracker socket");
+
+        Self::with_transport_config(id, tracker_addr, Arc::new(socket), config)
+    }
+
+    /// Creates a client communicating with the tracker server through `socket`, for
+    /// example a [`TcpTransport`][crate::transport::TcpTransport] where UDP is blocked
+    pub fn new_with_transport(
+        id: Id,
+        tracker_addr: SocketAddr,
+        socket: Arc<dyn Transport>,
+    ) -> Self {
+        let config = Config::get_config().expect("Error getting config");
+
+        Self::with_transport_config(id, tracker_addr, socket, config)
+    }
 
-        let socket = Arc::new(UdpSocket::bind(("0.0.0.0", 0)).unwrap());
+    fn with_transport_config(
+        id: Id,
+        tracker_addr: SocketAddr,
+        socket: Arc<dyn Transport>,
+        config: Config,
+    ) -> Self {
         socket
             .set_read_timeout(Some(Duration::from_millis(
                 config.aether.server_retry_delay,
             )))
             .expect("Unable to set read timeout");
+        let tracker =
+            TrackerClient::new(id.clone(), tracker_addr, socket).expect("Error getting public key");
+        let uid = tracker.uid().to_string();
+
         Aether {
             uid,
             private_id: id,
             requests: Arc::new(Mutex::new(VecDeque::new())),
-            tracker_addr,
-            socket,
-            connections: Arc::new(Mutex::new(HashMap::new())),
+            requests_wakeup: Arc::new(Wakeup::new()),
+            tracker,
+            tracker_counters: Arc::new(TrackerCounters::new()),
+            connections: Arc::new(ConnectionRegistry::new()),
+            fan_in: Arc::new(FanIn::new(config.aether.handler_threads)),
+            presence: Arc::new(PresenceChecks::new()),
+            stats: Arc::new(Mutex::new(Histograms::new())),
+            handshakes: Arc::new(AtomicUsize::new(0)),
+            rejections: Arc::new(RejectionCounters::new()),
+            events: unbounded(),
+            peer_cache: None,
+            telemetry: Arc::new(NoopTelemetry),
+            memory: Arc::new(MemoryBudget::new(config.aether.memory_budget)),
+            contacts: Arc::new(Mutex::new(Contacts::new())),
+            outbox: Arc::new(Mutex::new(Outbox::new())),
+            display_name: Arc::new(Mutex::new(None)),
+            accept_policy: Arc::new(|_| true),
+            incoming: Arc::new(Incoming::new()),
+            stop: Arc::new(Stop::new()),
+            thread_handles: Mutex::new(Vec::new()),
             config,
         }
     }
 
+    /// Encrypt the traffic to the tracker server using its published public key. Must
+    /// be called before [`Aether::start`]
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the session key could not be sealed
+    pub fn set_tracker_key(&mut self, tracker_key: &PublicId) -> Result<(), AetherError> {
+        self.tracker
+            .set_channel(TrackerChannel::sealed(tracker_key)?);
+        Ok(())
+    }
+
+    /// Remember the endpoints of connected peers in `cache` and reconnect to cached
+    /// peers directly before asking the tracker server. Must be called before
+    /// [`Aether::start`]
+    pub fn set_peer_cache(&mut self, cache: PeerCache) {
+        self.peer_cache = Some(Arc::new(Mutex::new(cache)));
+    }
+
+    /// Report the telemetry of this client and its links to `telemetry`. Must be
+    /// called before [`Aether::start`]
+    pub fn set_telemetry(&mut self, telemetry: Arc<dyn Telemetry>) {
+        self.telemetry = telemetry;
+    }
+
+    /// Use `contacts` as the address book of this client, such as contacts
+    /// [loaded][Contacts::load] from a file. Clients created with [`Aether::new`] or
+    /// [`Aether::with_config`] use the contacts stored in their config dir, other
+    /// clients start with an empty address book that is only kept in memory
+    pub fn set_contacts(&mut self, contacts: Contacts) {
+        self.contacts = Arc::new(Mutex::new(contacts));
+    }
+
+    /// Keep the messages queued for peers that are not connected in `outbox`, such as
+    /// one [loaded][Outbox::load] from a file so that they are delivered after a
+    /// restart. By default they are only kept in memory. Must be called before
+    /// [`Aether::start`]
+    pub fn set_outbox(&mut self, outbox: Outbox) {
+        self.outbox = Arc::new(Mutex::new(outbox));
+    }
+
+    /// Decide whether to accept connection requests from peers this client did not
+    /// request a connection to, before the handshake starts. By default all requests
+    /// are accepted
+    ///
+    /// The policy is called on the thread handling all requests, so it should return
+    /// quickly. To prompt the user, enable
+    /// [`manual_accept`][AetherConfig::manual_accept] instead (refer
+    /// [`Aether::incoming`]). Requests accepted by the policy are queued then
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::net::SocketAddr;
+    /// use aether_lib::peer::Aether;
+    ///
+    /// let tracker_addr: SocketAddr = "149.129.129.226:8982".parse().unwrap();
+    /// let mut aether = Aether::new(tracker_addr);
+    ///
+    /// // Only accept requests from a known peer
+    /// aether.set_accept_policy(|request| request.username == "<peer-uid-here>");
+    /// aether.start();
+    /// ```
+    pub fn set_accept_policy<F>(&mut self, policy: F)
+    where
+        F: Fn(&ConnectionRequest) -> bool + Send + Sync + 'static,
+    {
+        self.accept_policy = Arc::new(policy);
+    }
+
+    /// Returns the connection requests waiting to be accepted or rejected, in the order
+    /// the peers first requested a connection. Requests are only queued with
+    /// [`manual_accept`][AetherConfig::manual_accept] enabled, each new one is reported
+    /// as [`AetherEvent::ConnectionRequested`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::net::SocketAddr;
+    /// use std::path::Path;
+    /// use aether_lib::config::Config;
+    /// use aether_lib::peer::Aether;
+    ///
+    /// let tracker_addr: SocketAddr = "149.129.129.226:8982".parse().unwrap();
+    /// let mut config = Config::default();
+    /// config.aether.manual_accept = true;
+    /// let aether = Aether::with_config(config, Path::new("./aether"), tracker_addr);
+    /// aether.start();
+    ///
+    /// for request in aether.incoming() {
+    ///     println!("{} wants to connect", request.username);
+    ///     aether.accept(&request).unwrap();
+    /// }
+    /// ```
+    pub fn incoming(&self) -> Vec<ConnectionRequest> {
+        self.incoming.pending()
+    }
+
+    /// Accept the connection `request` of a peer and start connecting to it, as with
+    /// [`Aether::connect`]
+    /// # Errors
+    /// * [`AetherError::ResourceBudgetExceeded`] - Another connection would exceed the
+    ///   [`memory_budget`][AetherConfig::memory_budget]
+    /// * [`AetherError::SocketBind`] - No port of the configured
+    ///   [peer port range][AetherConfig::peer_port_min] is free
+    pub fn accept(&self, request: &ConnectionRequest) -> Result<(), AetherError> {
+        let queued = self.incoming.take(&request.username);
+        self.connect(&request.username)?;
+
+        // Start the handshake with the queued endpoint instead of waiting for the
+        // other peer to request the connection again
+        let mut req_lock = self.requests.lock().expect("unable to lock request queue");
+        (*req_lock).push_back(queued.unwrap_or_else(|| request.clone()));
+        drop(req_lock);
+        self.requests_wakeup.notify();
+
+        Ok(())
+    }
+
+    /// Reject the connection `request` of a peer. Further requests of the peer are
+    /// ignored until this client connects to it
+    pub fn reject(&self, request: &ConnectionRequest) {
+        self.incoming.reject(&request.username);
+    }
+
+    /// Add or update a contact in the address book of this client. Refer
+    /// [`Contacts::add_contact`]
+    pub fn add_contact(&self, uid: &str, alias: &str, trust: Trust) -> Result<(), AetherError> {
+        match self.contacts.lock() {
+            Ok(mut contacts_lock) => (*contacts_lock).add_contact(uid, alias, trust),
+            Err(_) => Err(AetherError::MutexLock("contacts")),
+        }
+    }
+
+    /// Returns the alias of the contact with `uid`
+    pub fn alias_of(&self, uid: &str) -> Result<Option<String>, AetherError> {
+        match self.contacts.lock() {
+            Ok(contacts_lock) => Ok((*contacts_lock).alias_of(uid).map(String::from)),
+            Err(_) => Err(AetherError::MutexLock("contacts")),
+        }
+    }
+
+    /// Returns the UID of the contact if `name` is an alias, else `name` itself
+    pub fn resolve(&self, name: &str) -> Result<String, AetherError> {
+        match self.contacts.lock() {
+            Ok(contacts_lock) => Ok((*contacts_lock).resolve(name).to_string()),
+            Err(_) => Err(AetherError::MutexLock("contacts")),
+        }
+    }
+
     pub fn get_uid(&self) -> &str {
         &self.uid
     }
 
+    /// Publish `name` as the display name of this client, signed with its private key
+    /// (refer [`name`][crate::identity::name]). It is sent to the connected peers and to
+    /// every peer connected later, which show it in their [`PeerInfo`] once verified.
+    /// Peers on protocol versions without the frames of the library are skipped (refer
+    /// [`frames`])
+    /// # Errors
+    /// * [`AetherError::InvalidName`] - If the name cannot be shown to users (refer
+    ///   [`is_valid_name`][crate::identity::name::is_valid_name])
+    pub fn set_display_name(&self, name: &str) -> Result<(), AetherError> {
+        let signed = self.private_id.sign_name(name)?;
+        let frame = control::encode(&signed)?;
+
+        match self.display_name.lock() {
+            Ok(mut name_lock) => *name_lock = Some(signed),
+            Err(_) => return Err(AetherError::MutexLock("display name")),
+        }
+
+        let frames = self.frames();
+        for uid in self.connected_peers()? {
+            match frames.send(&uid, NAME_SUBTYPE, frame.clone()) {
+                Ok(()) | Err(AetherError::ExtensionUnsupported(_)) => (),
+                Err(err) => warn!(peer = %uid, "Unable to send display name: {}", err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch this client to the identity `id`, for example one imported with
+    /// [`Id::import`]. The client is stopped, which closes the links to all peers since
+    /// they know it by its previous UID, and started again if it was running. Peers have
+    /// to be connected again using the new UID
+    ///
+    /// The identity stored in the config dir is not changed, use [`Id::save`] to keep
+    /// using `id` in new clients
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the public key of `id` cannot be encoded
+    /// * [`AetherError::MutexLock`] - If the threads or connections cannot be locked
+    pub fn switch_identity(&mut self, id: Id) -> Result<(), AetherError> {
+        let uid = id.public_key_to_base64()?;
+
+        let running = match self.thread_handles.lock() {
+            Ok(handles_lock) => !handles_lock.is_empty(),
+            Err(_) => return Err(AetherError::MutexLock("thread handles")),
+        };
+        self.stop()?;
+
+        // The display name is bound to the previous UID
+        let display_name = match self.display_name.lock() {
+            Ok(name_lock) => (*name_lock)
+                .as_ref()
+                .map(|signed| id.sign_name(&signed.name)),
+            Err(_) => return Err(AetherError::MutexLock("display name")),
+        }
+        .transpose()?;
+
+        // Handshakes still running with the previous identity only update the previous
+        // registry and requests
+        self.tracker.set_identity(id.clone())?;
+        self.uid = uid;
+        self.private_id = id;
+        self.display_name = Arc::new(Mutex::new(display_name));
+        self.connections = Arc::new(ConnectionRegistry::new());
+        self.requests = Arc::new(Mutex::new(VecDeque::new()));
+        self.stop = Arc::new(Stop::new());
+
+        if running {
+            self.start();
+        }
+
+        Ok(())
+    }
+
     pub fn start(&self) {
         trace!("Starting aether service...");
-        self.connection_poll();
-        self.handle_sockets();
-        self.handle_requests();
+        let mut handles = vec![
+            self.connection_poll(),
+            self.handle_sockets(),
+            self.handle_requests(),
+        ];
+        handles.extend(self.monitor_network());
+
+        self.thread_handles
+            .lock()
+            .expect("unable to lock thread handles")
+            .extend(handles);
+    }
+
+    /// Stops the client, closing the links to all connected peers, which are removed from
+    /// its connections, and joining the threads started by [`Aether::start`]. Handshakes
+    /// in progress are abandoned, links they establish are closed immediately. Also
+    /// called when the client is dropped
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - If the threads or connections cannot be locked
+    pub fn stop(&self) -> Result<(), AetherError> {
+        self.stop.stop();
+        self.requests_wakeup.notify();
+
+        let handles: Vec<_> = match self.thread_handles.lock() {
+            Ok(mut handles_lock) => handles_lock.drain(..).collect(),
+            Err(_) => return Err(AetherError::MutexLock("thread handles")),
+        };
+        for handle in handles {
+            if handle.join().is_err() {
+                error!("Aether thread panicked");
+            }
+        }
+
+        for shard in self.connections.shards() {
+            let mut connections_lock = match shard.lock() {
+                Ok(lock) => lock,
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            };
+
+            let connected: Vec<String> = (*connections_lock)
+                .iter()
+                .filter(|(_, connection)| matches!(connection, Connection::Connected(_)))
+                .map(|(uid, _)| uid.clone())
+                .collect();
+            let peers: Vec<_> = connected
+                .into_iter()
+                .filter_map(|uid| match (*connections_lock).remove(&uid) {
+                    Some(Connection::Connected(peer)) => Some((uid, peer)),
+                    _ => None,
+                })
+                .collect();
+            drop(connections_lock);
+
+            // Links are stopped without holding the lock, as they wait for their peers
+            for (uid, peer) in peers {
+                if let Err(err) = peer.link.stop() {
+                    warn!(peer = %uid, "Unable to stop link: {}", err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connect to a peer by its uid or the alias of a contact
+    /// # Errors
+    /// * [`AetherError::ResourceBudgetExceeded`] - Another connection would exceed the
+    ///   [`memory_budget`][AetherConfig::memory_budget]
+    /// * [`AetherError::SocketBind`] - No port of the configured
+    ///   [peer port range][AetherConfig::peer_port_min] is free
+    pub fn connect(&self, uid: &str) -> Result<(), AetherError> {
+        self.connect_with(uid, ConnectOptions::default())
+    }
+
+    /// Connect to a peer for a limited time only. The link is closed `expiry` after
+    /// the connection is established, after which sending to the peer fails with
+    /// [`AetherError::SessionExpired`]
+    /// # Errors
+    /// * [`AetherError::ResourceBudgetExceeded`] - Another connection would exceed the
+    ///   [`memory_budget`][AetherConfig::memory_budget]
+    /// * [`AetherError::SocketBind`] - No port of the configured
+    ///   [peer port range][AetherConfig::peer_port_min] is free
+    pub fn connect_with_expiry(&self, uid: &str, expiry: Duration) -> Result<(), AetherError> {
+        let options = ConnectOptions {
+            expiry: Some(expiry),
+            ..Default::default()
+        };
+        self.connect_with(uid, options)
     }
 
-    pub fn connect(&self, uid: &str) {
-        let mut connections_lock = self.connections.lock().expect("Unable to lock peers");
+    /// Connect to a peer by its uid or the alias of a contact with `options` overriding
+    /// the configuration of this client. If connecting is given up,
+    /// [`AetherEvent::ConnectionFailed`] is reported
+    /// # Errors
+    /// * [`AetherError::ResourceBudgetExceeded`] - Another connection would exceed the
+    ///   [`memory_budget`][AetherConfig::memory_budget]
+    /// * [`AetherError::SocketBind`] - No port of the configured
+    ///   [peer port range][AetherConfig::peer_port_min] is free
+    pub fn connect_with(&self, name: &str, options: ConnectOptions) -> Result<(), AetherError> {
+        let uid = &self.resolve(name)?;
+
+        // Connecting accepts a queued request of the peer
+        self.incoming.take(uid);
+
+        // Connections kept failed once given up are replaced
+        let is_present = (*self.connections.lock(uid)?)
+            .get(uid)
+            .map_or(false, |connection| !connection.given_up());
+
+        // Connections still being established have not charged their links yet
+        if self.memory.limit() > 0 && !is_present {
+            let pending = self.connections.len()? - self.connected_peers()?.len();
+            let projected = projected_memory(&self.config.link);
+            self.memory.admit((pending + 1) * projected)?;
+        }
+
+        let mut connections_lock = self.connections.lock(uid)?;
 
-        let is_present = (*connections_lock).get(uid).is_some();
+        let is_present = (*connections_lock)
+            .get(uid)
+            .map_or(false, |connection| !connection.given_up());
 
         if !is_present {
-            let initialized = Initialized::new(uid.to_string());
+            let cached = self.cached_peer(uid);
+
+            let local_port = cached.as_ref().map(|cached| cached.local_port);
+            let mut initialized =
+                Initialized::with_port(uid.to_string(), local_port, &self.config.aether)
+                    .map_err(AetherError::SocketBind)?;
+            initialized.attempts =
+                Box::new(Attempts::with_policy(options, self.config.aether.retry));
+            initialized.cached_endpoint = cached.as_ref().map(|cached| (cached.ip, cached.port));
 
             (*connections_lock).insert(uid.to_string(), Connection::Init(initialized));
+            drop(connections_lock);
+
+            // Attempt the cached endpoint directly, the tracker server is requested in
+            // parallel in case it fails
+            if let Some(cached) = cached {
+                let mut req_lock = self.requests.lock().expect("unable to lock request queue");
+                (*req_lock).push_back(cached.to_request(uid));
+                self.requests_wakeup.notify();
+            }
         }
+
+        Ok(())
     }
 
-    pub fn send_to(&self, uid: &str, buf: Vec<u8>) -> Result<u8, u8> {
-        let mut connections_lock = self.connections.lock().expect("unable to lock peers list");
-        match (*connections_lock).get_mut(uid) {
-            Some(connection) => match connection {
-                Connection::Connected(peer) => {
-                    peer.link.send(buf).unwrap();
-                    Ok(0)
-                }
-                _ => Err(3),
-            },
+    /// Returns the [`MemoryBudget`] the links of this client are charged to
+    pub fn memory_budget(&self) -> &MemoryBudget {
+        &self.memory
+    }
 
-            None => Err(1),
+    fn cached_peer(&self, uid: &str) -> Option<CachedPeer> {
+        let cache = self.peer_cache.as_ref()?;
+        let cache_lock = cache.lock().expect("unable to lock peer cache");
+        (*cache_lock).get(uid).cloned()
+    }
+
+    /// Send bytes to a connected peer. Blocks while the send queue of the link to the
+    /// peer is full (refer [`send_queue_size`][crate::config::LinkConfig::send_queue_size])
+    /// # Arguments
+    /// * `uid` - UID of the peer to send the bytes to
+    /// * `buf` - Buffer containing the bytes to be sent
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    /// * [`AetherError::LinkBroken`] - [`Link`] to the peer has stopped
+    /// * [`AetherError::SessionExpired`] - The time granted for the connection ran out
+    /// * [`AetherError::MessageTooLarge`] - The bytes are larger than the maximum message
+    ///   size agreed on with the peer
+    ///
+    /// Other general errors might occur (refer to [`AetherError`])
+    pub fn send_to(&self, uid: &str, buf: Vec<u8>) -> Result<(), AetherError> {
+        let link = self.connections.link(uid)?;
+
+        match link.send(buf) {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, link.close_reason()?)),
+            result => result,
         }
     }
 
-    pub fn recv_from(&self, uid: &str) -> Result<Vec<u8>, AetherError> {
-        let connections_lock = match self.connections.lock() {
+    /// Send bytes to a connected peer without blocking
+    /// # Errors
+    /// * [`AetherError::SendQueueFull`] - The send queue of the link to the peer is full,
+    ///   the bytes are not sent
+    ///
+    /// Other errors are those of [`Aether::send_to`]
+    pub fn try_send_to(&self, uid: &str, buf: Vec<u8>) -> Result<(), AetherError> {
+        let link = self.connections.link(uid)?;
+
+        match link.try_send(buf) {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, link.close_reason()?)),
+            result => result,
+        }
+    }
+
+    /// Send bytes to a connected peer, returning a [`DeliveryHandle`] telling once the
+    /// peer received them, such as to show the message as delivered
+    /// # Errors
+    /// The same as [`Aether::send_to`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::net::SocketAddr;
+    /// use std::time::Duration;
+    /// use aether_lib::peer::Aether;
+    ///
+    /// let tracker_addr: SocketAddr = "149.129.129.226:8982".parse().unwrap();
+    /// let aether = Aether::new(tracker_addr);
+    /// aether.start();
+    ///
+    /// let handle = aether.send_to_tracked("<peer-uid-here>", b"Hello".to_vec()).unwrap();
+    /// if handle.wait_timeout(Duration::from_secs(5)).unwrap() {
+    ///     println!("Delivered");
+    /// }
+    /// ```
+    pub fn send_to_tracked(&self, uid: &str, buf: Vec<u8>) -> Result<DeliveryHandle, AetherError> {
+        let link = self.connections.link(uid)?;
+
+        match link.send_tracked(buf) {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, link.close_reason()?)),
+            result => result,
+        }
+    }
+
+    /// Cancel the message `id` sent to a connected peer with
+    /// [`Aether::send_to_tracked`], such as when a user aborts sending a file. The parts
+    /// of the message not sent yet are dropped and its [`DeliveryHandle`] tells that it
+    /// was cancelled (refer [`Link::cancel`]). Returns false if the peer already
+    /// received the whole message, or it was sent before the peer reconnected
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - The peer is not connected
+    pub fn cancel_message(&self, uid: &str, id: MessageId) -> Result<bool, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.cancel(id),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Send bytes to a connected peer, dropping them if they could not be delivered by
+    /// `deadline` instead of sending stale data again (refer
+    /// [`Link::send_with_deadline`])
+    /// # Errors
+    /// The same as [`Aether::send_to`]
+    pub fn send_to_with_deadline(
+        &self,
+        uid: &str,
+        buf: Vec<u8>,
+        deadline: Instant,
+    ) -> Result<(), AetherError> {
+        let link = self.connections.link(uid)?;
+
+        match link.send_with_deadline(buf, deadline) {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, link.close_reason()?)),
+            result => result,
+        }
+    }
+
+    /// Send bytes to a connected peer along with `headers`, which are returned with them
+    /// by [`Aether::recv_from_ext`] on the other peer (refer [`headers`]). Bytes sent
+    /// without headers are sent like with [`Aether::send_to`]
+    /// # Errors
+    /// * [`AetherError::InvalidHeaders`] - The headers exceed the limits (refer
+    ///   [`headers::validate`])
+    /// * [`AetherError::MessageTooLarge`] - The bytes along with their headers are larger
+    ///   than the maximum message size agreed on with the peer
+    /// * [`AetherError::ExtensionUnsupported`] - The peer does not support the frames of
+    ///   the library, which carry headers (refer [`frames`])
+    ///
+    /// Other errors are those of [`Aether::send_to`]
+    pub fn send_to_with_headers(
+        &self,
+        uid: &str,
+        headers: &Headers,
+        buf: Vec<u8>,
+    ) -> Result<(), AetherError> {
+        if headers.is_empty() {
+            return self.send_to(uid, buf);
+        }
+
+        self.frames()
+            .send(uid, HEADERS_SUBTYPE, headers::encode(headers, buf)?)
+    }
+
+    /// Send bytes to the peer if it is connected, or queue them in the [`outbox`] of this
+    /// client otherwise. Queued bytes are sent as soon as a connection to the peer is
+    /// established, before any bytes sent after connecting, unless they expired by then.
+    /// They are not connected to by this, refer [`Aether::connect`]. Returns true if the
+    /// bytes were sent right away
+    ///
+    /// Queued bytes larger than the maximum message size agreed on with the peer are
+    /// dropped when connected
+    /// # Errors
+    /// * [`AetherError::OutboxFull`] - The peer is not connected and the bytes would
+    ///   exceed the [`outbox`][crate::config::AetherConfig::outbox] limits
+    ///
+    /// Errors saving the outbox are returned as well, the bytes are still queued. Other
+    /// errors are those of [`Aether::send_to`]
+    pub fn send_to_or_queue(&self, uid: &str, buf: Vec<u8>) -> Result<bool, AetherError> {
+        // Held while queueing, so that the bytes are not queued after the connection
+        // sent the queued bytes already
+        let connections_lock = self.connections.lock(uid)?;
+
+        if let Some(Connection::Connected(peer)) = (*connections_lock).get(uid) {
+            // If the link broke, the bytes wait for the peer to reconnect
+            if !peer.link.is_stopped()? {
+                // Sent without holding the lock, as sending blocks while the send queue
+                // of the link is full
+                let link = peer.link.clone();
+                drop(connections_lock);
+
+                return match link.send(buf) {
+                    Err(AetherError::LinkStopped(_)) => {
+                        Err(closed_error(uid, link.close_reason()?))
+                    }
+                    result => result.map(|_| true),
+                };
+            }
+            if peer.link.close_reason()? == Some(CloseReason::Expired) {
+                return Err(AetherError::SessionExpired(uid.to_string()));
+            }
+        }
+
+        let mut outbox_lock = match self.outbox.lock() {
             Ok(lock) => lock,
-            Err(_) => return Err(AetherError::MutexLock("connections")),
+            Err(_) => return Err(AetherError::MutexLock("outbox")),
         };
+        (*outbox_lock).push(uid, buf, &self.config.aether.outbox)?;
+        (*outbox_lock).save()?;
+        Ok(false)
+    }
+
+    /// Returns the number of messages queued for the peer `uid` (refer
+    /// [`Aether::send_to_or_queue`])
+    pub fn queued_for(&self, uid: &str) -> Result<usize, AetherError> {
+        match self.outbox.lock() {
+            Ok(outbox_lock) => Ok((*outbox_lock).pending(uid)),
+            Err(_) => Err(AetherError::MutexLock("outbox")),
+        }
+    }
+
+    /// Disconnect from a connected peer. Bytes already sent to the peer are delivered
+    /// before the link is closed, waiting at most
+    /// [`linger_timeout`][crate::config::LinkConfig::linger_timeout]. The peer can still
+    /// receive them after its end of the link closed
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    ///
+    /// Other general errors might occur (refer to [`AetherError`])
+    pub fn disconnect(&self, uid: &str) -> Result<(), AetherError> {
+        let mut connections_lock = self.connections.lock(uid)?;
+        if !matches!((*connections_lock).get(uid), Some(Connection::Connected(_))) {
+            return Err(AetherError::NotConnected(uid.to_string()));
+        }
+        let connection = (*connections_lock).remove(uid);
+        drop(connections_lock);
+
+        // The link is stopped without holding the lock, as it waits for the peer
+        if let Some(Connection::Connected(peer)) = connection {
+            peer.link.stop()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the progress of a message larger than a packet being received from a
+    /// connected peer, such as to show the progress of receiving a file before it is
+    /// complete. [`None`] if no such message is partially received (refer
+    /// [`Link::incoming_progress`])
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    pub fn incoming_progress(&self, uid: &str) -> Result<Option<IncomingProgress>, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.incoming_progress(),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Receive bytes from a connected peer. Headers the bytes were sent with are dropped
+    /// (refer [`Aether::recv_from_ext`])
+    ///
+    /// If the peer announces a new key, it is verified before receiving further bytes
+    /// (refer [`rotation`]). The session then continues under the new UID, reported
+    /// as [`AetherEvent::IdentityRotated`]
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    /// * [`AetherError::LinkBroken`] - [`Link`] to the peer has stopped, also while
+    ///   waiting for bytes
+    /// * [`AetherError::SessionExpired`] - The time granted for the connection ran out
+    /// * [`AetherError::IdentityChanged`] - The peer announced a new key that could not
+    ///   be verified, the connection has been dropped
+    pub fn recv_from(&self, uid: &str) -> Result<Vec<u8>, AetherError> {
+        Ok(self.recv_from_ext(uid)?.payload)
+    }
+
+    /// Receive bytes from a connected peer along with the headers they were sent with
+    /// (refer [`Aether::send_to_with_headers`]). Bytes sent without headers are returned
+    /// with empty headers
+    /// # Errors
+    /// The errors are those of [`Aether::recv_from`]
+    pub fn recv_from_ext(&self, uid: &str) -> Result<Message, AetherError> {
+        self.recv_following(&mut uid.to_string())
+    }
+
+    /// Receive bytes from a connected peer if there are any, without waiting, so that
+    /// peers can be polled from a single thread. Headers the bytes were sent with are
+    /// dropped (refer [`Aether::recv_from_ext`])
+    ///
+    /// A key rotation announced by the peer is still verified like in
+    /// [`Aether::recv_from`], waiting for the peer to answer the challenge of its new key
+    /// # Returns
+    /// * `Option<Vec<u8>>` - Bytes received from the peer, [`None`] if nothing was
+    ///   received yet
+    /// # Errors
+    /// The errors are those of [`Aether::recv_from`]
+    pub fn try_recv_from(&self, uid: &str) -> Result<Option<Vec<u8>>, AetherError> {
+        let receiver = self.receiver_of(uid)?;
+        let frames = self.frames();
+        let mut uid = uid.to_string();
+
+        loop {
+            let packet = match receiver.try_recv() {
+                Ok(packet) => packet,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(self.link_closed(&uid)),
+            };
+
+            if let Some(message) = frames.receive(&mut uid, packet, &receiver)? {
+                return Ok(Some(message.payload));
+            }
+        }
+    }
+
+    /// Returns an iterator over the bytes received from a connected peer, waiting for
+    /// each like [`Aether::recv_from`]. The iterator keeps following the peer across
+    /// key rotations
+    ///
+    /// It ends once receiving fails, for example when the [`Link`] to the peer stopped
+    /// or the peer is not connected. [`Aether::recv_from`] returns the reason
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use aether_lib::peer::Aether;
+    /// # fn run(aether: &Aether, uid: &str) {
+    /// for msg in aether.messages(uid) {
+    ///     println!("{}", String::from_utf8_lossy(&msg));
+    /// }
+    /// # }
+    /// ```
+    pub fn messages(&self, uid: &str) -> PeerMessages<'_> {
+        PeerMessages {
+            aether: self,
+            uid: uid.to_string(),
+        }
+    }
+
+    /// Receive the next message of the peer `uid`, which is updated if the peer
+    /// rotates its key (refer [`Aether::recv_from_ext`])
+    fn recv_following(&self, uid: &mut String) -> Result<Message, AetherError> {
+        let receiver = self.receiver_of(uid)?;
+        let frames = self.frames();
+
+        loop {
+            let packet = match receiver.recv() {
+                Ok(packet) => packet,
+                Err(_) => return Err(self.link_closed(uid)),
+            };
+
+            if let Some(message) = frames.receive(uid, packet, &receiver)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Receive bytes from a connected peer, waiting at most `timeout`. Key rotations are
+    /// handled like in [`Aether::recv_from`]
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    /// * [`AetherError::RecvTimeout`] - Nothing was received within `timeout`
+    /// * [`AetherError::LinkBroken`] - [`Link`] to the peer has stopped, also while
+    ///   waiting for bytes
+    /// * [`AetherError::SessionExpired`] - The time granted for the connection ran out
+    /// * [`AetherError::IdentityChanged`] - The peer announced a new key that could not
+    ///   be verified, the connection has been dropped
+    pub fn recv_timeout_from(&self, uid: &str, timeout: Duration) -> Result<Vec<u8>, AetherError> {
+        Ok(self.recv_timeout_from_ext(uid, timeout)?.payload)
+    }
+
+    /// Receive bytes from a connected peer along with their headers, waiting at most
+    /// `timeout` (refer [`Aether::recv_from_ext`])
+    /// # Errors
+    /// The errors are those of [`Aether::recv_timeout_from`]
+    pub fn recv_timeout_from_ext(
+        &self,
+        uid: &str,
+        timeout: Duration,
+    ) -> Result<Message, AetherError> {
+        let receiver = self.receiver_of(uid)?;
+        let frames = self.frames();
+        let mut uid = uid.to_string();
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let packet = match receiver.recv_timeout(remaining) {
+                Ok(packet) => packet,
+                Err(RecvTimeoutError::Disconnected) => return Err(self.link_closed(&uid)),
+                Err(err) => return Err(AetherError::from(err)),
+            };
+
+            if let Some(message) = frames.receive(&mut uid, packet, &receiver)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Announce the key of `new_id` to the connected peer `uid` and answer its
+    /// challenge of the new key, so that it keeps the session once this client uses
+    /// `new_id` (refer [`rotation`]). Messages received from the peer before its
+    /// challenge are dropped, so nothing else should be received from it meanwhile
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    /// * [`AetherError::AuthenticationFailed`] - The peer did not challenge the new key
+    ///   within the [`handshake_timeout`][crate::config::HandshakeConfig::handshake_timeout]
+    /// * [`AetherError::ExtensionUnsupported`] - The peer does not support the frames of
+    ///   the library (refer [`frames`])
+    pub fn announce_key_rotation(&self, uid: &str, new_id: &Id) -> Result<(), AetherError> {
+        let receiver = self.receiver_of(uid)?;
+
+        let frames = self.frames();
+        let rotation = KeyRotation::new(&self.private_id, new_id)?;
+        frames.send(uid, ROTATION_SUBTYPE, control::encode(&rotation)?)?;
+
+        let timeout = Duration::from_millis(self.config.handshake.handshake_timeout);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let packet = match receiver.recv_timeout(remaining) {
+                Ok(packet) => packet,
+                Err(RecvTimeoutError::Disconnected) => return Err(self.link_closed(uid)),
+                Err(_) => return Err(AetherError::AuthenticationFailed(uid.to_string())),
+            };
+
+            match Frame::decode(packet) {
+                Ok(Frame::Challenge(challenge)) => {
+                    let nonce = new_id.private_decrypt(&challenge.nonce)?;
+                    let response = control::encode(&RotationResponse { nonce })?;
+                    return frames.send(uid, ROTATION_SUBTYPE, response);
+                }
+                _ => debug!(peer = %uid, "Dropping message received during key rotation"),
+            }
+        }
+    }
+
+    /// Receive bytes from any connected peer
+    ///
+    /// The first call starts forwarding the messages of all links to a shared queue,
+    /// including links to peers connected later. A message is returned by only one of
+    /// [`Aether::recv_any`] and [`Aether::recv_from`], so they should not be used for
+    /// the same peer. Headers are dropped, key rotations and names announced by peers
+    /// are handled like by [`Aether::recv_from`] (refer [`frames`])
+    /// # Returns
+    /// * `(String, Vec<u8>)` - UID of the peer and the bytes received from it
+    /// # Errors
+    /// * [`AetherError::ChannelRecvError`] - The shared queue has been disconnected
+    pub fn recv_any(&self) -> Result<(String, Vec<u8>), AetherError> {
+        self.enable_fan_in()?;
+
+        Ok(self.fan_in.receiver().recv()?)
+    }
+
+    /// Receive bytes from any connected peer, waiting at most `timeout` (refer
+    /// [`Aether::recv_any`])
+    /// # Errors
+    /// * [`AetherError::RecvTimeout`] - Nothing was received within `timeout`
+    pub fn recv_any_timeout(&self, timeout: Duration) -> Result<(String, Vec<u8>), AetherError> {
+        self.enable_fan_in()?;
+
+        Ok(self.fan_in.receiver().recv_timeout(timeout)?)
+    }
+
+    /// Forward the links of the connected peers to the shared queue the first time
+    /// [`Aether::recv_any`] is used. Peers connected later are forwarded when they
+    /// connect
+    fn enable_fan_in(&self) -> Result<(), AetherError> {
+        if !self.fan_in.enable() {
+            return Ok(());
+        }
+
+        self.forward_connected()
+    }
+
+    /// Call `handler` with the bytes received from the peer `uid`, instead of returning
+    /// them from [`Aether::recv_from`], so that applications do not need a thread
+    /// receiving from each peer. A previous handler of the peer is replaced
+    ///
+    /// Handlers are called by a pool of
+    /// [`handler_threads`][crate::config::AetherConfig::handler_threads] threads, the
+    /// bytes of a peer in the order they were received. The handler is kept if the peer
+    /// reconnects. Headers the bytes were sent with are dropped. Key rotations and names
+    /// announced by the peer are handled like by [`Aether::recv_from`] (refer
+    /// [`frames`])
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - The connections could not be locked
+    pub fn on_message<F>(&self, uid: &str, handler: F) -> Result<(), AetherError>
+    where
+        F: Fn(Vec<u8>) + Send + Sync + 'static,
+    {
+        self.fan_in
+            .set_handler(uid, Arc::new(move |_, payload| handler(payload)));
+
+        // Peers connecting later are forwarded when they connect
+        let connections_lock = self.connections.lock(uid)?;
+        if let Some(Connection::Connected(peer)) = (*connections_lock).get(uid) {
+            if let Ok(receiver) = peer.link.get_receiver() {
+                self.fan_in
+                    .forward(uid.to_string(), receiver, self.frames());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Call `handler` with the UID of a peer and the bytes received from it, for all
+    /// peers without a handler of their own (refer [`Aether::on_message`]). A previous
+    /// handler is replaced
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - The connections could not be locked
+    pub fn on_any_message<F>(&self, handler: F) -> Result<(), AetherError>
+    where
+        F: Fn(String, Vec<u8>) + Send + Sync + 'static,
+    {
+        if !self.fan_in.set_any_handler(Arc::new(handler)) {
+            return Ok(());
+        }
+
+        self.forward_connected()
+    }
+
+    /// Forward the links of the connected peers to the fan-in. Peers connected later
+    /// are forwarded when they connect
+    fn forward_connected(&self) -> Result<(), AetherError> {
+        for shard in self.connections.shards() {
+            let connections_lock = match shard.lock() {
+                Ok(lock) => lock,
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            };
+
+            for (uid, connection) in (*connections_lock).iter() {
+                if let Connection::Connected(peer) = connection {
+                    if let Ok(receiver) = peer.link.get_receiver() {
+                        self.fan_in.forward(uid.clone(), receiver, self.frames());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the handler of the frames received from peers, sharing the state of this
+    /// client
+    fn frames(&self) -> Arc<Frames> {
+        Arc::new(Frames::new(
+            self.connections.clone(),
+            self.contacts.clone(),
+            self.outbox.clone(),
+            self.events.0.clone(),
+            &self.config,
+        ))
+    }
+
+    /// Returns the receiver of the link to a connected peer, so that receiving does
+    /// not keep the connections locked
+    fn receiver_of(&self, uid: &str) -> Result<Receiver<Packet>, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
 
         let peer = match (*connections_lock).get(uid) {
             Some(Connection::Connected(peer)) => peer,
             _ => return Err(AetherError::NotConnected(uid.to_string())),
         };
 
-        let receiver = peer.link.get_receiver()?;
+        match peer.link.get_receiver() {
+            Err(AetherError::LinkStopped(_)) => Err(closed_error(uid, peer.link.close_reason()?)),
+            result => result,
+        }
+    }
+
+    /// Returns the error for the stopped link to a peer
+    fn link_closed(&self, uid: &str) -> AetherError {
+        let connections_lock = match self.connections.lock(uid) {
+            Ok(lock) => lock,
+            Err(err) => return err,
+        };
+
+        let reason = match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.close_reason().ok().flatten(),
+            _ => None,
+        };
 
+        closed_error(uid, reason)
+    }
+
+    /// Returns the [`PeerInfo`] of the connected peer `uid`
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    pub fn peer_info(&self, uid: &str) -> Result<PeerInfo, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+        let (name, address, encrypted, uptime, stats) = match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => (
+                peer.name.clone(),
+                peer.link.peer_addr(),
+                peer.link.is_encrypted(),
+                peer.connected_at.elapsed(),
+                peer.link.stats()?,
+            ),
+            _ => return Err(AetherError::NotConnected(uid.to_string())),
+        };
         drop(connections_lock);
 
-        let packet = receiver.recv()?;
+        Ok(PeerInfo {
+            uid: uid.to_string(),
+            name,
+            alias: self.alias_of(uid)?,
+            fingerprint: PublicId::from_base64(uid)?.fingerprint()?,
+            address,
+            encrypted,
+            uptime,
+            rtt_us: stats.rtt_us,
+            loss_rate: stats.loss_rate,
+        })
+    }
+
+    /// Returns the fingerprint of the key of this client (refer
+    /// [`PublicId::fingerprint`])
+    pub fn fingerprint(&self) -> Result<String, AetherError> {
+        PublicId::from_base64(&self.uid)?.fingerprint()
+    }
+
+    /// Returns the short authentication string of the session with the connected peer
+    /// `uid`. Both users reading out the same string verifies that nobody intercepted
+    /// the session (refer [`AetherCipher::short_auth_string`][crate::encryption::AetherCipher::short_auth_string])
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state or its link is
+    ///   not encrypted
+    pub fn short_auth_string(&self, uid: &str) -> Result<String, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        let sas = match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.short_auth_string()?,
+            _ => None,
+        };
+        sas.ok_or_else(|| AetherError::NotConnected(uid.to_string()))
+    }
+
+    /// Returns the local ports currently bound by this client in ascending order: the
+    /// port used to communicate with the tracker server and the ports of all connections,
+    /// which are taken from the configured
+    /// [`peer_port_min`][AetherConfig::peer_port_min] range if it is set
+    pub fn bound_ports(&self) -> Result<Vec<u16>, AetherError> {
+        let mut ports: Vec<u16> = self
+            .tracker
+            .local_addr()
+            .map(|addr| addr.port())
+            .into_iter()
+            .collect();
+
+        for shard in self.connections.shards() {
+            let connections_lock = match shard.lock() {
+                Ok(lock) => lock,
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            };
+
+            ports.extend(
+                (*connections_lock)
+                    .values()
+                    .filter_map(Connection::local_port),
+            );
+        }
+
+        ports.sort_unstable();
+        ports.dedup();
+        Ok(ports)
+    }
+
+    /// Returns the UIDs of the peers in connected state
+    pub fn connected_peers(&self) -> Result<Vec<String>, AetherError> {
+        let mut peers = Vec::new();
+
+        for shard in self.connections.shards() {
+            let connections_lock = match shard.lock() {
+                Ok(lock) => lock,
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            };
+
+            for (uid, connection) in (*connections_lock).iter() {
+                if let Connection::Connected(_) = connection {
+                    peers.push(uid.clone());
+                }
+            }
+        }
 
-        Ok(packet.payload)
+        Ok(peers)
+    }
+
+    /// Returns the [`Histograms`] recorded by this client, including those of the
+    /// links to all connected peers
+    pub fn histograms(&self) -> Result<Histograms, AetherError> {
+        let mut histograms = match self.stats.lock() {
+            Ok(stats_lock) => stats_lock.clone(),
+            Err(_) => return Err(AetherError::MutexLock("stats")),
+        };
+
+        for shard in self.connections.shards() {
+            let connections_lock = match shard.lock() {
+                Ok(lock) => lock,
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            };
+
+            for connection in (*connections_lock).values() {
+                if let Connection::Connected(peer) = connection {
+                    histograms.merge(&peer.link.histograms()?);
+                }
+            }
+        }
+
+        Ok(histograms)
+    }
+
+    /// Returns the [`LinkStats`] of the link to the connected peer with the given uid
+    pub fn stats_for(&self, uid: &str) -> Result<LinkStats, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.stats(),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Returns the [`Negotiated`] parameters of the link to the connected peer with the
+    /// given uid
+    pub fn negotiated(&self, uid: &str) -> Result<Negotiated, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.negotiated(),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Returns the cause of the link to a connected peer timing out, once the tracker
+    /// server answered whether the peer is still present (also reported as
+    /// [`AetherEvent::LinkFailed`])
+    /// # Returns
+    /// * [`None`] - If the link has not timed out or the cause is not known yet
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    pub fn link_failure(&self, uid: &str) -> Result<Option<LinkFailure>, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => Ok(peer.failure),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Returns the error a thread of the link to a connected peer failed with, which
+    /// is reported as [`LinkFailure::Internal`]
+    /// # Returns
+    /// * [`None`] - If no thread of the link failed
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    pub fn link_error(&self, uid: &str) -> Result<Option<String>, AetherError> {
+        let connections_lock = self.connections.lock(uid)?;
+
+        match (*connections_lock).get(uid) {
+            Some(Connection::Connected(peer)) => peer.link.failure(),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Returns a receiver for the [`AetherEvent`]s reported by this client
+    pub fn events(&self) -> Receiver<AetherEvent> {
+        self.events.1.clone()
+    }
+
+    /// Returns the number of handshake attempts rejected before the other peer's
+    /// address was validated
+    pub fn rejections(&self) -> Rejections {
+        self.rejections.snapshot()
+    }
+
+    /// Returns the latency and failures of the polls sent to the tracker server, to tell
+    /// whether new connections can be formed
+    pub fn tracker_stats(&self) -> TrackerStats {
+        self.tracker_counters.snapshot()
     }
 
     pub fn wait_connection(&self, uid: &str) -> Result<u8, u8> {
@@ -185,145 +1678,483 @@ impl Aether {
         Ok(0)
     }
 
-    pub fn is_connected(&self, uid: &str) -> bool {
-        let connections_lock = self.connections.lock().expect("unable to lock peers list");
-        matches!((*connections_lock).get(uid), Some(Connection::Connected(_)))
-    }
-
-    pub fn is_connecting(&self, uid: &str) -> bool {
+    /// Returns the state of the connection to the peer `uid`, taking the lock of the
+    /// connections list once. Prefer matching on it over calling several of
+    /// [`is_connected`][Aether::is_connected], [`is_connecting`][Aether::is_connecting]
+    /// and [`is_initialized`][Aether::is_initialized], as the state can change between
+    /// those calls
+    pub fn connection_status(&self, uid: &str) -> ConnectionStatus {
         let connections_lock = self
             .connections
-            .lock()
-            .expect("unable to lock connecting list");
-        match (*connections_lock).get(uid) {
-            Some(connection) => {
-                !matches!(connection, Connection::Failed(_) | Connection::Connected(_))
+            .lock(uid)
+            .expect("unable to lock peers list");
+        (*connections_lock)
+            .get(uid)
+            .map_or(ConnectionStatus::Unknown, Connection::status)
+    }
+
+    /// Ask the tracker server whether the peer with the given uid is online, which it is
+    /// if it polled the tracker server recently. The answer is received along with the
+    /// polls of this client, which has to be [started][Aether::start], so it is waited
+    /// for at most [`server_poll_time`][crate::config::AetherConfig::server_poll_time]
+    /// and [`server_retry_delay`][crate::config::AetherConfig::server_retry_delay]
+    ///
+    /// Useful to show whether a peer is reachable before connecting to it, connected
+    /// peers are online as long as their link is up
+    /// # Errors
+    /// * [`AetherError::TrackerTimeout`] - The tracker server did not answer in time,
+    ///   for example because it does not support presence queries (refer
+    ///   [`TrackerPacketType::Presence`])
+    pub fn is_online(&self, uid: &str) -> Result<bool, AetherError> {
+        let timeout = Duration::from_millis(
+            self.config.aether.server_poll_time + self.config.aether.server_retry_delay,
+        );
+        let deadline = Instant::now() + timeout;
+
+        // A check of the peer already in progress is answered by the same query
+        if self.presence.start(uid) {
+            Self::send_presence_request(&self.tracker, uid);
+        }
+
+        // The check may also be ended by another thread waiting for it, such as the one
+        // checking timed out links, in which case the answer is missed
+        loop {
+            if let Some(present) = self.presence.outcome(uid, timeout) {
+                return present.ok_or(AetherError::TrackerTimeout);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                // Ends the check, unless it was ended already
+                let present = self.presence.outcome(uid, Duration::ZERO).flatten();
+                return present.ok_or(AetherError::TrackerTimeout);
             }
-            None => false,
+            self.presence.wait(deadline - now);
         }
     }
 
+    pub fn is_connected(&self, uid: &str) -> bool {
+        self.connection_status(uid) == ConnectionStatus::Connected
+    }
+
+    pub fn is_connecting(&self, uid: &str) -> bool {
+        matches!(
+            self.connection_status(uid),
+            ConnectionStatus::Initialized | ConnectionStatus::Handshaking
+        )
+    }
+
     pub fn is_initialized(&self, uid: &str) -> bool {
-        let connections_lock = self
-            .connections
-            .lock()
-            .expect("unable to lock connecting list");
-        matches!((*connections_lock).get(uid), Some(Connection::Init(_)))
+        self.connection_status(uid) == ConnectionStatus::Initialized
     }
 
-    fn handle_sockets(&self) {
-        let my_uid = self.uid.clone();
+    fn handle_sockets(&self) -> JoinHandle<()> {
         let connections = self.connections.clone();
-        let tracker_addr = self.tracker_addr;
+        let tracker = self.tracker.clone();
+        let events = self.events.0.clone();
+        let presence = self.presence.clone();
+        let stop = self.stop.clone();
         let config = self.config;
-        thread::spawn(move || {
+        spawn_reported("sockets", self.events.0.clone(), move || {
             loop {
-                // Lock connections list
-                let connections_lock = connections.lock().expect("unable to lock initialized list");
-
-                // For each connection
-                for (_, connection) in (*connections_lock).iter() {
-                    // If connection is in initialized or failed state, send connection
-                    // request
-                    match connection {
-                        Connection::Init(init) => {
-                            Self::send_connection_request(
-                                my_uid.clone(),
-                                init.uid.clone(),
-                                &init.socket,
-                                tracker_addr,
-                            );
+                // Lock one shard of the connections list at a time
+                for shard in connections.shards() {
+                    let mut connections_lock =
+                        shard.lock().expect("unable to lock initialized list");
+
+                    // Give up on peers that did not answer within their options
+                    let exhausted: Vec<String> = (*connections_lock)
+                        .iter()
+                        .filter(|(_, connection)| match connection {
+                            Connection::Init(init) => init.attempts.exhausted(),
+                            Connection::Failed(failed) => {
+                                !failed.attempts.given_up() && failed.attempts.exhausted()
+                            }
+                            _ => false,
+                        })
+                        .map(|(uid, _)| uid.clone())
+                        .collect();
+                    for uid in exhausted {
+                        debug!(peer = %uid, "Giving up connecting");
+                        let kept = (*connections_lock)
+                            .remove(&uid)
+                            .and_then(Connection::give_up);
+                        if let Some(connection) = kept {
+                            (*connections_lock).insert(uid.clone(), connection);
                         }
-                        Connection::Failed(failed) => Self::send_connection_request(
-                            my_uid.clone(),
-                            failed.uid.clone(),
-                            &failed.socket,
-                            tracker_addr,
-                        ),
-                        _ => {}
-                    };
+                        let _ = events.send(AetherEvent::ConnectionFailed { uid });
+                    }
+
+                    // For each connection
+                    for (_, connection) in (*connections_lock).iter_mut() {
+                        // If connection is in initialized or failed state, send connection
+                        // request
+                        match connection {
+                            Connection::Init(init) => Self::send_connection_request(
+                                &tracker,
+                                &init.uid,
+                                &init.socket,
+                                config.aether.mutual_intent,
+                            ),
+                            // Given up connections are not requested any more
+                            Connection::Failed(failed) if failed.attempts.given_up() => (),
+                            Connection::Failed(failed) => Self::send_connection_request(
+                                &tracker,
+                                &failed.uid,
+                                &failed.socket,
+                                config.aether.mutual_intent,
+                            ),
+                            Connection::Direct(direct) => Self::send_connection_request(
+                                &tracker,
+                                &direct.uid,
+                                &direct.socket,
+                                config.aether.mutual_intent,
+                            ),
+                            // Ask the tracker server whether a peer whose link timed out
+                            // is still present
+                            Connection::Connected(peer) if peer.failure.is_none() => {
+                                let close_reason = peer.link.close_reason().ok().flatten();
+                                if close_reason == Some(CloseReason::Failed) {
+                                    let error = peer.link.failure().ok().flatten();
+                                    error!(peer = %peer.uid, ?error, "Link failed");
+                                    peer.failure = Some(LinkFailure::Internal);
+                                    let _ = events.send(AetherEvent::LinkFailed {
+                                        uid: peer.uid.clone(),
+                                        failure: LinkFailure::Internal,
+                                    });
+                                    continue;
+                                }
+                                if close_reason != Some(CloseReason::Broken) {
+                                    continue;
+                                }
+
+                                if presence.start(&peer.uid) {
+                                    Self::send_presence_request(&tracker, &peer.uid);
+                                } else if let Some(failure) = presence.finish(
+                                    &peer.uid,
+                                    Duration::from_millis(config.aether.server_retry_delay),
+                                ) {
+                                    warn!(peer = %peer.uid, ?failure, "Link timed out");
+                                    peer.failure = Some(failure);
+                                    let _ = events.send(AetherEvent::LinkFailed {
+                                        uid: peer.uid.clone(),
+                                        failure,
+                                    });
+                                }
+                            }
+                            _ => {}
+                        };
+                    }
+
+                    // Unlock shard
+                    drop(connections_lock);
                 }
 
-                // Unlock initailized list
-                drop(connections_lock);
-                thread::sleep(Duration::from_millis(config.aether.server_poll_time));
+                if stop.sleep(Duration::from_millis(config.aether.server_poll_time)) {
+                    break;
+                }
             }
-        });
+        })
     }
 
+    /// Count a poll the tracker left unanswered, reporting the tracker as unreachable once
+    /// `threshold` polls in a row were
+    fn tracker_failure(counters: &TrackerCounters, threshold: u32, events: &Sender<AetherEvent>) {
+        let failures = counters.failure();
+        if threshold > 0 && failures == threshold {
+            warn!("Tracker left {} polls in a row unanswered", failures);
+            // Nobody listening for events is not an error
+            let _ = events.send(AetherEvent::TrackerUnreachable { failures });
+        }
+    }
+
+    /// Request a connection to the peer `peer_uid` from `socket`, the socket of the link
+    /// to the peer
     fn send_connection_request(
-        uid: String,
-        peer_uid: String,
-        socket: &UdpSocket,
-        tracker_addr: SocketAddr,
+        tracker: &TrackerClient,
+        peer_uid: &str,
+        socket: &dyn Transport,
+        mutual: bool,
     ) {
-        let packet = TrackerPacket {
-            username: uid,
-            peer_username: peer_uid,
-            identity_number: 1,
-            packet_type: 2,
-            req: true,
-            ..Default::default()
-        };
-
-        let packet_data: Vec<u8> = Vec::try_from(packet).expect("Unable to encode packet");
-
-        socket
-            .send_to(&packet_data, tracker_addr)
-            .expect("unable to send packet to server");
+        if let Err(err) = tracker.request_connection(peer_uid, socket, mutual) {
+            error!(peer = %peer_uid, "Unable to send connection request to tracker: {}", err);
+        }
     }
 
-    fn connection_poll(&self) {
-        let poll_request = TrackerPacket {
-            username: self.uid.clone(),
-            packet_type: 3,
-            req: true,
-            ..Default::default()
-        };
-
-        let data_bytes: Vec<u8> = Vec::try_from(poll_request).expect("Unable to encode packet");
-        let mut buf: [u8; 1024] = [0; 1024];
+    fn send_presence_request(tracker: &TrackerClient, peer_uid: &str) {
+        if let Err(err) = tracker.query_presence(peer_uid) {
+            error!("Unable to send presence query to tracker: {}", err);
+        }
+    }
 
-        let socket = self.socket.clone();
-        let tracker_addr = self.tracker_addr;
+    fn connection_poll(&self) -> JoinHandle<()> {
+        let tracker = self.tracker.clone();
 
         let requests = self.requests.clone();
+        let requests_wakeup = self.requests_wakeup.clone();
+        let presence = self.presence.clone();
+        let counters = self.tracker_counters.clone();
+        let events = self.events.0.clone();
+        let stop = self.stop.clone();
 
         let config = self.config;
+        let threshold = config.aether.tracker_failure_threshold;
+        let mut log_limiter = LogLimiter::new(config.telemetry);
 
-        thread::spawn(move || loop {
-            socket
-                .send_to(&data_bytes, tracker_addr)
-                .expect("Unable to send to server");
+        spawn_reported("tracker poll", self.events.0.clone(), move || loop {
+            if stop.is_stopped() {
+                break;
+            }
 
-            let response_data = match socket.recv(&mut buf) {
-                Ok(size) => buf[..size].to_vec(),
-                Err(_) => Vec::new(),
-            };
+            // Waited for before polling again after errors, so that they do not flood the
+            // tracker with polls
+            let retry_delay = Duration::from_millis(config.aether.server_retry_delay);
+
+            // Sealed again for each poll as the channel may fall back to plaintext
+            let sent_at = Instant::now();
+            let sent = tracker.register();
+            counters.poll();
+
+            // Such as while the network is down, which counts as a poll left unanswered
+            if let Err(err) = sent {
+                limited!(warn, log_limiter, "Unable to send poll to tracker: {}", err);
+                Self::tracker_failure(&counters, threshold, &events);
+                if stop.sleep(retry_delay) {
+                    break;
+                }
+                continue;
+            }
 
-            if !response_data.is_empty() {
-                let response_packet =
-                    TrackerPacket::try_from(response_data).expect("Unable to decode packet");
+            match tracker.recv() {
+                Ok(None) => {
+                    let fallback_attempts = config.aether.tracker_fallback_attempts;
+                    if tracker.channel().unanswered(fallback_attempts) {
+                        warn!("Tracker does not reply to encrypted packets, falling back to plaintext");
+                    }
 
-                for v in response_packet.connections {
-                    let mut req_lock = requests.lock().expect("unable to lock request queue");
-                    (*req_lock).push_back(v);
+                    Self::tracker_failure(&counters, threshold, &events);
+                }
+                Err(err) => {
+                    limited!(
+                        warn,
+                        log_limiter,
+                        "Dropping invalid packet from tracker: {}",
+                        err
+                    );
+                    if stop.sleep(retry_delay) {
+                        break;
+                    }
                 }
+                Ok(Some(response_packet)) => {
+                    // Answers to presence queries arrive on the same socket as polls, they
+                    // show that the tracker is reachable but say nothing about the latency
+                    let latency = match response_packet.packet_type {
+                        TrackerPacketType::Poll => Some(sent_at.elapsed()),
+                        TrackerPacketType::Presence => None,
+                        // Only ever sent to the tracker
+                        TrackerPacketType::Connection => {
+                            limited!(
+                                warn,
+                                log_limiter,
+                                "Dropping connection request from tracker"
+                            );
+                            if stop.sleep(retry_delay) {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+                    let failures = counters.success(latency);
+                    if threshold > 0 && failures >= threshold {
+                        info!("Tracker is reachable again");
+                        let _ = events.send(AetherEvent::TrackerReachable);
+                    }
+
+                    if response_packet.packet_type == TrackerPacketType::Presence {
+                        presence.answer(&response_packet.peer_username, response_packet.present);
+                        continue;
+                    }
 
-                thread::sleep(Duration::from_millis(config.aether.server_poll_time));
+                    if !response_packet.connections.is_empty() {
+                        let mut req_lock = requests.lock().expect("unable to lock request queue");
+                        (*req_lock).extend(response_packet.connections);
+                        requests_wakeup.notify();
+                    }
+
+                    if stop.sleep(Duration::from_millis(config.aether.server_poll_time)) {
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    fn monitor_network(&self) -> Option<JoinHandle<()>> {
+        let poll_time = self.config.aether.network_poll_time;
+        if poll_time == 0 {
+            return None;
+        }
+
+        let tracker = self.tracker.clone();
+        let tracker_addr = tracker.tracker_addr();
+        let events = self.events.0.clone();
+        let stop = self.stop.clone();
+
+        let handle = spawn_reported("network monitor", self.events.0.clone(), move || {
+            // Last environment detected, kept while there is no route at all
+            let mut environment = network::detect(tracker_addr);
+
+            loop {
+                if stop.sleep(Duration::from_millis(poll_time)) {
+                    break;
+                }
+
+                let current = match network::detect(tracker_addr) {
+                    Some(current) => current,
+                    None => continue,
+                };
+
+                let previous = match environment.replace(current.clone()) {
+                    Some(previous) if previous != current => previous,
+                    _ => continue,
+                };
+
+                warn!(
+                    "Route to tracker changed from {} to {} (vpn: {})",
+                    previous.local_ip, current.local_ip, current.vpn
+                );
+
+                // Re-announce through the new route so the tracker learns the new address
+                if let Err(err) = tracker.register() {
+                    error!("Unable to re-announce to tracker: {}", err);
+                }
+
+                // Nobody listening for events is not an error
+                let _ = events.send(AetherEvent::NetworkEnvironmentChanged { previous, current });
             }
         });
+
+        Some(handle)
     }
 
-    fn handle_requests(&self) {
+    /// Add the started `link` as the connection to the peer `uid`, the same way a
+    /// handshake with the peer adds it but without authenticating the peer
+    #[cfg(feature = "test-util")]
+    pub(crate) fn insert_peer(&self, uid: &str, link: Link) {
+        let peer = Peer {
+            uid: uid.to_string(),
+            identity_number: 0,
+            link: Arc::new(link),
+            failure: None,
+            name: None,
+            connected_at: Instant::now(),
+        };
+
+        let mut connections_lock = Self::flush_outbox(
+            uid,
+            &peer.link,
+            &self.outbox,
+            &self.config.aether,
+            &self.connections,
+        );
+
+        if self.fan_in.wants(uid) {
+            if let Ok(receiver) = peer.link.get_receiver() {
+                self.fan_in
+                    .forward(uid.to_string(), receiver, self.frames());
+            }
+        }
+
+        if self.stop.is_stopped() {
+            if let Err(err) = peer.link.stop() {
+                warn!("Unable to stop link: {}", err);
+            }
+        }
+
+        (*connections_lock).insert(uid.to_string(), Connection::Connected(Box::new(peer)));
+        let _ = self.events.0.send(AetherEvent::Connected {
+            uid: uid.to_string(),
+            reconnected: false,
+        });
+    }
+
+    /// Send the messages queued for the peer `uid` on its new `link`, then lock the
+    /// shard of `connections` to list the peer in. Messages are sent without holding the
+    /// lock, as sending blocks while the send queue of the link is full. Messages
+    /// [`Aether::send_to_or_queue`] queued meanwhile, while the peer was not listed yet,
+    /// are sent before returning the lock, so that none are left behind
+    fn flush_outbox<'a>(
+        uid: &str,
+        link: &Link,
+        outbox: &Mutex<Outbox>,
+        config: &AetherConfig,
+        connections: &'a ConnectionRegistry,
+    ) -> MutexGuard<'a, Shard> {
+        loop {
+            let mut outbox_lock = outbox.lock().expect("unable to lock outbox");
+            let messages = (*outbox_lock).take(uid, &config.outbox);
+            if !messages.is_empty() {
+                if let Err(err) = (*outbox_lock).save() {
+                    warn!("Unable to save outbox: {}", err);
+                }
+            }
+            drop(outbox_lock);
+
+            if !messages.is_empty() {
+                debug!(count = messages.len(), "Sending queued messages");
+            }
+            for message in messages {
+                if let Err(err) = link.send(message) {
+                    warn!("Unable to send queued message: {}", err);
+                }
+            }
+
+            let connections_lock = connections.lock(uid).expect("unable to lock peer list");
+            let outbox_lock = outbox.lock().expect("unable to lock outbox");
+            if (*outbox_lock).pending(uid) == 0 {
+                return connections_lock;
+            }
+        }
+    }
+
+    fn handle_requests(&self) -> JoinHandle<()> {
         let requests = self.requests.clone();
         let connections = self.connections.clone();
         let my_uid = self.uid.clone();
-        let tracker_addr = self.tracker_addr;
+        let tracker = self.tracker.clone();
         let config = self.config;
         let private_id = self.private_id.clone();
+        let stats = self.stats.clone();
+        let handshakes = self.handshakes.clone();
+        let rejections = self.rejections.clone();
+        let peer_cache = self.peer_cache.clone();
+        let telemetry = self.telemetry.clone();
+        let memory = self.memory.clone();
+        let contacts = self.contacts.clone();
+        let outbox = self.outbox.clone();
+        let display_name = self.display_name.clone();
+        let accept_policy = self.accept_policy.clone();
+        let incoming = self.incoming.clone();
+        let requests_wakeup = self.requests_wakeup.clone();
+        let fan_in = self.fan_in.clone();
+        let events = self.events.0.clone();
+        let requests_clone = requests.clone();
+        let requests_wakeup_clone = requests_wakeup.clone();
+        let stop = self.stop.clone();
+
+        spawn_reported("requests", self.events.0.clone(), move || loop {
+            // Stopping wakes the thread up as well
+            if stop.is_stopped() {
+                break;
+            }
+
+            // Completed handshakes wake the thread up to handle further requests
+            let max_handshakes = config.handshake.max_handshakes;
+            if max_handshakes > 0 && handshakes.load(Ordering::SeqCst) >= max_handshakes {
+                requests_wakeup.wait_timeout(Duration::from_millis(config.aether.server_poll_time));
+                continue;
+            }
 
-        thread::spawn(move || loop {
             let mut req_lock = requests.lock().expect("Unable to lock requests queue");
 
             // For each request received
@@ -332,63 +2163,245 @@ impl Aether {
                     private_id.clone(),
                     request,
                     my_uid.clone(),
-                    &mut connections.clone(),
-                    tracker_addr,
+                    &connections,
+                    &tracker,
                     &mut req_lock,
+                    &stats,
+                    &handshakes,
+                    &rejections,
+                    &peer_cache,
+                    &telemetry,
+                    &memory,
+                    &contacts,
+                    &outbox,
+                    &display_name,
+                    &accept_policy,
+                    &incoming,
+                    &fan_in,
+                    &requests_clone,
+                    &requests_wakeup_clone,
+                    &events,
+                    &stop,
                     config,
                 )
-            }
+            } else {
+                drop(req_lock);
 
-            drop(req_lock);
-            thread::sleep(Duration::from_micros(config.aether.poll_time_us));
-        });
+                // Queued requests wake the thread up, waiting is bounded only in case
+                // a request is queued without doing so
+                requests_wakeup.wait_timeout(Duration::from_millis(config.aether.server_poll_time));
+            }
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_request(
         private_id: Id,
         request: ConnectionRequest,
         my_uid: String,
-        connections: &mut Arc<Mutex<HashMap<String, Connection>>>,
-        tracker_addr: SocketAddr,
+        connections: &Arc<ConnectionRegistry>,
+        tracker: &TrackerClient,
         req_lock: &mut MutexGuard<VecDeque<ConnectionRequest>>,
+        stats: &Arc<Mutex<Histograms>>,
+        handshakes: &Arc<AtomicUsize>,
+        rejections: &Arc<RejectionCounters>,
+        peer_cache: &Option<Arc<Mutex<PeerCache>>>,
+        telemetry: &Arc<dyn Telemetry>,
+        memory: &Arc<MemoryBudget>,
+        contacts: &Arc<Mutex<Contacts>>,
+        outbox: &Arc<Mutex<Outbox>>,
+        display_name: &Arc<Mutex<Option<SignedName>>>,
+        accept_policy: &Arc<AcceptPolicy>,
+        incoming: &Arc<Incoming>,
+        fan_in: &Arc<FanIn>,
+        requests: &Arc<Mutex<VecDeque<ConnectionRequest>>>,
+        requests_wakeup: &Arc<Wakeup>,
+        events: &Sender<AetherEvent>,
+        stop: &Arc<Stop>,
         config: Config,
     ) {
-        let mut connections_lock = connections.lock().expect("unable to lock failed list");
+        let mut connections_lock = connections
+            .lock(&request.username)
+            .expect("unable to lock failed list");
         // Clone important data to pass to handshake thread
         let connections_clone = connections.clone();
         let my_uid_clone = my_uid.clone();
 
         let config_clone = config;
-
-        let handshake_thread = move |init: Initialized, request: ConnectionRequest| {
+        let stats_clone = stats.clone();
+        let handshakes_clone = handshakes.clone();
+        let rejections_clone = rejections.clone();
+        let peer_cache_clone = peer_cache.clone();
+        let telemetry_clone = telemetry.clone();
+        let memory_clone = memory.clone();
+        let display_name_clone = display_name.clone();
+        let outbox_clone = outbox.clone();
+        let fan_in_clone = fan_in.clone();
+        let frames_clone = Arc::new(Frames::new(
+            connections.clone(),
+            contacts.clone(),
+            outbox.clone(),
+            events.clone(),
+            &config,
+        ));
+        let requests_clone = requests.clone();
+        let requests_wakeup_clone = requests_wakeup.clone();
+        let events_clone = events.clone();
+        let stop_clone = stop.clone();
+
+        let handshake_thread = move |init: Initialized, request: ConnectionRequest, cancel| {
             // Initailize data values for handshake
             let peer_ip = IpAddr::V4(Ipv4Addr::from(request.ip));
             let peer_addr = SocketAddr::new(peer_ip, request.port);
             let peer_uid = request.username;
+            let mut attempts = init.attempts;
+            let expiry = attempts.options.expiry;
+            let local_port = init.socket.local_addr().map(|addr| addr.port()).ok();
+
+            // Correlates the handshake, authentication and link of this peer
+            let span = info_span!("connection", peer = %peer_uid);
+            let _enter = span.enter();
 
             let mut success = false; // This bool DOES in fact get read and modified. Not sure why compiler doesn't recognize its usage.
 
+            // Why the handshake failed, recorded in the attempts
+            let mut reason = String::new();
+
+            // Require proof-of-work from the other peer under high load
+            let in_progress = handshakes_clone.fetch_add(1, Ordering::SeqCst) + 1;
+            let options = HandshakeOptions {
+                pow_difficulty: if in_progress > config_clone.handshake.pow_load_threshold {
+                    config_clone.handshake.pow_difficulty
+                } else {
+                    0
+                },
+                rejections: Some(rejections_clone),
+                telemetry: Some(telemetry_clone.clone()),
+                memory: Some(memory_clone.clone()),
+                cancel,
+            };
+
+            let mut handshake_config = config_clone;
+            if let Some(timeout) = attempts.options.handshake_timeout {
+                handshake_config.handshake.handshake_timeout = timeout.as_millis() as u64;
+            }
+
             // Start handshake
-            let link_result = handshake(
+            let handshake_start = Instant::now();
+            let link_result = handshake_with_options(
                 private_id,
                 init.socket,
                 peer_addr,
                 my_uid_clone.clone(),
                 peer_uid.clone(),
-                config_clone,
+                options,
+                handshake_config,
             );
 
+            // Requests may be waiting for handshakes to complete
+            handshakes_clone.fetch_sub(1, Ordering::SeqCst);
+            requests_wakeup_clone.notify();
+
             match link_result {
                 Ok(link) => {
-                    trace!("Handshake success");
-
-                    match authenticate(link, peer_uid.clone(), request.identity_number, config) {
+                    let duration_ms = handshake_start.elapsed().as_millis() as u64;
+                    debug!(duration_ms, "Handshake success");
+
+                    let mut stats_lock = stats_clone.lock().expect("unable to lock stats");
+                    stats_lock.handshake_duration_ms.record(duration_ms);
+                    drop(stats_lock);
+
+                    telemetry_clone.event(&TelemetryEvent::HandshakePhase {
+                        peer: &peer_uid,
+                        phase: HandshakePhase::Authentication,
+                    });
+                    let authentication_span =
+                        telemetry::span(&telemetry_clone, SPAN_AUTHENTICATION);
+                    let authentication =
+                        authenticate(link, peer_uid.clone(), request.identity_number, config);
+                    drop(authentication_span);
+
+                    match authentication {
                         Ok(mut peer) => {
-                            if let Err(err) = peer.link.enable_encryption() {
+                            telemetry_clone.event(&TelemetryEvent::HandshakePhase {
+                                peer: &peer_uid,
+                                phase: HandshakePhase::KeyExchange,
+                            });
+
+                            // The link is not shared before the peer is listed
+                            let link =
+                                Arc::get_mut(&mut peer.link).expect("link of a new peer is shared");
+                            if let Err(err) = link.enable_encryption() {
                                 error!("Cannot enable encryption: {}", err);
+                                reason = err.to_string();
                             } else {
-                                let mut connections_lock =
-                                    connections_clone.lock().expect("unable to lock peer list");
+                                if let Some(expiry) = expiry {
+                                    link.set_expiry(expiry);
+                                }
+
+                                let display_name = display_name_clone
+                                    .lock()
+                                    .expect("unable to lock display name")
+                                    .clone();
+                                if let Some(signed) = display_name {
+                                    let sent = control::encode(&signed).and_then(|frame| {
+                                        peer.link.send_extended(NAME_SUBTYPE, frame)
+                                    });
+                                    match sent {
+                                        Ok(()) | Err(AetherError::ExtensionUnsupported(_)) => (),
+                                        Err(err) => warn!("Unable to send display name: {}", err),
+                                    }
+                                }
+
+                                if let (Some(cache), Some(local_port)) =
+                                    (&peer_cache_clone, local_port)
+                                {
+                                    let mut cache_lock =
+                                        cache.lock().expect("unable to lock peer cache");
+                                    (*cache_lock).insert(
+                                        &peer_uid,
+                                        peer_addr,
+                                        local_port,
+                                        request.identity_number,
+                                    );
+                                    if let Ok(negotiated) = peer.link.negotiated() {
+                                        (*cache_lock).set_negotiated(&peer_uid, &negotiated);
+                                    }
+                                    if let Err(err) = (*cache_lock).save() {
+                                        warn!("Unable to save peer cache: {}", err);
+                                    }
+                                }
+
+                                // Sent before the peer is listed, as the lock
+                                // Aether::send_to_or_queue needs to queue is
+                                // returned only once none are left behind
+                                let mut connections_lock = Self::flush_outbox(
+                                    &peer_uid,
+                                    &peer.link,
+                                    &outbox_clone,
+                                    &config_clone.aether,
+                                    &connections_clone,
+                                );
+
+                                // Checked while holding the lock recv_any and handlers
+                                // need to list the peer, so that it is forwarded
+                                if fan_in_clone.wants(&peer_uid) {
+                                    if let Ok(receiver) = peer.link.get_receiver() {
+                                        fan_in_clone.forward(
+                                            peer_uid.clone(),
+                                            receiver,
+                                            frames_clone.clone(),
+                                        );
+                                    }
+                                }
+
+                                // Checked while holding the lock Aether::stop needs to
+                                // stop the links of connected peers
+                                if stop_clone.is_stopped() {
+                                    if let Err(err) = peer.link.stop() {
+                                        warn!("Unable to stop link: {}", err);
+                                    }
+                                }
 
                                 // Add connected peer to connections list
                                 // with connected state
@@ -396,39 +2409,106 @@ impl Aether {
                                     peer_uid.clone(),
                                     Connection::Connected(Box::new(peer)),
                                 );
+                                let _ = events_clone.send(AetherEvent::Connected {
+                                    uid: peer_uid.clone(),
+                                    reconnected: attempts.failures() > 0,
+                                });
                                 success = true;
                             }
                         }
-                        Err(AetherError::AuthenticationFailed(_)) => {
-                            trace!("Cannot reach");
+                        Err(err @ AetherError::AuthenticationFailed(_)) => {
+                            debug!("Cannot reach peer during authentication");
+                            reason = err.to_string();
                         }
-                        Err(AetherError::AuthenticationInvalid(_)) => {
-                            error!("Identity could not be authenticated")
+                        Err(err @ AetherError::AuthenticationInvalid(_)) => {
+                            error!("Identity could not be authenticated");
+                            reason = err.to_string();
                         }
                         Err(other) => {
-                            panic!("Unexpected error {}", other);
+                            warn!(error = %other, "Authentication failed");
+                            reason = other.to_string();
                         }
                     }
                 }
                 Err(e) => {
-                    trace!("Handshake failed {}", e);
+                    debug!(error = %e, "Handshake failed");
+                    reason = e.to_string();
                 }
             }
 
+            if success {
+                telemetry_clone.counter(COUNTER_HANDSHAKES, 1);
+            } else {
+                telemetry_clone.counter(COUNTER_HANDSHAKE_FAILURES, 1);
+            }
+
             // If unsuccessful store time of failure
             if !success {
-                let mut connections_lock =
-                    connections_clone.lock().expect("unable to lock peer list");
+                let mut connections_lock = connections_clone
+                    .lock(&peer_uid)
+                    .expect("unable to lock peer list");
+
+                let mut given_up = false;
+                let mut retry = None;
+                let (connection, request) = match (*connections_lock).remove(&peer_uid) {
+                    // The tracker server answered with another endpoint while the cached
+                    // one was attempted, so it is attempted without waiting to retry
+                    Some(Connection::Direct(direct)) if direct.request.is_some() => {
+                        let direct = *direct;
+                        let init = Initialized {
+                            uid: direct.uid,
+                            socket: direct.socket,
+                            attempts,
+                            cached_endpoint: None,
+                        };
+                        (Some(Connection::Init(init)), direct.request)
+                    }
+                    _ => {
+                        attempts.fail(reason);
+                        given_up = attempts.exhausted();
+                        if !given_up {
+                            retry = Some((attempts.reason().to_string(), attempts.retry_delay()));
+                        }
+                        let failure = Connection::Failed(Failure {
+                            time: SystemTime::now(),
+                            socket: bind_peer_socket(&config_clone.aether, None)
+                                .expect("unable to create socket"),
+                            uid: peer_uid.clone(),
+                            attempts,
+                        });
+                        if given_up {
+                            debug!("Giving up connecting");
+                            (failure.give_up(), None)
+                        } else {
+                            (Some(failure), None)
+                        }
+                    }
+                };
 
                 // Add failure entry to connection list
-                (*connections_lock).insert(
-                    peer_uid.clone(),
-                    Connection::Failed(Failure {
-                        time: SystemTime::now(),
-                        socket: UdpSocket::bind(("0.0.0.0", 0)).expect("unable to create socket"),
+                if let Some(connection) = connection {
+                    (*connections_lock).insert(peer_uid.clone(), connection);
+                }
+                drop(connections_lock);
+
+                if given_up {
+                    let _ = events_clone.send(AetherEvent::ConnectionFailed { uid: peer_uid });
+                } else if let Some((reason, retry_in)) = retry {
+                    let _ = events_clone.send(AetherEvent::HandshakeFailed {
                         uid: peer_uid,
-                    }),
-                );
+                        reason,
+                        retry_in,
+                    });
+                }
+
+                // The requests queue is locked before the connections by the thread
+                // handling requests, so it is only locked once they are unlocked
+                if let Some(request) = request {
+                    let mut req_lock = requests_clone.lock().expect("unable to lock request queue");
+                    (*req_lock).push_back(request);
+                    drop(req_lock);
+                    requests_wakeup_clone.notify();
+                }
             }
         };
 
@@ -438,29 +2518,60 @@ impl Aether {
             // Initailized either since connection request was made by us first
             // Or initailized after receiving connection request from other peer
             Some(Connection::Init(init)) => {
-                // Put current user in handshake state
-                (*connections_lock).insert(init.uid.clone(), Connection::Handshake);
+                // Put current user in handshake state, attempts of the cached endpoint
+                // keep requesting the other peer through the tracker server
+                let endpoint = (request.ip, request.port);
+                let (state, cancel) = match init.socket.try_clone() {
+                    Ok(socket) if init.cached_endpoint == Some(endpoint) => {
+                        let cancel = Arc::new(AtomicBool::new(false));
+                        let direct = Direct {
+                            uid: init.uid.clone(),
+                            socket,
+                            endpoint,
+                            request: None,
+                            cancel: cancel.clone(),
+                        };
+                        (Connection::Direct(Box::new(direct)), Some(cancel))
+                    }
+                    _ => {
+                        let port = init.socket.local_addr().map_or(0, |addr| addr.port());
+                        (Connection::Handshake(port), None)
+                    }
+                };
+                (*connections_lock).insert(init.uid.clone(), state);
+
+                // The request of the other peer may have arrived before this socket was
+                // ever announced, then the other peer would not know where to send to
+                Self::send_connection_request(
+                    tracker,
+                    &init.uid,
+                    &init.socket,
+                    config.aether.mutual_intent,
+                );
 
                 // Create a thread to start handshake and establish connection
-                thread::spawn(move || handshake_thread(init, request));
+                spawn_reported("handshake", events.clone(), move || {
+                    handshake_thread(init, request, cancel)
+                });
             }
             Some(Connection::Failed(failed)) => {
-                let delta = thread_rng().gen_range(0..config.aether.delta_time);
-                let elapsed = failed
-                    .time
-                    .elapsed()
-                    .expect("unable to get system time")
-                    .as_millis();
-
-                // if elapsed time since the fail is greater than threshold
-                // then put back in initialized state
-                if elapsed > (config.aether.handshake_retry_delay + delta).into() {
+                let elapsed = failed.time.elapsed().expect("unable to get system time");
+
+                // if elapsed time since the fail is greater than the delay of the retry
+                // policy then put back in initialized state, unless connecting was given
+                // up
+                if !failed.attempts.given_up() && elapsed > failed.attempts.retry_delay() {
+                    let _ = events.send(AetherEvent::Reconnecting {
+                        uid: failed.uid.clone(),
+                        attempt: failed.attempts.failures() + 1,
+                    });
                     (*connections_lock).insert(
                         failed.uid.clone(),
                         Connection::Init(Initialized {
                             uid: failed.uid,
                             socket: failed.socket,
-                            identity_number: 1,
+                            attempts: failed.attempts,
+                            cached_endpoint: None,
                         }),
                     );
                 } else {
@@ -469,35 +2580,73 @@ impl Aether {
                     (*connections_lock).insert(failed.uid.clone(), Connection::Failed(failed));
                 }
             }
+            Some(Connection::Direct(mut direct)) => {
+                // The other peer moved, unless it answers from the cached endpoint
+                // anyway the endpoint from the tracker server is attempted instead
+                if (request.ip, request.port) != direct.endpoint {
+                    direct.request = Some(request);
+                    direct.cancel.store(true, Ordering::SeqCst);
+                }
+                (*connections_lock).insert(direct.uid.clone(), Connection::Direct(direct));
+            }
             Some(other) => {
                 // If in other state, insert back the value
                 (*connections_lock).insert(request.username.clone(), other);
             }
+            // With mutual intent, only peers this client chose to connect to may
+            // learn its address
+            None if config.aether.mutual_intent => {
+                debug!(peer = %request.username, "Ignoring unsolicited connection request");
+            }
+            None if contacts
+                .lock()
+                .expect("unable to lock contacts")
+                .trust_of(&request.username)
+                == Trust::Blocked =>
+            {
+                debug!(peer = %request.username, "Ignoring connection request from blocked contact");
+            }
+            None if !accept_policy(&request) => {
+                debug!(peer = %request.username, "Connection request rejected by policy");
+            }
+            // Wait for the application to accept the request
+            None if config.aether.manual_accept => {
+                let uid = request.username.clone();
+                if incoming.offer(request) {
+                    debug!(peer = %uid, "Connection request queued");
+                    let _ = events.send(AetherEvent::ConnectionRequested { uid });
+                }
+            }
             // If not in connections (other peer is initiator)
             // Initailize the request
             None => {
-                // Create new identity
-                let connection = Initialized {
-                    identity_number: 1,
-                    socket: UdpSocket::bind(("0.0.0.0", 0)).expect("unable to create socket"),
-                    uid: request.username.clone(),
-                };
+                // Reuse the local port the other peer may have cached
+                let local_port = peer_cache.as_ref().and_then(|cache| {
+                    let cache_lock = cache.lock().expect("unable to lock peer cache");
+                    (*cache_lock)
+                        .get(&request.username)
+                        .map(|cached| cached.local_port)
+                });
 
-                let packet = TrackerPacket {
-                    username: my_uid,
-                    peer_username: connection.uid.clone(),
-                    identity_number: connection.identity_number,
-                    packet_type: 2,
-                    req: true,
-                    ..Default::default()
+                // Create new identity
+                let connection = match Initialized::with_port(
+                    request.username.clone(),
+                    local_port,
+                    &config.aether,
+                ) {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        warn!(peer = %request.username, "Unable to bind socket for connection request: {}", err);
+                        return;
+                    }
                 };
 
-                let packet_data: Vec<u8> = Vec::try_from(packet).expect("Unable to encode packet");
-
-                connection
-                    .socket
-                    .send_to(&packet_data, tracker_addr)
-                    .expect("unable to send packet to server");
+                Self::send_connection_request(
+                    tracker,
+                    &connection.uid,
+                    &connection.socket,
+                    config.aether.mutual_intent,
+                );
 
                 // Insert new initialized connection
                 (*connections_lock).insert(request.username.clone(), Connection::Init(connection));
@@ -507,3 +2656,112 @@ impl Aether {
         }
     }
 }
+
+/// Iterator over the bytes received from a connected peer (refer [`Aether::messages`])
+pub struct PeerMessages<'a> {
+    aether: &'a Aether,
+    /// Current UID of the peer
+    uid: String,
+}
+
+impl PeerMessages<'_> {
+    /// Returns the current UID of the peer, which changes if the peer rotates its key
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+}
+
+impl Iterator for PeerMessages<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.aether
+            .recv_following(&mut self.uid)
+            .ok()
+            .map(|message| message.payload)
+    }
+}
+
+impl Drop for Aether {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            error!("Unable to stop aether: {}", err);
+        }
+    }
+}
+
+/// Spawn the background thread `name` of a client running `work`. If it fails, the
+/// client is told with [`AetherEvent::ThreadFailed`]
+fn spawn_reported<F>(name: &'static str, events: Sender<AetherEvent>, work: F) -> JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(error) = catch_panic(work) {
+            error!("Thread {} failed: {}", name, error);
+            // Nobody listening for events is not an error
+            let _ = events.send(AetherEvent::ThreadFailed {
+                thread: name,
+                error,
+            });
+        }
+    })
+}
+
+/// Error for the link to the peer `uid` closed for `reason`
+fn closed_error(uid: &str, reason: Option<CloseReason>) -> AetherError {
+    match reason {
+        Some(CloseReason::Expired) => AetherError::SessionExpired(uid.to_string()),
+        _ => AetherError::LinkBroken(uid.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+
+    use super::bind_peer_socket;
+    use crate::config::AetherConfig;
+
+    #[test]
+    fn bind_peer_socket_test() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let free_port = || {
+            UdpSocket::bind((localhost, 0))
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+
+        let mut config = AetherConfig {
+            bind_address: localhost,
+            ..Default::default()
+        };
+
+        // Without a range the preferred port is used if free
+        let port = free_port();
+        let socket = bind_peer_socket(&config, Some(port)).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), localhost);
+        assert_eq!(socket.local_addr().unwrap().port(), port);
+        assert_ne!(
+            bind_peer_socket(&config, Some(port))
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port(),
+            port
+        );
+
+        // Preferred ports outside the range are ignored
+        let port = free_port();
+        config.peer_port_min = port;
+        let socket = bind_peer_socket(&config, Some(free_port())).unwrap();
+        assert_eq!(socket.local_addr().unwrap().port(), port);
+
+        // The range is exhausted while the only port is taken
+        assert!(bind_peer_socket(&config, None).is_err());
+        drop(socket);
+        assert!(bind_peer_socket(&config, None).is_ok());
+    }
+}
@@ -0,0 +1,190 @@
+//! Detection of the network environment used to reach the tracker server.
+//!
+//! The environment is the local address (and interface where it can be found) of the
+//! route to the tracker server. It changes when the default route moves, for example
+//! when a VPN is toggled, which silently breaks links using the old route.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+/// Prefixes of the names of interfaces commonly created by VPN software
+pub const VPN_INTERFACE_PREFIXES: [&str; 8] = [
+    "tun",
+    "tap",
+    "wg",
+    "ppp",
+    "utun",
+    "ipsec",
+    "tailscale",
+    "zt",
+];
+
+/// Route used to reach the tracker server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkEnvironment {
+    /// Local address used for packets to the tracker server
+    pub local_ip: IpAddr,
+    /// Name of the interface of the route, if it could be found
+    pub interface: Option<String>,
+    /// Set if the interface looks like a VPN interface
+    pub vpn: bool,
+}
+
+/// Detect the [`NetworkEnvironment`] used to reach `target`. Returns [`None`] if there
+/// is no route to `target`
+pub fn detect(target: SocketAddr) -> Option<NetworkEnvironment> {
+    // Connecting a UDP socket only selects a route, no packets are sent
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).ok()?;
+    socket.connect(target).ok()?;
+    let local_ip = socket.local_addr().ok()?.ip();
+
+    let interface = match target.ip() {
+        IpAddr::V4(ip) => system_routes().and_then(|routes| route_interface(&routes, ip)),
+        IpAddr::V6(_) => None,
+    };
+
+    let vpn = interface.as_deref().map_or(false, is_vpn_interface);
+
+    Some(NetworkEnvironment {
+        local_ip,
+        interface,
+        vpn,
+    })
+}
+
+/// Check if the interface `name` looks like a VPN interface
+pub fn is_vpn_interface(name: &str) -> bool {
+    VPN_INTERFACE_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Find the interface of the most specific route to `target` in a routing table in the
+/// format of `/proc/net/route`
+pub fn route_interface(routes: &str, target: Ipv4Addr) -> Option<String> {
+    let target = u32::from(target);
+
+    // (prefix length, metric, interface) of the best route so far
+    let mut best: Option<(u32, u32, &str)> = None;
+
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            continue;
+        }
+
+        let (destination, metric, mask) = match (
+            parse_address(fields[1]),
+            fields[6].parse::<u32>(),
+            parse_address(fields[7]),
+        ) {
+            (Some(destination), Ok(metric), Some(mask)) => (destination, metric, mask),
+            _ => continue,
+        };
+
+        if target & mask != destination & mask {
+            continue;
+        }
+
+        // VPNs often override the default route with two more specific halves
+        let prefix = mask.count_ones();
+        let better = match best {
+            Some((best_prefix, best_metric, _)) => {
+                prefix > best_prefix || (prefix == best_prefix && metric < best_metric)
+            }
+            None => true,
+        };
+
+        if better {
+            best = Some((prefix, metric, fields[0]));
+        }
+    }
+
+    best.map(|(_, _, interface)| interface.to_string())
+}
+
+/// Parse an address from `/proc/net/route`, which are hexadecimal in host byte order
+fn parse_address(field: &str) -> Option<u32> {
+    let value = u32::from_str_radix(field, 16).ok()?;
+    Some(u32::from(Ipv4Addr::from(value.to_ne_bytes())))
+}
+
+#[cfg(target_os = "linux")]
+fn system_routes() -> Option<String> {
+    std::fs::read_to_string("/proc/net/route").ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn system_routes() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_vpn_interface, route_interface};
+    use std::net::Ipv4Addr;
+
+    fn route(interface: &str, destination: Ipv4Addr, mask: Ipv4Addr, metric: u32) -> String {
+        format!(
+            "{}\t{:08X}\t00000000\t0001\t0\t0\t{}\t{:08X}\t0\t0\t0",
+            interface,
+            u32::from_ne_bytes(destination.octets()),
+            metric,
+            u32::from_ne_bytes(mask.octets()),
+        )
+    }
+
+    fn table(routes: &[String]) -> String {
+        let header =
+            "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT";
+        let mut table = vec![header.to_string()];
+        table.extend_from_slice(routes);
+        table.join("\n")
+    }
+
+    #[test]
+    fn default_route_test() {
+        let routes = table(&[
+            route("eth0", Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, 100),
+            route("wlan0", Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, 600),
+            route(
+                "eth0",
+                Ipv4Addr::new(192, 168, 1, 0),
+                Ipv4Addr::new(255, 255, 255, 0),
+                100,
+            ),
+        ]);
+
+        let tracker = Ipv4Addr::new(149, 129, 129, 226);
+        assert_eq!(route_interface(&routes, tracker).unwrap(), "eth0");
+
+        let local = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(route_interface(&routes, local).unwrap(), "eth0");
+
+        assert_eq!(route_interface(&table(&[]), tracker), None);
+    }
+
+    #[test]
+    fn vpn_route_test() {
+        // Split default route as installed by OpenVPN
+        let routes = table(&[
+            route("eth0", Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED, 100),
+            route(
+                "tun0",
+                Ipv4Addr::UNSPECIFIED,
+                Ipv4Addr::new(128, 0, 0, 0),
+                0,
+            ),
+            route(
+                "tun0",
+                Ipv4Addr::new(128, 0, 0, 0),
+                Ipv4Addr::new(128, 0, 0, 0),
+                0,
+            ),
+        ]);
+
+        let interface = route_interface(&routes, Ipv4Addr::new(149, 129, 129, 226)).unwrap();
+        assert_eq!(interface, "tun0");
+        assert!(is_vpn_interface(&interface));
+        assert!(!is_vpn_interface("eth0"));
+    }
+}
@@ -0,0 +1,246 @@
+//! Messages kept for peers that are not connected (refer
+//! [`Aether::send_to_or_queue`][crate::peer::Aether::send_to_or_queue]).
+//!
+//! Messages are queued per peer and sent in order as soon as a connection to the peer
+//! is established, before any message sent after connecting. Queues are bounded by the
+//! [`OutboxConfig`] of the client, and messages older than its `max_age` are dropped
+//! instead of delivered.
+//!
+//! An outbox that is [loaded][Outbox::load] from a file keeps the messages across
+//! restarts. It is stored in [YAML](https://yaml.org/) format, with the messages
+//! encoded in base64, by default in `$HOME/.config/aether/outbox.yaml`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::OutboxConfig;
+use crate::error::AetherError;
+use crate::identity::Id;
+
+/// A message waiting for its peer to be connected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QueuedMessage {
+    /// Bytes of the message
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    pub data: Vec<u8>,
+    /// Time the message was queued in milliseconds since the Unix epoch
+    pub queued_at: u64,
+}
+
+impl QueuedMessage {
+    /// Returns true if the message is older than the `max_age` of `limits`
+    fn expired(&self, limits: &OutboxConfig, now: u64) -> bool {
+        limits.max_age > 0 && now.saturating_sub(self.queued_at) > limits.max_age
+    }
+}
+
+/// Queues of [`QueuedMessage`]s by uid, stored in a file if loaded from one
+#[derive(Debug, Default)]
+pub struct Outbox {
+    /// File the outbox is stored in, if any
+    path: Option<PathBuf>,
+    messages: HashMap<String, VecDeque<QueuedMessage>>,
+}
+
+impl Outbox {
+    /// Creates an empty outbox that is not stored on the filesystem
+    pub fn new() -> Outbox {
+        Outbox::default()
+    }
+
+    /// Returns the default location of the outbox on the filesystem
+    pub fn default_path() -> PathBuf {
+        let mut path = Id::get_config_dir();
+        path.push("outbox.yaml");
+        path
+    }
+
+    /// Load the outbox stored in `path`. A missing file is an empty outbox. Changes are
+    /// saved to the same file
+    /// # Errors
+    /// * [`AetherError::FileRead`] - If the file exists but cannot be read
+    /// * [`AetherError::YamlParse`] - If the file is not a valid outbox
+    pub fn load(path: &Path) -> Result<Outbox, AetherError> {
+        let messages = match fs::read_to_string(path) {
+            Ok(data) => serde_yaml::from_str(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(AetherError::FileRead(err)),
+        };
+
+        Ok(Outbox {
+            path: Some(path.to_path_buf()),
+            messages,
+        })
+    }
+
+    /// Write the outbox to its file, if any
+    pub fn save(&self) -> Result<(), AetherError> {
+        match &self.path {
+            Some(path) => {
+                let data = serde_yaml::to_string(&self.messages)?;
+                fs::write(path, data).map_err(AetherError::FileWrite)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Queue `data` for the peer with `uid`, after the messages already queued for it.
+    /// Expired messages are dropped first
+    /// # Errors
+    /// * [`AetherError::OutboxFull`] - If the message would exceed the `max_messages` or
+    ///   `max_bytes` of `limits`, it is not queued
+    pub fn push(
+        &mut self,
+        uid: &str,
+        data: Vec<u8>,
+        limits: &OutboxConfig,
+    ) -> Result<(), AetherError> {
+        let now = now_ms();
+        let queue = self.messages.entry(uid.to_string()).or_default();
+        queue.retain(|message| !message.expired(limits, now));
+
+        let bytes: usize = queue.iter().map(|message| message.data.len()).sum();
+        let full = (limits.max_messages > 0 && queue.len() >= limits.max_messages)
+            || (limits.max_bytes > 0 && bytes + data.len() > limits.max_bytes);
+        if full {
+            if queue.is_empty() {
+                self.messages.remove(uid);
+            }
+            return Err(AetherError::OutboxFull(uid.to_string()));
+        }
+
+        queue.push_back(QueuedMessage {
+            data,
+            queued_at: now,
+        });
+        Ok(())
+    }
+
+    /// Remove the messages queued for the peer with `uid`, in the order they were
+    /// queued. Expired messages are dropped
+    pub fn take(&mut self, uid: &str, limits: &OutboxConfig) -> Vec<Vec<u8>> {
+        let now = now_ms();
+        self.messages
+            .remove(uid)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|message| !message.expired(limits, now))
+            .map(|message| message.data)
+            .collect()
+    }
+
+    /// Returns the number of messages queued for the peer with `uid`, including expired
+    /// messages that were not dropped yet
+    pub fn pending(&self, uid: &str) -> usize {
+        self.messages.get(uid).map_or(0, VecDeque::len)
+    }
+
+    /// Move the messages queued for the peer `uid` to `new_uid`, after the messages
+    /// already queued for `new_uid`
+    pub fn rename(&mut self, uid: &str, new_uid: &str) {
+        if let Some(queue) = self.messages.remove(uid) {
+            self.messages
+                .entry(new_uid.to_string())
+                .or_default()
+                .extend(queue);
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn to_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(data))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    base64::decode(encoded).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::Outbox;
+    use crate::config::OutboxConfig;
+    use crate::error::AetherError;
+
+    #[test]
+    fn outbox_test() {
+        fs::create_dir_all("./tmp").unwrap();
+        let path = Path::new("./tmp/outbox.yaml");
+        let _ = fs::remove_file(path);
+
+        let limits = OutboxConfig {
+            max_messages: 3,
+            max_bytes: 10,
+            max_age: 0,
+        };
+        let mut outbox = Outbox::load(path).unwrap();
+        outbox.push("peer", b"first".to_vec(), &limits).unwrap();
+        outbox.push("peer", b"2nd".to_vec(), &limits).unwrap();
+        outbox.push("other", b"third".to_vec(), &limits).unwrap();
+        assert_eq!(outbox.pending("peer"), 2);
+
+        // Limits apply to every peer separately
+        assert!(matches!(
+            outbox.push("peer", b"3rd".to_vec(), &limits),
+            Err(AetherError::OutboxFull(_))
+        ));
+        outbox.push("peer", b"3".to_vec(), &limits).unwrap();
+        assert!(matches!(
+            outbox.push("peer", Vec::new(), &limits),
+            Err(AetherError::OutboxFull(_))
+        ));
+        assert!(matches!(
+            outbox.push("empty", b"too large".repeat(2), &limits),
+            Err(AetherError::OutboxFull(_))
+        ));
+        assert_eq!(outbox.pending("empty"), 0);
+        outbox.save().unwrap();
+
+        let mut loaded = Outbox::load(path).unwrap();
+        assert_eq!(
+            loaded.take("peer", &limits),
+            vec![b"first".to_vec(), b"2nd".to_vec(), b"3".to_vec()]
+        );
+        assert!(loaded.take("peer", &limits).is_empty());
+
+        loaded.rename("other", "renamed");
+        assert_eq!(loaded.pending("other"), 0);
+        assert_eq!(loaded.take("renamed", &limits), vec![b"third".to_vec()]);
+
+        fs::write(path, "peer:\n  - data: not base64!\n    queued_at: 0\n").unwrap();
+        assert!(Outbox::load(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn expiry_test() {
+        let limits = OutboxConfig {
+            max_messages: 1,
+            max_bytes: 0,
+            max_age: 1,
+        };
+        let mut outbox = Outbox::new();
+        outbox.push("peer", b"stale".to_vec(), &limits).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        // Expired messages make room for new ones and are not delivered
+        outbox.push("peer", b"fresh".to_vec(), &limits).unwrap();
+        assert_eq!(outbox.pending("peer"), 1);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(outbox.take("peer", &limits).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Presence checks of peers on the tracker server, used to tell why the link to a
+//! connected peer timed out and to answer
+//! [`Aether::is_online`][crate::peer::Aether::is_online].
+//!
+//! A peer that stopped polling the tracker server is offline. A peer that still polls it
+//! is online, so only the path between the peers broke (for example because a NAT
+//! mapping expired).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::util::Wakeup;
+
+/// Longest time [`PresenceChecks::wait`] blocks, in case another waiting thread took the
+/// notification of an answer
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Cause of a timed out link to a connected peer, found by asking the tracker server
+/// whether the peer is still present
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkFailure {
+    /// The peer is no longer present on the tracker server. Reconnecting is pointless
+    /// until it is back online
+    PeerOffline,
+    /// The peer is still present on the tracker server, so the path between the peers
+    /// broke. Reconnecting immediately or through a relay may succeed
+    PathBroken,
+    /// The tracker server did not answer in time, for example because it does not
+    /// support presence queries
+    Unknown,
+    /// A thread of the link failed, rather than the link timing out (refer
+    /// [`CloseReason::Failed`][crate::link::CloseReason::Failed]). Refer
+    /// [`Aether::link_error`][crate::peer::Aether::link_error]
+    Internal,
+}
+
+/// A presence query sent to the tracker server
+#[derive(Debug)]
+struct Check {
+    /// Time the query was sent
+    started: Instant,
+    /// Answer of the tracker server, if received
+    present: Option<bool>,
+}
+
+/// Presence queries in progress, by the UID of the queried peer
+#[derive(Debug, Default)]
+pub struct PresenceChecks {
+    checks: Mutex<HashMap<String, Check>>,
+    /// Notified when an answer is recorded
+    answered: Wakeup,
+}
+
+impl PresenceChecks {
+    /// Creates an empty list of presence checks
+    pub fn new() -> PresenceChecks {
+        PresenceChecks::default()
+    }
+
+    /// Start checking the presence of `uid`. Returns false if a check of the peer is
+    /// already in progress, in which case no query has to be sent
+    pub fn start(&self, uid: &str) -> bool {
+        let mut checks_lock = self.checks.lock().expect("unable to lock presence checks");
+        if checks_lock.contains_key(uid) {
+            return false;
+        }
+
+        checks_lock.insert(
+            uid.to_string(),
+            Check {
+                started: Instant::now(),
+                present: None,
+            },
+        );
+        true
+    }
+
+    /// Record the answer of the tracker server for `uid`. Answers for peers not being
+    /// checked are ignored
+    pub fn answer(&self, uid: &str, present: bool) {
+        let mut checks_lock = self.checks.lock().expect("unable to lock presence checks");
+        if let Some(check) = checks_lock.get_mut(uid) {
+            check.present = Some(present);
+            self.answered.notify();
+        }
+    }
+
+    /// Block until an answer may have been recorded, at most `timeout`
+    pub fn wait(&self, timeout: Duration) {
+        self.answered.wait_timeout(timeout.min(MAX_WAIT));
+    }
+
+    /// Returns the [`LinkFailure`] of `uid` once the tracker server answered or did not
+    /// answer within `timeout`, ending the check
+    pub fn finish(&self, uid: &str, timeout: Duration) -> Option<LinkFailure> {
+        let failure = match self.outcome(uid, timeout)? {
+            Some(true) => LinkFailure::PathBroken,
+            Some(false) => LinkFailure::PeerOffline,
+            None => LinkFailure::Unknown,
+        };
+        Some(failure)
+    }
+
+    /// Returns the answer of the tracker server for `uid`, or [`None`] inside if it did
+    /// not answer within `timeout`, ending the check. Returns [`None`] while waiting for
+    /// the answer, and if no check of `uid` is in progress
+    pub fn outcome(&self, uid: &str, timeout: Duration) -> Option<Option<bool>> {
+        let mut checks_lock = self.checks.lock().expect("unable to lock presence checks");
+        let check = checks_lock.get(uid)?;
+
+        if check.present.is_none() && check.started.elapsed() <= timeout {
+            return None;
+        }
+
+        let present = check.present;
+        checks_lock.remove(uid);
+        Some(present)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{LinkFailure, PresenceChecks};
+
+    #[test]
+    fn presence_checks_test() {
+        let checks = PresenceChecks::new();
+        let timeout = Duration::from_millis(20);
+
+        // Answers without a check in progress are ignored
+        checks.answer("online", true);
+        assert_eq!(checks.finish("online", timeout), None);
+
+        assert!(checks.start("online"));
+        assert!(!checks.start("online"));
+        assert!(checks.start("offline"));
+        assert!(checks.start("silent"));
+        assert_eq!(checks.finish("online", timeout), None);
+
+        checks.answer("online", true);
+        checks.answer("offline", false);
+        assert_eq!(
+            checks.finish("online", timeout),
+            Some(LinkFailure::PathBroken)
+        );
+        assert_eq!(
+            checks.finish("offline", timeout),
+            Some(LinkFailure::PeerOffline)
+        );
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(checks.finish("silent", timeout), Some(LinkFailure::Unknown));
+
+        // Finished checks can be started again
+        assert_eq!(checks.finish("online", timeout), None);
+        assert!(checks.start("online"));
+
+        assert_eq!(checks.outcome("online", timeout), None);
+        checks.answer("online", false);
+        assert_eq!(checks.outcome("online", timeout), Some(Some(false)));
+        assert_eq!(checks.outcome("online", timeout), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Sharded registry of the connections of an [`Aether`][crate::peer::Aether] client.
+//!
+//! Connections are split over [`SHARD_COUNT`] independently locked maps based on the
+//! hash of the peer's UID. Operations on one peer only lock the shard containing it,
+//! so the background threads polling all connections and handshake threads updating
+//! single connections do not contend on one global lock.
+//!
+//! The `connection_registry` benchmark connects one client to 1,000 peers simulated
+//! over the in-memory transport of `test-util` and sends to all of them from 8 threads,
+//! each also polling the peers of the others. It reports the memory per connection and,
+//! with `lock-profiling`, the contention of the shards. On a single core a round takes
+//! about 200 ms and a connection about 220 KiB, nearly all of it the threads and
+//! buffers of its link. A [`Connection`] entry itself takes at most 64 bytes inline
+//! plus its UID.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, MutexGuard};
+
+use crate::error::AetherError;
+use crate::link::Link;
+use crate::peer::Connection;
+use crate::sync::Mutex;
+
+/// Number of shards the connections are split over
+pub const SHARD_COUNT: usize = 16;
+
+/// A single shard of the [`ConnectionRegistry`]
+pub type Shard = HashMap<String, Connection>;
+
+/// Registry of connections split over multiple independently locked shards
+#[derive(Debug)]
+pub struct ConnectionRegistry {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl ConnectionRegistry {
+    /// Creates a new empty [`ConnectionRegistry`]
+    pub fn new() -> ConnectionRegistry {
+        ConnectionRegistry {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new("connections", HashMap::new()))
+                .collect(),
+        }
+    }
+
+    /// Index of the shard containing the given UID
+    fn shard_index(uid: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        uid.hash(&mut hasher);
+        (hasher.finish() % SHARD_COUNT as u64) as usize
+    }
+
+    /// Lock the shard containing the connection to the given peer
+    ///
+    /// # Arguments
+    ///
+    /// * `uid` -   UID of the peer
+    pub fn lock(&self, uid: &str) -> Result<MutexGuard<'_, Shard>, AetherError> {
+        match self.shards[Self::shard_index(uid)].lock() {
+            Ok(lock) => Ok(lock),
+            Err(_) => Err(AetherError::MutexLock("connections")),
+        }
+    }
+
+    /// Returns the link to the connected peer `uid`, to use it without holding the lock
+    /// of its shard, such as to send while the send queue of the link is full
+    /// # Errors
+    /// * [`AetherError::NotConnected`] - Peer is not in connected state
+    pub fn link(&self, uid: &str) -> Result<Arc<Link>, AetherError> {
+        match (*self.lock(uid)?).get(uid) {
+            Some(Connection::Connected(peer)) => Ok(peer.link.clone()),
+            _ => Err(AetherError::NotConnected(uid.to_string())),
+        }
+    }
+
+    /// Iterate over all shards of the registry. Each shard has to be locked
+    /// separately, so only one shard is locked at a time
+    pub fn shards(&self) -> impl Iterator<Item = &Mutex<Shard>> {
+        self.shards.iter()
+    }
+
+    /// Returns the total number of connections in the registry
+    pub fn len(&self) -> Result<usize, AetherError> {
+        let mut len = 0;
+        for shard in self.shards() {
+            match shard.lock() {
+                Ok(lock) => len += lock.len(),
+                Err(_) => return Err(AetherError::MutexLock("connections")),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Returns true if there are no connections in the registry
+    pub fn is_empty(&self) -> Result<bool, AetherError> {
+        Ok(self.len()? == 0)
+    }
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use crate::peer::{Connection, Initialized};
+
+    use super::ConnectionRegistry;
+
+    #[test]
+    fn registry_test() {
+        let registry = ConnectionRegistry::new();
+
+        for i in 0..100 {
+            let uid = format!("peer-{}", i);
+            let mut shard = registry.lock(&uid).unwrap();
+            shard.insert(
+                uid.clone(),
+                Connection::Init(Initialized::new(uid).unwrap()),
+            );
+        }
+
+        assert_eq!(registry.len().unwrap(), 100);
+
+        for i in 0..100 {
+            let uid = format!("peer-{}", i);
+            let shard = registry.lock(&uid).unwrap();
+            assert!(matches!(shard.get(&uid), Some(Connection::Init(_))));
+        }
+
+        // Connections should be spread over the shards
+        let used = registry
+            .shards()
+            .filter(|shard| !shard.lock().unwrap().is_empty())
+            .count();
+        assert!(used > 1);
+    }
+
+    #[test]
+    fn connection_size_test() {
+        assert!(size_of::<Connection>() <= 64);
+    }
+}
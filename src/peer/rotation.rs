@@ -0,0 +1,143 @@
+//! Re-verification of connected peers changing their identity.
+//!
+//! The UID of a peer is its public key, so a peer rotating its key would lose its
+//! sessions. Instead, it announces the new key to its connected peers in a
+//! [`KeyRotation`] control frame (see [`control`]) holding a transition statement: the
+//! [`transition_message`] from the old to the new key signed with the old key.
+//!
+//! A peer receiving the announcement verifies the statement against the key it
+//! authenticated the session with, and then authenticates the new key like a new
+//! connection: it sends a nonce encrypted with the new key in a [`RotationChallenge`],
+//! which only the owner of the new key can answer with a [`RotationResponse`]. The
+//! session is kept under the new UID only if both steps succeed, otherwise the link is
+//! closed with [`AetherError::IdentityChanged`].
+//!
+//! Rotations are started with [`Aether::announce_key_rotation`] and handled by every
+//! receive path of the other peer. Their frames are sent in packets of their own
+//! subtype (refer [`frames`]).
+//!
+//! [`Aether::announce_key_rotation`]: crate::peer::Aether::announce_key_rotation
+//! [`control`]: crate::wire::control
+//! [`frames`]: crate::peer::frames
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::identity::{Id, PublicId};
+use crate::wire::control::Control;
+
+/// Prefix of the [`transition_message`], so that statements cannot be mistaken for
+/// signatures of other data
+const TRANSITION_CONTEXT: &[u8] = b"aether key rotation";
+
+/// Control message announcing the new key of the sending peer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyRotation {
+    /// New public key of the sending peer, encoded as base64 like a UID
+    pub new_key: String,
+    /// [`transition_message`] signed with the old key
+    pub statement: Vec<u8>,
+}
+
+impl Control for KeyRotation {
+    const KIND: u16 = 0x0003;
+}
+
+/// Control message challenging the new key of a [`KeyRotation`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RotationChallenge {
+    /// Nonce encrypted with the new key
+    pub nonce: Vec<u8>,
+}
+
+impl Control for RotationChallenge {
+    const KIND: u16 = 0x0004;
+}
+
+/// Control message answering a [`RotationChallenge`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RotationResponse {
+    /// Nonce decrypted with the new key
+    pub nonce: Vec<u8>,
+}
+
+impl Control for RotationResponse {
+    const KIND: u16 = 0x0005;
+}
+
+/// Returns the message signed by the old key to hand over from `old_key` to `new_key`
+pub fn transition_message(old_key: &str, new_key: &str) -> Vec<u8> {
+    let mut message = TRANSITION_CONTEXT.to_vec();
+    message.push(0);
+    message.extend(old_key.as_bytes());
+    message.push(0);
+    message.extend(new_key.as_bytes());
+    message
+}
+
+impl KeyRotation {
+    /// Creates the announcement of the rotation from `old_id` to `new_id`
+    pub fn new(old_id: &Id, new_id: &Id) -> Result<KeyRotation, AetherError> {
+        let old_key = old_id.public_key_to_base64()?;
+        let new_key = new_id.public_key_to_base64()?;
+        let statement = old_id.sign(&transition_message(&old_key, &new_key))?;
+
+        Ok(KeyRotation { new_key, statement })
+    }
+
+    /// Verify the transition statement against `old_key`, the UID the session was
+    /// authenticated with. Returns the new key if the statement verifies
+    /// # Errors
+    /// * [`AetherError::IdentityChanged`] - If the statement was not signed by `old_key`
+    ///   or the new key is invalid
+    pub fn verify(&self, old_key: &str) -> Result<PublicId, AetherError> {
+        let changed = || AetherError::IdentityChanged(old_key.to_string());
+
+        let old_id = PublicId::from_base64(old_key)?;
+        let new_id = PublicId::from_base64(&self.new_key).map_err(|_| changed())?;
+
+        let message = transition_message(old_key, &self.new_key);
+        match old_id.verify(&message, &self.statement) {
+            Ok(true) if self.new_key != old_key => Ok(new_id),
+            _ => Err(changed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AetherError;
+
+    use super::KeyRotation;
+    use crate::identity::Id;
+
+    #[test]
+    fn verify_test() {
+        let old_id = Id::new().unwrap();
+        let new_id = Id::new().unwrap();
+        let old_key = old_id.public_key_to_base64().unwrap();
+        let new_key = new_id.public_key_to_base64().unwrap();
+
+        let rotation = KeyRotation::new(&old_id, &new_id).unwrap();
+        let verified = rotation.verify(&old_key).unwrap();
+        assert_eq!(verified.public_key_to_base64().unwrap(), new_key);
+
+        // Statements of other keys are rejected
+        let mallory_id = Id::new().unwrap();
+        let forged = KeyRotation::new(&mallory_id, &new_id).unwrap();
+        assert!(matches!(
+            forged.verify(&old_key),
+            Err(AetherError::IdentityChanged(_))
+        ));
+
+        // Statements cannot be redirected to another key
+        let redirected = KeyRotation {
+            new_key: mallory_id.public_key_to_base64().unwrap(),
+            ..rotation
+        };
+        assert!(matches!(
+            redirected.verify(&old_key),
+            Err(AetherError::IdentityChanged(_))
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+//! Types commonly needed to use Aether, to be glob imported.
+//!
+//! Includes the client, identities, configuration, errors, events and statistics, along
+//! with the third-party types appearing in their signatures. Code importing from here
+//! does not depend on the module each type is defined in.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::net::SocketAddr;
+//! use std::time::Duration;
+//!
+//! use aether_lib::prelude::*;
+//!
+//! fn run(tracker_addr: SocketAddr, peer_uid: &str) -> Result<(), AetherError> {
+//!     let aether = Aether::new_with_config(Id::new()?, tracker_addr, Config::default());
+//!     aether.start();
+//!
+//!     aether.connect(peer_uid)?;
+//!     aether.send_to(peer_uid, b"Hello".to_vec())?;
+//!
+//!     let stats: LinkStats = aether.stats_for(peer_uid)?;
+//!     println!("{:?}", stats);
+//!
+//!     let events: Receiver<AetherEvent> = aether.events();
+//!     while let Ok(event) = events.recv_timeout(Duration::from_secs(1)) {
+//!         println!("{:?}", event);
+//!     }
+//!
+//!     aether.stop()
+//! }
+//! ```
+
+pub use crate::config::{
+    AetherConfig, Config, GiveUp, HandshakeConfig, LinkConfig, RetryPolicy, TelemetryConfig,
+};
+pub use crate::contacts::{Contacts, Trust};
+pub use crate::error::{AetherError, PacketError};
+pub use crate::identity::{Id, PublicId};
+pub use crate::link::delivery::{DeliveryHandle, MessageId};
+#[cfg(feature = "raw")]
+pub use crate::link::Link;
+pub use crate::link::{CloseReason, IncomingProgress, Negotiated};
+pub use crate::peer::connect::ConnectOptions;
+pub use crate::peer::presence::LinkFailure;
+pub use crate::peer::{Aether, AetherEvent, ConnectionStatus, PeerInfo, PeerMessages};
+pub use crate::stats::{Histograms, LinkStats, Rejections, TrackerStats};
+pub use crate::telemetry::{Telemetry, TelemetryEvent};
+pub use crate::transport::Transport;
+pub use crate::wire::headers::{Headers, Message};
+
+pub use crossbeam::channel::{Receiver, RecvTimeoutError};
@@ -0,0 +1,414 @@
+//! Publish/subscribe topics on top of the connections of an [`Aether`] client.
+//!
+//! Peers subscribe to string topics using [`PubSub::subscribe`], and
+//! [`PubSub::publish`] delivers bytes to every connected peer subscribed to the topic.
+//! Peers tell each other the topics they are subscribed to in [`Subscriptions`] control
+//! frames (see [`control`]), sent whenever the topics change and when a peer connects.
+//!
+//! [`PubSub`] receives all messages of the client using
+//! [`Aether::recv_any`][Aether::recv_any], so the peers it is used with should only
+//! exchange messages through it.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+//! use std::sync::Arc;
+//! use aether_lib::peer::Aether;
+//! use aether_lib::pubsub::PubSub;
+//!
+//! let tracker_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(149, 129, 129, 226)), 8982);
+//! let aether = Arc::new(Aether::new(tracker_addr));
+//! aether.start();
+//! aether.connect("<peer-uid-here>").unwrap();
+//!
+//! let pubsub = PubSub::new(aether);
+//! pubsub.start();
+//! pubsub.subscribe("news").unwrap();
+//!
+//! // deliver to all connected peers subscribed to "weather"
+//! pubsub.publish("weather", b"Sunny".to_vec()).unwrap();
+//!
+//! // receive from peers publishing to "news"
+//! let publication = pubsub.recv().unwrap();
+//! println!("{}: {:?}", publication.from, publication.payload);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::AetherError;
+use crate::peer::Aether;
+use crate::wire::control::{self, Control};
+
+/// How often to check for newly connected peers to send the subscribed topics to, and
+/// for disconnected peers to forget
+const SYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Control message with all topics the sending peer is subscribed to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Subscriptions {
+    pub topics: Vec<String>,
+}
+
+impl Control for Subscriptions {
+    const KIND: u16 = 0x0001;
+}
+
+/// Control message with bytes published to a topic
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+impl Control for Publish {
+    const KIND: u16 = 0x0002;
+}
+
+/// Bytes received from a peer on a subscribed topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publication {
+    /// UID of the publishing peer
+    pub from: String,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+/// Subscription state of this client and its peers
+#[derive(Debug, Default)]
+struct State {
+    /// Topics this client is subscribed to
+    topics: HashSet<String>,
+    /// Peers that were sent the current topics
+    announced: HashSet<String>,
+    /// Topics each peer is subscribed to
+    subscribers: HashMap<String, HashSet<String>>,
+}
+
+impl State {
+    /// Returns the [`Subscriptions`] control frame for the current topics
+    fn frame(&self) -> Result<Vec<u8>, AetherError> {
+        let mut topics: Vec<String> = self.topics.iter().cloned().collect();
+        topics.sort();
+
+        control::encode(&Subscriptions { topics })
+    }
+
+    /// Returns the peers subscribed to `topic`
+    fn subscribers_of(&self, topic: &str) -> Vec<String> {
+        let mut subscribers: Vec<String> = self
+            .subscribers
+            .iter()
+            .filter(|(_, topics)| topics.contains(topic))
+            .map(|(uid, _)| uid.clone())
+            .collect();
+        subscribers.sort();
+
+        subscribers
+    }
+
+    /// Forget peers that are not in `connected`. Returns the connected peers that have
+    /// not been sent the current topics yet
+    fn sync(&mut self, connected: &[String]) -> Vec<String> {
+        self.announced.retain(|uid| connected.contains(uid));
+        self.subscribers.retain(|uid, _| connected.contains(uid));
+
+        connected
+            .iter()
+            .filter(|uid| !self.announced.contains(*uid))
+            .cloned()
+            .collect()
+    }
+
+    /// Handle a control frame received from the peer `uid`. Returns the publication if
+    /// the frame holds one on a subscribed topic
+    fn handle(&mut self, uid: String, frame: &[u8]) -> Result<Option<Publication>, AetherError> {
+        match control::kind(frame)? {
+            Subscriptions::KIND => {
+                let subscriptions: Subscriptions = control::decode(frame)?;
+                self.subscribers
+                    .insert(uid, subscriptions.topics.into_iter().collect());
+                Ok(None)
+            }
+            Publish::KIND => {
+                let publish: Publish = control::decode(frame)?;
+                if !self.topics.contains(&publish.topic) {
+                    return Ok(None);
+                }
+
+                Ok(Some(Publication {
+                    from: uid,
+                    topic: publish.topic,
+                    payload: publish.payload,
+                }))
+            }
+            _ => Err(AetherError::ControlFrame("unknown kind")),
+        }
+    }
+}
+
+/// Publish/subscribe interface over the connections of an [`Aether`] client
+pub struct PubSub {
+    /// Client whose connections are used
+    aether: Arc<Aether>,
+    /// Subscription state shared with the thread receiving messages
+    state: Arc<Mutex<State>>,
+    /// Queue of received publications
+    queue: (Sender<Publication>, Receiver<Publication>),
+    /// Set once the thread receiving messages is started
+    started: AtomicBool,
+}
+
+impl PubSub {
+    /// Creates a [`PubSub`] without any subscriptions. Messages are only received once
+    /// [`PubSub::start`] is called
+    pub fn new(aether: Arc<Aether>) -> PubSub {
+        PubSub {
+            aether,
+            state: Arc::new(Mutex::new(State::default())),
+            queue: unbounded(),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Start receiving messages from peers and exchanging subscriptions with them. The
+    /// thread stops when the [`PubSub`] is dropped
+    pub fn start(&self) {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let aether = self.aether.clone();
+        let weak_state = Arc::downgrade(&self.state);
+        let sender = self.queue.0.clone();
+
+        thread::spawn(move || loop {
+            let received = aether.recv_any_timeout(SYNC_INTERVAL);
+
+            let state = match weak_state.upgrade() {
+                Some(state) => state,
+                None => break,
+            };
+
+            match received {
+                Ok((uid, frame)) => {
+                    let mut state_lock = state.lock().expect("unable to lock pubsub state");
+                    match state_lock.handle(uid, &frame) {
+                        Ok(Some(publication)) => {
+                            let _ = sender.send(publication);
+                        }
+                        Ok(None) => (),
+                        Err(err) => debug!("Dropping message: {}", err),
+                    }
+                }
+                Err(AetherError::RecvTimeout(_)) => (),
+                Err(err) => {
+                    warn!("Unable to receive messages: {}", err);
+                    break;
+                }
+            }
+
+            let connected = match aether.connected_peers() {
+                Ok(connected) => connected,
+                Err(err) => {
+                    warn!("Unable to list connected peers: {}", err);
+                    continue;
+                }
+            };
+
+            let mut state_lock = state.lock().expect("unable to lock pubsub state");
+            let peers = state_lock.sync(&connected);
+            if !peers.is_empty() {
+                announce(&aether, &mut state_lock, peers);
+            }
+        });
+    }
+
+    /// Subscribe to `topic`, receiving bytes published to it by other peers
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - If the subscription state cannot be locked
+    pub fn subscribe(&self, topic: &str) -> Result<(), AetherError> {
+        self.update(|topics| topics.insert(topic.to_string()))
+    }
+
+    /// Unsubscribe from `topic`
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - If the subscription state cannot be locked
+    pub fn unsubscribe(&self, topic: &str) -> Result<(), AetherError> {
+        self.update(|topics| topics.remove(topic))
+    }
+
+    /// Change the subscribed topics using `change`, which returns true if they changed,
+    /// and send them to all connected peers
+    fn update<F: FnOnce(&mut HashSet<String>) -> bool>(
+        &self,
+        change: F,
+    ) -> Result<(), AetherError> {
+        let connected = self.aether.connected_peers()?;
+
+        let mut state_lock = match self.state.lock() {
+            Ok(lock) => lock,
+            Err(_) => return Err(AetherError::MutexLock("pubsub state")),
+        };
+
+        if !change(&mut state_lock.topics) {
+            return Ok(());
+        }
+
+        state_lock.announced.clear();
+        let peers = state_lock.sync(&connected);
+        announce(&self.aether, &mut state_lock, peers);
+
+        Ok(())
+    }
+
+    /// Publish `payload` to `topic`, delivering it to all connected peers subscribed to
+    /// the topic
+    /// # Returns
+    /// * `usize` - Number of peers the bytes were sent to
+    /// # Errors
+    /// * [`AetherError::MessageTooLarge`] - The bytes with the topic are larger than the
+    ///   maximum message size agreed on with a subscriber
+    /// * [`AetherError::ControlFrame`] - If the message cannot be encoded
+    ///
+    /// Subscribers that disconnected are skipped
+    pub fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<usize, AetherError> {
+        let frame = control::encode(&Publish {
+            topic: topic.to_string(),
+            payload,
+        })?;
+
+        let subscribers = match self.state.lock() {
+            Ok(state_lock) => state_lock.subscribers_of(topic),
+            Err(_) => return Err(AetherError::MutexLock("pubsub state")),
+        };
+
+        let mut sent = 0;
+        for uid in subscribers {
+            match self.aether.send_to(&uid, frame.clone()) {
+                Ok(()) => sent += 1,
+                Err(AetherError::NotConnected(_))
+                | Err(AetherError::LinkBroken(_))
+                | Err(AetherError::SessionExpired(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Returns the UIDs of the connected peers subscribed to `topic`
+    /// # Errors
+    /// * [`AetherError::MutexLock`] - If the subscription state cannot be locked
+    pub fn subscribers(&self, topic: &str) -> Result<Vec<String>, AetherError> {
+        match self.state.lock() {
+            Ok(state_lock) => Ok(state_lock.subscribers_of(topic)),
+            Err(_) => Err(AetherError::MutexLock("pubsub state")),
+        }
+    }
+
+    /// Receive bytes published by another peer to a subscribed topic
+    /// # Errors
+    /// * [`AetherError::ChannelRecvError`] - The thread receiving messages has stopped
+    pub fn recv(&self) -> Result<Publication, AetherError> {
+        Ok(self.queue.1.recv()?)
+    }
+
+    /// Receive bytes published to a subscribed topic, waiting at most `timeout`
+    /// # Errors
+    /// * [`AetherError::RecvTimeout`] - Nothing was received within `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Publication, AetherError> {
+        Ok(self.queue.1.recv_timeout(timeout)?)
+    }
+}
+
+/// Send the subscribed topics to `peers`, marking those they were sent to
+fn announce(aether: &Aether, state: &mut State, peers: Vec<String>) {
+    let frame = match state.frame() {
+        Ok(frame) => frame,
+        Err(err) => {
+            warn!("Unable to encode subscriptions: {}", err);
+            return;
+        }
+    };
+
+    for uid in peers {
+        match aether.send_to(&uid, frame.clone()) {
+            Ok(()) => {
+                state.announced.insert(uid);
+            }
+            Err(err) => debug!("Unable to send subscriptions to {}: {}", uid, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Publication, Publish, State, Subscriptions};
+    use crate::wire::control;
+
+    #[test]
+    fn state_test() {
+        let mut state = State::default();
+        state.topics.insert(String::from("news"));
+
+        let first = String::from("first");
+        let second = String::from("second");
+        let connected = vec![first.clone(), second.clone()];
+        assert_eq!(state.sync(&connected), connected);
+        state.announced.insert(first.clone());
+        assert_eq!(state.sync(&connected), vec![second.clone()]);
+
+        let subscriptions = |topics: &[&str]| {
+            control::encode(&Subscriptions {
+                topics: topics.iter().map(|t| t.to_string()).collect(),
+            })
+            .unwrap()
+        };
+        let handled = state.handle(first.clone(), &subscriptions(&["news", "weather"]));
+        assert_eq!(handled.unwrap(), None);
+        let handled = state.handle(second.clone(), &subscriptions(&["weather"]));
+        assert_eq!(handled.unwrap(), None);
+        assert_eq!(state.subscribers_of("weather"), connected);
+        assert_eq!(state.subscribers_of("news"), vec![first.clone()]);
+
+        // Subscriptions replace the previous ones of the peer
+        state.handle(first.clone(), &subscriptions(&[])).unwrap();
+        assert_eq!(state.subscribers_of("news"), Vec::<String>::new());
+
+        let publish = |topic: &str| {
+            control::encode(&Publish {
+                topic: topic.to_string(),
+                payload: b"Hello".to_vec(),
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            state.handle(second.clone(), &publish("news")).unwrap(),
+            Some(Publication {
+                from: second.clone(),
+                topic: String::from("news"),
+                payload: b"Hello".to_vec(),
+            })
+        );
+        // Publications on topics not subscribed to are dropped
+        assert_eq!(
+            state.handle(second.clone(), &publish("weather")).unwrap(),
+            None
+        );
+        assert!(state.handle(second.clone(), b"Hello").is_err());
+
+        // Disconnected peers are forgotten
+        assert!(state.sync(&[first]).is_empty());
+        assert_eq!(state.subscribers_of("weather"), Vec::<String>::new());
+        assert_eq!(state.announced.len(), 1);
+    }
+}
@@ -0,0 +1,265 @@
+//! Sequence numbers of packets on a [`Link`][crate::link::Link] and windows of them
+//! being acknowledged.
+//!
+//! Sequence numbers wrap around once `u32::MAX` is reached, so they are compared using
+//! serial number arithmetic ([RFC 1982](https://www.rfc-editor.org/rfc/rfc1982)): a
+//! sequence number is later than another one if it is less than half the number space
+//! ahead of it. Plain `u32` arithmetic on them overflows, which is why they are kept
+//! in their own type.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
+
+use serde::{Deserialize, Serialize};
+
+use crate::acknowledgement::MAX_WINDOW;
+
+/// Half of the sequence number space. Sequence numbers this far apart cannot be
+/// ordered
+const HALF: u32 = 1 << 31;
+
+/// Sequence number of a packet
+///
+/// Adding to or subtracting from a sequence number wraps around, and sequence numbers
+/// are ordered using serial number arithmetic. Sequence numbers exactly half the
+/// number space apart are not ordered, so [`Seq`] only implements [`PartialOrd`]
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use aether_lib::sequence::Seq;
+///
+/// let last = Seq(u32::MAX);
+/// assert_eq!(last + 1, Seq(0));
+/// assert!(last < last + 1);
+/// assert_eq!((last + 5).distance(last), 5);
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Seq(pub u32);
+
+impl Seq {
+    /// Returns the number of sequence numbers from `earlier` up to this one, wrapping
+    /// around if `earlier` is later than this one
+    pub fn distance(self, earlier: Seq) -> u32 {
+        self.0.wrapping_sub(earlier.0)
+    }
+}
+
+impl Add<u32> for Seq {
+    type Output = Seq;
+
+    fn add(self, rhs: u32) -> Seq {
+        Seq(self.0.wrapping_add(rhs))
+    }
+}
+
+impl AddAssign<u32> for Seq {
+    fn add_assign(&mut self, rhs: u32) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<u32> for Seq {
+    type Output = Seq;
+
+    fn sub(self, rhs: u32) -> Seq {
+        Seq(self.0.wrapping_sub(rhs))
+    }
+}
+
+impl PartialOrd for Seq {
+    fn partial_cmp(&self, other: &Seq) -> Option<Ordering> {
+        match self.distance(*other) {
+            0 => Some(Ordering::Equal),
+            HALF => None,
+            distance if distance < HALF => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+}
+
+impl From<u32> for Seq {
+    fn from(value: u32) -> Seq {
+        Seq(value)
+    }
+}
+
+impl From<Seq> for u32 {
+    fn from(value: Seq) -> u32 {
+        value.0
+    }
+}
+
+impl fmt::Display for Seq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Window of sequence numbers from [`AckWindow::begin`] to [`AckWindow::last`], at
+/// most [`AckWindow::limit`] (by default [`MAX_WINDOW`]) sequence numbers long
+///
+/// Sequence numbers in the window are addressed by their offset from `begin`, as in
+/// the [`Acknowledgement`][crate::acknowledgement::Acknowledgement] format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckWindow {
+    /// First sequence number of the window
+    begin: Seq,
+    /// Offset of the last sequence number of the window from `begin`
+    end: u16,
+    /// Largest offset the window can be extended to
+    limit: u16,
+}
+
+impl AckWindow {
+    /// Creates a window containing only `begin`
+    pub fn new(begin: Seq) -> AckWindow {
+        Self::with_limit(begin, MAX_WINDOW)
+    }
+
+    /// Creates a window containing only `begin`, which can be extended at most `limit`
+    /// sequence numbers after `begin`
+    pub fn with_limit(begin: Seq, limit: u16) -> AckWindow {
+        AckWindow {
+            begin,
+            end: 0,
+            limit,
+        }
+    }
+
+    /// Creates a window from `begin` to `begin + end`. The window is cut to
+    /// [`MAX_WINDOW`] if `end` is larger
+    pub fn with_end(begin: Seq, end: u16) -> AckWindow {
+        AckWindow {
+            begin,
+            end: end.min(MAX_WINDOW),
+            limit: MAX_WINDOW,
+        }
+    }
+
+    /// Returns the first sequence number of the window
+    pub fn begin(&self) -> Seq {
+        self.begin
+    }
+
+    /// Returns the offset of the last sequence number of the window from
+    /// [`AckWindow::begin`]
+    pub fn end(&self) -> u16 {
+        self.end
+    }
+
+    /// Returns the largest offset from [`AckWindow::begin`] the window can be extended to
+    pub fn limit(&self) -> u16 {
+        self.limit
+    }
+
+    /// Returns the last sequence number of the window
+    pub fn last(&self) -> Seq {
+        self.begin + self.end as u32
+    }
+
+    /// Returns the sequence number at `offset` from [`AckWindow::begin`]
+    pub fn at(&self, offset: u16) -> Seq {
+        self.begin + offset as u32
+    }
+
+    /// Returns the offset of `seq` from [`AckWindow::begin`] if the window can be
+    /// extended to include it, that is if it is at most [`AckWindow::limit`] after
+    /// `begin`
+    pub fn offset(&self, seq: Seq) -> Option<u16> {
+        match seq.distance(self.begin) {
+            distance if distance <= self.limit as u32 => Some(distance as u16),
+            _ => None,
+        }
+    }
+
+    /// Returns true if `seq` is in the window
+    pub fn contains(&self, seq: Seq) -> bool {
+        matches!(self.offset(seq), Some(offset) if offset <= self.end)
+    }
+
+    /// Extend the window to include `seq`. Returns false if `seq` is before
+    /// [`AckWindow::begin`] or too far after it, in which case the window is unchanged
+    pub fn extend(&mut self, seq: Seq) -> bool {
+        match self.offset(seq) {
+            Some(offset) => {
+                self.end = self.end.max(offset);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the beginning of the window one sequence number ahead, keeping its last
+    /// sequence number unless the window is empty afterwards
+    pub fn advance(&mut self) {
+        self.begin += 1;
+        self.end = self.end.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AckWindow, Seq};
+    use crate::acknowledgement::MAX_WINDOW;
+
+    #[test]
+    fn seq_test() {
+        let last = Seq(u32::MAX);
+
+        assert_eq!(last + 1, Seq(0));
+        assert_eq!(Seq(0) - 1, last);
+        assert_eq!(Seq(3).distance(last), 4);
+        assert_eq!(last.distance(Seq(3)), u32::MAX - 3);
+
+        assert!(Seq(1) > Seq(0));
+        assert!(Seq(0) > last);
+        assert!(last < Seq(1 << 30));
+        assert!(Seq(10) <= Seq(10));
+        // Sequence numbers half the number space apart are not ordered
+        assert_eq!(Seq(0).partial_cmp(&Seq(1 << 31)), None);
+    }
+
+    #[test]
+    fn ack_window_test() {
+        let begin = Seq(u32::MAX - 1);
+        let mut window = AckWindow::new(begin);
+
+        assert!(window.contains(begin));
+        assert!(!window.contains(begin + 1));
+        assert!(!window.contains(begin - 1));
+
+        // Extending across the wrap around
+        assert!(window.extend(begin + 4));
+        assert_eq!(window.end(), 4);
+        assert_eq!(window.last(), Seq(2));
+        assert_eq!(window.at(2), Seq(0));
+        assert_eq!(window.offset(Seq(1)), Some(3));
+        assert!(window.contains(Seq(1)));
+
+        // Sequence numbers before the window or too far after it are refused
+        assert!(!window.extend(begin - 1));
+        assert!(!window.extend(begin + MAX_WINDOW as u32 + 1));
+        assert!(window.extend(begin + MAX_WINDOW as u32));
+        assert_eq!(window.end(), MAX_WINDOW);
+
+        let mut window = AckWindow::with_end(begin, 1);
+        window.advance();
+        assert_eq!(window, AckWindow::new(begin + 1));
+        window.advance();
+        assert_eq!(window, AckWindow::new(begin + 2));
+
+        assert_eq!(AckWindow::with_end(begin, u16::MAX).end(), MAX_WINDOW);
+
+        // Constrained receivers limit the window
+        let mut window = AckWindow::with_limit(begin, 3);
+        assert!(!window.extend(begin + 4));
+        assert!(window.extend(begin + 3));
+        window.advance();
+        assert!(window.extend(begin + 4));
+        assert_eq!(window.limit(), 3);
+    }
+}
@@ -0,0 +1,592 @@
+//! Runtime statistics of the Aether protocol.
+//!
+//! Values are recorded into [`Histogram`]s with logarithmic buckets (similar to HDR
+//! histograms) so that tail latencies can be inspected and not just averages.
+//! Each bucket covers a range of values within [`SUB_BUCKET_BITS`] significant bits of
+//! precision, which keeps the error of a reported value below 12.5%.
+//!
+//! Events that only need to be counted are recorded in [`RejectionCounters`],
+//! [`LinkCounters`] and [`TrackerCounters`].
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Number of significant bits kept for each recorded value
+pub const SUB_BUCKET_BITS: u32 = 3;
+
+/// Largest value that can be recorded is `2^MAX_VALUE_BITS - 1`, larger values are
+/// recorded as this value
+pub const MAX_VALUE_BITS: u32 = 40;
+
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKET_COUNT: usize = (MAX_VALUE_BITS - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+const MAX_VALUE: u64 = (1 << MAX_VALUE_BITS) - 1;
+
+/// Histogram of values recorded with logarithmic buckets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Number of values recorded in each bucket
+    counts: Vec<u64>,
+    /// Total number of values recorded
+    count: u64,
+    /// Sum of all values recorded
+    sum: u64,
+    /// Smallest value recorded
+    min: u64,
+    /// Largest value recorded
+    max: u64,
+}
+
+impl Histogram {
+    /// Creates a new empty [`Histogram`]
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; BUCKET_COUNT],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Index of the bucket the given value falls in
+    fn index(value: u64) -> usize {
+        if value < SUB_BUCKETS as u64 {
+            return value as usize;
+        }
+
+        let exponent = 63 - value.leading_zeros();
+        let shift = exponent - SUB_BUCKET_BITS;
+        let mantissa = (value >> shift) as usize & (SUB_BUCKETS - 1);
+
+        (shift as usize + 1) * SUB_BUCKETS + mantissa
+    }
+
+    /// Smallest value that falls in the bucket with the given index
+    fn lowest_value(index: usize) -> u64 {
+        if index < SUB_BUCKETS {
+            return index as u64;
+        }
+
+        let shift = index / SUB_BUCKETS - 1;
+        let mantissa = index % SUB_BUCKETS;
+
+        ((SUB_BUCKETS + mantissa) as u64) << shift
+    }
+
+    /// Record a value into the histogram
+    ///
+    /// # Arguments
+    ///
+    /// * `value`   -   The value to be recorded
+    pub fn record(&mut self, value: u64) {
+        let value = value.min(MAX_VALUE);
+
+        self.counts[Self::index(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Add all values recorded in `other` to this histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Returns the number of values recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the smallest value recorded, `0` if nothing has been recorded
+    pub fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    /// Returns the largest value recorded
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the mean of the values recorded
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Returns the value below which the given percentage of recorded values fall
+    ///
+    /// # Arguments
+    ///
+    /// * `percentile`  -   Percentile to look up between `0.0` and `100.0`
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let percentile = percentile.clamp(0.0, 100.0);
+        let target = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+
+        let mut total = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            total += count;
+            if total >= target {
+                // Report the highest value of the bucket, bounded by the values seen
+                let highest = Self::lowest_value(index + 1) - 1;
+                return highest.clamp(self.min, self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collection of the [`Histogram`]s recorded by Aether
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histograms {
+    /// Round trip time samples (in us)
+    pub rtt_us: Histogram,
+    /// Size of packets sent and received (in bytes)
+    pub packet_size: Histogram,
+    /// Time between receiving a packet and sending its acknowledgement (in us)
+    pub ack_delay_us: Histogram,
+    /// Time taken by successful handshakes (in ms)
+    pub handshake_duration_ms: Histogram,
+}
+
+impl Histograms {
+    /// Creates a new set of empty [`Histograms`]
+    pub fn new() -> Histograms {
+        Histograms::default()
+    }
+
+    /// Add all values recorded in `other` to these histograms
+    pub fn merge(&mut self, other: &Histograms) {
+        self.rtt_us.merge(&other.rtt_us);
+        self.packet_size.merge(&other.packet_size);
+        self.ack_delay_us.merge(&other.ack_delay_us);
+        self.handshake_duration_ms
+            .merge(&other.handshake_duration_ms);
+    }
+}
+
+/// Counters of handshake attempts rejected before the other peer's address was
+/// validated. Shared between the threads performing handshakes
+#[derive(Debug, Default)]
+pub struct RejectionCounters {
+    unexpected_address: AtomicU64,
+    invalid_cookie: AtomicU64,
+}
+
+/// Snapshot of the [`RejectionCounters`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rejections {
+    /// Packets received from an address other than the expected peer address
+    pub unexpected_address: u64,
+    /// Hellos echoing a cookie that was never sent to the peer
+    pub invalid_cookie: u64,
+}
+
+impl RejectionCounters {
+    /// Creates a new set of [`RejectionCounters`] set to zero
+    pub fn new() -> RejectionCounters {
+        RejectionCounters::default()
+    }
+
+    /// Count a packet received from an unexpected address
+    pub fn reject_address(&self) {
+        self.unexpected_address.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a hello with an invalid cookie echo
+    pub fn reject_cookie(&self) {
+        self.invalid_cookie.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the counters
+    pub fn snapshot(&self) -> Rejections {
+        Rejections {
+            unexpected_address: self.unexpected_address.load(Ordering::Relaxed),
+            invalid_cookie: self.invalid_cookie.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Diagnostics of the polls sent to the tracker server. Shared between the threads
+/// communicating with it
+#[derive(Debug, Default)]
+pub struct TrackerCounters {
+    polls: AtomicU64,
+    responses: AtomicU64,
+    consecutive_failures: AtomicU32,
+    /// Latency of answered polls (in us) and the time of the last answer
+    latency: Mutex<(Histogram, Option<SystemTime>)>,
+}
+
+/// Snapshot of the [`TrackerCounters`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackerStats {
+    /// Polls sent to the tracker server
+    pub polls: u64,
+    /// Packets received from the tracker server, answering polls or other requests
+    pub responses: u64,
+    /// Polls left unanswered in a row since the tracker server last answered
+    pub consecutive_failures: u32,
+    /// Time between sending a poll and receiving its answer (in us)
+    pub latency_us: Histogram,
+    /// Time the tracker server last answered, [`None`] if it never did
+    pub last_success: Option<SystemTime>,
+}
+
+impl TrackerCounters {
+    /// Creates a new set of [`TrackerCounters`] set to zero
+    pub fn new() -> TrackerCounters {
+        TrackerCounters::default()
+    }
+
+    /// Count a poll sent to the tracker server
+    pub fn poll(&self) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a packet received from the tracker server, `latency` after sending the
+    /// poll it answers if it is an answer to a poll. Returns the number of polls that
+    /// were left unanswered in a row before
+    pub fn success(&self, latency: Option<Duration>) -> u32 {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+
+        let mut latency_lock = self.latency.lock().expect("Unable to lock tracker latency");
+        if let Some(latency) = latency {
+            latency_lock.0.record(latency.as_micros() as u64);
+        }
+        latency_lock.1 = Some(SystemTime::now());
+        drop(latency_lock);
+
+        self.consecutive_failures.swap(0, Ordering::Relaxed)
+    }
+
+    /// Count a poll left unanswered. Returns the number of polls left unanswered in a
+    /// row, including this one
+    pub fn failure(&self) -> u32 {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Returns the current values of the counters
+    pub fn snapshot(&self) -> TrackerStats {
+        let latency_lock = self.latency.lock().expect("Unable to lock tracker latency");
+        let (latency_us, last_success) = latency_lock.clone();
+        drop(latency_lock);
+
+        TrackerStats {
+            polls: self.polls.load(Ordering::Relaxed),
+            responses: self.responses.load(Ordering::Relaxed),
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+            latency_us,
+            last_success,
+        }
+    }
+}
+
+/// Counters of the traffic on a [`Link`][crate::link::Link]. Shared between the
+/// threads of the link
+#[derive(Debug, Default)]
+pub struct LinkCounters {
+    packets_sent: AtomicU64,
+    packets_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    reliable_sent: AtomicU64,
+    ack_only: AtomicU64,
+    retransmissions: AtomicU64,
+    suppressed: AtomicU64,
+    nacks_sent: AtomicU64,
+    nack_retransmissions: AtomicU64,
+    replays: AtomicU64,
+    forgeries: AtomicU64,
+    expired: AtomicU64,
+}
+
+/// Statistics of a [`Link`][crate::link::Link]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkStats {
+    /// Packets sent to the other peer, including retransmissions and acknowledgements
+    pub packets_sent: u64,
+    /// Well formed packets received from the other peer
+    pub packets_received: u64,
+    /// Bytes sent to the other peer
+    pub bytes_sent: u64,
+    /// Bytes received in well formed packets from the other peer
+    pub bytes_received: u64,
+    /// Packets that needed acknowledgement and were sent again
+    pub retransmissions: u64,
+    /// Retransmissions skipped because the packet was still in flight
+    pub suppressed_retransmissions: u64,
+    /// NACK packets sent to request missing packets from the other peer
+    pub nacks_sent: u64,
+    /// Retransmissions requested by NACK packets of the other peer, included in
+    /// `retransmissions`
+    pub nack_retransmissions: u64,
+    /// Packets dropped because they were received before, as when captured packets are
+    /// injected again (refer [`ReplayWindow`][crate::link::replay::ReplayWindow])
+    pub replays_dropped: u64,
+    /// Packets dropped because their authentication tag was missing or wrong, as when
+    /// captured packets are altered (refer
+    /// [`has_packet_tags`][crate::packet::has_packet_tags]), or because their encrypted
+    /// payload could not be decrypted
+    pub forgeries_dropped: u64,
+    /// Messages dropped because their deadline passed before they were delivered
+    /// (refer [`Link::send_with_deadline`][crate::link::Link::send_with_deadline])
+    pub expired: u64,
+    /// Acknowledgement only packets sent, for acknowledgements that could not be sent
+    /// along with other packets
+    pub ack_only_packets: u64,
+    /// Smoothed round trip time (in us), 0 if no sample was taken yet
+    pub rtt_us: u64,
+    /// Fraction of the packets needing acknowledgement that were retransmitted
+    pub loss_rate: f64,
+}
+
+impl LinkCounters {
+    /// Creates a new set of [`LinkCounters`] set to zero
+    pub fn new() -> LinkCounters {
+        LinkCounters::default()
+    }
+
+    /// Count a packet of `size` bytes sent to the other peer. `reliable` is set if
+    /// the packet needs to be acknowledged
+    pub fn sent(&self, size: usize, reliable: bool) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+        if reliable {
+            self.reliable_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a packet of `size` bytes received from the other peer
+    pub fn received(&self, size: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of packets received so far
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received.load(Ordering::Relaxed)
+    }
+
+    /// Count an acknowledgement only packet sent to the other peer, in addition to
+    /// [`LinkCounters::sent`]
+    pub fn ack_only(&self) {
+        self.ack_only.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of packets needing acknowledgement sent so far
+    pub fn reliable_sent(&self) -> u64 {
+        self.reliable_sent.load(Ordering::Relaxed)
+    }
+
+    /// Count a packet needing acknowledgement being sent again
+    pub fn retransmit(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a NACK packet sent to the other peer, in addition to [`LinkCounters::sent`]
+    pub fn nack(&self) {
+        self.nacks_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a retransmission requested by the other peer, in addition to
+    /// [`LinkCounters::retransmit`]
+    pub fn nack_retransmit(&self) {
+        self.nack_retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet dropped because it was received before
+    pub fn replay(&self) {
+        self.replays.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet dropped because its authentication tag was missing or wrong, or
+    /// its payload could not be decrypted
+    pub fn forgery(&self) {
+        self.forgeries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a message dropped because its deadline passed
+    pub fn expire(&self) {
+        self.expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a retransmission skipped because the packet was sent recently
+    pub fn suppress(&self) {
+        self.suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the counters, with `rtt_us` as the current
+    /// round trip time estimate
+    pub fn snapshot(&self, rtt_us: u64) -> LinkStats {
+        let reliable_sent = self.reliable_sent.load(Ordering::Relaxed);
+        let retransmissions = self.retransmissions.load(Ordering::Relaxed);
+
+        let loss_rate = if reliable_sent == 0 {
+            0.0
+        } else {
+            retransmissions as f64 / reliable_sent as f64
+        };
+
+        LinkStats {
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            retransmissions,
+            suppressed_retransmissions: self.suppressed.load(Ordering::Relaxed),
+            nacks_sent: self.nacks_sent.load(Ordering::Relaxed),
+            nack_retransmissions: self.nack_retransmissions.load(Ordering::Relaxed),
+            replays_dropped: self.replays.load(Ordering::Relaxed),
+            forgeries_dropped: self.forgeries.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            ack_only_packets: self.ack_only.load(Ordering::Relaxed),
+            rtt_us,
+            loss_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Histogram, Histograms, LinkCounters, RejectionCounters, TrackerCounters};
+
+    #[test]
+    fn bucket_test() {
+        for value in [0, 1, 7, 8, 9, 100, 1_000, 123_456, 1 << 39] {
+            let index = Histogram::index(value);
+            assert!(Histogram::lowest_value(index) <= value);
+            assert!(value < Histogram::lowest_value(index + 1));
+        }
+    }
+
+    #[test]
+    fn percentile_test() {
+        let mut histogram = Histogram::new();
+
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.min(), 1);
+        assert_eq!(histogram.max(), 1000);
+        assert!((histogram.mean() - 500.5).abs() < f64::EPSILON);
+
+        let p50 = histogram.value_at_percentile(50.0);
+        assert!((500..=563).contains(&p50));
+
+        let p99 = histogram.value_at_percentile(99.0);
+        assert!((990..=1000).contains(&p99));
+
+        assert_eq!(histogram.value_at_percentile(100.0), 1000);
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut histograms = Histograms::new();
+        let mut other = Histograms::new();
+
+        histograms.rtt_us.record(10);
+        other.rtt_us.record(1_000);
+        other.packet_size.record(64);
+
+        histograms.merge(&other);
+
+        assert_eq!(histograms.rtt_us.count(), 2);
+        assert_eq!(histograms.rtt_us.max(), 1_000);
+        assert_eq!(histograms.packet_size.count(), 1);
+        assert_eq!(histograms.handshake_duration_ms.count(), 0);
+    }
+
+    #[test]
+    fn rejection_test() {
+        let counters = RejectionCounters::new();
+
+        counters.reject_address();
+        counters.reject_address();
+        counters.reject_cookie();
+
+        let rejections = counters.snapshot();
+        assert_eq!(rejections.unexpected_address, 2);
+        assert_eq!(rejections.invalid_cookie, 1);
+    }
+
+    #[test]
+    fn tracker_counters_test() {
+        let counters = TrackerCounters::new();
+        assert_eq!(counters.snapshot().last_success, None);
+
+        counters.poll();
+        assert_eq!(counters.failure(), 1);
+        counters.poll();
+        assert_eq!(counters.failure(), 2);
+        counters.poll();
+        assert_eq!(counters.success(Some(Duration::from_millis(20))), 2);
+        assert_eq!(counters.success(None), 0);
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.polls, 3);
+        assert_eq!(stats.responses, 2);
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(stats.latency_us.count(), 1);
+        assert!(stats.last_success.is_some());
+    }
+
+    #[test]
+    fn link_counters_test() {
+        let counters = LinkCounters::new();
+        assert_eq!(counters.snapshot(0).loss_rate, 0.0);
+
+        for _ in 0..4 {
+            counters.sent(100, true);
+        }
+        counters.sent(20, false);
+        counters.ack_only();
+        counters.retransmit();
+        counters.suppress();
+        counters.received(50);
+
+        let stats = counters.snapshot(1500);
+        assert_eq!(stats.packets_sent, 5);
+        assert_eq!(stats.bytes_sent, 420);
+        assert_eq!(stats.packets_received, 1);
+        assert_eq!(stats.bytes_received, 50);
+        assert_eq!(stats.retransmissions, 1);
+        assert_eq!(stats.suppressed_retransmissions, 1);
+        assert_eq!(stats.ack_only_packets, 1);
+        assert_eq!(counters.reliable_sent(), 4);
+        assert_eq!(stats.rtt_us, 1500);
+        assert_eq!(stats.loss_rate, 0.25);
+    }
+}
@@ -0,0 +1,278 @@
+//! Locks shared between the threads of Aether.
+//!
+//! [`Mutex`] behaves like [`std::sync::Mutex`] but carries the name of the data it
+//! protects. With the `lock-profiling` feature, every lock records how often it was
+//! acquired, how often a thread had to wait for it and for how long. Locks with the
+//! same name (such as the acknowledgement lists of all links) are counted together.
+//! The most contended locks are logged every [`REPORT_INTERVAL`] and can be read with
+//! [`report`].
+//!
+//! Without the feature, [`Mutex`] only forwards to [`std::sync::Mutex`].
+
+use std::fmt;
+use std::sync::{LockResult, MutexGuard, TryLockResult};
+
+#[cfg(feature = "lock-profiling")]
+pub use profiling::{report, LockReport, REPORT_INTERVAL, REPORT_SIZE};
+
+/// Mutual exclusion lock named after the data it protects
+///
+/// # Examples
+///
+/// ```
+/// use aether_lib::sync::Mutex;
+///
+/// let counter = Mutex::new("counter", 0);
+/// *counter.lock().unwrap() += 1;
+///
+/// assert_eq!(*counter.lock().unwrap(), 1);
+/// assert_eq!(counter.name(), "counter");
+/// ```
+pub struct Mutex<T> {
+    /// Name of the lock
+    name: &'static str,
+    /// Contention of all locks with this name
+    #[cfg(feature = "lock-profiling")]
+    profile: std::sync::Arc<profiling::LockProfile>,
+    /// The lock itself
+    inner: std::sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Creates a new [`Mutex`] protecting `value`
+    ///
+    /// # Arguments
+    ///
+    /// * `name`    -   Name the lock is reported as, shared by all locks protecting
+    ///   the same kind of data
+    /// * `value`   -   Value to be protected
+    pub fn new(name: &'static str, value: T) -> Mutex<T> {
+        Mutex {
+            name,
+            #[cfg(feature = "lock-profiling")]
+            profile: profiling::LockProfile::get(name),
+            inner: std::sync::Mutex::new(value),
+        }
+    }
+
+    /// Returns the name of the lock
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Acquire the lock, blocking the current thread until it is available. See
+    /// [`std::sync::Mutex::lock`]
+    #[cfg(not(feature = "lock-profiling"))]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.inner.lock()
+    }
+
+    /// Acquire the lock, blocking the current thread until it is available. See
+    /// [`std::sync::Mutex::lock`]
+    #[cfg(feature = "lock-profiling")]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        use std::sync::TryLockError;
+        use std::time::Instant;
+
+        match self.inner.try_lock() {
+            Ok(guard) => {
+                self.profile.acquired(None);
+                Ok(guard)
+            }
+            Err(TryLockError::Poisoned(err)) => Err(err),
+            Err(TryLockError::WouldBlock) => {
+                let start = Instant::now();
+                let result = self.inner.lock();
+                self.profile.acquired(Some(start.elapsed()));
+                result
+            }
+        }
+    }
+
+    /// Attempt to acquire the lock without blocking. See [`std::sync::Mutex::try_lock`]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("name", &self.name)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(feature = "lock-profiling")]
+mod profiling {
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, Once};
+    use std::thread;
+    use std::time::Duration;
+
+    use tracing::info;
+
+    /// Interval in which the most contended locks are logged
+    pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Number of locks included in a logged report
+    pub const REPORT_SIZE: usize = 5;
+
+    /// Profiles of all locks by their name, created by [`profiles`]
+    static PROFILES: AtomicPtr<Mutex<Vec<Arc<LockProfile>>>> = AtomicPtr::new(ptr::null_mut());
+
+    /// Creates the profiles and starts the thread logging reports
+    static INIT: Once = Once::new();
+
+    /// Returns the profiles of all locks
+    fn profiles() -> &'static Mutex<Vec<Arc<LockProfile>>> {
+        INIT.call_once(|| {
+            let profiles = Box::new(Mutex::new(Vec::new()));
+            PROFILES.store(Box::into_raw(profiles), Ordering::SeqCst);
+
+            thread::spawn(|| loop {
+                thread::sleep(REPORT_INTERVAL);
+                log_report();
+            });
+        });
+
+        // Set once above and never freed
+        unsafe { &*PROFILES.load(Ordering::SeqCst) }
+    }
+
+    /// Contention of all locks with the same name
+    #[derive(Debug)]
+    pub struct LockProfile {
+        name: &'static str,
+        acquisitions: AtomicU64,
+        contentions: AtomicU64,
+        wait_ns: AtomicU64,
+        max_wait_ns: AtomicU64,
+    }
+
+    impl LockProfile {
+        /// Returns the profile of locks named `name`, creating it if this is the first
+        /// such lock
+        pub fn get(name: &'static str) -> Arc<LockProfile> {
+            let mut profiles = profiles().lock().expect("Unable to lock lock profiles");
+            match profiles.iter().find(|profile| profile.name == name) {
+                Some(profile) => profile.clone(),
+                None => {
+                    let profile = Arc::new(LockProfile {
+                        name,
+                        acquisitions: AtomicU64::new(0),
+                        contentions: AtomicU64::new(0),
+                        wait_ns: AtomicU64::new(0),
+                        max_wait_ns: AtomicU64::new(0),
+                    });
+                    profiles.push(profile.clone());
+                    profile
+                }
+            }
+        }
+
+        /// Record that a lock was acquired, after waiting for `wait` if it was held by
+        /// another thread
+        pub fn acquired(&self, wait: Option<Duration>) {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+
+            if let Some(wait) = wait {
+                let wait_ns = wait.as_nanos() as u64;
+                self.contentions.fetch_add(1, Ordering::Relaxed);
+                self.wait_ns.fetch_add(wait_ns, Ordering::Relaxed);
+                self.max_wait_ns.fetch_max(wait_ns, Ordering::Relaxed);
+            }
+        }
+
+        fn snapshot(&self) -> LockReport {
+            LockReport {
+                name: self.name,
+                acquisitions: self.acquisitions.load(Ordering::Relaxed),
+                contentions: self.contentions.load(Ordering::Relaxed),
+                wait: Duration::from_nanos(self.wait_ns.load(Ordering::Relaxed)),
+                max_wait: Duration::from_nanos(self.max_wait_ns.load(Ordering::Relaxed)),
+            }
+        }
+    }
+
+    /// Contention of all locks with the same name since the start of the process
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LockReport {
+        /// Name of the locks
+        pub name: &'static str,
+        /// Number of times the locks were acquired
+        pub acquisitions: u64,
+        /// Number of times a thread had to wait for one of the locks
+        pub contentions: u64,
+        /// Total time threads waited for the locks
+        pub wait: Duration,
+        /// Longest time a thread waited for one of the locks
+        pub max_wait: Duration,
+    }
+
+    /// Returns the contention of all locks, the most contended (by total wait) first
+    pub fn report() -> Vec<LockReport> {
+        let profiles = profiles().lock().expect("Unable to lock lock profiles");
+        let mut report: Vec<LockReport> =
+            profiles.iter().map(|profile| profile.snapshot()).collect();
+        drop(profiles);
+
+        report.sort_by_key(|lock| std::cmp::Reverse(lock.wait));
+        report
+    }
+
+    /// Log the [`REPORT_SIZE`] most contended locks
+    fn log_report() {
+        for lock in report()
+            .iter()
+            .filter(|lock| lock.contentions > 0)
+            .take(REPORT_SIZE)
+        {
+            info!(
+                lock = lock.name,
+                acquisitions = lock.acquisitions,
+                contentions = lock.contentions,
+                wait_us = lock.wait.as_micros() as u64,
+                max_wait_us = lock.max_wait.as_micros() as u64,
+                "Lock contention"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Mutex;
+
+    #[test]
+    fn mutex_test() {
+        let mutex = Arc::new(Mutex::new("sync.test", 0));
+
+        let guard = mutex.lock().unwrap();
+        let mutex_clone = mutex.clone();
+        let handle = thread::spawn(move || *mutex_clone.lock().unwrap() += 1);
+
+        thread::sleep(Duration::from_millis(100));
+        drop(guard);
+        handle.join().unwrap();
+
+        assert_eq!(*mutex.lock().unwrap(), 1);
+        assert!(mutex.try_lock().is_ok());
+
+        #[cfg(feature = "lock-profiling")]
+        {
+            let report = super::report();
+            let lock = report.iter().find(|lock| lock.name == "sync.test").unwrap();
+
+            assert_eq!(lock.acquisitions, 3);
+            assert_eq!(lock.contentions, 1);
+            assert!(lock.wait >= Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Hooks to export the internals of Aether to other observability stacks.
+//!
+//! A [`Telemetry`] receives events, counters and spans from key points such as the
+//! phases of a handshake, retransmissions and the key exchange. All methods default to
+//! doing nothing, so implementations only need to handle what they want to export.
+//!
+//! [`NoopTelemetry`] is used unless another implementation is set using
+//! [`Aether::set_telemetry`][crate::peer::Aether::set_telemetry]. [`TracingTelemetry`]
+//! reports everything as [`tracing`] events, which are forwarded to [`log`](https://docs.rs/log)
+//! if no tracing subscriber is installed.
+//!
+//! Messages logged in hot paths go through a [`LogLimiter`], so packet storms cannot
+//! flood the logs.
+
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace};
+
+use crate::config::TelemetryConfig;
+use crate::sequence::Seq;
+
+/// Number of handshakes completed
+pub const COUNTER_HANDSHAKES: &str = "aether.handshakes";
+/// Number of handshakes that failed
+pub const COUNTER_HANDSHAKE_FAILURES: &str = "aether.handshake_failures";
+/// Number of packets sent again
+pub const COUNTER_RETRANSMISSIONS: &str = "aether.retransmissions";
+/// Number of messages dropped because their deadline passed
+pub const COUNTER_EXPIRED: &str = "aether.expired";
+
+/// Span of a handshake, from the first hello until the link is started
+pub const SPAN_HANDSHAKE: &str = "handshake";
+/// Span of the authentication of the other peer
+pub const SPAN_AUTHENTICATION: &str = "authentication";
+/// Span of the key exchange enabling encryption on a link
+pub const SPAN_KEY_EXCHANGE: &str = "key_exchange";
+
+/// Phases of connecting to another peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakePhase {
+    /// Sending hellos until a valid hello of the other peer is received
+    Hello,
+    /// Waiting for the other peer to acknowledge the hello
+    Acknowledge,
+    /// Link started, proving the identities of the peers
+    Authentication,
+    /// Exchanging secrets to encrypt the link
+    KeyExchange,
+}
+
+/// Events reported to a [`Telemetry`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryEvent<'a> {
+    /// A handshake with the peer with uid `peer` entered `phase`
+    HandshakePhase {
+        peer: &'a str,
+        phase: HandshakePhase,
+    },
+    /// The packet with `sequence` is sent to `peer_addr` again
+    Retransmit {
+        peer_addr: SocketAddr,
+        sequence: Seq,
+    },
+    /// The packet with `sequence` to `peer_addr` is dropped because its deadline passed
+    Expired {
+        peer_addr: SocketAddr,
+        sequence: Seq,
+    },
+    /// The link to `peer_addr` is now encrypted using `cipher`
+    KeyExchange {
+        peer_addr: SocketAddr,
+        cipher: &'static str,
+    },
+}
+
+/// Receiver of the telemetry of Aether
+pub trait Telemetry: Debug + Send + Sync {
+    /// Report an event
+    fn event(&self, _event: &TelemetryEvent) {}
+
+    /// Increase the counter `name` by `value`
+    fn counter(&self, _name: &'static str, _value: u64) {}
+
+    /// The span `name` started
+    fn span_start(&self, _name: &'static str) {}
+
+    /// The span `name` ended after `elapsed`
+    fn span_end(&self, _name: &'static str, _elapsed: Duration) {}
+}
+
+/// Start the span `name`, which ends when the returned [`TelemetrySpan`] is dropped
+pub fn span(telemetry: &Arc<dyn Telemetry>, name: &'static str) -> TelemetrySpan {
+    telemetry.span_start(name);
+
+    TelemetrySpan {
+        telemetry: telemetry.clone(),
+        name,
+        start: Instant::now(),
+    }
+}
+
+/// Guard of a span started using [`span`]
+#[derive(Debug)]
+pub struct TelemetrySpan {
+    telemetry: Arc<dyn Telemetry>,
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for TelemetrySpan {
+    fn drop(&mut self) {
+        self.telemetry.span_end(self.name, self.start.elapsed());
+    }
+}
+
+/// [`Telemetry`] discarding everything
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopTelemetry;
+
+impl Telemetry for NoopTelemetry {}
+
+/// [`Telemetry`] reporting everything as [`tracing`] events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingTelemetry;
+
+impl Telemetry for TracingTelemetry {
+    fn event(&self, event: &TelemetryEvent) {
+        debug!(?event, "Telemetry event");
+    }
+
+    fn counter(&self, name: &'static str, value: u64) {
+        trace!(counter = name, value, "Telemetry counter");
+    }
+
+    fn span_start(&self, name: &'static str) {
+        trace!(span = name, "Telemetry span started");
+    }
+
+    fn span_end(&self, name: &'static str, elapsed: Duration) {
+        debug!(
+            span = name,
+            elapsed_us = elapsed.as_micros() as u64,
+            "Telemetry span ended"
+        );
+    }
+}
+
+/// Limits how often a hot path logs a message (refer [`TelemetryConfig`])
+///
+/// At most [`TelemetryConfig::log_burst`] messages are logged per interval, of the rest
+/// only every [`TelemetryConfig::log_sample`]-th is logged. The number of messages
+/// suppressed is reported along with the next message logged
+#[derive(Debug, Clone)]
+pub struct LogLimiter {
+    config: TelemetryConfig,
+    /// Start of the current interval
+    interval_start: Instant,
+    /// Messages seen in the current interval
+    seen: u64,
+    /// Messages suppressed since the last one logged
+    suppressed: u64,
+}
+
+impl LogLimiter {
+    pub fn new(config: TelemetryConfig) -> LogLimiter {
+        LogLimiter {
+            config,
+            interval_start: Instant::now(),
+            seen: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns the number of messages suppressed since the last one logged if the next
+    /// message is to be logged, [`None`] if it is suppressed
+    pub fn check(&mut self) -> Option<u64> {
+        if self.config.log_burst == 0 {
+            return Some(0);
+        }
+
+        if self.interval_start.elapsed() >= Duration::from_millis(self.config.log_interval) {
+            self.interval_start = Instant::now();
+            self.seen = 0;
+        }
+        self.seen += 1;
+
+        let burst = self.config.log_burst as u64;
+        let sample = self.config.log_sample as u64;
+        if self.seen <= burst || (sample > 0 && (self.seen - burst) % sample == 0) {
+            Some(std::mem::take(&mut self.suppressed))
+        } else {
+            self.suppressed += 1;
+            None
+        }
+    }
+}
+
+/// Log a message at `level` (such as `warn`) if the [`LogLimiter`] allows it, preceded
+/// by the number of messages it suppressed before. The message takes the arguments of
+/// the [`tracing`] macro of the level
+macro_rules! limited {
+    ($level:ident, $limiter:expr, $($arg:tt)+) => {
+        if let Some(suppressed) = $limiter.check() {
+            if suppressed > 0 {
+                tracing::$level!(suppressed, "{} messages suppressed", suppressed);
+            }
+            tracing::$level!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use limited;
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{
+        span, LogLimiter, Telemetry, TelemetryEvent, COUNTER_RETRANSMISSIONS, SPAN_HANDSHAKE,
+    };
+    use crate::config::TelemetryConfig;
+    use crate::sequence::Seq;
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        records: Mutex<Vec<String>>,
+    }
+
+    impl Telemetry for Recorder {
+        fn event(&self, event: &TelemetryEvent) {
+            self.records.lock().unwrap().push(format!("{:?}", event));
+        }
+
+        fn counter(&self, name: &'static str, value: u64) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", name, value));
+        }
+
+        fn span_end(&self, name: &'static str, _elapsed: Duration) {
+            self.records.lock().unwrap().push(format!("end {}", name));
+        }
+    }
+
+    #[test]
+    fn telemetry_test() {
+        let recorder = Arc::new(Recorder::default());
+        let telemetry: Arc<dyn Telemetry> = recorder.clone();
+
+        let guard = span(&telemetry, SPAN_HANDSHAKE);
+        telemetry.event(&TelemetryEvent::Retransmit {
+            peer_addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            sequence: Seq(7),
+        });
+        telemetry.counter(COUNTER_RETRANSMISSIONS, 1);
+        drop(guard);
+
+        let records = recorder.records.lock().unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].contains("sequence: Seq(7)"));
+        assert_eq!(records[1], "aether.retransmissions 1");
+        assert_eq!(records[2], "end handshake");
+    }
+
+    #[test]
+    fn log_limiter_test() {
+        let mut limiter = LogLimiter::new(TelemetryConfig {
+            log_burst: 3,
+            log_interval: 50,
+            log_sample: 5,
+        });
+
+        let logged: Vec<_> = (0..13).map(|_| limiter.check()).collect();
+        assert_eq!(
+            logged,
+            vec![
+                Some(0),
+                Some(0),
+                Some(0),
+                None,
+                None,
+                None,
+                None,
+                // Every fifth message after the burst is sampled
+                Some(4),
+                None,
+                None,
+                None,
+                None,
+                Some(4),
+            ]
+        );
+
+        // The suppressed messages are reported in the next interval
+        limiter.check();
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.check(), Some(1));
+        assert_eq!(limiter.check(), Some(0));
+
+        let mut unlimited = LogLimiter::new(TelemetryConfig {
+            log_burst: 0,
+            ..Default::default()
+        });
+        assert!((0..1000).all(|_| unlimited.check() == Some(0)));
+    }
+}
@@ -0,0 +1,130 @@
+//! [`Transport`] deciding what happens to each sent packet with a closure.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::transport::Transport;
+
+/// What a [`FilteringTransport`] does with a sent packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Send the packet as it is
+    Send,
+    /// Lose the packet
+    Drop,
+    /// Send the given bytes instead of the packet
+    Replace(Vec<u8>),
+    /// Send the packet, followed by the given bytes
+    Inject(Vec<u8>),
+}
+
+/// [`Transport`] wrapping another one and passing every sent packet to a filter, which
+/// decides whether the packet is sent, lost or altered (refer [`Verdict`]). Useful to
+/// check how a [`Link`][crate::link::Link] copes with specific packets being lost,
+/// corrupted or forged, where [`SimulatedTransport`][super::SimulatedTransport] affects
+/// packets at random. Received packets are passed through as they are
+pub struct FilteringTransport<T, F> {
+    inner: T,
+    filter: F,
+}
+
+impl<T, F> FilteringTransport<T, F>
+where
+    T: Transport,
+    F: Fn(&[u8]) -> Verdict + Send + Sync,
+{
+    /// Wrap `inner` in a transport passing the packets sent through it to `filter`
+    pub fn new(inner: T, filter: F) -> FilteringTransport<T, F> {
+        FilteringTransport { inner, filter }
+    }
+}
+
+impl<T: Debug, F> Debug for FilteringTransport<T, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilteringTransport")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, F> Transport for FilteringTransport<T, F>
+where
+    T: Transport,
+    F: Fn(&[u8]) -> Verdict + Send + Sync,
+{
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        // Like UDP, lost and altered packets are sent successfully as far as the sender
+        // can tell
+        match (self.filter)(buf) {
+            Verdict::Send => self.inner.send_to(buf, addr),
+            Verdict::Drop => Ok(buf.len()),
+            Verdict::Replace(bytes) => self.inner.send_to(&bytes, addr).map(|_| buf.len()),
+            Verdict::Inject(bytes) => {
+                let size = self.inner.send_to(buf, addr)?;
+                self.inner.send_to(&bytes, addr)?;
+                Ok(size)
+            }
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.recv(buf)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FilteringTransport, Verdict};
+    use crate::test_util::MemoryNetwork;
+    use crate::transport::Transport;
+
+    #[test]
+    fn filter_test() {
+        let network = MemoryNetwork::new();
+        let (a, b) = network.pair();
+        let addr = b.local_addr().unwrap();
+        b.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0; 16];
+
+        let filtering = FilteringTransport::new(a, |buf: &[u8]| match buf {
+            b"lost" => Verdict::Drop,
+            b"old" => Verdict::Replace(b"new".to_vec()),
+            b"first" => Verdict::Inject(b"second".to_vec()),
+            _ => Verdict::Send,
+        });
+
+        assert_eq!(filtering.send_to(b"lost", addr).unwrap(), 4);
+        assert!(b.recv(&mut buf).is_err());
+
+        assert_eq!(filtering.send_to(b"old", addr).unwrap(), 3);
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"new");
+
+        assert_eq!(filtering.send_to(b"first", addr).unwrap(), 5);
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"first");
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"second");
+
+        assert_eq!(filtering.send_to(b"sent", addr).unwrap(), 4);
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"sent");
+    }
+}
@@ -0,0 +1,173 @@
+//! [`Transport`] delivering packets in memory, without any sockets.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::transport::Transport;
+
+/// First port assigned by a [`MemoryNetwork`]
+const FIRST_PORT: u16 = 10_000;
+
+/// Packets waiting to be received, along with the address of their sender
+type Inbox = Sender<(Vec<u8>, SocketAddr)>;
+
+/// A network of [`MemoryTransport`]s that can send packets to each other
+#[derive(Debug, Clone)]
+pub struct MemoryNetwork {
+    /// Inboxes of the transports by their address, and the next port to assign
+    inner: Arc<Mutex<(HashMap<SocketAddr, Inbox>, u16)>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork {
+            inner: Arc::new(Mutex::new((HashMap::new(), FIRST_PORT))),
+        }
+    }
+
+    /// Creates a transport on this network with an unused address
+    pub fn bind(&self) -> MemoryTransport {
+        let (inbox, receiver) = unbounded();
+
+        let mut inner_lock = self.inner.lock().expect("unable to lock network");
+        let (inboxes, next_port) = &mut *inner_lock;
+
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, *next_port));
+        *next_port += 1;
+        inboxes.insert(addr, inbox);
+
+        MemoryTransport {
+            addr,
+            receiver,
+            network: self.clone(),
+            read_timeout: Mutex::new(None),
+        }
+    }
+
+    /// Creates two transports, for example to start a [`Link`][crate::link::Link]
+    /// between them
+    pub fn pair(&self) -> (MemoryTransport, MemoryTransport) {
+        (self.bind(), self.bind())
+    }
+
+    fn deliver(&self, buf: &[u8], from: SocketAddr, to: SocketAddr) {
+        let inner_lock = self.inner.lock().expect("unable to lock network");
+
+        // Like UDP, packets to unknown or closed addresses are lost
+        if let Some(inbox) = inner_lock.0.get(&to) {
+            let _ = inbox.send((buf.to_vec(), from));
+        }
+    }
+
+    fn unbind(&self, addr: SocketAddr) {
+        let mut inner_lock = self.inner.lock().expect("unable to lock network");
+        inner_lock.0.remove(&addr);
+    }
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`Transport`] created by a [`MemoryNetwork`]
+#[derive(Debug)]
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    receiver: Receiver<(Vec<u8>, SocketAddr)>,
+    network: MemoryNetwork,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.network.deliver(buf, self.addr, addr);
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let timeout = match self.read_timeout.lock() {
+            Ok(timeout_lock) => *timeout_lock,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "poisoned lock")),
+        };
+
+        let (packet, from) = match timeout {
+            Some(timeout) => match self.receiver.recv_timeout(timeout) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::from(io::ErrorKind::WouldBlock))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::from(io::ErrorKind::NotConnected))
+                }
+            },
+            None => self
+                .receiver
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?,
+        };
+
+        // Truncate packets larger than the buffer, like UDP
+        let size = packet.len().min(buf.len());
+        buf[..size].copy_from_slice(&packet[..size]);
+        Ok((size, from))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+
+        match self.read_timeout.lock() {
+            Ok(mut timeout_lock) => {
+                *timeout_lock = timeout;
+                Ok(())
+            }
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "poisoned lock")),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.unbind(self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::MemoryNetwork;
+    use crate::transport::Transport;
+
+    #[test]
+    fn memory_test() {
+        let network = MemoryNetwork::new();
+        let (a, b) = network.pair();
+
+        a.send_to(b"hello", b.local_addr().unwrap()).unwrap();
+
+        let mut buf = [0; 3];
+        let (size, from) = b.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..size], from), (&b"hel"[..], a.local_addr().unwrap()));
+
+        b.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert!(b.recv(&mut buf).is_err());
+
+        // Packets to closed transports are lost
+        let addr = b.local_addr().unwrap();
+        drop(b);
+        assert_eq!(a.send_to(b"lost", addr).unwrap(), 4);
+    }
+}
@@ -0,0 +1,114 @@
+//! Utilities for testing applications built on Aether. Requires the `test-util`
+//! feature.
+//!
+//! - [`TestTracker`] runs a tracker server inside the test process
+//! - [`MemoryNetwork`] creates [`Transport`][crate::transport::Transport]s delivering
+//!   packets in memory, for example to test a [`Link`][crate::link::Link]
+//! - [`SimulatedTransport`] wraps a transport to lose, duplicate, reorder and delay
+//!   packets as configured by [`NetworkConditions`]
+//! - [`FilteringTransport`] wraps a transport to lose or alter the packets chosen by a
+//!   closure
+//! - [`identity`] generates throwaway identities which are never saved
+//! - [`aether_pair`] and [`assert_delivery`] set up and check connected clients
+//! - [`connect_link`] connects a client to a simulated peer without a tracker
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use aether_lib::test_util::{aether_pair, assert_delivery, TestTracker};
+//!
+//! let tracker = TestTracker::start();
+//! let (alice, bob) = aether_pair(&tracker, Duration::from_secs(10));
+//!
+//! assert_delivery(&alice, &bob, b"Hello".to_vec(), Duration::from_secs(5));
+//! ```
+
+pub mod filter;
+pub mod memory;
+pub mod simulator;
+pub mod tracker;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::identity::{Id, PublicId};
+use crate::link::Link;
+use crate::peer::Aether;
+
+pub use filter::{FilteringTransport, Verdict};
+pub use memory::{MemoryNetwork, MemoryTransport};
+pub use simulator::{NetworkConditions, SimulatedTransport, SimulatorStats};
+pub use tracker::TestTracker;
+
+/// How often to check for a condition while waiting for it
+const WAIT_POLL_TIME: Duration = Duration::from_millis(10);
+
+/// Generate a throwaway identity along with its public identity
+pub fn identity() -> (Id, PublicId) {
+    let id = Id::new().expect("unable to generate identity");
+    let public_id = PublicId::from_base64(
+        &id.public_key_to_base64()
+            .expect("unable to encode public key"),
+    )
+    .expect("unable to decode public key");
+
+    (id, public_id)
+}
+
+/// Start two clients with throwaway identities using `tracker` and connect them to
+/// each other
+/// # Panics
+/// If the clients are not connected within `timeout`
+pub fn aether_pair(tracker: &TestTracker, timeout: Duration) -> (Aether, Aether) {
+    let first = Aether::new_with_id(identity().0, tracker.addr());
+    let second = Aether::new_with_id(identity().0, tracker.addr());
+
+    first.start();
+    second.start();
+
+    first.connect(second.get_uid()).expect("unable to connect");
+    second.connect(first.get_uid()).expect("unable to connect");
+
+    let connected = wait_until(timeout, || {
+        first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+    });
+    assert!(connected, "clients did not connect within {:?}", timeout);
+
+    (first, second)
+}
+
+/// Add the started `link` to `aether` as the connection to the peer `uid`, as if a
+/// handshake with the peer had succeeded. The peer is not authenticated, the other
+/// end of `link` can for example be a [`Link`] over a [`MemoryNetwork`] simulating
+/// the peer
+pub fn connect_link(aether: &Aether, uid: &str, link: Link) {
+    aether.insert_peer(uid, link);
+}
+
+/// Send `payload` from `from` to `to` and assert that it is received within `timeout`
+/// # Panics
+/// If sending fails or the received bytes are not `payload`
+pub fn assert_delivery(from: &Aether, to: &Aether, payload: Vec<u8>, timeout: Duration) {
+    from.send_to(to.get_uid(), payload.clone())
+        .expect("unable to send payload");
+
+    let received = to
+        .recv_timeout_from(from.get_uid(), timeout)
+        .expect("payload was not received");
+    assert_eq!(received, payload, "received bytes differ from the payload");
+}
+
+/// Wait until `condition` is true, returns false if it is still false after `timeout`
+pub fn wait_until<F: Fn() -> bool>(timeout: Duration, condition: F) -> bool {
+    let start = Instant::now();
+
+    while !condition() {
+        if start.elapsed() > timeout {
+            return false;
+        }
+        thread::sleep(WAIT_POLL_TIME);
+    }
+
+    true
+}
@@ -0,0 +1,360 @@
+//! [`Transport`] simulating adverse network conditions.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+use crate::transport::Transport;
+
+/// Conditions a [`SimulatedTransport`] applies to the packets it sends. Each packet is
+/// affected independently of the others
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConditions {
+    /// Probability of a packet being lost
+    pub loss: f64,
+    /// Probability of a packet being delivered twice
+    pub duplication: f64,
+    /// Probability of a packet being held back by another [`latency`][Self::latency]
+    /// and [`jitter`][Self::jitter], so that packets sent after it overtake it
+    pub reordering: f64,
+    /// Delay of every packet
+    pub latency: Duration,
+    /// Largest random delay added to the [`latency`][Self::latency] of each packet
+    pub jitter: Duration,
+    /// Seed of the random decisions, so that failing runs can be repeated. Random if
+    /// [`None`]
+    pub seed: Option<u64>,
+}
+
+impl Default for NetworkConditions {
+    /// A perfect network, packets are sent right away
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            seed: None,
+        }
+    }
+}
+
+/// Number of packets affected by each of the [`NetworkConditions`] of a
+/// [`SimulatedTransport`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulatorStats {
+    /// Packets sent through the transport
+    pub sent: u64,
+    /// Packets lost
+    pub lost: u64,
+    /// Packets delivered twice
+    pub duplicated: u64,
+    /// Packets held back to be overtaken
+    pub reordered: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    lost: AtomicU64,
+    duplicated: AtomicU64,
+    reordered: AtomicU64,
+}
+
+/// Packet waiting for its delay, ordered by the time it is due
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Delayed {
+    due: Instant,
+    /// Order of sending, to keep packets due at the same time in order
+    order: u64,
+    addr: SocketAddr,
+    buf: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    packets: BinaryHeap<Reverse<Delayed>>,
+    next_order: u64,
+    stopped: bool,
+}
+
+/// [`Transport`] wrapping another one and applying [`NetworkConditions`] to the
+/// packets sent through it, for example to check that a
+/// [`Link`][crate::link::Link] delivers everything in order over a bad network.
+/// Received packets are passed through as they are, so wrap the transports of both
+/// ends to affect both directions
+///
+/// Delayed packets are sent by a thread of the transport. Packets still delayed when
+/// the transport is dropped are lost
+#[derive(Debug)]
+pub struct SimulatedTransport<T: Transport + 'static> {
+    inner: Arc<T>,
+    conditions: NetworkConditions,
+    rng: Mutex<StdRng>,
+    counters: Counters,
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: Transport + 'static> SimulatedTransport<T> {
+    /// Wrap `inner` in a transport applying `conditions`
+    pub fn new(inner: T, conditions: NetworkConditions) -> SimulatedTransport<T> {
+        let inner = Arc::new(inner);
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+
+        let inner_clone = inner.clone();
+        let queue_clone = queue.clone();
+        let handle = thread::spawn(move || deliver(&*inner_clone, &queue_clone));
+
+        let seed = conditions.seed.unwrap_or_else(|| thread_rng().gen());
+
+        SimulatedTransport {
+            inner,
+            conditions,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            counters: Counters::default(),
+            queue,
+            handle: Some(handle),
+        }
+    }
+
+    /// Returns the conditions applied to sent packets
+    pub fn conditions(&self) -> NetworkConditions {
+        self.conditions
+    }
+
+    /// Returns the number of packets affected by each condition so far
+    pub fn stats(&self) -> SimulatorStats {
+        SimulatorStats {
+            sent: self.counters.sent.load(Ordering::SeqCst),
+            lost: self.counters.lost.load(Ordering::SeqCst),
+            duplicated: self.counters.duplicated.load(Ordering::SeqCst),
+            reordered: self.counters.reordered.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the delay of the next packet, or [`None`] if it is lost
+    fn delay(&self, rng: &mut StdRng) -> Option<Duration> {
+        let conditions = &self.conditions;
+
+        if rng.gen_bool(conditions.loss.clamp(0.0, 1.0)) {
+            self.counters.lost.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let mut delay = conditions.latency + random_jitter(rng, conditions.jitter);
+        if rng.gen_bool(conditions.reordering.clamp(0.0, 1.0)) {
+            self.counters.reordered.fetch_add(1, Ordering::SeqCst);
+            // Held back for at least a millisecond, so that it can be overtaken
+            delay += (conditions.latency + random_jitter(rng, conditions.jitter))
+                .max(Duration::from_millis(1));
+        }
+
+        Some(delay)
+    }
+
+    fn schedule(&self, buf: &[u8], addr: SocketAddr, delay: Duration) -> io::Result<usize> {
+        if delay.is_zero() {
+            return self.inner.send_to(buf, addr);
+        }
+
+        let (queue, wakeup) = &*self.queue;
+        let mut queue_lock = queue
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "poisoned lock"))?;
+
+        let order = queue_lock.next_order;
+        queue_lock.next_order += 1;
+        queue_lock.packets.push(Reverse(Delayed {
+            due: Instant::now() + delay,
+            order,
+            addr,
+            buf: buf.to_vec(),
+        }));
+        wakeup.notify_one();
+
+        Ok(buf.len())
+    }
+}
+
+impl<T: Transport + 'static> Transport for SimulatedTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.counters.sent.fetch_add(1, Ordering::SeqCst);
+
+        let (delay, duplicate) = {
+            let mut rng = self
+                .rng
+                .lock()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "poisoned lock"))?;
+
+            let delay = self.delay(&mut rng);
+            let duplicate = match delay {
+                Some(_) if rng.gen_bool(self.conditions.duplication.clamp(0.0, 1.0)) => {
+                    self.delay(&mut rng)
+                }
+                _ => None,
+            };
+            (delay, duplicate)
+        };
+
+        if let Some(delay) = duplicate {
+            self.counters.duplicated.fetch_add(1, Ordering::SeqCst);
+            self.schedule(buf, addr, delay)?;
+        }
+
+        // Like UDP, lost packets are sent successfully as far as the sender can tell
+        match delay {
+            Some(delay) => self.schedule(buf, addr, delay),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.recv(buf)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl<T: Transport + 'static> Drop for SimulatedTransport<T> {
+    fn drop(&mut self) {
+        let (queue, wakeup) = &*self.queue;
+        if let Ok(mut queue_lock) = queue.lock() {
+            queue_lock.stopped = true;
+        }
+        wakeup.notify_one();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Returns a random delay of up to `jitter`
+fn random_jitter(rng: &mut StdRng, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        Duration::ZERO
+    } else {
+        rng.gen_range(Duration::ZERO..=jitter)
+    }
+}
+
+/// Send the packets of `queue` through `inner` once they are due, until the transport
+/// is dropped
+fn deliver<T: Transport>(inner: &T, queue: &(Mutex<Queue>, Condvar)) {
+    let (queue, wakeup) = queue;
+    let mut queue_lock = queue.lock().expect("unable to lock queue");
+
+    while !queue_lock.stopped {
+        let now = Instant::now();
+        let due = match queue_lock.packets.peek() {
+            Some(Reverse(packet)) => packet.due,
+            None => {
+                queue_lock = wakeup.wait(queue_lock).expect("unable to lock queue");
+                continue;
+            }
+        };
+
+        if due > now {
+            queue_lock = wakeup
+                .wait_timeout(queue_lock, due - now)
+                .expect("unable to lock queue")
+                .0;
+            continue;
+        }
+
+        if let Some(Reverse(packet)) = queue_lock.packets.pop() {
+            // Like UDP, packets failing to send are lost
+            let _ = inner.send_to(&packet.buf, packet.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{NetworkConditions, SimulatedTransport};
+    use crate::test_util::MemoryNetwork;
+    use crate::transport::Transport;
+
+    #[test]
+    fn simulator_test() {
+        let network = MemoryNetwork::new();
+        let (a, b) = network.pair();
+        let addr = b.local_addr().unwrap();
+        b.set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0; 16];
+
+        // Everything is lost
+        let lossy = SimulatedTransport::new(
+            a,
+            NetworkConditions {
+                loss: 1.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(lossy.send_to(b"lost", addr).unwrap(), 4);
+        assert!(b.recv(&mut buf).is_err());
+        assert_eq!(lossy.stats().lost, 1);
+
+        // Everything is delivered twice, after the latency
+        let duplicating = SimulatedTransport::new(
+            network.bind(),
+            NetworkConditions {
+                duplication: 1.0,
+                latency: Duration::from_millis(50),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        duplicating.send_to(b"twice", addr).unwrap();
+        for _ in 0..2 {
+            let size = b.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..size], b"twice");
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(duplicating.stats().duplicated, 1);
+
+        // Held back packets are overtaken by the next one
+        let reordering = SimulatedTransport::new(
+            network.bind(),
+            NetworkConditions {
+                reordering: 1.0,
+                seed: Some(1),
+                ..Default::default()
+            },
+        );
+        reordering.send_to(b"first", addr).unwrap();
+        let mut perfect = reordering.conditions();
+        perfect.reordering = 0.0;
+        let perfect = SimulatedTransport::new(network.bind(), perfect);
+        perfect.send_to(b"second", addr).unwrap();
+
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"second");
+        let size = b.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"first");
+        assert_eq!(reordering.stats().reordered, 1);
+    }
+}
@@ -0,0 +1,184 @@
+//! Tracker server running in the test process.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::error::AetherError;
+use crate::identity::Id;
+use crate::tracker::channel::{open_request, seal_response};
+use crate::tracker::protocol::negotiate;
+use crate::tracker::{ConnectionRequest, TrackerPacket, TrackerPacketType};
+
+/// How often the tracker checks if it has been stopped
+const STOP_POLL_TIME: Duration = Duration::from_millis(50);
+
+/// How long after its last poll a peer is still answered as present
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Minimal tracker server listening on localhost, with the same protocol as the
+/// [tracker server](https://github.com/Prototype-Aether/Aether-Tracker)
+///
+/// Connection requests are relayed to the requested peer on its next poll. Peers are
+/// present while they polled within [`PRESENCE_TIMEOUT`]. Connection requests and polls
+/// not signed by the peer they claim to come from are dropped (refer
+/// [`TrackerPacket::verify`]). The peers run real UDP sockets for their connections, so
+/// the tracker uses one as well. It stops when dropped
+#[derive(Debug)]
+pub struct TestTracker {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestTracker {
+    /// Start a tracker accepting plaintext packets
+    pub fn start() -> TestTracker {
+        Self::start_with_id(None)
+    }
+
+    /// Start a tracker accepting packets sealed to `id` as well as plaintext packets
+    /// (refer [`TrackerChannel`][crate::tracker::TrackerChannel])
+    pub fn start_sealed(id: Id) -> TestTracker {
+        Self::start_with_id(Some(id))
+    }
+
+    fn start_with_id(id: Option<Id>) -> TestTracker {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("unable to create socket");
+        socket
+            .set_read_timeout(Some(STOP_POLL_TIME))
+            .expect("unable to set read timeout");
+        let addr = socket.local_addr().expect("unable to get address");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+
+        let handle = thread::spawn(move || serve(socket, id, stop_clone));
+
+        TestTracker {
+            addr,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Returns the address of the tracker
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for TestTracker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(socket: UdpSocket, id: Option<Id>, stop: Arc<AtomicBool>) {
+    // Connection requests waiting to be polled, by the uid of the requested peer
+    let mut pending: HashMap<String, Vec<ConnectionRequest>> = HashMap::new();
+    // Time of the last poll, by the uid of the polling peer
+    let mut last_seen: HashMap<String, Instant> = HashMap::new();
+    let mut buf = [0; 4096];
+
+    while !stop.load(Ordering::SeqCst) {
+        let (size, source) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+
+        let bytes = buf[..size].to_vec();
+        let opened = match &id {
+            Some(id) => open_request(id, bytes),
+            None => TrackerPacket::try_from(bytes)
+                .map(|packet| (packet, None))
+                .map_err(AetherError::TrackerPacket),
+        };
+
+        let (packet, cipher) = match opened {
+            Ok(opened) => opened,
+            Err(err) => {
+                warn!("Test tracker dropping invalid packet: {}", err);
+                continue;
+            }
+        };
+
+        // Replies are encoded in the most compact encoding the peer can decode
+        let encoding = negotiate(packet.encodings);
+
+        // Presence queries are answered to anyone, other packets act in the name of
+        // the peer sending them
+        if packet.packet_type != TrackerPacketType::Presence {
+            if let Err(err) = packet.verify() {
+                warn!("Test tracker dropping packet: {}", err);
+                continue;
+            }
+        }
+
+        match packet.packet_type {
+            TrackerPacketType::Connection => {
+                let ip = match source.ip() {
+                    IpAddr::V4(ip) => ip.octets(),
+                    IpAddr::V6(_) => continue,
+                };
+
+                let username = packet.username;
+                let requests = pending.entry(packet.peer_username).or_default();
+                requests.retain(|request| request.username != username);
+                requests.push(ConnectionRequest {
+                    identity_number: packet.identity_number,
+                    username,
+                    ip,
+                    port: source.port(),
+                });
+            }
+            TrackerPacketType::Poll => {
+                last_seen.insert(packet.username.clone(), Instant::now());
+
+                let reply = TrackerPacket {
+                    connections: pending.remove(&packet.username).unwrap_or_default(),
+                    username: packet.username,
+                    packet_type: TrackerPacketType::Poll,
+                    ..Default::default()
+                };
+
+                match seal_response(cipher.as_ref(), reply, encoding) {
+                    Ok(data) => {
+                        let _ = socket.send_to(&data, source);
+                    }
+                    Err(err) => warn!("Test tracker unable to reply: {}", err),
+                }
+            }
+            TrackerPacketType::Presence => {
+                let present = match last_seen.get(&packet.peer_username) {
+                    Some(time) => time.elapsed() < PRESENCE_TIMEOUT,
+                    None => false,
+                };
+
+                let reply = TrackerPacket {
+                    username: packet.username,
+                    peer_username: packet.peer_username,
+                    packet_type: TrackerPacketType::Presence,
+                    present,
+                    ..Default::default()
+                };
+
+                match seal_response(cipher.as_ref(), reply, encoding) {
+                    Ok(data) => {
+                        let _ = socket.send_to(&data, source);
+                    }
+                    Err(err) => warn!("Test tracker unable to reply: {}", err),
+                }
+            }
+        }
+    }
+}
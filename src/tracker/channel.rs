@@ -0,0 +1,342 @@
+//! Encryption of the traffic between clients and the tracker server
+//!
+//! Packets to the tracker are sealed to the published public key of the tracker: each
+//! client generates a random session key, encrypts it with the tracker's [`PublicId`]
+//! and sends it along with every packet encrypted using the session key. The tracker
+//! encrypts its replies with the same session key. On-path observers can so no longer
+//! read which UID is announced from which address.
+//!
+//! Sealed packets start with [`SEALED_MARKER`] followed by [`CHANNEL_VERSION`], which
+//! can never start the plaintext encodings of a [`TrackerPacket`]. The channel also
+//! keeps the encoding negotiated with the tracker (refer
+//! [`protocol`][crate::tracker::protocol#encodings]), sealed packets are encrypted
+//! in that encoding too.
+//!
+//! ```text
+//! request:  marker | version | key length (u16 BE) | sealed key | encrypted packet
+//! response: marker | version | encrypted packet
+//! ```
+//!
+//! Trackers that do not know the sealed format do not reply to it. A client can be
+//! configured to fall back to plaintext after a number of unanswered polls (refer
+//! [`AetherConfig::tracker_fallback_attempts`][crate::config::AetherConfig::tracker_fallback_attempts]).
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use crate::encryption::{AetherCipher, Encrypted, KEY_SIZE};
+use crate::error::AetherError;
+#[cfg(any(test, feature = "raw"))]
+use crate::identity::Id;
+use crate::identity::PublicId;
+use crate::tracker::protocol::{self, supported_encodings, ENCODING_JSON};
+use crate::tracker::TrackerPacket;
+use crate::util::gen_nonce;
+
+/// First byte of sealed packets
+pub const SEALED_MARKER: u8 = 0xAE;
+/// Version of the sealed format defined in this module
+pub const CHANNEL_VERSION: u8 = 1;
+
+const PREFIX_SIZE: usize = 2;
+#[cfg(any(test, feature = "raw"))]
+const KEY_LENGTH_SIZE: usize = 2;
+
+/// Client side of the channel to the tracker server
+#[derive(Debug)]
+pub struct TrackerChannel {
+    /// Session key and the session key sealed to the tracker, if the tracker's public
+    /// key is known
+    session: Option<(AetherCipher, Vec<u8>)>,
+    /// Set while packets are sealed, cleared after falling back to plaintext
+    sealed: AtomicBool,
+    /// Number of polls without a reply since the last reply
+    unanswered: AtomicU32,
+    /// Encoding of packets sent, the encoding of the last reply of the tracker
+    encoding: AtomicU8,
+}
+
+impl TrackerChannel {
+    /// Creates a channel sending packets in plaintext
+    pub fn plaintext() -> TrackerChannel {
+        TrackerChannel {
+            session: None,
+            sealed: AtomicBool::new(false),
+            unanswered: AtomicU32::new(0),
+            encoding: AtomicU8::new(ENCODING_JSON),
+        }
+    }
+
+    /// Creates a channel sealing packets to the given public key of the tracker
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the session key could not be sealed
+    pub fn sealed(tracker_key: &PublicId) -> Result<TrackerChannel, AetherError> {
+        let session_key = gen_nonce(KEY_SIZE);
+        let sealed_key = tracker_key.public_encrypt(&session_key)?;
+
+        Ok(TrackerChannel {
+            session: Some((AetherCipher::new(session_key), sealed_key)),
+            sealed: AtomicBool::new(true),
+            unanswered: AtomicU32::new(0),
+            encoding: AtomicU8::new(ENCODING_JSON),
+        })
+    }
+
+    /// Returns true if packets are currently sealed
+    pub fn is_sealed(&self) -> bool {
+        self.sealed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the encoding packets are sent in, such as [`ENCODING_JSON`]
+    pub fn encoding(&self) -> u8 {
+        self.encoding.load(Ordering::SeqCst)
+    }
+
+    /// Encode a packet to be sent to the tracker, listing the encodings this client
+    /// can decode
+    pub fn seal(&self, mut packet: TrackerPacket) -> Result<Vec<u8>, AetherError> {
+        packet.encodings = supported_encodings();
+        let bytes =
+            protocol::encode(packet, self.encoding()).map_err(AetherError::TrackerPacket)?;
+
+        match &self.session {
+            Some((cipher, sealed_key)) if self.is_sealed() => {
+                let encrypted = cipher.encrypt_bytes(bytes)?;
+
+                let mut result = vec![SEALED_MARKER, CHANNEL_VERSION];
+                result.extend_from_slice(&(sealed_key.len() as u16).to_be_bytes());
+                result.extend_from_slice(sealed_key);
+                result.append(&mut Vec::from(encrypted));
+                Ok(result)
+            }
+            _ => Ok(bytes),
+        }
+    }
+
+    /// Decode a packet received from the tracker. Plaintext packets are only accepted
+    /// while packets are not sealed. Later packets are sent in the encoding of the
+    /// packet
+    pub fn open(&self, bytes: Vec<u8>) -> Result<TrackerPacket, AetherError> {
+        let (packet, encoding) = match &self.session {
+            Some((cipher, _)) if is_sealed_packet(&bytes) => {
+                let encrypted = split_encrypted(bytes[PREFIX_SIZE..].to_vec())?;
+                protocol::decode(cipher.decrypt_bytes(encrypted)?)
+                    .map_err(AetherError::TrackerPacket)?
+            }
+            _ if self.is_sealed() => {
+                return Err(AetherError::TrackerPacket("Expected a sealed packet"))
+            }
+            _ => protocol::decode(bytes).map_err(AetherError::TrackerPacket)?,
+        };
+
+        self.unanswered.store(0, Ordering::SeqCst);
+        self.encoding.store(encoding, Ordering::SeqCst);
+        Ok(packet)
+    }
+
+    /// Record a poll that the tracker did not reply to. Falls back to plaintext once
+    /// `fallback_attempts` polls in a row went unanswered, `0` never falls back.
+    /// Returns true if the channel fell back to plaintext
+    pub fn unanswered(&self, fallback_attempts: u32) -> bool {
+        let unanswered = self.unanswered.fetch_add(1, Ordering::SeqCst) + 1;
+
+        fallback_attempts > 0
+            && unanswered >= fallback_attempts
+            && self.sealed.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Tracker side: decode a packet sent by a client. Returns the packet and, for sealed
+/// packets, the session cipher to seal the reply with using [`seal_response`]
+#[cfg(any(test, feature = "raw"))]
+pub fn open_request(
+    tracker_id: &Id,
+    bytes: Vec<u8>,
+) -> Result<(TrackerPacket, Option<AetherCipher>), AetherError> {
+    if !is_sealed_packet(&bytes) {
+        let packet = TrackerPacket::try_from(bytes).map_err(AetherError::TrackerPacket)?;
+        return Ok((packet, None));
+    }
+
+    let key_start = PREFIX_SIZE + KEY_LENGTH_SIZE;
+    if bytes.len() < key_start {
+        return Err(AetherError::TrackerPacket("Sealed packet is truncated"));
+    }
+
+    let key_length = u16::from_be_bytes([bytes[PREFIX_SIZE], bytes[PREFIX_SIZE + 1]]) as usize;
+    let key_end = key_start + key_length;
+    if bytes.len() < key_end {
+        return Err(AetherError::TrackerPacket("Sealed packet is truncated"));
+    }
+
+    let session_key = tracker_id.private_decrypt(&bytes[key_start..key_end])?;
+    let cipher = AetherCipher::new(session_key);
+
+    let encrypted = split_encrypted(bytes[key_end..].to_vec())?;
+    let packet = TrackerPacket::try_from(cipher.decrypt_bytes(encrypted)?)
+        .map_err(AetherError::TrackerPacket)?;
+
+    Ok((packet, Some(cipher)))
+}
+
+/// Tracker side: encode a reply to a client in `encoding`, sealed if the request was
+/// sealed. The reply lists the encodings the tracker can decode, the client answers
+/// in the encoding of the reply from then on (refer
+/// [`negotiate`][crate::tracker::protocol::negotiate])
+#[cfg(any(test, feature = "raw"))]
+pub fn seal_response(
+    cipher: Option<&AetherCipher>,
+    mut packet: TrackerPacket,
+    encoding: u8,
+) -> Result<Vec<u8>, AetherError> {
+    packet.encodings = supported_encodings();
+    let bytes = protocol::encode(packet, encoding).map_err(AetherError::TrackerPacket)?;
+
+    match cipher {
+        Some(cipher) => {
+            let mut result = vec![SEALED_MARKER, CHANNEL_VERSION];
+            result.append(&mut Vec::from(cipher.encrypt_bytes(bytes)?));
+            Ok(result)
+        }
+        None => Ok(bytes),
+    }
+}
+
+fn is_sealed_packet(bytes: &[u8]) -> bool {
+    bytes.len() >= PREFIX_SIZE && bytes[0] == SEALED_MARKER && bytes[1] == CHANNEL_VERSION
+}
+
+fn split_encrypted(bytes: Vec<u8>) -> Result<Encrypted, AetherError> {
+    Encrypted::try_from(bytes).map_err(|_| AetherError::TrackerPacket("Sealed packet is truncated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{open_request, seal_response, TrackerChannel, SEALED_MARKER};
+    use crate::identity::{Id, PublicId};
+    use crate::tracker::protocol::{supported_encodings, ENCODING_JSON};
+    use crate::tracker::{ConnectionRequest, TrackerPacket, TrackerPacketType};
+
+    fn tracker() -> (Id, PublicId) {
+        let id = Id::new().unwrap();
+        let public = PublicId::from_base64(&id.public_key_to_base64().unwrap()).unwrap();
+        (id, public)
+    }
+
+    fn poll() -> TrackerPacket {
+        TrackerPacket {
+            username: "test".to_string(),
+            packet_type: TrackerPacketType::Poll,
+            req: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns `packet` as received, listing the encodings of its sender
+    fn sent(packet: TrackerPacket) -> TrackerPacket {
+        TrackerPacket {
+            encodings: supported_encodings(),
+            ..packet
+        }
+    }
+
+    #[test]
+    fn sealed_test() {
+        let (tracker_id, tracker_key) = tracker();
+        let channel = TrackerChannel::sealed(&tracker_key).unwrap();
+
+        let request = channel.seal(poll()).unwrap();
+        assert_eq!(request[0], SEALED_MARKER);
+        assert!(!String::from_utf8_lossy(&request).contains("test"));
+
+        let (packet, cipher) = open_request(&tracker_id, request).unwrap();
+        assert_eq!(packet, sent(poll()));
+
+        let reply = TrackerPacket {
+            connections: vec![ConnectionRequest {
+                identity_number: 1,
+                username: "another".to_string(),
+                port: 1234,
+                ip: [1, 2, 3, 4],
+            }],
+            ..Default::default()
+        };
+
+        let response = seal_response(cipher.as_ref(), reply.clone(), ENCODING_JSON).unwrap();
+        assert_eq!(channel.open(response).unwrap(), sent(reply.clone()));
+
+        // Plaintext replies cannot be injected into a sealed channel
+        let plaintext = Vec::try_from(reply).unwrap();
+        assert!(channel.open(plaintext).is_err());
+    }
+
+    #[test]
+    fn plaintext_test() {
+        let (tracker_id, _) = tracker();
+        let channel = TrackerChannel::plaintext();
+
+        let request = channel.seal(poll()).unwrap();
+        assert_eq!(
+            TrackerPacket::try_from(request.clone()).unwrap(),
+            sent(poll())
+        );
+
+        let (packet, cipher) = open_request(&tracker_id, request).unwrap();
+        assert_eq!(packet, sent(poll()));
+        assert!(cipher.is_none());
+
+        let response = seal_response(None, poll(), ENCODING_JSON).unwrap();
+        assert_eq!(channel.open(response).unwrap(), sent(poll()));
+    }
+
+    #[test]
+    fn fallback_test() {
+        let (tracker_id, tracker_key) = tracker();
+
+        let never = TrackerChannel::sealed(&tracker_key).unwrap();
+        for _ in 0..10 {
+            assert!(!never.unanswered(0));
+        }
+        assert!(never.is_sealed());
+
+        let channel = TrackerChannel::sealed(&tracker_key).unwrap();
+        assert!(!channel.unanswered(3));
+        assert!(!channel.unanswered(3));
+
+        // A reply resets the count
+        let (_, cipher) = open_request(&tracker_id, channel.seal(poll()).unwrap()).unwrap();
+        let sealed_response = seal_response(cipher.as_ref(), poll(), ENCODING_JSON).unwrap();
+        assert_eq!(channel.open(sealed_response).unwrap(), sent(poll()));
+
+        // Plaintext is only accepted after falling back
+        let response = seal_response(None, poll(), ENCODING_JSON).unwrap();
+        assert!(channel.open(response.clone()).is_err());
+
+        assert!(!channel.unanswered(3));
+        assert!(!channel.unanswered(3));
+        assert!(channel.unanswered(3));
+        assert!(!channel.unanswered(3));
+
+        assert!(!channel.is_sealed());
+        assert_eq!(
+            TrackerPacket::try_from(channel.seal(poll()).unwrap()).unwrap(),
+            sent(poll())
+        );
+        assert_eq!(channel.open(response).unwrap(), sent(poll()));
+    }
+
+    #[test]
+    fn truncated_test() {
+        let (tracker_id, tracker_key) = tracker();
+        let channel = TrackerChannel::sealed(&tracker_key).unwrap();
+
+        let request = channel.seal(poll()).unwrap();
+        for size in [2, 3, 100, 140] {
+            assert!(open_request(&tracker_id, request[..size].to_vec()).is_err());
+        }
+
+        assert!(channel.open(vec![SEALED_MARKER, 1, 0]).is_err());
+    }
+}
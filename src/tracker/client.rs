@@ -0,0 +1,192 @@
+//! Client side of the tracker protocol (refer [`protocol`][super::protocol]).
+//!
+//! A [`TrackerClient`] sends the packets of one peer to the tracker server, signed with
+//! the [`Id`] of the peer and encoded by its [`TrackerChannel`], and receives the
+//! answers. [`Aether`][crate::peer::Aether] talks to the tracker through one, and it can
+//! be used on its own as well, for example to test a tracker implementation.
+//!
+//! The tracker learns the address of a peer from its polls, and answers every packet
+//! on the socket it was sent from. Answers to polls and presence queries share the
+//! socket of the client, so an answer received after a poll may be that of an earlier
+//! query. Connection requests are sent from the socket of the link to the other peer
+//! instead, as the tracker relays their source address to it.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tracing::error;
+
+use crate::error::AetherError;
+use crate::identity::Id;
+use crate::tracker::{TrackerChannel, TrackerPacket, TrackerPacketType};
+use crate::transport::Transport;
+
+/// Largest packet received from the tracker server
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// Client of the tracker server for one peer. Clones share the socket and channel
+#[derive(Debug, Clone)]
+pub struct TrackerClient {
+    /// UID of the peer, encoding the public key of `id`
+    uid: String,
+    /// Identity the packets are signed with
+    id: Id,
+    /// Address of the tracker server
+    tracker_addr: SocketAddr,
+    /// Socket polls and presence queries are sent from
+    socket: Arc<dyn Transport>,
+    /// Channel used to encode the packets
+    channel: Arc<TrackerChannel>,
+}
+
+impl TrackerClient {
+    /// Creates a client for the peer with the identity `id`, communicating with the
+    /// tracker server at `tracker_addr` through `socket` in plaintext. Answers are
+    /// waited for as long as the read timeout of `socket`
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the public key of `id` cannot be encoded
+    pub fn new(
+        id: Id,
+        tracker_addr: SocketAddr,
+        socket: Arc<dyn Transport>,
+    ) -> Result<TrackerClient, AetherError> {
+        Ok(TrackerClient {
+            uid: id.public_key_to_base64()?,
+            id,
+            tracker_addr,
+            socket,
+            channel: Arc::new(TrackerChannel::plaintext()),
+        })
+    }
+
+    /// Encode packets using `channel`, such as a [sealed][TrackerChannel::sealed] one
+    pub fn set_channel(&mut self, channel: TrackerChannel) {
+        self.channel = Arc::new(channel);
+    }
+
+    /// Sign packets with `id` and send them on behalf of its UID
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the public key of `id` cannot be encoded
+    pub fn set_identity(&mut self, id: Id) -> Result<(), AetherError> {
+        self.uid = id.public_key_to_base64()?;
+        self.id = id;
+        Ok(())
+    }
+
+    /// Returns the UID of the peer
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+
+    /// Returns the address of the tracker server
+    pub fn tracker_addr(&self) -> SocketAddr {
+        self.tracker_addr
+    }
+
+    /// Returns the local address of the socket polls are sent from
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the channel the packets are encoded by
+    pub fn channel(&self) -> &TrackerChannel {
+        &self.channel
+    }
+
+    /// Announce the peer to the tracker server at the address of the socket, by sending
+    /// a poll without waiting for the answer (refer [`TrackerClient::recv`])
+    /// # Errors
+    /// * [`AetherError::TrackerIo`] - If the poll cannot be sent
+    ///
+    /// Errors encoding the packet are returned as well (refer [`TrackerChannel::seal`])
+    pub fn register(&self) -> Result<(), AetherError> {
+        let packet = TrackerPacket {
+            username: self.uid.clone(),
+            packet_type: TrackerPacketType::Poll,
+            req: true,
+            ..Default::default()
+        };
+
+        self.send(packet, &*self.socket)
+    }
+
+    /// Poll the tracker server for connection requests from other peers, which also
+    /// [registers][TrackerClient::register] the peer. Returns the first packet
+    /// received, which may answer an earlier query instead of the poll, or [`None`] if
+    /// nothing was received in time
+    /// # Errors
+    /// The same as [`TrackerClient::register`] and [`TrackerClient::recv`]
+    #[cfg(feature = "raw")]
+    pub fn poll(&self) -> Result<Option<TrackerPacket>, AetherError> {
+        self.register()?;
+        self.recv()
+    }
+
+    /// Request a connection to the peer `peer_uid`, sent from `socket` so that the
+    /// tracker relays the address of `socket` to the other peer. If `mutual` is set, the
+    /// tracker only relays it once the other peer requested a connection too (refer
+    /// [`TrackerPacket::mutual`])
+    /// # Errors
+    /// The same as [`TrackerClient::register`]
+    pub fn request_connection(
+        &self,
+        peer_uid: &str,
+        socket: &dyn Transport,
+        mutual: bool,
+    ) -> Result<(), AetherError> {
+        let packet = TrackerPacket {
+            username: self.uid.clone(),
+            peer_username: peer_uid.to_string(),
+            identity_number: 1,
+            packet_type: TrackerPacketType::Connection,
+            req: true,
+            mutual,
+            ..Default::default()
+        };
+
+        self.send(packet, socket)
+    }
+
+    /// Ask the tracker server whether the peer `peer_uid` is present, without waiting
+    /// for the answer (refer [`TrackerPacketType::Presence`])
+    /// # Errors
+    /// The same as [`TrackerClient::register`]
+    pub fn query_presence(&self, peer_uid: &str) -> Result<(), AetherError> {
+        let packet = TrackerPacket {
+            username: self.uid.clone(),
+            peer_username: peer_uid.to_string(),
+            packet_type: TrackerPacketType::Presence,
+            req: true,
+            ..Default::default()
+        };
+
+        self.send(packet, &*self.socket)
+    }
+
+    /// Receive the next packet from the tracker server, [`None`] if nothing was
+    /// received within the read timeout of the socket
+    /// # Errors
+    /// Errors decoding the packet (refer [`TrackerChannel::open`])
+    pub fn recv(&self) -> Result<Option<TrackerPacket>, AetherError> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        match self.socket.recv(&mut buf) {
+            Ok(size) if size > 0 => self.channel.open(buf[..size].to_vec()).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Sign `packet` so the tracker server knows it was sent by this peer (refer
+    /// [`TrackerPacket::sign`]) and send it from `socket`
+    fn send(&self, mut packet: TrackerPacket, socket: &dyn Transport) -> Result<(), AetherError> {
+        if let Err(err) = packet.sign(&self.id) {
+            error!("Unable to sign tracker packet: {}", err);
+        }
+
+        let bytes = self.channel.seal(packet)?;
+        socket
+            .send_to(&bytes, self.tracker_addr)
+            .map_err(AetherError::TrackerIo)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+//! Primitives for communicating with the tracker server
+//!
+//! The wire format shared with the [tracker server](https://github.com/Prototype-Aether/Aether-Tracker)
+//! is defined in [`protocol`]. The server depends on the same module, so any change to
+//! the format has to bump [`protocol::TRACKER_PROTOCOL_VERSION`]. Packets can be
+//! encrypted for the tracker using a [`TrackerChannel`], and are exchanged with the
+//! tracker through a [`TrackerClient`].
+
+pub mod channel;
+pub mod client;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod protocol;
+
+pub use channel::TrackerChannel;
+pub use client::TrackerClient;
+pub use protocol::{ConnectionRequest, TrackerPacket, TrackerPacketType};
@@ -0,0 +1,200 @@
+//! Protobuf encoding of tracker packets. Requires the `protobuf` feature.
+//!
+//! Packets follow the schema in `proto/tracker.proto`, so trackers implemented in
+//! other languages can use code generated from it instead of a JSON parser. The
+//! messages of [`schema`] are generated from it with `prost` by the build script. Like
+//! any protobuf implementation, fields holding their default value are omitted and
+//! unknown fields are skipped. On the wire the message is preceded by
+//! [`PROTOBUF_MARKER`][super::protocol::PROTOBUF_MARKER] (refer
+//! [`protocol::encode`][super::protocol::encode]).
+
+use std::convert::TryFrom;
+
+use prost::Message;
+
+use crate::tracker::{ConnectionRequest, TrackerPacket, TrackerPacketType};
+
+/// Messages generated from `proto/tracker.proto`
+#[allow(missing_docs)]
+pub mod schema {
+    include!(concat!(env!("OUT_DIR"), "/aether.tracker.rs"));
+}
+
+/// Encode `packet` as a `TrackerPacket` message
+pub fn encode(packet: &TrackerPacket) -> Vec<u8> {
+    let packet_type = match packet.packet_type {
+        TrackerPacketType::Connection => schema::TrackerPacketType::Connection,
+        TrackerPacketType::Poll => schema::TrackerPacketType::Poll,
+        TrackerPacketType::Presence => schema::TrackerPacketType::Presence,
+    };
+
+    schema::TrackerPacket {
+        identity_number: packet.identity_number,
+        username: packet.username.clone(),
+        peer_username: packet.peer_username.clone(),
+        req: packet.req,
+        packet_type: packet_type as i32,
+        port: packet.port as u32,
+        ip: ip_bytes(packet.ip),
+        connections: packet.connections.iter().map(encode_connection).collect(),
+        mutual: packet.mutual,
+        present: packet.present,
+        timestamp: packet.timestamp,
+        signature: packet.signature.clone(),
+        encodings: packet.encodings as u32,
+    }
+    .encode_to_vec()
+}
+
+/// Decode a `TrackerPacket` message
+pub fn decode(bytes: &[u8]) -> Result<TrackerPacket, &'static str> {
+    let message =
+        schema::TrackerPacket::decode(bytes).map_err(|_| "Unable to decode protobuf packet")?;
+
+    // No type is 0, so the field is always present
+    let packet_type = match schema::TrackerPacketType::from_i32(message.packet_type) {
+        Some(schema::TrackerPacketType::Connection) => TrackerPacketType::Connection,
+        Some(schema::TrackerPacketType::Poll) => TrackerPacketType::Poll,
+        Some(schema::TrackerPacketType::Presence) => TrackerPacketType::Presence,
+        Some(schema::TrackerPacketType::Unspecified) => return Err("Packet type is missing"),
+        None => return Err("Unknown packet type"),
+    };
+
+    Ok(TrackerPacket {
+        identity_number: message.identity_number,
+        username: message.username,
+        peer_username: message.peer_username,
+        req: message.req,
+        packet_type,
+        port: narrow(message.port)?,
+        ip: ip(&message.ip)?,
+        connections: message
+            .connections
+            .iter()
+            .map(decode_connection)
+            .collect::<Result<_, _>>()?,
+        mutual: message.mutual,
+        present: message.present,
+        timestamp: message.timestamp,
+        signature: message.signature,
+        encodings: narrow(message.encodings)?,
+    })
+}
+
+/// Convert `connection` to a `ConnectionRequest` message
+fn encode_connection(connection: &ConnectionRequest) -> schema::ConnectionRequest {
+    schema::ConnectionRequest {
+        identity_number: connection.identity_number,
+        username: connection.username.clone(),
+        port: connection.port as u32,
+        ip: ip_bytes(connection.ip),
+    }
+}
+
+/// Convert a `ConnectionRequest` message
+fn decode_connection(
+    connection: &schema::ConnectionRequest,
+) -> Result<ConnectionRequest, &'static str> {
+    Ok(ConnectionRequest {
+        identity_number: connection.identity_number,
+        username: connection.username.clone(),
+        port: narrow(connection.port)?,
+        ip: ip(&connection.ip)?,
+    })
+}
+
+/// Convert a field to the narrower type of the packet
+fn narrow<T: TryFrom<u32>>(value: u32) -> Result<T, &'static str> {
+    T::try_from(value).map_err(|_| "Field is out of range")
+}
+
+/// Convert an address to a bytes field, empty (and omitted) if it is unspecified
+fn ip_bytes(ip: [u8; 4]) -> Vec<u8> {
+    if ip == [0; 4] {
+        Vec::new()
+    } else {
+        ip.to_vec()
+    }
+}
+
+/// Convert a bytes field to an IPv4 address, unspecified if the field was omitted
+fn ip(bytes: &[u8]) -> Result<[u8; 4], &'static str> {
+    if bytes.is_empty() {
+        return Ok([0; 4]);
+    }
+    <[u8; 4]>::try_from(bytes).map_err(|_| "Address is not 4 bytes long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use crate::tracker::protocol::{ENCODING_JSON, ENCODING_PROTOBUF};
+    use crate::tracker::{ConnectionRequest, TrackerPacket, TrackerPacketType};
+
+    #[test]
+    fn protobuf_test() {
+        let packet = TrackerPacket {
+            identity_number: u32::MAX,
+            username: "\u{1F600} ünïcödé".to_string(),
+            peer_username: "another".to_string(),
+            req: true,
+            packet_type: TrackerPacketType::Poll,
+            port: u16::MAX,
+            ip: [1, 2, 3, 4],
+            connections: vec![
+                ConnectionRequest {
+                    identity_number: 32,
+                    username: "someone".to_string(),
+                    port: 4200,
+                    ip: [42, 32, 22, 12],
+                },
+                ConnectionRequest::default(),
+            ],
+            mutual: true,
+            present: true,
+            timestamp: u64::MAX,
+            signature: "c2lnbmF0dXJl".to_string(),
+            encodings: ENCODING_JSON | ENCODING_PROTOBUF,
+        };
+        assert_eq!(decode(&encode(&packet)).unwrap(), packet);
+
+        // Defaults are omitted, except for the type
+        assert_eq!(encode(&TrackerPacket::default()), [0x28, 0x03]);
+        assert_eq!(decode(&[0x28, 0x03]).unwrap(), TrackerPacket::default());
+
+        // Changing this encoding breaks compatibility with code generated from the
+        // schema
+        let wire = [
+            0x08, 0x01, 0x12, 0x04, b't', b'e', b's', b't', 0x28, 0x03, 0x30, 0xD2, 0x09,
+        ];
+        let packet = TrackerPacket {
+            identity_number: 1,
+            username: "test".to_string(),
+            packet_type: TrackerPacketType::Poll,
+            port: 1234,
+            ..Default::default()
+        };
+        assert_eq!(encode(&packet), wire);
+
+        // Unknown fields are skipped
+        let mut extended = wire.to_vec();
+        extended.extend_from_slice(&[0xA0, 0x06, 0x2A, 0xAA, 0x06, 0x01, 0x00]);
+        assert_eq!(decode(&extended).unwrap(), packet);
+    }
+
+    #[test]
+    fn invalid_test() {
+        // Truncated varint and string
+        assert!(decode(&[0x08]).is_err());
+        assert!(decode(&[0x12, 0x04, b't']).is_err());
+        // Wrong wire type of a known field
+        assert!(decode(&[0x0A, 0x00]).is_err());
+        // Values out of range of their field
+        assert!(decode(&[0x28, 0x80, 0x02]).is_err());
+        assert!(decode(&[0x3A, 0x03, 1, 2, 3]).is_err());
+        assert!(decode(&[0x12, 0x02, 0xff, 0xfe]).is_err());
+        // Missing and unknown types
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0x28, 0x05]).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+//! Packets used to communicate with the tracker server
+//!
+//! Packets are encoded as UTF-8 JSON objects with the field names of [`TrackerPacket`]
+//! and [`ConnectionRequest`]. This module is the single definition of the format for
+//! both the library and the tracker server, so the two cannot drift apart silently.
+//!
+//! # Encodings
+//!
+//! With the `protobuf` feature, packets can also be encoded as protobuf messages
+//! (refer `tracker::protobuf`), starting with [`PROTOBUF_MARKER`]. Both ends list the
+//! encodings they can decode in [`TrackerPacket::encodings`]. Clients send JSON until
+//! the tracker answers in another encoding, which it only does if the client listed
+//! it (refer [`negotiate`]). Trackers unaware of the field ignore it and keep using
+//! JSON.
+//!
+//! # Signatures
+//!
+//! Packets sent on behalf of a peer are signed with its [`Id`] using
+//! [`TrackerPacket::sign`], so that nobody else can request connections or poll for
+//! requests in its name. The tracker checks them with [`TrackerPacket::verify`] against
+//! the public key the UID in [`TrackerPacket::username`] encodes. Signatures carry the
+//! time they were made at and expire after [`MAX_SIGNATURE_AGE`], which bounds how long
+//! a captured packet can be sent again from another address.
+
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::identity::{Id, PublicId};
+
+/// Version of the tracker protocol defined in this module. Bump this whenever the
+/// encoding or the meaning of any field changes
+///
+/// - Version 2 adds [`TrackerPacket::mutual`], omitted when not set so packets stay
+///   readable by trackers on version 1
+/// - Version 3 adds [`TrackerPacketType::Presence`] along with
+///   [`TrackerPacket::present`]. Trackers on earlier versions do not answer presence
+///   queries
+/// - Version 4 adds [`TrackerPacket::timestamp`] and [`TrackerPacket::signature`],
+///   omitted when not set. Trackers on earlier versions ignore them
+/// - Version 5 adds [`TrackerPacket::encodings`], omitted when not set, and the
+///   protobuf encoding
+#[cfg(feature = "raw")]
+pub const TRACKER_PROTOCOL_VERSION: u8 = 5;
+
+/// Bit of [`TrackerPacket::encodings`] set if JSON packets can be decoded, which
+/// every version can
+pub const ENCODING_JSON: u8 = 1;
+
+/// Bit of [`TrackerPacket::encodings`] set if protobuf packets can be decoded
+pub const ENCODING_PROTOBUF: u8 = 1 << 1;
+
+/// First byte of protobuf packets, which can never start the JSON encoding or a
+/// sealed packet (refer [`channel`][super::channel])
+pub const PROTOBUF_MARKER: u8 = 0xAF;
+
+/// Number of [`TrackerPacketType::Connection`] on the wire
+pub const PACKET_TYPE_CONNECTION: u8 = 2;
+
+/// Number of [`TrackerPacketType::Poll`] on the wire
+pub const PACKET_TYPE_POLL: u8 = 3;
+
+/// Number of [`TrackerPacketType::Presence`] on the wire
+pub const PACKET_TYPE_PRESENCE: u8 = 4;
+
+/// Longest time a signature is accepted for after it was made, and by how much its
+/// time may be ahead of the verifier's clock (in seconds)
+pub const MAX_SIGNATURE_AGE: u64 = 60;
+
+/// Prefix of the [`TrackerPacket::signed_message`], so that packets cannot be mistaken
+/// for signatures of other data
+const SIGNATURE_CONTEXT: &[u8] = b"aether tracker packet";
+
+/// Type of a [`TrackerPacket`], encoded as its number on the wire such as
+/// [`PACKET_TYPE_POLL`]. Packets of any other number cannot be decoded
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum TrackerPacketType {
+    /// A request to connect to another peer. Relayed to the other peer along with the
+    /// address it was sent from, in the answer to its next poll
+    Connection,
+    /// A poll for connection requests from other peers, which also tells the tracker
+    /// the address of the peer. Answered with a packet of the same type listing the
+    /// requests in [`TrackerPacket::connections`]
+    #[default]
+    Poll,
+    /// A query whether the peer [`TrackerPacket::peer_username`] is present, which it is
+    /// if it polled the tracker recently. Answered with a packet of the same type and
+    /// [`TrackerPacket::present`] set accordingly
+    Presence,
+}
+
+impl From<TrackerPacketType> for u8 {
+    fn from(packet_type: TrackerPacketType) -> u8 {
+        match packet_type {
+            TrackerPacketType::Connection => PACKET_TYPE_CONNECTION,
+            TrackerPacketType::Poll => PACKET_TYPE_POLL,
+            TrackerPacketType::Presence => PACKET_TYPE_PRESENCE,
+        }
+    }
+}
+
+impl TryFrom<u8> for TrackerPacketType {
+    type Error = &'static str;
+
+    fn try_from(packet_type: u8) -> Result<Self, Self::Error> {
+        match packet_type {
+            PACKET_TYPE_CONNECTION => Ok(TrackerPacketType::Connection),
+            PACKET_TYPE_POLL => Ok(TrackerPacketType::Poll),
+            PACKET_TYPE_PRESENCE => Ok(TrackerPacketType::Presence),
+            _ => Err("Unknown packet type"),
+        }
+    }
+}
+
+/// A request from another peer to connect, as relayed by the tracker server
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct ConnectionRequest {
+    pub identity_number: u32,
+    pub username: String,
+    pub port: u16,
+    pub ip: [u8; 4],
+}
+
+impl Clone for ConnectionRequest {
+    fn clone(&self) -> Self {
+        ConnectionRequest {
+            identity_number: self.identity_number,
+            username: self.username.clone(),
+            port: self.port,
+            ip: self.ip,
+        }
+    }
+}
+
+/// A packet sent to or received from the tracker server
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+pub struct TrackerPacket {
+    pub identity_number: u32,
+    pub username: String,
+    pub peer_username: String,
+    pub req: bool,
+    pub packet_type: TrackerPacketType,
+    pub port: u16,
+    pub ip: [u8; 4],
+    pub connections: Vec<ConnectionRequest>,
+    /// Set on connection requests that may only be relayed once the other peer has
+    /// requested a connection too, so addresses are not revealed to arbitrary peers
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub mutual: bool,
+    /// Set on answers to presence queries if the queried peer is present
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub present: bool,
+    /// Time the packet was signed at (in seconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub timestamp: u64,
+    /// Base64 encoded signature of the [`TrackerPacket::signed_message`] made by the
+    /// peer [`TrackerPacket::username`], empty if the packet is not signed
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+    /// Encodings the sender can decode, such as [`ENCODING_PROTOBUF`]. Not covered by
+    /// the signature, since it only affects how packets are encoded
+    #[serde(default, skip_serializing_if = "is_zero_u8")]
+    pub encodings: u8,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_zero_u8(value: &u8) -> bool {
+    *value == 0
+}
+
+/// Returns the [`TrackerPacket::encodings`] this build can decode
+pub fn supported_encodings() -> u8 {
+    if cfg!(feature = "protobuf") {
+        ENCODING_JSON | ENCODING_PROTOBUF
+    } else {
+        ENCODING_JSON
+    }
+}
+
+/// Returns the encoding to answer a packet listing `encodings` in, the most compact
+/// one both ends can decode
+#[cfg(any(test, feature = "raw"))]
+pub fn negotiate(encodings: u8) -> u8 {
+    if encodings & supported_encodings() & ENCODING_PROTOBUF != 0 {
+        ENCODING_PROTOBUF
+    } else {
+        ENCODING_JSON
+    }
+}
+
+/// Encode `packet` in `encoding`, one of [`ENCODING_JSON`] and [`ENCODING_PROTOBUF`].
+/// Falls back to JSON for encodings this build cannot encode
+pub fn encode(packet: TrackerPacket, encoding: u8) -> Result<Vec<u8>, &'static str> {
+    #[cfg(feature = "protobuf")]
+    if encoding == ENCODING_PROTOBUF {
+        let mut bytes = vec![PROTOBUF_MARKER];
+        bytes.extend(super::protobuf::encode(&packet));
+        return Ok(bytes);
+    }
+    #[cfg(not(feature = "protobuf"))]
+    let _ = encoding;
+
+    Vec::try_from(packet)
+}
+
+/// Decode a packet in any encoding. Returns the packet along with its encoding
+pub fn decode(bytes: Vec<u8>) -> Result<(TrackerPacket, u8), &'static str> {
+    if bytes.first() != Some(&PROTOBUF_MARKER) {
+        return decode_json(bytes).map(|packet| (packet, ENCODING_JSON));
+    }
+
+    #[cfg(feature = "protobuf")]
+    return super::protobuf::decode(&bytes[1..]).map(|packet| (packet, ENCODING_PROTOBUF));
+    #[cfg(not(feature = "protobuf"))]
+    Err("Protobuf encoding is not supported")
+}
+
+/// Returns the current time in seconds since the Unix epoch
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+impl TrackerPacket {
+    /// Returns the message signed by the sender of the packet: the encoding of the
+    /// packet without its signature
+    pub fn signed_message(&self) -> Vec<u8> {
+        let unsigned = TrackerPacket {
+            signature: String::new(),
+            encodings: 0,
+            ..self.clone()
+        };
+
+        let mut message = SIGNATURE_CONTEXT.to_vec();
+        message.push(0);
+        message.extend(serde_json::to_vec(&unsigned).unwrap_or_default());
+        message
+    }
+
+    /// Sign the packet with `id`, which has to be the identity of the peer
+    /// [`TrackerPacket::username`]
+    /// # Errors
+    /// * [`AetherError::OpenSSLError`] - If the packet could not be signed
+    pub fn sign(&mut self, id: &Id) -> Result<(), AetherError> {
+        self.timestamp = unix_time();
+        self.signature = base64::encode(id.sign(&self.signed_message())?);
+        Ok(())
+    }
+
+    /// Verify that the packet was signed by the peer [`TrackerPacket::username`] no
+    /// longer than [`MAX_SIGNATURE_AGE`] ago
+    /// # Errors
+    /// * [`AetherError::TrackerSignature`] - If the packet is not signed, the signature
+    ///   does not verify or it expired
+    pub fn verify(&self) -> Result<(), AetherError> {
+        if self.signature.is_empty() {
+            return Err(AetherError::TrackerSignature("packet is not signed"));
+        }
+
+        let now = unix_time();
+        if self.timestamp.saturating_add(MAX_SIGNATURE_AGE) < now
+            || self.timestamp > now.saturating_add(MAX_SIGNATURE_AGE)
+        {
+            return Err(AetherError::TrackerSignature("signature expired"));
+        }
+
+        let verified = PublicId::from_base64(&self.username)
+            .and_then(|public_id| {
+                let signature = base64::decode(&self.signature)?;
+                public_id.verify(&self.signed_message(), &signature)
+            })
+            .unwrap_or(false);
+
+        if !verified {
+            return Err(AetherError::TrackerSignature("invalid signature"));
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<TrackerPacket> for Vec<u8> {
+    type Error = &'static str;
+
+    fn try_from(packet: TrackerPacket) -> Result<Self, Self::Error> {
+        match serde_json::to_string(&packet) {
+            Ok(json) => Ok(json.into_bytes()),
+            Err(_) => Err("Error converting to json"),
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for TrackerPacket {
+    type Error = &'static str;
+
+    /// Decode a packet in any encoding (refer [`decode`])
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        decode(bytes).map(|(packet, _)| packet)
+    }
+}
+
+fn decode_json(bytes: Vec<u8>) -> Result<TrackerPacket, &'static str> {
+    match String::from_utf8(bytes) {
+        Ok(json) => match serde_json::from_str(&json) {
+            Ok(data) => Ok(data),
+            Err(_) => Err("Unable to parse json"),
+        },
+        Err(_) => Err("Unable to parse utf8"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::AetherError;
+    use crate::identity::Id;
+    use crate::tracker::protocol::{
+        decode, encode, negotiate, supported_encodings, ConnectionRequest, TrackerPacket,
+        TrackerPacketType, ENCODING_JSON, ENCODING_PROTOBUF, MAX_SIGNATURE_AGE,
+        PACKET_TYPE_CONNECTION, PACKET_TYPE_POLL, PACKET_TYPE_PRESENCE, PROTOBUF_MARKER,
+    };
+    use std::convert::TryFrom;
+
+    fn round_trip(packet: TrackerPacket) {
+        let original_packet = packet.clone();
+
+        let parsed_packet: Vec<u8> = TryFrom::try_from(packet).unwrap();
+        let unparsed_packet: TrackerPacket = TryFrom::try_from(parsed_packet).unwrap();
+
+        assert_eq!(unparsed_packet, original_packet);
+    }
+
+    fn connection(identity_number: u32, username: &str) -> ConnectionRequest {
+        ConnectionRequest {
+            identity_number,
+            username: username.to_string(),
+            port: 4200,
+            ip: [42, 32, 22, 12],
+        }
+    }
+
+    #[test]
+    fn tracker_test() {
+        let packet = TrackerPacket {
+            identity_number: 42,
+            peer_username: "another".to_string(),
+            connections: vec![connection(32, "someone")],
+            username: "test".to_string(),
+            req: true,
+            packet_type: TrackerPacketType::Connection,
+            port: 1234,
+            ip: [1, 2, 3, 4],
+            mutual: false,
+            present: false,
+            ..Default::default()
+        };
+
+        round_trip(packet);
+    }
+
+    #[test]
+    fn default_test() {
+        round_trip(TrackerPacket::default());
+    }
+
+    #[test]
+    fn packet_type_test() {
+        for (packet_type, number) in [
+            (TrackerPacketType::Connection, PACKET_TYPE_CONNECTION),
+            (TrackerPacketType::Poll, PACKET_TYPE_POLL),
+            (TrackerPacketType::Presence, PACKET_TYPE_PRESENCE),
+        ] {
+            assert_eq!(u8::from(packet_type), number);
+            assert_eq!(TrackerPacketType::try_from(number), Ok(packet_type));
+
+            for req in [true, false] {
+                round_trip(TrackerPacket {
+                    username: "test".to_string(),
+                    packet_type,
+                    req,
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Packets of unknown types are not decoded
+        for number in [0, 1, 5, u8::MAX] {
+            assert!(TrackerPacketType::try_from(number).is_err());
+            let json = format!(
+                r#"{{"identity_number":0,"username":"test","peer_username":"","req":true,"packet_type":{},"port":0,"ip":[0,0,0,0],"connections":[]}}"#,
+                number
+            );
+            assert!(TrackerPacket::try_from(json.into_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn limits_test() {
+        let packet = TrackerPacket {
+            identity_number: u32::MAX,
+            username: "\u{1F600} \"quoted\" \\ ünïcödé".to_string(),
+            peer_username: String::new(),
+            req: false,
+            packet_type: TrackerPacketType::Presence,
+            port: u16::MAX,
+            ip: [255, 255, 255, 255],
+            connections: vec![ConnectionRequest {
+                identity_number: u32::MAX,
+                username: String::new(),
+                port: u16::MAX,
+                ip: [0, 0, 0, 0],
+            }],
+            mutual: true,
+            present: true,
+            timestamp: u64::MAX,
+            signature: "c2lnbmF0dXJl".to_string(),
+            encodings: u8::MAX,
+        };
+
+        round_trip(packet);
+    }
+
+    #[test]
+    fn connections_test() {
+        let connections = (0..100)
+            .map(|i| connection(i, &format!("peer{}", i)))
+            .collect();
+
+        round_trip(TrackerPacket {
+            username: "test".to_string(),
+            packet_type: TrackerPacketType::Poll,
+            connections,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn wire_format_test() {
+        // Changing this encoding breaks compatibility with the tracker server
+        let wire = concat!(
+            r#"{"identity_number":1,"username":"test","peer_username":"another","#,
+            r#""req":true,"packet_type":2,"port":1234,"ip":[1,2,3,4],"#,
+            r#""connections":[{"identity_number":32,"username":"someone","#,
+            r#""port":4200,"ip":[42,32,22,12]}]}"#
+        );
+
+        let packet = TrackerPacket {
+            identity_number: 1,
+            username: "test".to_string(),
+            peer_username: "another".to_string(),
+            req: true,
+            packet_type: TrackerPacketType::Connection,
+            port: 1234,
+            ip: [1, 2, 3, 4],
+            connections: vec![connection(32, "someone")],
+            mutual: false,
+            present: false,
+            ..Default::default()
+        };
+
+        let encoded: Vec<u8> = TryFrom::try_from(packet.clone()).unwrap();
+        assert_eq!(String::from_utf8(encoded).unwrap(), wire);
+
+        let decoded = TrackerPacket::try_from(wire.as_bytes().to_vec()).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn mutual_test() {
+        let packet = TrackerPacket {
+            username: "test".to_string(),
+            peer_username: "another".to_string(),
+            packet_type: TrackerPacketType::Connection,
+            req: true,
+            mutual: true,
+            ..Default::default()
+        };
+
+        let encoded: Vec<u8> = TryFrom::try_from(packet.clone()).unwrap();
+        assert!(String::from_utf8(encoded.clone())
+            .unwrap()
+            .ends_with(r#","mutual":true}"#));
+        assert_eq!(TrackerPacket::try_from(encoded).unwrap(), packet);
+    }
+
+    #[test]
+    fn presence_test() {
+        let packet = TrackerPacket {
+            username: "test".to_string(),
+            peer_username: "another".to_string(),
+            packet_type: TrackerPacketType::Presence,
+            present: true,
+            ..Default::default()
+        };
+
+        let encoded: Vec<u8> = TryFrom::try_from(packet.clone()).unwrap();
+        assert!(String::from_utf8(encoded.clone())
+            .unwrap()
+            .ends_with(r#","present":true}"#));
+        assert_eq!(TrackerPacket::try_from(encoded).unwrap(), packet);
+
+        // Absent peers are answered without the field, as by trackers on version 2
+        let absent = r#"{"identity_number":0,"username":"test","peer_username":"another","req":false,"packet_type":4,"port":0,"ip":[0,0,0,0],"connections":[]}"#;
+        let decoded = TrackerPacket::try_from(absent.as_bytes().to_vec()).unwrap();
+        assert!(!decoded.present);
+    }
+
+    #[test]
+    fn signature_test() {
+        let id = Id::new().unwrap();
+        let mut packet = TrackerPacket {
+            username: id.public_key_to_base64().unwrap(),
+            peer_username: "another".to_string(),
+            packet_type: TrackerPacketType::Connection,
+            req: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            packet.verify(),
+            Err(AetherError::TrackerSignature(_))
+        ));
+
+        packet.sign(&id).unwrap();
+        packet.verify().unwrap();
+
+        // Signatures survive the encoding
+        let encoded: Vec<u8> = TryFrom::try_from(packet.clone()).unwrap();
+        let decoded = TrackerPacket::try_from(encoded).unwrap();
+        decoded.verify().unwrap();
+
+        // Packets changed after signing or claiming another UID are rejected
+        let mut changed = packet.clone();
+        changed.peer_username = "someone".to_string();
+        assert!(changed.verify().is_err());
+
+        let mut spoofed = packet.clone();
+        spoofed.username = Id::new().unwrap().public_key_to_base64().unwrap();
+        assert!(spoofed.verify().is_err());
+
+        let mut expired = packet.clone();
+        expired.timestamp -= MAX_SIGNATURE_AGE + 1;
+        expired.signature = base64::encode(id.sign(&expired.signed_message()).unwrap());
+        assert!(expired.verify().is_err());
+    }
+
+    #[test]
+    fn encodings_test() {
+        let id = Id::new().unwrap();
+        let mut packet = TrackerPacket {
+            username: id.public_key_to_base64().unwrap(),
+            packet_type: TrackerPacketType::Poll,
+            req: true,
+            connections: vec![connection(32, "someone")],
+            ..Default::default()
+        };
+        packet.sign(&id).unwrap();
+
+        // Listing encodings does not affect the signature
+        packet.encodings = supported_encodings();
+        packet.verify().unwrap();
+        let encoded: Vec<u8> = TryFrom::try_from(packet.clone()).unwrap();
+        assert!(String::from_utf8(encoded)
+            .unwrap()
+            .ends_with(&format!(r#","encodings":{}}}"#, supported_encodings())));
+
+        // Peers on earlier versions only decode JSON
+        assert_eq!(negotiate(0), ENCODING_JSON);
+        assert_eq!(negotiate(ENCODING_JSON), ENCODING_JSON);
+
+        let encoding = negotiate(ENCODING_JSON | ENCODING_PROTOBUF);
+        let encoded = encode(packet.clone(), encoding).unwrap();
+        let (decoded, decoded_encoding) = decode(encoded.clone()).unwrap();
+        assert_eq!(decoded, packet);
+        assert_eq!(decoded_encoding, encoding);
+        decoded.verify().unwrap();
+
+        if cfg!(feature = "protobuf") {
+            assert_eq!(encoding, ENCODING_PROTOBUF);
+            assert_eq!(encoded[0], PROTOBUF_MARKER);
+        } else {
+            assert_eq!(encoding, ENCODING_JSON);
+            assert!(decode(vec![PROTOBUF_MARKER]).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_test() {
+        assert!(TrackerPacket::try_from(vec![0xff, 0xfe]).is_err());
+        assert!(TrackerPacket::try_from(b"{".to_vec()).is_err());
+        assert!(TrackerPacket::try_from(b"{}".to_vec()).is_err());
+        assert!(TrackerPacket::try_from(Vec::new()).is_err());
+    }
+}
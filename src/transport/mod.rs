@@ -0,0 +1,62 @@
+//! Abstraction over the sockets used to exchange packets with other peers and the
+//! tracker server.
+//!
+//! Aether is designed for UDP, so [`UdpSocket`] implements [`Transport`] directly.
+//! [`TcpTransport`] carries the same packets over a TCP connection to a single peer,
+//! as a fallback for networks that block UDP. [`WebSocketTransport`] does the same
+//! over a WebSocket, for networks that only let web traffic through to a relay. Both
+//! are built on [`std::net`] and only run on native targets, not in a browser.
+
+pub mod tcp;
+pub mod websocket;
+
+use std::fmt::Debug;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+pub use tcp::TcpTransport;
+pub use websocket::WebSocketTransport;
+
+/// A socket that sends and receives individual packets (datagrams)
+pub trait Transport: Debug + Send + Sync {
+    /// Send `buf` as a single packet to `addr`, returns the number of bytes sent
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Receive a single packet into `buf`, returns the number of bytes read and the
+    /// address of the sender. Packets larger than `buf` are truncated
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Receive a single packet into `buf`, returns the number of bytes read
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv_from(buf).map(|(size, _)| size)
+    }
+
+    /// Set the timeout for receiving packets, [`None`] blocks indefinitely
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Returns the local address of the transport
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        UdpSocket::recv(self, buf)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
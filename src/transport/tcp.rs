@@ -0,0 +1,216 @@
+//! [`Transport`] over a TCP connection to a single peer
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::transport::Transport;
+
+/// Size of the length prefix of each packet sent over a [`TcpTransport`]
+pub const FRAME_HEADER_SIZE: usize = 2;
+
+/// Read half of a [`TcpTransport`] keeping bytes of packets that were not completely
+/// received before a read timed out
+#[derive(Debug)]
+struct FrameReader {
+    stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Remove the first complete packet from the pending bytes
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        if self.pending.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+
+        let size = u16::from_be_bytes([self.pending[0], self.pending[1]]) as usize;
+        if self.pending.len() < FRAME_HEADER_SIZE + size {
+            return None;
+        }
+
+        let frame = self.pending[FRAME_HEADER_SIZE..(FRAME_HEADER_SIZE + size)].to_vec();
+        self.pending.drain(..(FRAME_HEADER_SIZE + size));
+        Some(frame)
+    }
+}
+
+/// [`Transport`] sending packets over a TCP connection to a single peer. Each packet
+/// is prefixed with its length as a 16 bit big endian integer
+#[derive(Debug)]
+pub struct TcpTransport {
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    writer: Mutex<TcpStream>,
+    reader: Mutex<FrameReader>,
+}
+
+impl TcpTransport {
+    /// Connect to a peer listening on `addr`
+    pub fn connect(addr: SocketAddr) -> io::Result<TcpTransport> {
+        TcpTransport::from_stream(TcpStream::connect(addr)?)
+    }
+
+    /// Accept a connection from a peer on `listener`
+    pub fn accept(listener: &TcpListener) -> io::Result<TcpTransport> {
+        let (stream, _) = listener.accept()?;
+        TcpTransport::from_stream(stream)
+    }
+
+    /// Creates a [`TcpTransport`] from an already connected [`TcpStream`]
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpTransport> {
+        // Packets are small and latency sensitive
+        stream.set_nodelay(true)?;
+
+        Ok(TcpTransport {
+            peer_addr: stream.peer_addr()?,
+            local_addr: stream.local_addr()?,
+            writer: Mutex::new(stream.try_clone()?),
+            reader: Mutex::new(FrameReader {
+                stream,
+                pending: Vec::new(),
+            }),
+        })
+    }
+
+    /// Returns the address of the peer at the other end of the connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if addr != self.peer_addr {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "TCP transport can only send to the connected peer",
+            ));
+        }
+
+        if buf.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Packet too large for TCP transport",
+            ));
+        }
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + buf.len());
+        frame.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        frame.extend_from_slice(buf);
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => return Err(io::Error::new(ErrorKind::Other, "Unable to lock writer")),
+        };
+        writer.write_all(&frame)?;
+
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut reader = match self.reader.lock() {
+            Ok(reader) => reader,
+            Err(_) => return Err(io::Error::new(ErrorKind::Other, "Unable to lock reader")),
+        };
+
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(frame) = reader.take_frame() {
+                let size = frame.len().min(buf.len());
+                buf[..size].copy_from_slice(&frame[..size]);
+                return Ok((size, self.peer_addr));
+            }
+
+            let size = reader.stream.read(&mut chunk)?;
+            if size == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "TCP connection closed by peer",
+                ));
+            }
+
+            reader.pending.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        // The reader and writer share the same socket. The reader may be locked by
+        // a blocking read, so the timeout is set through the writer
+        match self.writer.lock() {
+            Ok(writer) => writer.set_read_timeout(timeout),
+            Err(_) => Err(io::Error::new(ErrorKind::Other, "Unable to lock writer")),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TcpTransport;
+    use crate::transport::Transport;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    fn pair() -> (TcpTransport, TcpTransport) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = thread::spawn(move || TcpTransport::accept(&listener).unwrap());
+        let client = TcpTransport::connect(addr).unwrap();
+
+        (client, accept.join().unwrap())
+    }
+
+    #[test]
+    fn tcp_test() {
+        let (client, server) = pair();
+
+        let packets: Vec<Vec<u8>> = vec![vec![1, 2, 3], Vec::new(), vec![42; 2000]];
+        for packet in &packets {
+            let size = client.send_to(packet, client.peer_addr()).unwrap();
+            assert_eq!(size, packet.len());
+        }
+
+        let mut buf = [0; 4096];
+        for packet in &packets {
+            let (size, source) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..size], &packet[..]);
+            assert_eq!(source, client.local_addr().unwrap());
+        }
+
+        assert!(client.send_to(&[1], client.local_addr().unwrap()).is_err());
+        assert!(client
+            .send_to(&vec![0; 70_000], client.peer_addr())
+            .is_err());
+    }
+
+    #[test]
+    fn partial_frame_test() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let transport = TcpTransport::accept(&listener).unwrap();
+
+        transport
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        // Half of a packet is kept when the read times out
+        stream.write_all(&[0, 4, 1, 2]).unwrap();
+        let mut buf = [0; 16];
+        assert!(transport.recv(&mut buf).is_err());
+
+        stream.write_all(&[3, 4]).unwrap();
+        let size = transport.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], &[1, 2, 3, 4]);
+
+        drop(stream);
+        assert!(transport.recv(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,592 @@
+//! [`Transport`] over a WebSocket ([RFC 6455](https://www.rfc-editor.org/rfc/rfc6455))
+//! connection to a single peer.
+//!
+//! Each packet is sent as a single binary message, for networks that only let web
+//! traffic through to a relay. Frames and messages larger than a packet
+//! ([`max_packet_size`]) are rejected, as are frames of clients that are not masked, and
+//! the connection is closed.
+//!
+//! The transport is built on [`TcpStream`], so it only runs on native targets. Peers
+//! compiled to WASM cannot use it in a browser, which only gives access to WebSockets
+//! through its own API.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use openssl::sha::sha1;
+
+use crate::packet::max_packet_size;
+use crate::transport::Transport;
+use crate::util::gen_nonce;
+
+/// Value appended to the key of the opening handshake to compute the accept value
+pub const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest opening handshake accepted (in bytes)
+pub const MAX_HANDSHAKE_SIZE: usize = 8192;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A single WebSocket frame
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Encode the frame, masking the payload with `mask` if given (required for
+    /// frames sent by clients)
+    fn compile(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.payload.len() + 14);
+        bytes.push(((self.fin as u8) << 7) | self.opcode);
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        let len = self.payload.len();
+        if len < 126 {
+            bytes.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            bytes.push(mask_bit | 126);
+            bytes.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            bytes.push(mask_bit | 127);
+            bytes.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        match mask {
+            Some(mask) => {
+                bytes.extend_from_slice(&mask);
+                bytes.extend(
+                    self.payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ mask[i % 4]),
+                );
+            }
+            None => bytes.extend_from_slice(&self.payload),
+        }
+
+        bytes
+    }
+
+    /// Parse a frame from the start of `bytes`, returns the frame and the number of
+    /// bytes it takes up, or [`None`] if `bytes` does not hold a complete frame yet
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Bytes received so far
+    /// * `masked` - Whether the frame must be masked, as frames sent by clients are
+    /// * `max_len` - Largest payload accepted (in bytes)
+    ///
+    /// # Errors
+    ///
+    /// [`ErrorKind::InvalidData`] if the frame is masked when it must not be or the
+    /// other way around, or its payload is larger than `max_len`. Checked as soon as
+    /// the header is received
+    fn parse(bytes: &[u8], masked: bool, max_len: usize) -> io::Result<Option<(Frame, usize)>> {
+        if bytes.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin = bytes[0] & 0x80 != 0;
+        let opcode = bytes[0] & 0x0F;
+        if (bytes[1] & 0x80 != 0) != masked {
+            return Err(invalid_frame("wrong masking"));
+        }
+
+        let (len, mut offset) = match bytes[1] & 0x7F {
+            126 => {
+                if bytes.len() < 4 {
+                    return Ok(None);
+                }
+                (u16::from_be_bytes([bytes[2], bytes[3]]) as u64, 4)
+            }
+            127 => {
+                if bytes.len() < 10 {
+                    return Ok(None);
+                }
+                let mut len = [0; 8];
+                len.copy_from_slice(&bytes[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if len > max_len as u64 {
+            return Err(invalid_frame("frame too large"));
+        }
+        let len = len as usize;
+
+        let mut mask = None;
+        if masked {
+            if bytes.len() < offset + 4 {
+                return Ok(None);
+            }
+            mask = Some([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]);
+            offset += 4;
+        }
+
+        let end = offset + len;
+        if bytes.len() < end {
+            return Ok(None);
+        }
+
+        let payload = &bytes[offset..end];
+        let payload = match mask {
+            Some(mask) => payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4])
+                .collect(),
+            None => payload.to_vec(),
+        };
+
+        Ok(Some((
+            Frame {
+                fin,
+                opcode,
+                payload,
+            },
+            end,
+        )))
+    }
+}
+
+/// Returns the `Sec-WebSocket-Accept` value for the `Sec-WebSocket-Key` of a handshake
+pub fn accept_key(key: &str) -> String {
+    base64::encode(sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// Read the head of an HTTP request or response, up to and including the empty line
+fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+
+    // Read byte by byte so that no frame following the head is consumed
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed during WebSocket handshake",
+            ));
+        }
+        head.push(byte[0]);
+
+        if head.len() > MAX_HANDSHAKE_SIZE {
+            return Err(invalid_handshake("head too large"));
+        }
+    }
+
+    String::from_utf8(head).map_err(|_| invalid_handshake("head is not UTF-8"))
+}
+
+/// Returns the value of the header `name` (case insensitive) in an HTTP head
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+fn invalid_frame(reason: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid WebSocket frame: {}", reason),
+    )
+}
+
+fn invalid_handshake(reason: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid WebSocket handshake: {}", reason),
+    )
+}
+
+/// Read half of a [`WebSocketTransport`] keeping bytes of frames that were not
+/// completely received before a read timed out
+#[derive(Debug)]
+struct MessageReader {
+    stream: TcpStream,
+    pending: Vec<u8>,
+    /// Payload of the fragments of the current message received so far
+    message: Vec<u8>,
+}
+
+impl MessageReader {
+    /// Close the connection after the other peer broke the protocol, so that the link
+    /// stops instead of receiving the rest of the stream out of step. Returns `err`
+    fn fail(&mut self, err: io::Error) -> io::Error {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.pending.clear();
+        self.message.clear();
+        err
+    }
+}
+
+/// [`Transport`] sending each packet as a binary message over a WebSocket connection
+/// to a single peer (usually a relay)
+#[derive(Debug)]
+pub struct WebSocketTransport {
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    /// Set on the client side, which has to mask every frame it sends
+    client: bool,
+    writer: Mutex<TcpStream>,
+    reader: Mutex<MessageReader>,
+}
+
+impl WebSocketTransport {
+    /// Connect to a WebSocket server listening on `addr`, using `host` and `path`
+    /// in the opening handshake
+    pub fn connect(addr: SocketAddr, host: &str, path: &str) -> io::Result<WebSocketTransport> {
+        let mut stream = TcpStream::connect(addr)?;
+
+        let key = base64::encode(gen_nonce(16));
+        let request = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let response = read_head(&mut stream)?;
+        if !response.starts_with("HTTP/1.1 101") {
+            return Err(invalid_handshake("server did not switch protocols"));
+        }
+
+        if header(&response, "Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+            return Err(invalid_handshake("wrong accept key"));
+        }
+
+        WebSocketTransport::from_stream(stream, true)
+    }
+
+    /// Accept a WebSocket connection from a client on `listener`
+    pub fn accept(listener: &TcpListener) -> io::Result<WebSocketTransport> {
+        let (mut stream, _) = listener.accept()?;
+
+        let request = read_head(&mut stream)?;
+        if !request.starts_with("GET ") {
+            return Err(invalid_handshake("not a GET request"));
+        }
+
+        let upgrade = header(&request, "Upgrade").unwrap_or_default();
+        if !upgrade.eq_ignore_ascii_case("websocket") {
+            return Err(invalid_handshake("not a WebSocket upgrade"));
+        }
+
+        let key = match header(&request, "Sec-WebSocket-Key") {
+            Some(key) => key,
+            None => return Err(invalid_handshake("missing key")),
+        };
+
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        );
+        stream.write_all(response.as_bytes())?;
+
+        WebSocketTransport::from_stream(stream, false)
+    }
+
+    fn from_stream(stream: TcpStream, client: bool) -> io::Result<WebSocketTransport> {
+        // Packets are small and latency sensitive
+        stream.set_nodelay(true)?;
+
+        Ok(WebSocketTransport {
+            peer_addr: stream.peer_addr()?,
+            local_addr: stream.local_addr()?,
+            client,
+            writer: Mutex::new(stream.try_clone()?),
+            reader: Mutex::new(MessageReader {
+                stream,
+                pending: Vec::new(),
+                message: Vec::new(),
+            }),
+        })
+    }
+
+    /// Returns the address of the peer at the other end of the connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    fn send_frame(&self, frame: Frame) -> io::Result<()> {
+        let mask = if self.client {
+            let nonce = gen_nonce(4);
+            Some([nonce[0], nonce[1], nonce[2], nonce[3]])
+        } else {
+            None
+        };
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => return Err(io::Error::new(ErrorKind::Other, "Unable to lock writer")),
+        };
+        writer.write_all(&frame.compile(mask))
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if addr != self.peer_addr {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "WebSocket transport can only send to the connected peer",
+            ));
+        }
+
+        self.send_frame(Frame {
+            fin: true,
+            opcode: OPCODE_BINARY,
+            payload: buf.to_vec(),
+        })?;
+
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut reader = match self.reader.lock() {
+            Ok(reader) => reader,
+            Err(_) => return Err(io::Error::new(ErrorKind::Other, "Unable to lock reader")),
+        };
+
+        // Frames of clients are masked, those of servers are not
+        let masked = !self.client;
+        let max_len = max_packet_size();
+
+        let mut chunk = [0; 4096];
+        loop {
+            loop {
+                let (frame, size) = match Frame::parse(&reader.pending, masked, max_len) {
+                    Ok(Some(parsed)) => parsed,
+                    Ok(None) => break,
+                    Err(err) => return Err(reader.fail(err)),
+                };
+                reader.pending.drain(..size);
+
+                match frame.opcode {
+                    OPCODE_BINARY | OPCODE_TEXT | OPCODE_CONTINUATION => {
+                        if reader.message.len() + frame.payload.len() > max_len {
+                            return Err(reader.fail(invalid_frame("message too large")));
+                        }
+                        reader.message.extend(frame.payload);
+                        if frame.fin {
+                            let message = std::mem::take(&mut reader.message);
+                            let size = message.len().min(buf.len());
+                            buf[..size].copy_from_slice(&message[..size]);
+                            return Ok((size, self.peer_addr));
+                        }
+                    }
+                    OPCODE_PING => self.send_frame(Frame {
+                        fin: true,
+                        opcode: OPCODE_PONG,
+                        payload: frame.payload,
+                    })?,
+                    OPCODE_CLOSE => {
+                        return Err(io::Error::new(
+                            ErrorKind::ConnectionAborted,
+                            "WebSocket closed by peer",
+                        ))
+                    }
+                    // Pongs and unknown control frames are ignored
+                    _ => (),
+                }
+            }
+
+            let size = reader.stream.read(&mut chunk)?;
+            if size == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "WebSocket connection closed by peer",
+                ));
+            }
+
+            reader.pending.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        // The reader may be locked by a blocking read, so the timeout is set through
+        // the writer sharing the same socket
+        match self.writer.lock() {
+            Ok(writer) => writer.set_read_timeout(timeout),
+            Err(_) => Err(io::Error::new(ErrorKind::Other, "Unable to lock writer")),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        accept_key, Frame, WebSocketTransport, OPCODE_BINARY, OPCODE_CONTINUATION, OPCODE_PING,
+    };
+    use crate::packet::max_packet_size;
+    use crate::transport::Transport;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn accept_key_test() {
+        // Example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frame_test() {
+        for len in [0, 5, 125, 126, 1000, 70_000] {
+            let frame = Frame {
+                fin: true,
+                opcode: OPCODE_BINARY,
+                payload: (0..len).map(|i| i as u8).collect(),
+            };
+
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let masked = mask.is_some();
+                let bytes = frame.compile(mask);
+                assert_eq!(
+                    Frame::parse(&bytes, masked, len).unwrap(),
+                    Some((frame.clone(), bytes.len()))
+                );
+
+                // Incomplete frames are not parsed
+                let incomplete = &bytes[..(bytes.len() - 1)];
+                assert_eq!(Frame::parse(incomplete, masked, len).unwrap(), None);
+
+                // Frames masked the wrong way are rejected
+                assert!(Frame::parse(&bytes, !masked, len).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn frame_size_test() {
+        // The length is checked before the payload is received
+        let mut bytes = vec![0x80 | OPCODE_BINARY, 127];
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(Frame::parse(&bytes, false, max_packet_size()).is_err());
+
+        let frame = Frame {
+            fin: true,
+            opcode: OPCODE_BINARY,
+            payload: vec![0; max_packet_size() + 1],
+        };
+        let bytes = frame.compile(None);
+        assert!(Frame::parse(&bytes[..10], false, max_packet_size()).is_err());
+        assert!(Frame::parse(&bytes, false, max_packet_size() + 1).is_ok());
+    }
+
+    #[test]
+    fn websocket_test() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = thread::spawn(move || WebSocketTransport::accept(&listener).unwrap());
+        let client = WebSocketTransport::connect(addr, "localhost", "/aether").unwrap();
+        let server = accept.join().unwrap();
+
+        let packets: Vec<Vec<u8>> = vec![vec![1, 2, 3], Vec::new(), vec![42; 2000]];
+        for packet in &packets {
+            client.send_to(packet, client.peer_addr()).unwrap();
+        }
+
+        // Pings are answered and do not show up as packets
+        server
+            .send_frame(Frame {
+                fin: true,
+                opcode: OPCODE_PING,
+                payload: vec![7],
+            })
+            .unwrap();
+        server.send_to(&[9, 9], server.peer_addr()).unwrap();
+
+        let mut buf = [0; 4096];
+        for packet in &packets {
+            let (size, source) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..size], &packet[..]);
+            assert_eq!(source, client.local_addr().unwrap());
+        }
+
+        let size = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], &[9, 9]);
+
+        assert!(client.send_to(&[1], client.local_addr().unwrap()).is_err());
+    }
+
+    /// Returns a server transport and the raw stream of a client that completed the
+    /// opening handshake with it
+    fn raw_client() -> (WebSocketTransport, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = thread::spawn(move || WebSocketTransport::accept(&listener).unwrap());
+        let client = WebSocketTransport::connect(addr, "localhost", "/aether").unwrap();
+        let server = accept.join().unwrap();
+
+        let stream = client.writer.lock().unwrap().try_clone().unwrap();
+        (server, stream)
+    }
+
+    #[test]
+    fn violation_test() {
+        let mut buf = vec![0; max_packet_size()];
+
+        // Frames of clients must be masked
+        let (server, mut stream) = raw_client();
+        let frame = Frame {
+            fin: true,
+            opcode: OPCODE_BINARY,
+            payload: vec![1, 2, 3],
+        };
+        stream.write_all(&frame.compile(None)).unwrap();
+        assert!(server.recv(&mut buf).is_err());
+        // and the connection is closed
+        assert!(server.recv(&mut buf).is_err());
+
+        // Messages are no larger than a packet, whatever the size of their fragments
+        let (server, mut stream) = raw_client();
+        let fragment = |fin, opcode| Frame {
+            fin,
+            opcode,
+            payload: vec![0; max_packet_size() / 2 + 1],
+        };
+        stream
+            .write_all(&fragment(false, OPCODE_BINARY).compile(Some([1, 2, 3, 4])))
+            .unwrap();
+        stream
+            .write_all(&fragment(true, OPCODE_CONTINUATION).compile(Some([1, 2, 3, 4])))
+            .unwrap();
+        assert!(server.recv(&mut buf).is_err());
+    }
+}
@@ -1,5 +1,9 @@
 //! General purpose utilities used by [`aether_lib`](crate) often.
 
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
 use rand::{rngs::OsRng, RngCore};
 
 /// Compile a 32-bit value into vector of bytes
@@ -11,9 +15,12 @@ use rand::{rngs::OsRng, RngCore};
 /// # Examples
 ///
 /// ```
+/// # #[cfg(feature = "raw")] {
 /// use aether_lib::util::compile_u32;
 /// let bytes: Vec<u8> = compile_u32(32);
+/// # }
 /// ```
+#[cfg(feature = "raw")]
 pub fn compile_u32(nu32: u32) -> Vec<u8> {
     vec![
         (nu32 >> 24) as u8,
@@ -32,8 +39,10 @@ pub fn compile_u32(nu32: u32) -> Vec<u8> {
 /// # Examples
 ///
 /// ```
+/// # #[cfg(feature = "raw")] {
 /// use aether_lib::util::compile_u16;
 /// let bytes: Vec<u8> = compile_u16(3242);
+/// # }
 /// ```
 pub fn compile_u16(nu16: u16) -> Vec<u8> {
     vec![(nu16 >> 8) as u8, nu16 as u8]
@@ -48,9 +57,11 @@ pub fn compile_u16(nu16: u16) -> Vec<u8> {
 /// # Examples
 ///
 /// ```
+/// # #[cfg(feature = "raw")] {
 /// use aether_lib::util::gen_nonce;
 /// // to generate a 16 bytes nonce
 /// let nonce = gen_nonce(16);
+/// # }
 /// ```
 pub fn gen_nonce(size: usize) -> Vec<u8> {
     let mut buf = vec![0u8; size];
@@ -61,3 +72,181 @@ pub fn gen_nonce(size: usize) -> Vec<u8> {
 pub fn xor(lhs: Vec<u8>, rhs: Vec<u8>) -> Vec<u8> {
     lhs.iter().zip(rhs).map(|(x, y)| x ^ y).collect()
 }
+
+/// Lookup table of [`crc32`], one entry per byte value
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Compute the CRC-32 (IEEE 802.3) checksum of the given bytes
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use aether_lib::util::crc32;
+/// assert_eq!(crc32(b"123456789"), 0xCBF43926);
+/// # }
+/// ```
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Wakes up a thread waiting for work, so it does not have to poll for it
+///
+/// A notification sent while the thread is not waiting is kept until its next wait,
+/// so notifications cannot be missed. Several notifications before a wait wake the
+/// thread up only once
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+/// use aether_lib::util::Wakeup;
+///
+/// let wakeup = Arc::new(Wakeup::new());
+/// let notifier = wakeup.clone();
+/// thread::spawn(move || notifier.notify());
+///
+/// assert!(wakeup.wait_timeout(Duration::from_secs(5)));
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Wakeup {
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    pub fn new() -> Wakeup {
+        Wakeup::default()
+    }
+
+    /// Wake up the waiting thread, or the next one to wait
+    pub fn notify(&self) {
+        let mut notified_lock = self.notified.lock().expect("Unable to lock wakeup");
+        *notified_lock = true;
+        self.condvar.notify_all();
+    }
+
+    /// Block the current thread until notified or until `timeout` passed. Returns true
+    /// if notified
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let notified_lock = self.notified.lock().expect("Unable to lock wakeup");
+        let (mut notified_lock, _) = self
+            .condvar
+            .wait_timeout_while(notified_lock, timeout, |notified| !*notified)
+            .expect("Unable to lock wakeup");
+
+        let notified = *notified_lock;
+        *notified_lock = false;
+        notified
+    }
+}
+
+/// Signals threads to stop, waking them up from waits in between their work
+///
+/// Unlike a [`Wakeup`], the signal is never reset once [`Stop::stop`] is called, so it
+/// is seen by every thread sharing it
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+/// use aether_lib::util::Stop;
+///
+/// let stop = Arc::new(Stop::new());
+/// let stop_clone = stop.clone();
+/// let handle = thread::spawn(move || while !stop_clone.sleep(Duration::from_secs(60)) {});
+///
+/// stop.stop();
+/// handle.join().unwrap();
+/// assert!(stop.is_stopped());
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Stop {
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Stop {
+    pub fn new() -> Stop {
+        Stop::default()
+    }
+
+    /// Signal all threads to stop, waking up those that are sleeping
+    pub fn stop(&self) {
+        let mut stopped_lock = self.stopped.lock().expect("Unable to lock stop signal");
+        *stopped_lock = true;
+        self.condvar.notify_all();
+    }
+
+    /// Returns true if [`Stop::stop`] has been called
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.lock().expect("Unable to lock stop signal")
+    }
+
+    /// Block the current thread for `timeout` or until stopped. Returns true if stopped
+    pub fn sleep(&self, timeout: Duration) -> bool {
+        let stopped_lock = self.stopped.lock().expect("Unable to lock stop signal");
+        let (stopped_lock, _) = self
+            .condvar
+            .wait_timeout_while(stopped_lock, timeout, |stopped| !*stopped)
+            .expect("Unable to lock stop signal");
+
+        *stopped_lock
+    }
+}
+
+/// Run `f`, catching it panicking. Returns the panic message if it panicked, so that
+/// background threads can report why they stopped instead of dying silently
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "raw")] {
+/// use aether_lib::util::catch_panic;
+///
+/// assert_eq!(catch_panic(|| 42), Ok(42));
+/// assert_eq!(catch_panic(|| -> u8 { panic!("failed") }), Err("failed".to_string()));
+/// # }
+/// ```
+pub fn catch_panic<T, F: FnOnce() -> T>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown panic".to_string(),
+        }
+    })
+}
@@ -0,0 +1,193 @@
+//! Control frames exchanged between peers
+//!
+//! Features that need to exchange control messages with the other peer (for example
+//! capabilities, profiles, close reasons or group membership) define them as
+//! [`Control`] messages instead of their own byte layouts. Every message is sent in a
+//! control frame:
+//!
+//! ```text
+//! frame: version | kind (u16 BE) | message (CBOR)
+//! ```
+//!
+//! The version is [`CONTROL_VERSION`] and only changes with the layout of the frame
+//! itself. The kind identifies the message type, so frames can be dispatched using
+//! [`kind`] before decoding them. Messages are encoded as CBOR maps keyed by their field
+//! names.
+//!
+//! # Schema evolution
+//!
+//! Peers on different versions of the crate have to understand each other's messages,
+//! so changes to a [`Control`] message follow these rules:
+//!
+//! - New fields have a default (`#[serde(default)]`), which is used when decoding a
+//!   message from a peer that does not know the field yet
+//! - Decoders ignore fields they do not know, so messages must not deny unknown fields
+//! - Fields are never removed, renamed or changed to another type. Fields no longer
+//!   used keep being sent with their default value
+//! - Changes that cannot follow these rules define a new message with a new
+//!   [`Control::KIND`]. Kinds are never reused
+//! - Frames of unknown kinds are dropped by the receiver
+//!
+//! # Examples
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use aether_lib::wire::control::{self, Control};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Profile {
+//!     name: String,
+//!     #[serde(default)]
+//!     status: Option<String>,
+//! }
+//!
+//! impl Control for Profile {
+//!     const KIND: u16 = 0x8001;
+//! }
+//!
+//! let profile = Profile { name: "alice".to_string(), status: None };
+//! let frame = control::encode(&profile).unwrap();
+//!
+//! assert_eq!(control::kind(&frame).unwrap(), Profile::KIND);
+//! assert_eq!(control::decode::<Profile>(&frame).unwrap(), profile);
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::AetherError;
+use crate::util::compile_u16;
+
+/// Version of the control frame layout defined in this module
+pub const CONTROL_VERSION: u8 = 1;
+
+/// Size of the version and kind preceding the message
+const HEADER_SIZE: usize = 3;
+
+/// A message sent to the other peer in a control frame
+///
+/// Kinds below `0x8000` are reserved for messages of this crate, applications use kinds
+/// from `0x8000`
+pub trait Control: Serialize + DeserializeOwned {
+    /// Kind of the message, unique among all [`Control`] messages
+    const KIND: u16;
+}
+
+/// Encode `message` into a control frame
+/// # Errors
+/// * [`AetherError::ControlFrame`] - If the message cannot be encoded
+pub fn encode<C: Control>(message: &C) -> Result<Vec<u8>, AetherError> {
+    let mut frame = vec![CONTROL_VERSION];
+    frame.extend(compile_u16(C::KIND));
+
+    ciborium::ser::into_writer(message, &mut frame)
+        .map_err(|_| AetherError::ControlFrame("unable to encode message"))?;
+
+    Ok(frame)
+}
+
+/// Returns the kind of the message in the control frame `frame`
+/// # Errors
+/// * [`AetherError::ControlFrame`] - If the frame is truncated or of a newer version
+pub fn kind(frame: &[u8]) -> Result<u16, AetherError> {
+    if frame.len() < HEADER_SIZE {
+        return Err(AetherError::ControlFrame("truncated frame"));
+    }
+
+    if frame[0] > CONTROL_VERSION {
+        return Err(AetherError::ControlFrame("unsupported version"));
+    }
+
+    Ok(u16::from_be_bytes([frame[1], frame[2]]))
+}
+
+/// Decode the message in the control frame `frame`
+/// # Errors
+/// * [`AetherError::ControlFrame`] - If the frame does not hold a valid message of type
+///   `C`
+pub fn decode<C: Control>(frame: &[u8]) -> Result<C, AetherError> {
+    if kind(frame)? != C::KIND {
+        return Err(AetherError::ControlFrame("unexpected kind"));
+    }
+
+    ciborium::de::from_reader(&frame[HEADER_SIZE..])
+        .map_err(|_| AetherError::ControlFrame("invalid message"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{decode, encode, kind, Control, CONTROL_VERSION};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct MembershipV1 {
+        group: String,
+        members: Vec<String>,
+    }
+
+    impl Control for MembershipV1 {
+        const KIND: u16 = 0x8000;
+    }
+
+    /// Later version of [`MembershipV1`] with a field added
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct MembershipV2 {
+        group: String,
+        members: Vec<String>,
+        #[serde(default)]
+        epoch: u32,
+    }
+
+    impl Control for MembershipV2 {
+        const KIND: u16 = 0x8000;
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Close {
+        reason: u8,
+    }
+
+    impl Control for Close {
+        const KIND: u16 = 0x8001;
+    }
+
+    #[test]
+    fn control_test() {
+        let close = Close { reason: 2 };
+        let frame = encode(&close).unwrap();
+
+        assert_eq!(frame[..3], [CONTROL_VERSION, 0x80, 0x01]);
+        assert_eq!(kind(&frame).unwrap(), Close::KIND);
+        assert_eq!(decode::<Close>(&frame).unwrap(), close);
+
+        // Frames are checked before decoding
+        assert!(decode::<MembershipV1>(&frame).is_err());
+        assert!(decode::<Close>(&frame[..2]).is_err());
+        assert!(decode::<Close>(&frame[..frame.len() - 1]).is_err());
+
+        let mut newer = frame.clone();
+        newer[0] = CONTROL_VERSION + 1;
+        assert!(kind(&newer).is_err());
+    }
+
+    #[test]
+    fn evolution_test() {
+        let old = MembershipV1 {
+            group: "friends".to_string(),
+            members: vec!["alice".to_string(), "bob".to_string()],
+        };
+
+        // New fields take their default from old peers
+        let upgraded = decode::<MembershipV2>(&encode(&old).unwrap()).unwrap();
+        assert_eq!(upgraded.members, old.members);
+        assert_eq!(upgraded.epoch, 0);
+
+        // Old peers ignore fields they do not know
+        let new = MembershipV2 {
+            epoch: 7,
+            ..upgraded
+        };
+        assert_eq!(decode::<MembershipV1>(&encode(&new).unwrap()).unwrap(), old);
+    }
+}
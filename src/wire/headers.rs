@@ -0,0 +1,158 @@
+//! Headers attached to messages.
+//!
+//! Applications can convey metadata such as content types, message IDs or routing hints
+//! along with a message instead of wrapping every payload in an envelope of their own.
+//! A message sent with headers using [`Aether::send_to_with_headers`] is carried in an
+//! [`Annotated`] control frame (see [`control`]) sent as a packet of its own subtype
+//! (refer [`frames`]), which [`Aether::recv_from_ext`] and
+//! [`Aether::recv_timeout_from_ext`] return as a [`Message`]. Messages sent without
+//! headers are returned with empty headers.
+//!
+//! [`Aether::recv_from`], [`Aether::recv_timeout_from`] and [`Aether::recv_any`] return
+//! the payload of such messages without their headers.
+//!
+//! # Examples
+//!
+//! ```
+//! use aether_lib::wire::headers::{self, Headers};
+//!
+//! let mut headers = Headers::new();
+//! headers.insert("content-type".to_string(), "text/plain".to_string());
+//!
+//! let frame = headers::encode(&headers, b"Hello".to_vec()).unwrap();
+//! let message = headers::decode(&frame).unwrap();
+//!
+//! assert_eq!(message.headers, headers);
+//! assert_eq!(message.payload, b"Hello".to_vec());
+//! ```
+//!
+//! [`Aether::send_to_with_headers`]: crate::peer::Aether::send_to_with_headers
+//! [`Aether::recv_from_ext`]: crate::peer::Aether::recv_from_ext
+//! [`Aether::recv_timeout_from_ext`]: crate::peer::Aether::recv_timeout_from_ext
+//! [`Aether::recv_from`]: crate::peer::Aether::recv_from
+//! [`Aether::recv_timeout_from`]: crate::peer::Aether::recv_timeout_from
+//! [`Aether::recv_any`]: crate::peer::Aether::recv_any
+//! [`frames`]: crate::peer::frames
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AetherError;
+use crate::wire::control::{self, Control};
+
+/// Headers of a message, by name
+pub type Headers = BTreeMap<String, String>;
+
+/// Largest number of headers attached to a message
+pub const MAX_HEADER_COUNT: usize = 32;
+
+/// Largest total size of the names and values of the headers attached to a message
+/// (in bytes)
+pub const MAX_HEADERS_SIZE: usize = 1024;
+
+/// Control message carrying a message along with its headers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Annotated {
+    pub headers: Headers,
+    pub payload: Vec<u8>,
+}
+
+impl Control for Annotated {
+    const KIND: u16 = 0x0007;
+}
+
+/// Message received from a peer along with its headers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+    /// Headers attached by the sender, empty if it sent none
+    pub headers: Headers,
+    pub payload: Vec<u8>,
+}
+
+/// Check that `headers` are within [`MAX_HEADER_COUNT`] and [`MAX_HEADERS_SIZE`]
+/// # Errors
+/// * [`AetherError::InvalidHeaders`] - If the headers exceed the limits or a name is
+///   empty
+pub fn validate(headers: &Headers) -> Result<(), AetherError> {
+    if headers.len() > MAX_HEADER_COUNT {
+        return Err(AetherError::InvalidHeaders("too many headers"));
+    }
+
+    if headers.keys().any(|name| name.is_empty()) {
+        return Err(AetherError::InvalidHeaders("empty header name"));
+    }
+
+    let size: usize = headers
+        .iter()
+        .map(|(name, value)| name.len() + value.len())
+        .sum();
+    if size > MAX_HEADERS_SIZE {
+        return Err(AetherError::InvalidHeaders("headers too large"));
+    }
+
+    Ok(())
+}
+
+/// Encode `payload` along with `headers` into an [`Annotated`] control frame
+/// # Errors
+/// * [`AetherError::InvalidHeaders`] - If the headers are not valid (refer [`validate`])
+/// * [`AetherError::ControlFrame`] - If the frame cannot be encoded
+pub fn encode(headers: &Headers, payload: Vec<u8>) -> Result<Vec<u8>, AetherError> {
+    validate(headers)?;
+
+    control::encode(&Annotated {
+        headers: headers.clone(),
+        payload,
+    })
+}
+
+/// Decode the [`Message`] carried in the [`Annotated`] control frame `frame`
+/// # Errors
+/// * [`AetherError::ControlFrame`] - If `frame` is not a valid [`Annotated`] frame
+pub fn decode(frame: &[u8]) -> Result<Message, AetherError> {
+    let annotated: Annotated = control::decode(frame)?;
+
+    Ok(Message {
+        headers: annotated.headers,
+        payload: annotated.payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Headers, MAX_HEADERS_SIZE, MAX_HEADER_COUNT};
+    use crate::error::AetherError;
+
+    #[test]
+    fn message_test() {
+        let mut headers = Headers::new();
+        headers.insert("id".to_string(), "42".to_string());
+        headers.insert("route".to_string(), "inbox".to_string());
+
+        let received = decode(&encode(&headers, vec![1, 2, 3]).unwrap()).unwrap();
+        assert_eq!(received.headers, headers);
+        assert_eq!(received.payload, vec![1, 2, 3]);
+
+        // Bytes of messages are not frames
+        assert!(matches!(
+            decode(b"Hello"),
+            Err(AetherError::ControlFrame(_))
+        ));
+
+        let too_many: Headers = (0..=MAX_HEADER_COUNT)
+            .map(|i| (i.to_string(), String::new()))
+            .collect();
+        assert!(matches!(
+            encode(&too_many, Vec::new()),
+            Err(AetherError::InvalidHeaders(_))
+        ));
+
+        let mut too_large = Headers::new();
+        too_large.insert("data".to_string(), "a".repeat(MAX_HEADERS_SIZE));
+        assert!(matches!(
+            encode(&too_large, Vec::new()),
+            Err(AetherError::InvalidHeaders(_))
+        ));
+    }
+}
@@ -0,0 +1,7 @@
+//! Encodings of data exchanged with other peers on a [`Link`][crate::link::Link], on top
+//! of the [`Packet`][crate::packet::Packet] format.
+
+pub mod control;
+pub mod headers;
+
+pub use control::{Control, CONTROL_VERSION};
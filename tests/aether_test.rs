@@ -5,13 +5,18 @@ mod tests {
 
     use std::{
         net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+        sync::Arc,
         thread,
     };
 
     use aether_lib::{
         config::Config,
         identity::Id,
-        peer::{handshake::handshake, Aether},
+        peer::{
+            handshake::{handshake, handshake_with_options, HandshakeOptions},
+            Aether,
+        },
+        stats::RejectionCounters,
         util::gen_nonce,
     };
 
@@ -114,6 +119,78 @@ mod tests {
         println!("Stopping");
     }
 
+    #[test]
+    fn handshake_pow_test() {
+        let socket1 = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+        let socket2 = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+
+        let id1 = Id::new().unwrap();
+        let id2 = Id::new().unwrap();
+
+        let uid1 = id1.public_key_to_base64().unwrap();
+        let uid2 = id2.public_key_to_base64().unwrap();
+
+        let uid1_clone = uid1.clone();
+        let uid2_clone = uid2.clone();
+
+        let peer_addr1 = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            socket1.local_addr().unwrap().port(),
+        );
+        let peer_addr2 = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            socket2.local_addr().unwrap().port(),
+        );
+
+        // A packet from an unexpected address must be rejected
+        let rogue = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+        rogue.send_to(b"spoofed", peer_addr1).unwrap();
+
+        let rejections = Arc::new(RejectionCounters::new());
+        let rejections_clone = rejections.clone();
+
+        // Only the first peer requires a proof-of-work
+        let thread1 = thread::spawn(move || {
+            handshake_with_options(
+                id1,
+                socket1,
+                peer_addr2,
+                uid1,
+                uid2_clone,
+                HandshakeOptions {
+                    pow_difficulty: 12,
+                    rejections: Some(rejections_clone),
+                    ..Default::default()
+                },
+                Config::default(),
+            )
+            .expect("Handshake failed")
+        });
+
+        let thread2 = thread::spawn(move || {
+            handshake(
+                id2,
+                socket2,
+                peer_addr1,
+                uid2,
+                uid1_clone,
+                Config::default(),
+            )
+            .expect("Handshake failed")
+        });
+
+        let link1 = thread1.join().expect("Thread panicked");
+        let link2 = thread2.join().expect("Thread panicked");
+
+        link1.send(b"Hello".to_vec()).unwrap();
+        assert_eq!(link2.recv().unwrap(), b"Hello".to_vec());
+
+        link1.stop().unwrap();
+        link2.stop().unwrap();
+
+        assert_eq!(rejections.snapshot().unexpected_address, 1);
+    }
+
     pub fn init_linked_aether() -> (Aether, Aether) {
         let tracker_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8000);
         let aether1 = Aether::new_with_id(Id::new().unwrap(), tracker_addr);
@@ -125,9 +202,9 @@ mod tests {
         aether1.start();
         aether2.start();
 
-        aether1.connect(aether2.get_uid());
+        aether1.connect(aether2.get_uid()).unwrap();
 
-        aether2.connect(aether1.get_uid());
+        aether2.connect(aether1.get_uid()).unwrap();
 
         aether1
             .wait_connection(aether2.get_uid())
@@ -1,12 +1,61 @@
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+    use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+    use std::sync::mpsc;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use aether_lib::config::Config;
+    use aether_lib::encryption::CIPHER_NAME;
+    use aether_lib::error::AetherError;
     use aether_lib::identity::{Id, PublicId};
-    use aether_lib::link::Link;
+    use aether_lib::link::{CloseReason, Link};
+    use aether_lib::packet::MAX_PAYLOAD_SIZE;
+    use aether_lib::sequence::Seq;
+    use aether_lib::transport::{TcpTransport, Transport};
+
+    /// Create two links over UDP on localhost, connected to each other but not started
+    fn linked_pair(config: Config) -> (Link, Link) {
+        linked_pair_with(config, config)
+    }
+
+    /// Like [`linked_pair`], with a different config for each link
+    fn linked_pair_with(config1: Config, config2: Config) -> (Link, Link) {
+        let socket1 = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let socket2 = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+
+        let peer_addr1 = socket1.local_addr().unwrap();
+        let peer_addr2 = socket2.local_addr().unwrap();
+
+        let id1 = Id::new().unwrap();
+        let id2 = Id::new().unwrap();
+
+        let id1_public = PublicId::from_base64(&id1.public_key_to_base64().unwrap()).unwrap();
+        let id2_public = PublicId::from_base64(&id2.public_key_to_base64().unwrap()).unwrap();
+
+        let link1 = Link::new(
+            id1,
+            socket1,
+            peer_addr2,
+            id2_public,
+            Seq(0),
+            Seq(1000),
+            config1,
+        )
+        .unwrap();
+        let link2 = Link::new(
+            id2,
+            socket2,
+            peer_addr1,
+            id1_public,
+            Seq(1000),
+            Seq(0),
+            config2,
+        )
+        .unwrap();
+
+        (link1, link2)
+    }
 
     #[test]
     fn link_test() {
@@ -30,8 +79,8 @@ mod tests {
             socket1,
             peer_addr2,
             id2_public,
-            0,
-            1000,
+            Seq(0),
+            Seq(1000),
             Config::default(),
         )
         .unwrap();
@@ -40,8 +89,8 @@ mod tests {
             socket2,
             peer_addr1,
             id1_public,
-            1000,
-            0,
+            Seq(1000),
+            Seq(0),
             Config::default(),
         )
         .unwrap();
@@ -80,6 +129,13 @@ mod tests {
             println!("{} == {}", a, b);
             assert_eq!(recv[i], data[i]);
         }
+
+        let stats1 = link1.stats().unwrap();
+        let stats2 = link2.stats().unwrap();
+        assert!(stats1.packets_sent >= data.len() as u64);
+        assert!(stats2.packets_received >= data.len() as u64);
+        assert!(stats1.bytes_sent > stats1.packets_sent);
+        assert!(stats1.loss_rate <= 1.0);
     }
 
     #[test]
@@ -104,8 +160,8 @@ mod tests {
             socket1,
             peer_addr2,
             id2_public,
-            0,
-            1000,
+            Seq(0),
+            Seq(1000),
             Config::default(),
         )
         .unwrap();
@@ -114,8 +170,8 @@ mod tests {
             socket2,
             peer_addr1,
             id1_public,
-            1000,
-            0,
+            Seq(1000),
+            Seq(0),
             Config::default(),
         )
         .unwrap();
@@ -135,6 +191,14 @@ mod tests {
             handle2.join().unwrap();
         })
         .unwrap();
+
+        let negotiated = link1.negotiated().unwrap();
+        assert_eq!(negotiated.version, link2.negotiated().unwrap().version);
+        assert_eq!(negotiated.cipher, Some(CIPHER_NAME));
+        assert!(negotiated.capabilities.keepalive);
+        let sas = link1.short_auth_string().unwrap();
+        assert!(sas.is_some());
+        assert_eq!(sas, link2.short_auth_string().unwrap());
         let mut data: Vec<Vec<u8>> = Vec::new();
 
         for i in 1..100 {
@@ -165,4 +229,633 @@ mod tests {
             assert_eq!(recv[i], data[i]);
         }
     }
+
+    #[test]
+    fn keepalive_test() {
+        let mut config = Config::default();
+        config.link.keepalive_interval = 200;
+        config.link.keepalive_misses = 3;
+        // Stop without telling the other peer, as if it went away
+        config.link.close_timeout = 0;
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+
+        // Idle links are kept alive by keepalives
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!link1.is_stopped().unwrap());
+        assert!(!link2.is_stopped().unwrap());
+
+        // A dead peer is detected well before the link timeout
+        link1.stop().unwrap();
+        thread::sleep(Duration::from_millis(2500));
+        assert!(link2.is_stopped().unwrap());
+    }
+
+    #[test]
+    fn fast_path_test() {
+        // An idle send thread waits this long before looking for new packets
+        let mut config = Config::default();
+        config.link.ack_only_time = 200;
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+
+        let mut latencies = Vec::new();
+        for i in 0..5 {
+            thread::sleep(Duration::from_millis(250));
+
+            let start = Instant::now();
+            link1.send(format!("Ping {}", i).into_bytes()).unwrap();
+            let received = link2.recv_timeout(Duration::from_secs(5)).unwrap();
+            latencies.push(start.elapsed());
+
+            assert_eq!(received, format!("Ping {}", i).into_bytes());
+        }
+
+        // Small messages on an idle link do not wait for the send thread
+        latencies.sort();
+        assert!(latencies[2] < Duration::from_millis(50), "{:?}", latencies);
+
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(link1.stats().unwrap().retransmissions, 0);
+    }
+
+    #[test]
+    fn bidirectional_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+
+        // Both peers send and receive at the same time
+        let messages = 1000;
+        let transfer = |link: Link| {
+            thread::spawn(move || {
+                let receiver = thread::spawn({
+                    let receiver = link.get_receiver().unwrap();
+                    move || (0..messages).for_each(|_| drop(receiver.recv().unwrap()))
+                });
+
+                for i in 0..messages {
+                    link.send(format!("Message {}", i).into_bytes()).unwrap();
+                }
+
+                receiver.join().unwrap();
+                link.wait_empty().unwrap();
+                link
+            })
+        };
+
+        let handle1 = transfer(link1);
+        let handle2 = transfer(link2);
+        let link1 = handle1.join().unwrap();
+        let link2 = handle2.join().unwrap();
+
+        // Acknowledgements are sent along with the data
+        for link in [&link1, &link2] {
+            let stats = link.stats().unwrap();
+            assert!(stats.ack_only_packets < messages / 10, "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn peer_closed_test() {
+        for encrypted in [false, true] {
+            let mut config = Config::default();
+            config.link.keepalive_interval = 200;
+            config.link.keepalive_misses = 3;
+
+            let (mut link1, mut link2) = linked_pair(config);
+
+            link1.start();
+            link2.start();
+
+            if encrypted {
+                let handle = thread::spawn(move || {
+                    link2.enable_encryption().unwrap();
+                    link2
+                });
+                link1.enable_encryption().unwrap();
+                link2 = handle.join().unwrap();
+            }
+
+            // Block on receiving from a peer that goes away
+            let (result_tx, result_rx) = mpsc::channel();
+            thread::spawn(move || {
+                result_tx.send(link2.recv()).unwrap();
+            });
+
+            thread::sleep(Duration::from_millis(200));
+            drop(link1);
+
+            let result = result_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("receiver still blocked after the peer went away");
+            assert!(matches!(result, Err(AetherError::LinkStopped(_))));
+        }
+    }
+
+    #[test]
+    fn close_test() {
+        for version in [7, 8] {
+            let config = Config::default();
+
+            let (mut link1, mut link2) = linked_pair(config);
+
+            link1.set_version(version);
+            link2.set_version(version);
+            link1.start();
+            link2.start();
+
+            link1.send(b"Hello".to_vec()).unwrap();
+            assert_eq!(
+                link2.recv_timeout(Duration::from_secs(5)).unwrap(),
+                b"Hello".to_vec()
+            );
+
+            let start = Instant::now();
+            link1.stop().unwrap();
+            assert_eq!(link1.close_reason().unwrap(), Some(CloseReason::Stopped));
+
+            if version < 8 {
+                // Older peers are not told and wait for the link to time out
+                thread::sleep(Duration::from_millis(500));
+                assert!(!link2.is_stopped().unwrap());
+                continue;
+            }
+
+            // The other end closes immediately instead of timing out
+            while !link2.is_stopped().unwrap() {
+                assert!(start.elapsed() < Duration::from_secs(2));
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(link2.close_reason().unwrap(), Some(CloseReason::Closed));
+            assert!(matches!(
+                link2.send(b"Hi".to_vec()),
+                Err(AetherError::LinkStopped(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn linger_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+
+        let handle = thread::spawn(move || {
+            link2.enable_encryption().unwrap();
+            link2
+        });
+        link1.enable_encryption().unwrap();
+        let link2 = handle.join().unwrap();
+
+        // Both ends still have messages queued when the link is stopped
+        let data: Vec<Vec<u8>> = (0..100).map(|i| vec![i as u8; 1000]).collect();
+        for x in &data {
+            link2.send(x.clone()).unwrap();
+        }
+        for x in &data {
+            link1.send(x.clone()).unwrap();
+        }
+        link1.stop().unwrap();
+
+        for x in &data {
+            assert_eq!(&link2.recv_timeout(Duration::from_secs(5)).unwrap(), x);
+        }
+        for x in &data {
+            assert_eq!(&link1.recv_timeout(Duration::from_secs(5)).unwrap(), x);
+        }
+
+        // Both ends are closed once everything has been read
+        assert!(matches!(
+            link1.recv_timeout(Duration::from_secs(1)),
+            Err(AetherError::LinkStopped(_))
+        ));
+        assert!(link2.is_stopped().unwrap());
+        assert_eq!(link2.close_reason().unwrap(), Some(CloseReason::Closed));
+        assert!(matches!(
+            link2.recv_timeout(Duration::from_secs(1)),
+            Err(AetherError::LinkStopped(_))
+        ));
+    }
+
+    #[test]
+    fn max_message_size_test() {
+        // The second link accepts smaller messages than the first one sends, as if
+        // the first one ignored the limit agreed on during the handshake
+        let config1 = Config::default();
+        let mut config2 = Config::default();
+        config2.link.max_message_size = 100;
+
+        let (mut link1, mut link2) = linked_pair_with(config1, config2);
+
+        link1.start();
+        link2.start();
+
+        assert_eq!(link2.negotiated().unwrap().max_message_size, 100);
+        assert!(matches!(
+            link2.send(vec![0; 101]),
+            Err(AetherError::MessageTooLarge(100))
+        ));
+
+        link1.send(vec![1; 100]).unwrap();
+        assert_eq!(link2.recv().unwrap(), vec![1; 100]);
+
+        // The oversized message is never delivered and closes the link
+        link1.send(vec![2; 101]).unwrap();
+        assert!(matches!(link2.recv(), Err(AetherError::LinkStopped(_))));
+        assert_eq!(
+            link2.close_reason().unwrap(),
+            Some(CloseReason::ProtocolError)
+        );
+    }
+
+    #[test]
+    fn fragment_test() {
+        let mut config = Config::default();
+        config.link.send_queue_size = 8;
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        // Messages needing more packets than the send queue holds are never sent
+        // without blocking
+        assert!(matches!(
+            link1.try_send(vec![0; MAX_PAYLOAD_SIZE * 9]),
+            Err(AetherError::SendQueueFull(8))
+        ));
+
+        link1.start();
+        link2.start();
+        crossbeam::thread::scope(|s| {
+            let handle1 = s.spawn(|_| {
+                link1.enable_encryption().unwrap();
+            });
+            let handle2 = s.spawn(|_| {
+                link2.enable_encryption().unwrap();
+            });
+            handle1.join().unwrap();
+            handle2.join().unwrap();
+        })
+        .unwrap();
+
+        // Messages larger than a packet are split and joined again, in order with the
+        // messages around them
+        let large: Vec<u8> = (0..MAX_PAYLOAD_SIZE * 20).map(|i| i as u8).collect();
+        link1.send(b"Before".to_vec()).unwrap();
+        let handle = link1.send_tracked(large.clone()).unwrap();
+        link1.send(b"After".to_vec()).unwrap();
+
+        assert_eq!(link2.recv().unwrap(), b"Before".to_vec());
+        assert_eq!(link2.recv().unwrap(), large);
+        assert_eq!(link2.recv().unwrap(), b"After".to_vec());
+        assert!(handle.wait_timeout(Duration::from_secs(5)).unwrap());
+
+        let max_message_size = link1.negotiated().unwrap().max_message_size;
+        assert!(matches!(
+            link1.send(vec![0; max_message_size + 1]),
+            Err(AetherError::MessageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn max_window_test() {
+        // The second link only accepts a small window, as advertised in the handshake
+        let config1 = Config::default();
+        let mut config2 = Config::default();
+        config2.link.max_window = 4;
+
+        let (mut link1, mut link2) = linked_pair_with(config1, config2);
+
+        link1.set_max_window(config2.link.max_window);
+        link1.start();
+        link2.start();
+
+        let data: Vec<Vec<u8>> = (1..50)
+            .map(|i| format!("Hello {}", i).into_bytes())
+            .collect();
+
+        for x in &data {
+            link1.send(x.clone()).unwrap();
+        }
+
+        for x in &data {
+            assert_eq!(&link2.recv().unwrap(), x);
+        }
+
+        let negotiated = link1.negotiated().unwrap();
+        assert_eq!(negotiated.max_window, 4);
+        assert!(negotiated.window <= 4);
+    }
+
+    #[test]
+    fn send_queue_test() {
+        // Every message waits in the queue for the send thread
+        let mut config = Config::default();
+        config.link.send_queue_size = 2;
+        config.link.fast_path_size = 0;
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        // Nothing takes messages off the queue before the link is started
+        link1.try_send(b"Hello 0".to_vec()).unwrap();
+        link1.try_send(b"Hello 1".to_vec()).unwrap();
+        assert!(matches!(
+            link1.try_send(b"Hello 2".to_vec()),
+            Err(AetherError::SendQueueFull(2))
+        ));
+
+        link1.start();
+        link2.start();
+
+        // Blocking sends wait for the send thread to make space
+        let data: Vec<Vec<u8>> = (2..50)
+            .map(|i| format!("Hello {}", i).into_bytes())
+            .collect();
+        for x in &data {
+            link1.send(x.clone()).unwrap();
+        }
+
+        for i in 0..50 {
+            assert_eq!(link2.recv().unwrap(), format!("Hello {}", i).into_bytes());
+        }
+    }
+
+    #[test]
+    fn unbounded_send_queue_test() {
+        let mut config = Config::default();
+        config.link.send_queue_size = 0;
+        config.link.fast_path_size = 0;
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        // Nothing takes messages off the queue before the link is started, and an
+        // unbounded queue never fills up
+        let data: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("Hello {}", i).into_bytes())
+            .collect();
+        for x in &data {
+            link1.try_send(x.clone()).unwrap();
+        }
+        link1.try_send(vec![0; MAX_PAYLOAD_SIZE * 20]).unwrap();
+
+        link1.start();
+        link2.start();
+
+        for x in &data {
+            assert_eq!(&link2.recv().unwrap(), x);
+        }
+        assert_eq!(link2.recv().unwrap(), vec![0; MAX_PAYLOAD_SIZE * 20]);
+    }
+
+    #[test]
+    fn deadline_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        // Messages wait in the queue until the link is started, by when the deadline of
+        // the second one passed
+        let deadline = Instant::now() + Duration::from_millis(50);
+        link1.send(b"Hello 0".to_vec()).unwrap();
+        link1
+            .send_with_deadline(b"Stale".to_vec(), deadline)
+            .unwrap();
+        link1
+            .send_with_deadline(vec![0; MAX_PAYLOAD_SIZE * 3], deadline)
+            .unwrap();
+        link1
+            .send_with_deadline(b"Hello 1".to_vec(), deadline + Duration::from_secs(60))
+            .unwrap();
+        link1.send(b"Hello 2".to_vec()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        link1.start();
+        link2.start();
+
+        for i in 0..3 {
+            assert_eq!(link2.recv().unwrap(), format!("Hello {}", i).into_bytes());
+        }
+        // Every fragment of the stale message expired along with it
+        assert_eq!(link1.stats().unwrap().expired, 5);
+        assert_eq!(link2.stats().unwrap().expired, 0);
+    }
+
+    #[test]
+    fn cancel_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        // Messages cancelled before the link is started are never sent
+        link1.send(b"Hello 0".to_vec()).unwrap();
+        let unsent = link1.send_tracked(vec![0; MAX_PAYLOAD_SIZE * 3]).unwrap();
+        assert!(link1.cancel(unsent.id()).unwrap());
+        link1.send(b"Hello 1".to_vec()).unwrap();
+
+        // The other peer is started later, so that at most the first window of packets
+        // of the message is sent but not acknowledged when it is cancelled. The window
+        // is kept smaller than the message so that it is never sent whole
+        link1.set_max_window(8);
+        link1.start();
+        let partial = link1.send_tracked(vec![1; MAX_PAYLOAD_SIZE * 30]).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(link1.cancel(partial.id()).unwrap());
+        link1.send(b"Hello 2".to_vec()).unwrap();
+        link2.start();
+
+        // The other peer drops the fragments of the message it received
+        for i in 0..3 {
+            assert_eq!(link2.recv().unwrap(), format!("Hello {}", i).into_bytes());
+        }
+        assert!(unsent.is_cancelled().unwrap());
+        assert!(matches!(
+            partial.wait(),
+            Err(AetherError::MessageCancelled(id)) if id == partial.id()
+        ));
+
+        // Delivered messages cannot be cancelled
+        let delivered = link1.send_tracked(b"Hello 3".to_vec()).unwrap();
+        assert!(delivered.wait_timeout(Duration::from_secs(5)).unwrap());
+        assert!(!link1.cancel(delivered.id()).unwrap());
+        assert!(!delivered.is_cancelled().unwrap());
+        assert_eq!(link2.recv().unwrap(), b"Hello 3".to_vec());
+    }
+
+    #[test]
+    fn messages_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+        assert_eq!(link2.try_recv().unwrap(), None);
+
+        let data: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("Hello {}", i).into_bytes())
+            .collect();
+        for x in &data {
+            link1.send(x.clone()).unwrap();
+        }
+
+        let received: Vec<Vec<u8>> = link2.messages().take(10).collect();
+        assert_eq!(received, data[..10]);
+
+        // Messages received before the other end closed are still yielded, then the
+        // iteration ends
+        link1.wait_empty().unwrap();
+        link1.stop().unwrap();
+        let mut received = Vec::new();
+        for msg in &link2 {
+            received.push(msg);
+        }
+        assert_eq!(received, data[10..]);
+        assert!(link2.is_stopped().unwrap());
+        assert!(matches!(link2.try_recv(), Err(AetherError::LinkStopped(_))));
+    }
+
+    #[test]
+    fn tcp_link_test() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let transport1 = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let transport2 = TcpTransport::accept(&listener).unwrap();
+
+        let peer_addr1 = transport1.local_addr().unwrap();
+        let peer_addr2 = transport1.peer_addr();
+
+        let id1 = Id::new().unwrap();
+        let id2 = Id::new().unwrap();
+
+        let id1_public = PublicId::from_base64(&id1.public_key_to_base64().unwrap()).unwrap();
+        let id2_public = PublicId::from_base64(&id2.public_key_to_base64().unwrap()).unwrap();
+
+        let config = Config::default();
+
+        let mut link1 = Link::new(
+            id1,
+            transport1,
+            peer_addr2,
+            id2_public,
+            Seq(0),
+            Seq(1000),
+            config,
+        )
+        .unwrap();
+        let mut link2 = Link::new(
+            id2,
+            transport2,
+            peer_addr1,
+            id1_public,
+            Seq(1000),
+            Seq(0),
+            config,
+        )
+        .unwrap();
+
+        link1.start();
+        link2.start();
+
+        let data: Vec<Vec<u8>> = (1..50)
+            .map(|i| format!("Hello {}", i).into_bytes())
+            .collect();
+
+        for x in &data {
+            link1.send(x.clone()).unwrap();
+        }
+
+        for x in &data {
+            assert_eq!(&link2.recv().unwrap(), x);
+        }
+    }
+
+    #[test]
+    fn expiry_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+
+        link1.start();
+        link2.start();
+        link1.set_expiry(Duration::from_millis(300));
+
+        assert_eq!(link1.close_reason().unwrap(), None);
+        link1.send(b"Hello".to_vec()).unwrap();
+        assert_eq!(link2.recv().unwrap(), b"Hello".to_vec());
+
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(link1.close_reason().unwrap(), Some(CloseReason::Expired));
+        assert!(link1.send(b"Hello".to_vec()).is_err());
+
+        // Stopping an expired link keeps the reason
+        link1.stop().unwrap();
+        assert_eq!(link1.close_reason().unwrap(), Some(CloseReason::Expired));
+
+        link2.stop().unwrap();
+        assert_eq!(link2.close_reason().unwrap(), Some(CloseReason::Stopped));
+        assert_eq!(CloseReason::Expired.code(), 3);
+    }
+    #[test]
+    fn extension_test() {
+        let config = Config::default();
+
+        let (mut link1, mut link2) = linked_pair(config);
+        link1.start();
+        link2.start();
+        crossbeam::thread::scope(|s| {
+            let handle1 = s.spawn(|_| link1.enable_encryption().unwrap());
+            let handle2 = s.spawn(|_| link2.enable_encryption().unwrap());
+            handle1.join().unwrap();
+            handle2.join().unwrap();
+        })
+        .unwrap();
+        assert!(link1.negotiated().unwrap().capabilities.extensions);
+
+        let (sender, receiver) = mpsc::channel();
+        link2
+            .register_extension(1, move |payload| sender.send(payload).unwrap())
+            .unwrap();
+        assert!(matches!(
+            link2.register_extension(1, |_| ()),
+            Err(AetherError::ExtensionTaken(1))
+        ));
+
+        link1.send(b"Hello".to_vec()).unwrap();
+        link1.send_extended(1, b"ping".to_vec()).unwrap();
+        // Subtypes without a handler are dropped
+        link1.send_extended(2, b"pong".to_vec()).unwrap();
+        link1.send(b"World".to_vec()).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(link2.recv_timeout(timeout).unwrap(), b"Hello".to_vec());
+        assert_eq!(link2.recv_timeout(timeout).unwrap(), b"World".to_vec());
+        assert_eq!(receiver.recv_timeout(timeout).unwrap(), b"ping".to_vec());
+        assert!(receiver.try_recv().is_err());
+
+        assert!(link2.unregister_extension(1).unwrap());
+        assert!(!link2.unregister_extension(1).unwrap());
+
+        // Peers on older versions do not handle extended packets
+        let peer_addr = link2.local_addr().unwrap();
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let id = Id::new().unwrap();
+        let peer_id = PublicId::from_base64(&id.public_key_to_base64().unwrap()).unwrap();
+        let mut old_link =
+            Link::new(id, socket, peer_addr, peer_id, Seq(0), Seq(0), config).unwrap();
+        old_link.set_version(11);
+        assert!(matches!(
+            old_link.send_extended(1, b"ping".to_vec()),
+            Err(AetherError::ExtensionUnsupported(11))
+        ));
+    }
 }
@@ -0,0 +1,493 @@
+//! Generates the protocol reference in `docs/PROTOCOL.md` from the definitions of the
+//! wire format and their doc comments, and fails if the checked in reference is out of
+//! date. Run with `AETHER_UPDATE_PROTOCOL_DOC=1` to write the reference instead.
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fmt::Write;
+    use std::fs;
+    use std::path::Path;
+
+    use aether_lib::acknowledgement::{AcknowledgementList, MAX_MISS_COUNT, MAX_WINDOW};
+    use aether_lib::encryption::{AetherCipher, CIPHER_NAME, IV_SIZE, KEY_SIZE, TAG_SIZE};
+    use aether_lib::identity::name::SignedName;
+    use aether_lib::packet::{
+        reserved_flags, PType, PacketBuilder, ACK_FLAG_CONGESTION, BASE_HEADER_SIZE, BASE_VERSION,
+        CHECKSUM_SIZE, FLAG_MORE_FRAGMENTS, FLAG_TAGGED, MAX_NACK_COUNT, MAX_PAYLOAD_SIZE,
+        META_TYPE, PACKET_TAG_SIZE, PROTOCOL_VERSION, SUBTYPE_SIZE, VERSION_FIELD_SIZE,
+    };
+    use aether_lib::peer::frames::{HEADERS_SUBTYPE, NAME_SUBTYPE, ROTATION_SUBTYPE};
+    use aether_lib::peer::handshake::{HELLO_SIZE, UID_DIGEST_SIZE};
+    use aether_lib::peer::rotation::{KeyRotation, RotationChallenge, RotationResponse};
+    use aether_lib::pubsub::{Publish, Subscriptions};
+    use aether_lib::sequence::Seq;
+    use aether_lib::wire::control::{Control, CONTROL_VERSION};
+    use aether_lib::wire::headers::Annotated;
+
+    const REFERENCE_PATH: &str = "docs/PROTOCOL.md";
+    const UPDATE_VAR: &str = "AETHER_UPDATE_PROTOCOL_DOC";
+
+    const PACKET_SOURCE: &str = include_str!("../src/packet.rs");
+    const PUBSUB_SOURCE: &str = include_str!("../src/pubsub.rs");
+    const ROTATION_SOURCE: &str = include_str!("../src/peer/rotation.rs");
+    const NAME_SOURCE: &str = include_str!("../src/identity/name.rs");
+    const HEADERS_SOURCE: &str = include_str!("../src/wire/headers.rs");
+
+    /// Returns the lines of the doc comment preceding the first line of `source`
+    /// starting with `item` (ignoring indentation), without the `///` markers
+    fn doc_lines(source: &str, item: &str) -> Vec<String> {
+        let lines: Vec<&str> = source.lines().collect();
+        let position = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with(item))
+            .unwrap_or_else(|| panic!("{} not found", item));
+
+        let mut docs: Vec<String> = lines[..position]
+            .iter()
+            .rev()
+            .map(|line| line.trim_start())
+            .take_while(|line| line.starts_with("///") || line.starts_with("#["))
+            .filter(|line| line.starts_with("///"))
+            .map(|line| line.trim_start_matches("///").trim().to_string())
+            .collect();
+        docs.reverse();
+        docs
+    }
+
+    /// Returns the doc comment of `item` as a single line of markdown
+    fn doc(source: &str, item: &str) -> String {
+        plain(&doc_lines(source, item).join(" "))
+    }
+
+    /// Replace the intra-doc links of rustdoc by their text
+    fn plain(text: &str) -> String {
+        let mut result = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '[' => {
+                    let inner: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                    result.push_str(&inner);
+                    // Skip the target of the link
+                    if chars.peek() == Some(&'[') {
+                        chars.by_ref().take_while(|c| *c != ']').for_each(drop);
+                    }
+                }
+                c => result.push(c),
+            }
+        }
+        result
+    }
+
+    /// Returns the features enabled by later protocol versions: name, version they were
+    /// added in and description, taken from the `has_*` functions
+    fn features() -> Vec<(String, u8, String)> {
+        let mut features = Vec::new();
+        let mut rest = PACKET_SOURCE;
+        while let Some(start) = rest.find("pub fn has_") {
+            let signature = &rest[start..];
+            let name = &signature["pub fn ".len()..signature.find('(').unwrap()];
+            let body = &signature[signature.find('{').unwrap() + 1..];
+            let version: u8 = body
+                .trim_start()
+                .trim_start_matches("version >=")
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let description = doc(PACKET_SOURCE, &format!("pub fn {}(", name));
+            features.push((name.to_string(), version, description));
+            rest = &signature[1..];
+        }
+
+        features.sort_by_key(|(_, version, _)| *version);
+        features
+    }
+
+    /// Returns the entries of the version history in the doc comment of
+    /// `PROTOCOL_VERSION`
+    fn versions() -> Vec<String> {
+        let mut versions: Vec<String> = Vec::new();
+        for line in doc_lines(PACKET_SOURCE, "pub const PROTOCOL_VERSION") {
+            if let Some(entry) = line.strip_prefix("* ") {
+                versions.push(plain(entry));
+            } else if let Some(last) = versions.last_mut() {
+                if !line.is_empty() {
+                    last.push(' ');
+                    last.push_str(&plain(&line));
+                }
+            }
+        }
+        versions
+    }
+
+    /// Returns the description of each [`PType`] variant by its name
+    fn ptype_doc(name: &str) -> String {
+        let enum_source = &PACKET_SOURCE[PACKET_SOURCE.find("pub enum PType").unwrap()..];
+        let enum_source = &enum_source[..enum_source.find('}').unwrap()];
+        let lines = doc_lines(enum_source, name);
+        plain(&lines.join(" "))
+    }
+
+    /// Returns the rows of the packet layout: field, size in bytes and condition
+    fn packet_layout() -> Vec<(&'static str, String, String)> {
+        vec![
+            ("sequence", "4".to_string(), "always".to_string()),
+            ("ack begin", "4".to_string(), "always".to_string()),
+            ("ack end", "2".to_string(), "always".to_string()),
+            ("flags", "1".to_string(), "always".to_string()),
+            ("miss count", "2".to_string(), "always".to_string()),
+            (
+                "version",
+                VERSION_FIELD_SIZE.to_string(),
+                "`has_header_version`".to_string(),
+            ),
+            (
+                "miss list",
+                "2 × miss count".to_string(),
+                "always".to_string(),
+            ),
+            (
+                "ack receive time (µs)",
+                "4".to_string(),
+                "ack flag and `has_ack_timestamps`".to_string(),
+            ),
+            (
+                "ack flags",
+                "1".to_string(),
+                "ack flag and `has_ack_flags`".to_string(),
+            ),
+            (
+                "subtype",
+                SUBTYPE_SIZE.to_string(),
+                "`Extended` packets".to_string(),
+            ),
+            ("payload", "rest".to_string(), "always".to_string()),
+            (
+                "authentication tag (HMAC-SHA-256)",
+                PACKET_TAG_SIZE.to_string(),
+                "tagged flag and `has_packet_tags`".to_string(),
+            ),
+            (
+                "checksum (CRC-32)",
+                CHECKSUM_SIZE.to_string(),
+                "`has_checksum`".to_string(),
+            ),
+        ]
+    }
+
+    fn generate() -> String {
+        let mut out = String::new();
+        let w = &mut out;
+
+        writeln!(w, "# Aether protocol reference").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "<!-- Generated by tests/protocol_doc_test.rs from the definitions of the wire \
+             format, do not edit. Run `{}=1 cargo test --test protocol_doc_test` to update. -->",
+            UPDATE_VAR
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "All integers are big endian. Sizes are in bytes unless noted otherwise."
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        // Versions
+        writeln!(w, "## Versions").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Latest version: {}. Packets exchanged before a version has been negotiated \
+             (such as handshake packets) use version {}.",
+            PROTOCOL_VERSION, BASE_VERSION
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        for version in versions() {
+            writeln!(w, "- {}", version).unwrap();
+        }
+        writeln!(w).unwrap();
+
+        writeln!(w, "### Features").unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Feature | Since | Description |").unwrap();
+        writeln!(w, "| --- | --- | --- |").unwrap();
+        for (name, version, description) in features() {
+            writeln!(w, "| `{}` | {} | {} |", name, version, description).unwrap();
+        }
+        writeln!(w).unwrap();
+
+        // Packets
+        writeln!(w, "## Packets").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "The fixed header is {} bytes, followed by the fields of later versions. \
+             Payloads are at most {} bytes.",
+            BASE_HEADER_SIZE, MAX_PAYLOAD_SIZE
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Field | Size | Present |").unwrap();
+        writeln!(w, "| --- | --- | --- |").unwrap();
+        for (field, size, condition) in packet_layout() {
+            writeln!(w, "| {} | {} | {} |", field, size, condition).unwrap();
+        }
+        writeln!(w).unwrap();
+
+        writeln!(w, "### Flags").unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Bits | Meaning |").unwrap();
+        writeln!(w, "| --- | --- |").unwrap();
+        writeln!(w, "| 7-4 | Packet type |").unwrap();
+        writeln!(w, "| 3 | Carries an acknowledgement |").unwrap();
+        writeln!(w, "| 2 | Payload is encrypted |").unwrap();
+        writeln!(
+            w,
+            "| {:#04b} | More fragments of the message follow (`has_fragments`) |",
+            FLAG_MORE_FRAGMENTS
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "| {:#04b} | Packet ends with an authentication tag (`has_packet_tags`) |",
+            FLAG_TAGGED
+        )
+        .unwrap();
+        if reserved_flags(PROTOCOL_VERSION) != 0 {
+            writeln!(
+                w,
+                "| {:#04b} | Reserved, packets with these bits set are rejected |",
+                reserved_flags(PROTOCOL_VERSION)
+            )
+            .unwrap();
+        }
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Acknowledgement flags: `{:#04b}` congestion experienced.",
+            ACK_FLAG_CONGESTION
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        writeln!(w, "### Packet types").unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Value | Type | Description |").unwrap();
+        writeln!(w, "| --- | --- | --- |").unwrap();
+        let mut reserved = Vec::new();
+        for value in 0..16u8 {
+            match PType::from(value) {
+                PType::Reserved(value) => reserved.push(value.to_string()),
+                PType::Extended(_) => writeln!(
+                    w,
+                    "| {} | Extended | {} |",
+                    value,
+                    ptype_doc("Extended(u8)")
+                )
+                .unwrap(),
+                p_type => {
+                    let name = p_type.to_string();
+                    writeln!(w, "| {} | {} | {} |", value, name, ptype_doc(&name)).unwrap()
+                }
+            }
+        }
+        writeln!(
+            w,
+            "| {} | Reserved | Not assigned. {} is used internally and never sent |",
+            reserved.join(", "),
+            META_TYPE
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        writeln!(w, "### Acknowledgements").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Packets with the ack flag acknowledge `ack begin` and every sequence number up \
+             to `ack begin + ack end`, except those at the offsets in the miss list. The miss \
+             list holds at most {} offsets and windows span at most {} packets.",
+            MAX_MISS_COUNT, MAX_WINDOW
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "NACK payloads list at most {} sequence numbers of 4 bytes each.",
+            MAX_NACK_COUNT
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        writeln!(w, "### Encryption").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Encrypted payloads use {}: tag ({}) | nonce ({}) | cipher text.",
+            CIPHER_NAME, TAG_SIZE, IV_SIZE
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        // Handshake
+        writeln!(w, "## Handshake").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Both peers send `Initiation` packets carrying a hello until they received and \
+             acknowledged the hello of the other peer. The hello holds the fields of the \
+             version it was compiled with, in this order:"
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Field | Size | Present |").unwrap();
+        writeln!(w, "| --- | --- | --- |").unwrap();
+        for (field, size, condition) in [
+            ("version", "1", "always"),
+            ("puzzle difficulty", "1", "`has_handshake_puzzle`"),
+            ("puzzle solution", "8", "`has_handshake_puzzle`"),
+            ("cookie", "8", "`has_handshake_cookie`"),
+            ("echoed cookie", "8", "`has_handshake_cookie`"),
+            ("max message size", "4", "`has_message_size`"),
+            ("max window", "2", "`has_window_size`"),
+            ("UID", "rest", "before `has_handshake_padding`"),
+        ] {
+            writeln!(w, "| {} | {} | {} |", field, size, condition).unwrap();
+        }
+        writeln!(
+            w,
+            "| SHA-256 of the UID | {} | `has_handshake_padding` |",
+            UID_DIGEST_SIZE
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "| zero padding | up to {} in total | `has_handshake_padding` |",
+            HELLO_SIZE
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+
+        // Control frames
+        writeln!(w, "## Control frames").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "Control frames are version ({}) | kind (u16) | message (CBOR map keyed by field \
+             name). Kinds from `0x8000` are left to applications. Frames of the library are \
+             sent as `Extended` packets of their subtype (`has_reserved_subtypes`), the \
+             others as messages.",
+            CONTROL_VERSION
+        )
+        .unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "| Kind | Message | Sent as | Description |").unwrap();
+        writeln!(w, "| --- | --- | --- | --- |").unwrap();
+        let mut frames = vec![
+            (Subscriptions::KIND, "Subscriptions", None, PUBSUB_SOURCE),
+            (Publish::KIND, "Publish", None, PUBSUB_SOURCE),
+            (
+                KeyRotation::KIND,
+                "KeyRotation",
+                Some(ROTATION_SUBTYPE),
+                ROTATION_SOURCE,
+            ),
+            (
+                RotationChallenge::KIND,
+                "RotationChallenge",
+                Some(ROTATION_SUBTYPE),
+                ROTATION_SOURCE,
+            ),
+            (
+                RotationResponse::KIND,
+                "RotationResponse",
+                Some(ROTATION_SUBTYPE),
+                ROTATION_SOURCE,
+            ),
+            (
+                SignedName::KIND,
+                "SignedName",
+                Some(NAME_SUBTYPE),
+                NAME_SOURCE,
+            ),
+            (
+                Annotated::KIND,
+                "Annotated",
+                Some(HEADERS_SUBTYPE),
+                HEADERS_SOURCE,
+            ),
+        ];
+        frames.sort_by_key(|(kind, _, _, _)| *kind);
+        for (kind, name, subtype, source) in frames {
+            let sent_as = match subtype {
+                Some(subtype) => format!("subtype `{:#04x}`", subtype),
+                None => "message".to_string(),
+            };
+            let description = doc(source, &format!("pub struct {} ", name));
+            writeln!(
+                w,
+                "| `{:#06x}` | {} | {} | {} |",
+                kind, name, sent_as, description
+            )
+            .unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn protocol_doc_test() {
+        let reference = generate();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(REFERENCE_PATH);
+
+        if env::var_os(UPDATE_VAR).is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &reference).unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            current == reference,
+            "{} is out of date, run `{}=1 cargo test --test protocol_doc_test` to update it",
+            REFERENCE_PATH,
+            UPDATE_VAR
+        );
+    }
+
+    #[test]
+    fn packet_layout_test() {
+        // The layout documented adds up to the packets compiled
+        let mut ack_list = AcknowledgementList::new(Seq(10));
+        ack_list.insert(Seq(12));
+        let ack = ack_list.get();
+        let miss_count = ack.miss_count as usize;
+
+        let packet = PacketBuilder::new(PType::Extended(1))
+            .ack(ack)
+            .payload(vec![0; 5])
+            .version(PROTOCOL_VERSION)
+            .build()
+            .unwrap();
+
+        let size: usize = packet_layout()
+            .iter()
+            .map(|(_, size, _)| match size.as_str() {
+                "rest" => 5,
+                "2 × miss count" => 2 * miss_count,
+                size => size.parse::<usize>().unwrap(),
+            })
+            .sum();
+        let mut compiled = Vec::new();
+        packet.compile_tagged_into(&mut compiled, &AetherCipher::new(vec![0; KEY_SIZE]));
+        assert_eq!(compiled.len(), size);
+    }
+}
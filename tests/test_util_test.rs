@@ -0,0 +1,1702 @@
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::fs;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crossbeam::channel::unbounded;
+
+    use aether_lib::config::{Config, GiveUp, RetryPolicy};
+    use aether_lib::contacts::Trust;
+    use aether_lib::error::AetherError;
+    use aether_lib::identity::Id;
+    use aether_lib::link::Link;
+    use aether_lib::memory::{projected_memory, LINK_MEMORY};
+    use aether_lib::packet::{PType, Packet, CHECKSUM_SIZE, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION};
+    use aether_lib::peer::authentication::authenticate;
+    use aether_lib::peer::cache::PeerCache;
+    use aether_lib::peer::connect::ConnectOptions;
+    use aether_lib::peer::frames::{NAME_SUBTYPE, ROTATION_SUBTYPE};
+    use aether_lib::peer::rotation::KeyRotation;
+    use aether_lib::peer::{Aether, AetherEvent, ConnectionStatus};
+    use aether_lib::pubsub::PubSub;
+    use aether_lib::sequence::Seq;
+    use aether_lib::test_util::{
+        aether_pair, assert_delivery, connect_link, identity, wait_until, FilteringTransport,
+        MemoryNetwork, NetworkConditions, SimulatedTransport, TestTracker, Verdict,
+    };
+    use aether_lib::tracker::{TrackerClient, TrackerPacket, TrackerPacketType};
+    use aether_lib::transport::Transport;
+    use aether_lib::util::crc32;
+    use aether_lib::wire::control;
+    use aether_lib::wire::headers::Headers;
+
+    #[test]
+    fn aether_pair_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        assert_delivery(&first, &second, b"Hello".to_vec(), Duration::from_secs(5));
+        assert_delivery(&second, &first, b"Hi".to_vec(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn tracker_stats_test() {
+        let tracker = TestTracker::start();
+        let aether = Aether::new_with_id(identity().0, tracker.addr());
+        aether.start();
+
+        assert!(wait_until(Duration::from_secs(5), || {
+            aether.tracker_stats().responses > 0
+        }));
+        let stats = aether.tracker_stats();
+        assert!(stats.polls >= stats.responses);
+        assert_eq!(stats.consecutive_failures, 0);
+        assert!(stats.latency_us.count() > 0);
+        assert!(stats.last_success.is_some());
+        aether.stop().unwrap();
+
+        // Nothing answers the polls sent to this address
+        let silent = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let mut config = Config::default();
+        config.aether.server_retry_delay = 100;
+        config.aether.tracker_failure_threshold = 2;
+        let aether = Aether::new_with_config(identity().0, silent.local_addr().unwrap(), config);
+        aether.start();
+
+        let events = aether.events();
+        let unreachable = (0..10)
+            .map_while(|_| events.recv_timeout(Duration::from_secs(5)).ok())
+            .find(|event| matches!(event, AetherEvent::TrackerUnreachable { .. }));
+        assert_eq!(
+            unreachable,
+            Some(AetherEvent::TrackerUnreachable { failures: 2 })
+        );
+
+        let stats = aether.tracker_stats();
+        assert!(stats.consecutive_failures >= 2);
+        assert_eq!(stats.responses, 0);
+        assert_eq!(stats.last_success, None);
+        aether.stop().unwrap();
+
+        // Polls cannot even be sent to this address, which counts as unanswered polls
+        // instead of failing the thread
+        let unsendable = SocketAddr::from(([127, 0, 0, 1], 0));
+        let aether = Aether::new_with_config(identity().0, unsendable, config);
+        aether.start();
+
+        let events = aether.events();
+        let first = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first, AetherEvent::TrackerUnreachable { failures: 2 });
+        assert!(wait_until(Duration::from_secs(5), || {
+            aether.tracker_stats().consecutive_failures > 2
+        }));
+        aether.stop().unwrap();
+    }
+
+    #[test]
+    fn recv_any_test() {
+        let tracker = TestTracker::start();
+        let (first, server) = aether_pair(&tracker, Duration::from_secs(20));
+
+        // Peers connected before and after receiving from any peer started
+        assert!(server.recv_any_timeout(Duration::from_millis(10)).is_err());
+
+        let second = Aether::new_with_id(identity().0, tracker.addr());
+        second.start();
+        second.connect(server.get_uid()).unwrap();
+        server.connect(second.get_uid()).unwrap();
+        assert!(wait_until(Duration::from_secs(20), || {
+            second.is_connected(server.get_uid()) && server.is_connected(second.get_uid())
+        }));
+
+        first.send_to(server.get_uid(), b"First".to_vec()).unwrap();
+        second
+            .send_to(server.get_uid(), b"Second".to_vec())
+            .unwrap();
+
+        let mut received: Vec<_> = (0..2)
+            .map(|_| server.recv_any_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        received.sort();
+
+        let mut expected = vec![
+            (first.get_uid().to_string(), b"First".to_vec()),
+            (second.get_uid().to_string(), b"Second".to_vec()),
+        ];
+        expected.sort();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn pubsub_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let first = PubSub::new(Arc::new(first));
+        let second = PubSub::new(Arc::new(second));
+        first.start();
+        second.start();
+
+        second.subscribe("news").unwrap();
+        assert!(wait_until(Duration::from_secs(5), || {
+            !first.subscribers("news").unwrap().is_empty()
+        }));
+        assert_eq!(first.publish("weather", b"Sunny".to_vec()).unwrap(), 0);
+        assert_eq!(first.publish("news", b"Hello".to_vec()).unwrap(), 1);
+
+        let publication = second.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(publication.topic, "news");
+        assert_eq!(publication.payload, b"Hello".to_vec());
+
+        second.unsubscribe("news").unwrap();
+        assert!(wait_until(Duration::from_secs(5), || {
+            first.subscribers("news").unwrap().is_empty()
+        }));
+    }
+
+    #[test]
+    fn stop_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        let start = Instant::now();
+        first.stop().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Links are closed and no longer used
+        assert!(first.send_to(second.get_uid(), b"Hello".to_vec()).is_err());
+        first.stop().unwrap();
+
+        // Dropping stops the client as well
+        let start = Instant::now();
+        drop(second);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn disconnect_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        // Disconnecting right after sending does not lose the last messages
+        let messages: Vec<Vec<u8>> = (0..50).map(|i| vec![i as u8; 1000]).collect();
+        for message in &messages {
+            first.send_to(second.get_uid(), message.clone()).unwrap();
+        }
+        first.disconnect(second.get_uid()).unwrap();
+        assert!(!first.is_connected(second.get_uid()));
+
+        for message in &messages {
+            let received = second
+                .recv_timeout_from(first.get_uid(), Duration::from_secs(5))
+                .unwrap();
+            assert_eq!(&received, message);
+        }
+        assert!(matches!(
+            second.recv_timeout_from(first.get_uid(), Duration::from_secs(5)),
+            Err(AetherError::LinkBroken(_))
+        ));
+
+        assert!(matches!(
+            first.disconnect(second.get_uid()),
+            Err(AetherError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn connect_timeout_test() {
+        let tracker = TestTracker::start();
+        let client = Aether::new_with_id(identity().0, tracker.addr());
+        client.start();
+
+        // The peer never comes online
+        let absent = Aether::new_with_id(identity().0, tracker.addr());
+        let options = ConnectOptions {
+            timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert_eq!(
+            client.connection_status(absent.get_uid()),
+            ConnectionStatus::Unknown
+        );
+        client.connect_with(absent.get_uid(), options).unwrap();
+        assert!(client.is_connecting(absent.get_uid()));
+        assert!(matches!(
+            client.connection_status(absent.get_uid()),
+            ConnectionStatus::Initialized | ConnectionStatus::Handshaking
+        ));
+
+        let event = client
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            event,
+            AetherEvent::ConnectionFailed {
+                uid: absent.get_uid().to_string()
+            }
+        );
+        assert!(!client.is_connecting(absent.get_uid()));
+        assert_eq!(
+            client.connection_status(absent.get_uid()),
+            ConnectionStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn keep_failed_test() {
+        let tracker = TestTracker::start();
+        let client = Aether::new_with_id(identity().0, tracker.addr());
+        client.start();
+
+        // The peer never comes online
+        let absent = Aether::new_with_id(identity().0, tracker.addr());
+        let options = ConnectOptions {
+            timeout: Some(Duration::from_secs(1)),
+            retry: Some(RetryPolicy {
+                give_up: GiveUp::KeepFailed,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        client.connect_with(absent.get_uid(), options).unwrap();
+
+        let event = client
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            event,
+            AetherEvent::ConnectionFailed {
+                uid: absent.get_uid().to_string()
+            }
+        );
+
+        // The connection stays failed instead of being retried
+        thread::sleep(Duration::from_secs(3));
+        match client.connection_status(absent.get_uid()) {
+            ConnectionStatus::Failed { reason, .. } => assert!(!reason.is_empty()),
+            status => panic!("Unexpected status {:?}", status),
+        }
+        assert!(client.events().try_recv().is_err());
+
+        // Connecting again starts over
+        client.connect(absent.get_uid()).unwrap();
+        assert!(client.is_connecting(absent.get_uid()));
+    }
+
+    #[test]
+    fn connection_status_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(10));
+
+        assert_eq!(
+            first.connection_status(second.get_uid()),
+            ConnectionStatus::Connected
+        );
+        assert!(first.is_connected(second.get_uid()));
+        assert!(!first.is_connecting(second.get_uid()));
+        assert!(!first.is_initialized(second.get_uid()));
+        assert!(first.events().try_iter().any(|event| event
+            == AetherEvent::Connected {
+                uid: second.get_uid().to_string(),
+                reconnected: false,
+            }));
+    }
+
+    #[test]
+    fn reconnect_events_test() {
+        let tracker = TestTracker::start();
+        let mut config = Config::default();
+        config.handshake.handshake_timeout = 300;
+        config.aether.retry = RetryPolicy {
+            retry_delay: 100,
+            jitter: 0,
+            ..Default::default()
+        };
+        let aether = Aether::new_with_config(identity().0, tracker.addr(), config);
+        aether.start();
+
+        // A peer requesting the connection without ever answering handshakes
+        let (silent, _) = identity();
+        let silent_uid = silent.public_key_to_base64().unwrap();
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let mut packet = TrackerPacket {
+            username: silent_uid.clone(),
+            peer_username: aether.get_uid().to_string(),
+            packet_type: TrackerPacketType::Connection,
+            req: true,
+            ..Default::default()
+        };
+        packet.sign(&silent).unwrap();
+        let bytes: Vec<u8> = TryFrom::try_from(packet).unwrap();
+
+        let events = aether.events();
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(10)
+            && !received
+                .iter()
+                .any(|event| matches!(event, AetherEvent::Reconnecting { .. }))
+        {
+            socket.send_to(&bytes, tracker.addr()).unwrap();
+            received.extend(events.try_iter());
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        assert!(received.iter().any(|event| matches!(
+            event,
+            AetherEvent::HandshakeFailed { uid, retry_in, .. }
+                if *uid == silent_uid && *retry_in == Duration::from_millis(100)
+        )));
+        assert!(received.contains(&AetherEvent::Reconnecting {
+            uid: silent_uid,
+            attempt: 2,
+        }));
+    }
+
+    #[test]
+    fn peer_info_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(10));
+
+        assert_delivery(&first, &second, b"Hello".to_vec(), Duration::from_secs(5));
+        let info = first.peer_info(second.get_uid()).unwrap();
+        assert!(info.encrypted);
+        assert!(info.address.ip().is_loopback());
+        assert_ne!(info.address.port(), 0);
+        assert!(info.rtt_us > 0);
+        assert!((0.0..=1.0).contains(&info.loss_rate));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(first.peer_info(second.get_uid()).unwrap().uptime > info.uptime);
+    }
+
+    #[test]
+    fn delivery_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(10));
+
+        let handles: Vec<_> = (0..10u8)
+            .map(|index| {
+                first
+                    .send_to_tracked(second.get_uid(), vec![index])
+                    .unwrap()
+            })
+            .collect();
+        for handle in &handles {
+            assert!(handle.wait_timeout(Duration::from_secs(5)).unwrap());
+        }
+        // Delivered before being read by the application
+        for index in 0..10u8 {
+            assert_eq!(
+                second
+                    .recv_timeout_from(first.get_uid(), Duration::from_secs(1))
+                    .unwrap(),
+                vec![index]
+            );
+        }
+
+        assert!(matches!(
+            first.send_to_tracked("unknown", b"Hello".to_vec()),
+            Err(AetherError::NotConnected(_))
+        ));
+
+        // Nothing is partially received once the messages arrived
+        assert_eq!(second.incoming_progress(first.get_uid()).unwrap(), None);
+        assert!(matches!(
+            second.incoming_progress("unknown"),
+            Err(AetherError::NotConnected(_))
+        ));
+
+        // Delivered messages cannot be cancelled
+        assert!(!first
+            .cancel_message(second.get_uid(), handles[0].id())
+            .unwrap());
+        assert!(matches!(
+            first.cancel_message("unknown", handles[0].id()),
+            Err(AetherError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn manual_accept_test() {
+        let tracker = TestTracker::start();
+        let first = Aether::new_with_id(identity().0, tracker.addr());
+        let mut config = Config::default();
+        config.aether.manual_accept = true;
+        let second = Aether::new_with_config(identity().0, tracker.addr(), config);
+        first.start();
+        second.start();
+
+        first.connect(second.get_uid()).unwrap();
+        let event = second
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            event,
+            AetherEvent::ConnectionRequested {
+                uid: first.get_uid().to_string()
+            }
+        );
+
+        // The request stays queued while the other peer keeps requesting
+        thread::sleep(Duration::from_secs(2));
+        let incoming = second.incoming();
+        assert_eq!(incoming.len(), 1);
+        assert_eq!(incoming[0].username, first.get_uid());
+        assert_eq!(
+            second.connection_status(first.get_uid()),
+            ConnectionStatus::Unknown
+        );
+
+        second.accept(&incoming[0]).unwrap();
+        assert!(second.incoming().is_empty());
+        let connected = wait_until(Duration::from_secs(10), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        });
+        assert!(connected);
+
+        // Rejected peers are not queued again
+        let third = Aether::new_with_id(identity().0, tracker.addr());
+        third.start();
+        third.connect(second.get_uid()).unwrap();
+        let queued = wait_until(Duration::from_secs(5), || !second.incoming().is_empty());
+        assert!(queued);
+        second.reject(&second.incoming()[0]);
+        thread::sleep(Duration::from_secs(2));
+        assert!(second.incoming().is_empty());
+        assert!(!second.is_connecting(third.get_uid()));
+    }
+
+    #[test]
+    fn config_dir_test() {
+        let dir = Path::new("./tmp/aether_config_dir");
+        let _ = fs::remove_dir_all(dir);
+        let tracker = TestTracker::start();
+
+        // The config dir is created, and the identity is generated there and used again
+        // from there
+        let aether = Aether::with_config(Config::default(), dir, tracker.addr());
+        let id = Id::load_from(dir).unwrap();
+        assert_eq!(aether.get_uid(), id.public_key_to_base64().unwrap());
+        assert!(dir.join("schema_version").exists());
+        aether.add_contact("peer", "alias", Trust::Trusted).unwrap();
+        drop(aether);
+
+        let aether = Aether::with_config(Config::default(), dir, tracker.addr());
+        assert_eq!(aether.get_uid(), id.public_key_to_base64().unwrap());
+        assert_eq!(aether.resolve("alias").unwrap(), "peer");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn contacts_test() {
+        let tracker = TestTracker::start();
+        let first = Aether::new_with_id(identity().0, tracker.addr());
+        let second = Aether::new_with_id(identity().0, tracker.addr());
+        first.start();
+        second.start();
+
+        // Clients not using the identity in the config dir start without contacts
+        assert_eq!(first.alias_of(second.get_uid()).unwrap(), None);
+
+        // Peers can be connected to by their alias
+        first
+            .add_contact(second.get_uid(), "second", Trust::Trusted)
+            .unwrap();
+        first.connect("second").unwrap();
+        second.connect(first.get_uid()).unwrap();
+        let connected = wait_until(Duration::from_secs(10), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        });
+        assert!(connected);
+        assert_eq!(first.peer_info(second.get_uid()).unwrap().label(), "second");
+
+        // Connection requests of blocked contacts are ignored
+        let third = Aether::new_with_id(identity().0, tracker.addr());
+        third.start();
+        second
+            .add_contact(third.get_uid(), "third", Trust::Blocked)
+            .unwrap();
+        third.connect(second.get_uid()).unwrap();
+        thread::sleep(Duration::from_secs(2));
+        assert_eq!(
+            second.connection_status(third.get_uid()),
+            ConnectionStatus::Unknown
+        );
+        assert!(!third.is_connected(second.get_uid()));
+    }
+
+    #[test]
+    fn memory_budget_test() {
+        let tracker = TestTracker::start();
+
+        // Room for a single connection only
+        let mut config = Config::default();
+        config.aether.memory_budget = projected_memory(&config.link);
+        let client = Aether::new_with_config(identity().0, tracker.addr(), config);
+
+        let first = identity().0.public_key_to_base64().unwrap();
+        let second = identity().0.public_key_to_base64().unwrap();
+
+        client.connect(&first).unwrap();
+        // Connecting again does not need more memory
+        client.connect(&first).unwrap();
+
+        match client.connect(&second) {
+            Err(AetherError::ResourceBudgetExceeded { .. }) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(!client.is_connecting(&second));
+
+        // Links charge their memory even without a limit
+        let (first, _second) = aether_pair(&tracker, Duration::from_secs(10));
+        assert!(first.memory_budget().used() >= LINK_MEMORY);
+    }
+
+    #[test]
+    fn key_rotation_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let second = Arc::new(second);
+        let old_uid = first.get_uid().to_string();
+        let (new_id, _) = identity();
+        let new_uid = new_id.public_key_to_base64().unwrap();
+
+        let receiving = second.clone();
+        let uid = old_uid.clone();
+        let handle =
+            thread::spawn(move || receiving.recv_timeout_from(&uid, Duration::from_secs(10)));
+
+        // The session is kept under the new key
+        first
+            .announce_key_rotation(second.get_uid(), &new_id)
+            .unwrap();
+        first
+            .send_to(second.get_uid(), b"Rotated".to_vec())
+            .unwrap();
+        assert_eq!(handle.join().unwrap().unwrap(), b"Rotated".to_vec());
+
+        assert!(second.is_connected(&new_uid));
+        assert!(!second.is_connected(&old_uid));
+        assert!(second.events().try_iter().any(|event| event
+            == AetherEvent::IdentityRotated {
+                previous: old_uid.clone(),
+                current: new_uid.clone(),
+            }));
+
+        // Messages are never taken for rotations, forged rotations are tested in
+        // frames_test
+        let rotation = KeyRotation::new(&identity().0, &identity().0).unwrap();
+        let frame = control::encode(&rotation).unwrap();
+        first.send_to(second.get_uid(), frame.clone()).unwrap();
+        assert_eq!(
+            second
+                .recv_timeout_from(&new_uid, Duration::from_secs(5))
+                .unwrap(),
+            frame
+        );
+        assert!(second.is_connected(&new_uid));
+    }
+
+    /// Connect a client to a peer simulated by a [`Link`] over a [`MemoryNetwork`],
+    /// returning the client, the UID of the peer, its identity and its link
+    fn simulated_peer(network: &MemoryNetwork) -> (Aether, String, Id, Link) {
+        let (client_id, client_public) = identity();
+        let (peer_id, peer_public) = identity();
+        let uid = peer_id.public_key_to_base64().unwrap();
+        let config = Config::default();
+
+        let (local, remote) = network.pair();
+        let local_addr = local.local_addr().unwrap();
+        let mut peer = Link::new(
+            peer_id.clone(),
+            remote,
+            local_addr,
+            client_public,
+            Seq(1000),
+            Seq(0),
+            config,
+        )
+        .unwrap();
+        let mut link = Link::new(
+            client_id.clone(),
+            local,
+            peer.local_addr().unwrap(),
+            peer_public,
+            Seq(0),
+            Seq(1000),
+            config,
+        )
+        .unwrap();
+        peer.start();
+        link.start();
+
+        let tracker_addr = SocketAddr::from(([127, 0, 0, 1], 8982));
+        let aether = Aether::new_with_id(client_id, tracker_addr);
+        connect_link(&aether, &uid, link);
+
+        (aether, uid, peer_id, peer)
+    }
+
+    #[test]
+    fn frames_test() {
+        let network = MemoryNetwork::new();
+        let (aether, uid, peer_id, peer) = simulated_peer(&network);
+        let timeout = Duration::from_secs(5);
+
+        // Frames sent as bytes of a message are received as they are
+        let signed = peer_id.sign_name("Alice").unwrap();
+        let frame = control::encode(&signed).unwrap();
+        peer.send(frame.clone()).unwrap();
+        assert_eq!(aether.recv_timeout_from(&uid, timeout).unwrap(), frame);
+        assert_eq!(aether.peer_info(&uid).unwrap().name, None);
+
+        // Names are only taken from frames of their subtype, and dropped if they were
+        // signed for another UID
+        let forged = identity().0.sign_name("Mallory").unwrap();
+        peer.send_extended(NAME_SUBTYPE, control::encode(&forged).unwrap())
+            .unwrap();
+        peer.send_extended(NAME_SUBTYPE, frame).unwrap();
+        peer.send(b"Hello".to_vec()).unwrap();
+        assert_eq!(
+            aether.recv_timeout_from(&uid, timeout).unwrap(),
+            b"Hello".to_vec()
+        );
+        assert_eq!(
+            aether.peer_info(&uid).unwrap().name.as_deref(),
+            Some("Alice")
+        );
+
+        // The fan-in handles frames the same way
+        let signed = peer_id.sign_name("Bob").unwrap();
+        peer.send_extended(NAME_SUBTYPE, control::encode(&signed).unwrap())
+            .unwrap();
+        peer.send(b"Hello".to_vec()).unwrap();
+        assert_eq!(
+            aether.recv_any_timeout(timeout).unwrap(),
+            (uid.clone(), b"Hello".to_vec())
+        );
+        assert_eq!(aether.peer_info(&uid).unwrap().name.as_deref(), Some("Bob"));
+
+        // Rotations not signed by the old key drop the session
+        let forged = KeyRotation::new(&identity().0, &identity().0).unwrap();
+        peer.send_extended(ROTATION_SUBTYPE, control::encode(&forged).unwrap())
+            .unwrap();
+        assert!(wait_until(timeout, || !aether.is_connected(&uid)));
+    }
+
+    #[test]
+    fn messages_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let second = Arc::new(second);
+        let (new_id, _) = identity();
+        let new_uid = new_id.public_key_to_base64().unwrap();
+
+        let receiving = second.clone();
+        let uid = first.get_uid().to_string();
+        let handle = thread::spawn(move || {
+            let mut messages = receiving.messages(&uid);
+            let received: Vec<Vec<u8>> = messages.by_ref().take(2).collect();
+            (received, messages.uid().to_string())
+        });
+
+        // The iterator follows the peer across the rotation of its key
+        first.send_to(second.get_uid(), b"Before".to_vec()).unwrap();
+        first
+            .announce_key_rotation(second.get_uid(), &new_id)
+            .unwrap();
+        first.send_to(second.get_uid(), b"After".to_vec()).unwrap();
+
+        let (received, uid) = handle.join().unwrap();
+        assert_eq!(received, vec![b"Before".to_vec(), b"After".to_vec()]);
+        assert_eq!(uid, new_uid);
+
+        // Peers that are not connected have no messages
+        assert_eq!(second.messages("unknown").next(), None);
+    }
+
+    #[test]
+    fn try_recv_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let uid = first.get_uid();
+
+        assert_eq!(second.try_recv_from(uid).unwrap(), None);
+
+        first.send_to(second.get_uid(), b"Hello".to_vec()).unwrap();
+        let received = Mutex::new(None);
+        assert!(wait_until(Duration::from_secs(5), || {
+            let message = second.try_recv_from(uid).unwrap();
+            let found = message.is_some();
+            *received.lock().unwrap() = message;
+            found
+        }));
+        assert_eq!(received.into_inner().unwrap(), Some(b"Hello".to_vec()));
+        assert_eq!(second.try_recv_from(uid).unwrap(), None);
+
+        assert!(matches!(
+            second.try_recv_from("unknown"),
+            Err(AetherError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn message_handler_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let (sender, handled) = unbounded();
+
+        second
+            .on_message(first.get_uid(), move |payload| {
+                sender.send(payload).unwrap();
+            })
+            .unwrap();
+
+        for i in 0..10 {
+            first
+                .send_to(second.get_uid(), format!("Hello {}", i).into_bytes())
+                .unwrap();
+        }
+        for i in 0..10 {
+            assert_eq!(
+                handled.recv_timeout(Duration::from_secs(5)).unwrap(),
+                format!("Hello {}", i).into_bytes()
+            );
+        }
+        assert_eq!(second.try_recv_from(first.get_uid()).unwrap(), None);
+
+        // Other peers are passed to the handler of any peer
+        let (sender, handled) = unbounded();
+        first
+            .on_any_message(move |uid, payload| {
+                sender.send((uid, payload)).unwrap();
+            })
+            .unwrap();
+        second.send_to(first.get_uid(), b"Hi".to_vec()).unwrap();
+        assert_eq!(
+            handled.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (second.get_uid().to_string(), b"Hi".to_vec())
+        );
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn ffi_test() {
+        use std::ffi::{CStr, CString};
+        use std::ptr;
+
+        use aether_lib::ffi::{
+            aether_connect, aether_free, aether_free_bytes, aether_is_connected, aether_new,
+            aether_recv, aether_send, aether_uid, AETHER_ERR_NOT_CONNECTED, AETHER_ERR_TIMEOUT,
+            AETHER_OK,
+        };
+
+        let tracker = TestTracker::start();
+        let other = Aether::new_with_id(identity().0, tracker.addr());
+        other.start();
+
+        let tracker_addr = CString::new(tracker.addr().to_string()).unwrap();
+        let exported = CString::new(identity().0.export(None).unwrap()).unwrap();
+        let other_uid = CString::new(other.get_uid()).unwrap();
+
+        unsafe {
+            let client = aether_new(tracker_addr.as_ptr(), exported.as_ptr());
+            assert!(!client.is_null());
+            let uid = CStr::from_ptr(aether_uid(client))
+                .to_str()
+                .unwrap()
+                .to_string();
+
+            assert_eq!(
+                aether_send(client, other_uid.as_ptr(), ptr::null(), 0),
+                AETHER_ERR_NOT_CONNECTED
+            );
+
+            assert_eq!(aether_connect(client, other_uid.as_ptr()), AETHER_OK);
+            other.connect(&uid).unwrap();
+            assert!(wait_until(Duration::from_secs(20), || {
+                aether_is_connected(client, other_uid.as_ptr()) == 1 && other.is_connected(&uid)
+            }));
+
+            let hello = b"Hello";
+            assert_eq!(
+                aether_send(client, other_uid.as_ptr(), hello.as_ptr(), hello.len()),
+                AETHER_OK
+            );
+            assert_eq!(
+                other
+                    .recv_timeout_from(&uid, Duration::from_secs(5))
+                    .unwrap(),
+                hello.to_vec()
+            );
+
+            let mut data = ptr::null_mut();
+            let mut len = 0;
+            assert_eq!(
+                aether_recv(client, other_uid.as_ptr(), 100, &mut data, &mut len),
+                AETHER_ERR_TIMEOUT
+            );
+            other.send_to(&uid, b"Hi".to_vec()).unwrap();
+            assert_eq!(
+                aether_recv(client, other_uid.as_ptr(), 5000, &mut data, &mut len),
+                AETHER_OK
+            );
+            assert_eq!(std::slice::from_raw_parts(data, len), b"Hi");
+            aether_free_bytes(data, len);
+
+            aether_free(client);
+        }
+    }
+
+    #[test]
+    fn display_name_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        let info = second.peer_info(first.get_uid()).unwrap();
+        assert_eq!(info.name, None);
+        assert_eq!(info.label(), first.get_uid());
+
+        assert!(matches!(
+            first.set_display_name("Alice\u{202e}"),
+            Err(AetherError::InvalidName(_))
+        ));
+        first.set_display_name("Alice").unwrap();
+
+        // The name is kept while receiving from the peer
+        assert_delivery(&first, &second, b"Hello".to_vec(), Duration::from_secs(5));
+        let info = second.peer_info(first.get_uid()).unwrap();
+        assert_eq!(info.name.as_deref(), Some("Alice"));
+        assert_eq!(info.label(), "Alice");
+
+        // Messages are never taken for names (refer frames_test)
+        let forged = Id::new().unwrap().sign_name("Mallory").unwrap();
+        let frame = control::encode(&forged).unwrap();
+        assert_delivery(&first, &second, frame, Duration::from_secs(5));
+        let info = second.peer_info(first.get_uid()).unwrap();
+        assert_eq!(info.name.as_deref(), Some("Alice"));
+
+        assert!(matches!(
+            second.peer_info("unknown"),
+            Err(AetherError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn headers_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        let mut headers = Headers::new();
+        headers.insert("content-type".to_string(), "text/plain".to_string());
+        headers.insert("message-id".to_string(), "42".to_string());
+
+        first
+            .send_to_with_headers(second.get_uid(), &headers, b"Hello".to_vec())
+            .unwrap();
+        let message = second
+            .recv_timeout_from_ext(first.get_uid(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(message.headers, headers);
+        assert_eq!(message.payload, b"Hello".to_vec());
+
+        // Messages sent without headers have none
+        first.send_to(second.get_uid(), b"Hi".to_vec()).unwrap();
+        let message = second
+            .recv_timeout_from_ext(first.get_uid(), Duration::from_secs(5))
+            .unwrap();
+        assert!(message.headers.is_empty());
+        assert_eq!(message.payload, b"Hi".to_vec());
+
+        // and the headers are dropped when receiving bytes only
+        first
+            .send_to_with_headers(second.get_uid(), &headers, b"Hey".to_vec())
+            .unwrap();
+        let received = second
+            .recv_timeout_from(first.get_uid(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(received, b"Hey".to_vec());
+
+        headers.insert(String::new(), "empty".to_string());
+        assert!(matches!(
+            first.send_to_with_headers(second.get_uid(), &headers, b"Hello".to_vec()),
+            Err(AetherError::InvalidHeaders(_))
+        ));
+    }
+
+    #[test]
+    fn short_auth_string_test() {
+        let tracker = TestTracker::start();
+        let (first, second) = aether_pair(&tracker, Duration::from_secs(20));
+
+        let first_sas = first.short_auth_string(second.get_uid()).unwrap();
+        assert_eq!(
+            first_sas,
+            second.short_auth_string(first.get_uid()).unwrap()
+        );
+
+        // Fingerprints shown for a peer match those the peer shows for itself
+        let info = second.peer_info(first.get_uid()).unwrap();
+        assert_eq!(info.fingerprint, first.fingerprint().unwrap());
+        assert_ne!(info.fingerprint, second.fingerprint().unwrap());
+
+        assert!(matches!(
+            first.short_auth_string("unknown"),
+            Err(AetherError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn bound_ports_test() {
+        let tracker = TestTracker::start();
+
+        let port_min = UdpSocket::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = Config::default();
+        config.aether.peer_port_min = port_min;
+        config.aether.peer_port_max = port_min.saturating_add(20);
+        let range = config.aether.peer_port_min..=config.aether.peer_port_max;
+
+        let first = Aether::new_with_config(identity().0, tracker.addr(), config);
+        let second = Aether::new_with_id(identity().0, tracker.addr());
+        first.start();
+        second.start();
+
+        first.connect(second.get_uid()).unwrap();
+        let ports = first.bound_ports().unwrap();
+        assert_eq!(ports.iter().filter(|port| range.contains(port)).count(), 1);
+
+        second.connect(first.get_uid()).unwrap();
+        assert!(wait_until(Duration::from_secs(20), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        }));
+
+        // Only the socket of the tracker server is outside the range
+        let ports = first.bound_ports().unwrap();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports.iter().filter(|port| range.contains(port)).count(), 1);
+    }
+
+    #[test]
+    fn exhausted_ports_test() {
+        let tracker = TestTracker::start();
+
+        // The only port of the range is taken
+        let taken = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let mut config = Config::default();
+        config.aether.peer_port_min = port;
+        config.aether.peer_port_max = port;
+
+        let aether = Aether::new_with_config(identity().0, tracker.addr(), config);
+        let (other, _) = identity();
+        let uid = other.public_key_to_base64().unwrap();
+        assert!(matches!(
+            aether.connect(&uid),
+            Err(AetherError::SocketBind(_))
+        ));
+        assert!(!aether.is_connecting(&uid));
+
+        drop(taken);
+        aether.connect(&uid).unwrap();
+    }
+
+    #[test]
+    fn switch_identity_test() {
+        let tracker = TestTracker::start();
+        let (mut first, second) = aether_pair(&tracker, Duration::from_secs(20));
+        let old_uid = first.get_uid().to_string();
+
+        let (new_id, _) = identity();
+        let blob = new_id.export(Some(b"passphrase")).unwrap();
+        let imported = Id::import(&blob, Some(b"passphrase")).unwrap();
+        first.switch_identity(imported).unwrap();
+
+        assert_eq!(first.get_uid(), new_id.public_key_to_base64().unwrap());
+        assert!(!first.is_connected(second.get_uid()));
+
+        // The client keeps running with the new identity
+        first.connect(second.get_uid()).unwrap();
+        second.connect(first.get_uid()).unwrap();
+        assert!(wait_until(Duration::from_secs(20), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        }));
+        assert_delivery(&first, &second, b"Hello".to_vec(), Duration::from_secs(5));
+        assert_ne!(first.get_uid(), old_uid);
+    }
+
+    #[test]
+    fn stale_cache_test() {
+        let tracker = TestTracker::start();
+
+        // The cached endpoint of the other peer no longer answers
+        let stale = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let second = Aether::new_with_id(identity().0, tracker.addr());
+
+        fs::create_dir_all("./tmp").unwrap();
+        let path = Path::new("./tmp/stale_peers.yaml");
+        let _ = fs::remove_file(path);
+        let mut cache = PeerCache::load(path).unwrap();
+        cache.insert(second.get_uid(), stale.local_addr().unwrap(), 0, 1);
+
+        let mut first = Aether::new_with_id(identity().0, tracker.addr());
+        first.set_peer_cache(cache);
+
+        first.start();
+        second.start();
+        first.connect(second.get_uid()).unwrap();
+        second.connect(first.get_uid()).unwrap();
+
+        // The endpoint received from the tracker is attempted once the cached one fails
+        assert!(wait_until(Duration::from_secs(20), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        }));
+        assert_delivery(&first, &second, b"Hello".to_vec(), Duration::from_secs(5));
+
+        // The working endpoint replaces the stale one
+        let cache = PeerCache::load(path).unwrap();
+        let cached = cache.get(second.get_uid()).unwrap();
+        assert_ne!(cached.port, stale.local_addr().unwrap().port());
+        assert!(cached.version > 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn outbox_test() {
+        let tracker = TestTracker::start();
+        let first = Aether::new_with_id(identity().0, tracker.addr());
+        let second = Aether::new_with_id(identity().0, tracker.addr());
+
+        // Queued until the peer is connected
+        for message in [&b"Hello"[..], &b"again"[..]] {
+            assert!(!first
+                .send_to_or_queue(second.get_uid(), message.to_vec())
+                .unwrap());
+        }
+        assert_eq!(first.queued_for(second.get_uid()).unwrap(), 2);
+
+        first.start();
+        second.start();
+        first.connect(second.get_uid()).unwrap();
+        second.connect(first.get_uid()).unwrap();
+        assert!(wait_until(Duration::from_secs(20), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        }));
+
+        for expected in [&b"Hello"[..], &b"again"[..]] {
+            let received = second
+                .recv_timeout_from(first.get_uid(), Duration::from_secs(5))
+                .unwrap();
+            assert_eq!(received, expected);
+        }
+        assert_eq!(first.queued_for(second.get_uid()).unwrap(), 0);
+
+        // Sent right away once connected
+        assert!(first
+            .send_to_or_queue(second.get_uid(), b"Hi".to_vec())
+            .unwrap());
+        let received = second
+            .recv_timeout_from(first.get_uid(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(received, b"Hi");
+    }
+
+    #[test]
+    fn blocked_send_test() {
+        let network = MemoryNetwork::new();
+        let (client_id, client_public) = identity();
+        let (peer_id, peer_public) = identity();
+        let uid = peer_id.public_key_to_base64().unwrap();
+        let mut config = Config::default();
+        config.link.send_queue_size = 2;
+
+        // The peer is never started, so the send queue of the link to it fills up
+        let (local, remote) = network.pair();
+        let local_addr = local.local_addr().unwrap();
+        let peer = Link::new(
+            peer_id,
+            remote,
+            local_addr,
+            client_public,
+            Seq(1000),
+            Seq(0),
+            config,
+        )
+        .unwrap();
+        let mut link = Link::new(
+            client_id.clone(),
+            local,
+            peer.local_addr().unwrap(),
+            peer_public,
+            Seq(0),
+            Seq(1000),
+            config,
+        )
+        .unwrap();
+        link.start();
+
+        let tracker_addr = SocketAddr::from(([127, 0, 0, 1], 8982));
+        let aether = Aether::new_with_id(client_id, tracker_addr);
+        connect_link(&aether, &uid, link);
+
+        let (aether, uid) = (&aether, &uid);
+        crossbeam::thread::scope(|s| {
+            let sender = s.spawn(|_| aether.send_to(uid, vec![0; MAX_PAYLOAD_SIZE * 32]));
+            thread::sleep(Duration::from_millis(200));
+
+            // The blocked send does not hold up others using the connection
+            let (result_sender, result_receiver) = unbounded();
+            s.spawn(move |_| {
+                let _ = result_sender.send(aether.try_send_to(uid, b"Hello".to_vec()));
+            });
+            assert!(matches!(
+                result_receiver
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap(),
+                Err(AetherError::SendQueueFull(2))
+            ));
+            assert!(aether.is_connected(uid));
+
+            aether.disconnect(uid).unwrap();
+            assert!(sender.join().unwrap().is_err());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn presence_test() {
+        let tracker = TestTracker::start();
+        let aether = Aether::new_with_id(identity().0, tracker.addr());
+
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let is_present = || {
+            let query = TrackerPacket {
+                username: String::from("someone"),
+                peer_username: aether.get_uid().to_string(),
+                packet_type: TrackerPacketType::Presence,
+                req: true,
+                ..Default::default()
+            };
+            let bytes: Vec<u8> = TryFrom::try_from(query).unwrap();
+            socket.send_to(&bytes, tracker.addr()).unwrap();
+
+            let mut buf = [0; 1024];
+            let size = socket.recv(&mut buf).unwrap();
+            let answer = TrackerPacket::try_from(buf[..size].to_vec()).unwrap();
+            assert_eq!(answer.packet_type, TrackerPacketType::Presence);
+            answer.present
+        };
+
+        // Peers are present once they poll the tracker
+        assert!(!is_present());
+        aether.start();
+        assert!(wait_until(Duration::from_secs(5), is_present));
+    }
+
+    #[test]
+    fn is_online_test() {
+        let tracker = TestTracker::start();
+        let aether = Aether::new_with_id(identity().0, tracker.addr());
+        let other = Aether::new_with_id(identity().0, tracker.addr());
+
+        // Answers arrive with the polls of the asking client
+        assert!(matches!(
+            aether.is_online(other.get_uid()),
+            Err(AetherError::TrackerTimeout)
+        ));
+
+        aether.start();
+        assert!(!aether.is_online(other.get_uid()).unwrap());
+        other.start();
+        // Queries may time out while the test machine is busy
+        assert!(wait_until(Duration::from_secs(5), || matches!(
+            aether.is_online(other.get_uid()),
+            Ok(true)
+        )));
+    }
+
+    #[test]
+    fn tracker_client_test() {
+        let tracker = TestTracker::start();
+        let client = || {
+            let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            TrackerClient::new(identity().0, tracker.addr(), Arc::new(socket)).unwrap()
+        };
+        let first = client();
+        let second = client();
+
+        // Peers are present once registered
+        let is_present = || {
+            first.query_presence(second.uid()).unwrap();
+            let answer = first.recv().unwrap().unwrap();
+            assert_eq!(answer.packet_type, TrackerPacketType::Presence);
+            answer.present
+        };
+        assert!(!is_present());
+        assert!(second.poll().unwrap().unwrap().connections.is_empty());
+        assert!(is_present());
+
+        // Requests are relayed with the address they were sent from
+        let link_socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        first
+            .request_connection(second.uid(), &link_socket, false)
+            .unwrap();
+        let relayed = (0..5)
+            .find_map(|_| second.poll().unwrap().unwrap().connections.pop())
+            .unwrap();
+        assert_eq!(relayed.username, first.uid());
+        assert_eq!(relayed.port, link_socket.local_addr().unwrap().port());
+    }
+
+    #[test]
+    fn tracker_signature_test() {
+        let tracker = TestTracker::start();
+        let mut aether = Aether::new_with_id(identity().0, tracker.addr());
+
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let requested_clone = requested.clone();
+        aether.set_accept_policy(move |request| {
+            requested_clone
+                .lock()
+                .unwrap()
+                .push(request.username.clone());
+            false
+        });
+        aether.start();
+
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let request = |uid: String, signer: Option<&Id>| {
+            let mut packet = TrackerPacket {
+                username: uid,
+                peer_username: aether.get_uid().to_string(),
+                packet_type: TrackerPacketType::Connection,
+                req: true,
+                ..Default::default()
+            };
+            if let Some(id) = signer {
+                packet.sign(id).unwrap();
+            }
+
+            let bytes: Vec<u8> = TryFrom::try_from(packet).unwrap();
+            socket.send_to(&bytes, tracker.addr()).unwrap();
+        };
+
+        let (unsigned, _) = identity();
+        let (spoofed, _) = identity();
+        let (genuine, _) = identity();
+        let (attacker, _) = identity();
+
+        // Requests not signed by the peer they claim to come from are dropped
+        request(unsigned.public_key_to_base64().unwrap(), None);
+        request(spoofed.public_key_to_base64().unwrap(), Some(&attacker));
+
+        let genuine_uid = genuine.public_key_to_base64().unwrap();
+        request(genuine_uid.clone(), Some(&genuine));
+
+        assert!(wait_until(Duration::from_secs(5), || requested
+            .lock()
+            .unwrap()
+            .contains(&genuine_uid)));
+        assert_eq!(*requested.lock().unwrap(), vec![genuine_uid]);
+
+        aether.stop().unwrap();
+    }
+
+    #[test]
+    fn sealed_tracker_test() {
+        let (tracker_id, tracker_key) = identity();
+        let tracker = TestTracker::start_sealed(tracker_id);
+
+        let mut first = Aether::new_with_id(identity().0, tracker.addr());
+        let mut second = Aether::new_with_id(identity().0, tracker.addr());
+        first.set_tracker_key(&tracker_key).unwrap();
+        second.set_tracker_key(&tracker_key).unwrap();
+
+        first.start();
+        second.start();
+        first.connect(second.get_uid()).unwrap();
+        second.connect(first.get_uid()).unwrap();
+
+        assert!(wait_until(Duration::from_secs(20), || {
+            first.is_connected(second.get_uid()) && second.is_connected(first.get_uid())
+        }));
+        assert_delivery(&first, &second, b"Sealed".to_vec(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn memory_link_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        let mut link1 = Link::new(
+            id1,
+            socket1,
+            addr2,
+            public2,
+            Seq(0),
+            Seq(1000),
+            Config::default(),
+        )
+        .unwrap();
+        let mut link2 = Link::new(
+            id2,
+            socket2,
+            addr1,
+            public1,
+            Seq(1000),
+            Seq(0),
+            Config::default(),
+        )
+        .unwrap();
+        link1.start();
+        link2.start();
+
+        for i in 0..20 {
+            link1.send(format!("Hello {}", i).into_bytes()).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        for i in 0..20 {
+            let received = link2.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(received, format!("Hello {}", i).into_bytes());
+        }
+    }
+
+    #[test]
+    fn nack_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        // Without NACKs the lost packet is only sent again after the retry delay
+        let mut config = Config::default();
+        config.link.retry_delay = 5_000;
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        // The first packet of the third message is lost
+        let dropped = AtomicBool::new(false);
+        let socket1 = FilteringTransport::new(socket1, move |buf: &[u8]| {
+            let lost = buf.windows(7).any(|window| window == b"Hello 2");
+            if lost && !dropped.swap(true, Ordering::SeqCst) {
+                Verdict::Drop
+            } else {
+                Verdict::Send
+            }
+        });
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        link1.start();
+        link2.start();
+
+        let start = Instant::now();
+        for i in 0..5 {
+            link1.send(format!("Hello {}", i).into_bytes()).unwrap();
+        }
+
+        for i in 0..5 {
+            let received = link2.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(received, format!("Hello {}", i).into_bytes());
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        assert!(link2.stats().unwrap().nacks_sent >= 1);
+        let stats1 = link1.stats().unwrap();
+        assert!(stats1.nack_retransmissions >= 1);
+        assert!(stats1.retransmissions >= stats1.nack_retransmissions);
+    }
+
+    #[test]
+    fn corrupted_packet_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        // The payload of encrypted data packets is corrupted while `corrupt` is set
+        let corrupt = Arc::new(AtomicBool::new(false));
+        let corrupting = corrupt.clone();
+        let socket1 =
+            FilteringTransport::new(socket1, move |buf: &[u8]| {
+                match Packet::decode_slice(buf, PROTOCOL_VERSION) {
+                    Ok(mut packet)
+                        if corrupting.load(Ordering::SeqCst)
+                            && packet.flags.enc
+                            && packet.flags.p_type == PType::Data =>
+                    {
+                        packet.payload[0] ^= 0xFF;
+                        Verdict::Replace(packet.compile())
+                    }
+                    _ => Verdict::Send,
+                }
+            });
+        let config = Config::default();
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        // Packets without authentication tags reach the decryption thread when corrupted
+        link1.set_version(19);
+        link2.set_version(19);
+        link1.start();
+        link2.start();
+
+        let handle = thread::spawn(move || {
+            link2.enable_encryption().unwrap();
+            link2
+        });
+        link1.enable_encryption().unwrap();
+        let link2 = handle.join().unwrap();
+
+        // A packet failing to decrypt is dropped and counted
+        corrupt.store(true, Ordering::SeqCst);
+        link1.send(b"Corrupted".to_vec()).unwrap();
+        assert!(wait_until(Duration::from_secs(5), || {
+            link2.stats().unwrap().forgeries_dropped > 0
+        }));
+        assert!(link2.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // and the link keeps delivering the messages after it
+        corrupt.store(false, Ordering::SeqCst);
+        link1.send(b"Hello".to_vec()).unwrap();
+        assert_eq!(
+            link2.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"Hello".to_vec()
+        );
+        assert_eq!(link2.close_reason().unwrap(), None);
+        assert_eq!(link2.failure().unwrap(), None);
+        link2.stop().unwrap();
+    }
+
+    /// Returns a pair of started links over `network` at protocol version `version`,
+    /// along with the UID of the second peer
+    fn authentication_pair(network: &MemoryNetwork, version: u8) -> (Link, Link, String) {
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+        let uid2 = public2.public_key_to_base64().unwrap();
+
+        let config = Config::default();
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        link1.set_version(version);
+        link2.set_version(version);
+        link1.start();
+        link2.start();
+
+        (link1, link2, uid2)
+    }
+
+    #[test]
+    fn authentication_test() {
+        let network = MemoryNetwork::new();
+        let config = Config::default();
+
+        // A challenge that cannot be decrypted fails the authentication
+        let (link1, link2, uid2) = authentication_pair(&network, PROTOCOL_VERSION);
+        link2.send(vec![0; 256]).unwrap();
+        assert!(matches!(
+            authenticate(link1, uid2, 0, config),
+            Err(AetherError::AuthenticationInvalid(_))
+        ));
+
+        // Peers answering challenges with the nonce itself are not authenticated
+        let (link1, _link2, uid2) = authentication_pair(&network, 14);
+        assert!(matches!(
+            authenticate(link1, uid2, 0, config),
+            Err(AetherError::AuthenticationInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn forged_sequence_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        // While `forge` is set, a copy of encrypted data packets is sent with the next
+        // sequence number, as an attacker rewriting captured packets would
+        let forge = Arc::new(AtomicBool::new(false));
+        let forging = forge.clone();
+        let socket1 =
+            FilteringTransport::new(socket1, move |buf: &[u8]| {
+                match Packet::decode_slice(buf, PROTOCOL_VERSION) {
+                    Ok(packet)
+                        if forging.load(Ordering::SeqCst)
+                            && packet.flags.enc
+                            && packet.flags.p_type == PType::Data =>
+                    {
+                        let mut forged = buf.to_vec();
+                        let end = forged.len() - CHECKSUM_SIZE;
+                        forged[..4].copy_from_slice(&(packet.sequence + 1).0.to_be_bytes());
+                        let checksum = crc32(&forged[..end]);
+                        forged[end..].copy_from_slice(&checksum.to_be_bytes());
+                        Verdict::Inject(forged)
+                    }
+                    _ => Verdict::Send,
+                }
+            });
+        let config = Config::default();
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        link1.start();
+        link2.start();
+
+        let handle = thread::spawn(move || {
+            link2.enable_encryption().unwrap();
+            link2
+        });
+        link1.enable_encryption().unwrap();
+        let link2 = handle.join().unwrap();
+
+        // The copy of the first message would take the place of the second one, which
+        // would then be dropped as a replay, if the sequence number was not
+        // authenticated
+        forge.store(true, Ordering::SeqCst);
+        link1.send(b"Hello 0".to_vec()).unwrap();
+        assert_eq!(
+            link2.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"Hello 0".to_vec()
+        );
+        forge.store(false, Ordering::SeqCst);
+        link1.send(b"Hello 1".to_vec()).unwrap();
+        assert_eq!(
+            link2.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"Hello 1".to_vec()
+        );
+        assert!(link2.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let stats = link2.stats().unwrap();
+        assert!(stats.forgeries_dropped >= 1);
+        assert_eq!(link2.failure().unwrap(), None);
+
+        link1.stop().unwrap();
+        link2.stop().unwrap();
+    }
+
+    #[test]
+    fn incoming_progress_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        // The last fragment of every message is lost while `hold` is set
+        let hold = Arc::new(AtomicBool::new(true));
+        let holding = hold.clone();
+        let socket1 =
+            FilteringTransport::new(socket1, move |buf: &[u8]| {
+                match Packet::decode_slice(buf, PROTOCOL_VERSION) {
+                    Ok(packet)
+                        if holding.load(Ordering::SeqCst)
+                            && packet.flags.p_type == PType::Data
+                            && !packet.flags.more =>
+                    {
+                        Verdict::Drop
+                    }
+                    _ => Verdict::Send,
+                }
+            });
+        let config = Config::default();
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        link1.start();
+        link2.start();
+        assert_eq!(link2.incoming_progress().unwrap(), None);
+
+        // Every fragment but the last arrives
+        let message: Vec<u8> = (0..MAX_PAYLOAD_SIZE * 10).map(|i| i as u8).collect();
+        link1.send(message.clone()).unwrap();
+        assert!(wait_until(Duration::from_secs(5), || {
+            matches!(
+                link2.incoming_progress().unwrap(),
+                Some(progress) if progress.received + MAX_PAYLOAD_SIZE > message.len()
+            )
+        }));
+        let progress = link2.incoming_progress().unwrap().unwrap();
+        assert_eq!(progress.total, message.len());
+        assert!(progress.received < message.len());
+
+        hold.store(false, Ordering::SeqCst);
+        assert_eq!(link2.recv_timeout(Duration::from_secs(5)).unwrap(), message);
+        assert_eq!(link2.incoming_progress().unwrap(), None);
+    }
+
+    #[test]
+    fn simulated_link_test() {
+        let network = MemoryNetwork::new();
+        let (socket1, socket2) = network.pair();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+
+        let conditions = NetworkConditions {
+            loss: 0.1,
+            duplication: 0.1,
+            reordering: 0.1,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            seed: Some(2821),
+        };
+        let mut config = Config::default();
+        config.link.retry_delay = 100;
+
+        let (id1, public1) = identity();
+        let (id2, public2) = identity();
+
+        let socket1 = SimulatedTransport::new(socket1, conditions);
+        let socket2 = SimulatedTransport::new(socket2, conditions);
+        let mut link1 = Link::new(id1, socket1, addr2, public2, Seq(0), Seq(1000), config).unwrap();
+        let mut link2 = Link::new(id2, socket2, addr1, public1, Seq(1000), Seq(0), config).unwrap();
+        link1.start();
+        link2.start();
+
+        for i in 0..100 {
+            link1.send(format!("Hello {}", i).into_bytes()).unwrap();
+        }
+
+        // Everything arrives once and in order despite the network
+        for i in 0..100 {
+            let received = link2.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(received, format!("Hello {}", i).into_bytes());
+        }
+        assert!(link2.recv_timeout(Duration::from_millis(500)).is_err());
+        assert!(link1.stats().unwrap().retransmissions > 0);
+    }
+}